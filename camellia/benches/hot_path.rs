@@ -0,0 +1,93 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    time::{Duration, Instant},
+};
+
+use camellia::{
+    socket::af_xdp::{XskSocket, XskSocketBuilder},
+    umem::base::{DedicatedAccessorRef, UMemBuilder},
+};
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use test_utils::veth::{VethDeviceBuilder, VethPair};
+
+const BATCH_SIZE: usize = 64;
+const PACKET_SIZE: usize = 64;
+
+fn setup_veth() -> VethPair {
+    let left = VethDeviceBuilder::new("bench-left")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 12, 1)), 24);
+    let right = VethDeviceBuilder::new("bench-right")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 12, 2)), 24);
+    right.build(left).unwrap()
+}
+
+fn socket(ifname: &str) -> XskSocket<DedicatedAccessorRef> {
+    XskSocketBuilder::new()
+        .ifname(ifname)
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .enable_cooperate_schedule()
+        .build()
+        .unwrap()
+}
+
+// Sends a batch from one socket and receives it on the other, the packets go through
+// veth so most of the time is spent in the kernel. The overhead of the tracing and
+// stat updates of recv_bulk and send_bulk shows by comparing runs with and without the
+// trace feature, e.g., cargo bench --bench hot_path --no-default-features.
+fn hot_path_benchmark(c: &mut Criterion) {
+    let veth_pair = setup_veth();
+    let mut left = socket("bench-left");
+    let mut right = socket("bench-right");
+
+    let mut packet = [0u8; PACKET_SIZE];
+    packet[..6].copy_from_slice(&veth_pair.right.mac_addr.bytes());
+    packet[6..12].copy_from_slice(&veth_pair.left.mac_addr.bytes());
+    packet[12..14].copy_from_slice(&0x88b5u16.to_be_bytes());
+
+    let mut group = c.benchmark_group("hot_path");
+    group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+    group.bench_function("send_recv_bulk", |b| {
+        b.iter_custom(|iters| {
+            let start = Instant::now();
+            for _ in 0..iters {
+                let mut frames = left.allocate(BATCH_SIZE).unwrap();
+                for frame in frames.iter_mut() {
+                    frame
+                        .raw_buffer_append(PACKET_SIZE)
+                        .unwrap()
+                        .copy_from_slice(&packet);
+                }
+                assert!(left.send_bulk(frames).unwrap().is_empty());
+
+                let deadline = Instant::now() + Duration::from_secs(1);
+                let mut received = 0;
+                while received < BATCH_SIZE && Instant::now() < deadline {
+                    received += right.recv_bulk(BATCH_SIZE - received).unwrap().len();
+                }
+            }
+            start.elapsed()
+        })
+    });
+
+    // the TX ring alone, allocate takes the chunks of the previous batch back from the
+    // completion ring
+    group.bench_function("send_bulk", |b| {
+        b.iter(|| {
+            let mut frames = left.allocate(BATCH_SIZE).unwrap();
+            for frame in frames.iter_mut() {
+                frame
+                    .raw_buffer_append(PACKET_SIZE)
+                    .unwrap()
+                    .copy_from_slice(&packet);
+            }
+            left.send_bulk(frames).unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, hot_path_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,149 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use camellia::{
+    socket::af_xdp::{XskSocket, XskSocketBuilder},
+    umem::{
+        base::{DedicatedAccessorRef, UMemBuilder},
+        frame::RxFrame,
+    },
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use etherparse::PacketBuilder;
+use test_utils::{
+    ethtool::{get_coalescing, set_coalescing, set_coalescing_params},
+    nic::NicPair,
+    veth::{MacAddr, VethDeviceBuilder, VethPair},
+};
+
+const BATCH_SIZE: usize = 64;
+
+fn setup_veth() -> VethPair {
+    let left = VethDeviceBuilder::new("pps-left")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x3a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 13, 1)), 24);
+    let right = VethDeviceBuilder::new("pps-right")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x3b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 13, 2)), 24);
+    right.build(left).unwrap()
+}
+
+fn socket(ifname: &str, queue: u32) -> XskSocket<DedicatedAccessorRef> {
+    XskSocketBuilder::new()
+        .ifname(ifname)
+        .queue_index(queue)
+        .with_umem(UMemBuilder::new().num_chunks(16384).build().unwrap())
+        .build()
+        .unwrap()
+}
+
+// a minimum-size UDP frame, 60 bytes without the FCS
+fn udp_frame(source: MacAddr, destination: MacAddr) -> Vec<u8> {
+    let builder = PacketBuilder::ethernet2(source.bytes(), destination.bytes())
+        .ipv4([192, 168, 13, 1], [192, 168, 13, 2], 64)
+        .udp(9, 9);
+    let payload = [0u8; 18];
+    let mut frame = Vec::with_capacity(builder.size(payload.len()));
+    builder.write(&mut frame, &payload).unwrap();
+    frame
+}
+
+// Runs the benchmark while another thread floods the TX device with the packet
+fn with_flood<F: FnOnce()>(tx: &str, queue: u32, packet: &[u8], benchmark: F) {
+    let running = AtomicBool::new(true);
+    thread::scope(|s| {
+        s.spawn(|| {
+            let mut sender = socket(tx, queue);
+            while running.load(Ordering::Relaxed) {
+                let Ok(mut frames) = sender.allocate(BATCH_SIZE) else {
+                    continue;
+                };
+                for frame in frames.iter_mut() {
+                    frame
+                        .raw_buffer_append(packet.len())
+                        .unwrap()
+                        .copy_from_slice(packet);
+                }
+                sender.send_bulk(frames).unwrap();
+            }
+        });
+
+        benchmark();
+        running.store(false, Ordering::Relaxed);
+    });
+}
+
+// the time to receive iters batches
+fn receive(
+    receiver: &mut XskSocket<DedicatedAccessorRef>,
+    frames: &mut Vec<RxFrame<DedicatedAccessorRef>>,
+    iters: u64,
+) -> Duration {
+    let total = iters as usize * BATCH_SIZE;
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let start = Instant::now();
+    let mut received = 0;
+    while received < total && Instant::now() < deadline {
+        received += receiver
+            .recv_bulk_into(frames, BATCH_SIZE.min(total - received))
+            .unwrap();
+        frames.clear();
+    }
+    assert_eq!(received, total, "the sender stalled");
+    start.elapsed()
+}
+
+// A thread floods one end of a veth pair, the benchmark counts the frames arriving at
+// the other end, so the throughput is the packet rate of the receiving socket. Frames
+// dropped as the receiver falls behind are not counted. See examples/pps.rs to measure
+// real NICs.
+fn packet_rate_benchmark(c: &mut Criterion) {
+    let veth_pair = setup_veth();
+    let packet = udp_frame(veth_pair.left.mac_addr, veth_pair.right.mac_addr);
+    let mut receiver = socket("pps-right", 0);
+
+    with_flood("pps-left", 0, &packet, || {
+        let mut group = c.benchmark_group("packet_rate");
+        group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+        group.bench_function("veth_min_size", |b| {
+            let mut frames = Vec::with_capacity(BATCH_SIZE);
+            b.iter_custom(|iters| receive(&mut receiver, &mut frames, iters))
+        });
+        group.finish();
+    });
+}
+
+// The same between two physical ports for a range of interrupt coalescing delays of the
+// receiving one, which is restored afterwards. Skipped unless CAMELLIA_NIC_LEFT and
+// CAMELLIA_NIC_RIGHT name the ports, see test_utils::nic.
+fn coalescing_benchmark(c: &mut Criterion) {
+    let Some(pair) = NicPair::from_env().unwrap() else {
+        return;
+    };
+    let rx = pair.right.name.as_str();
+    let original = get_coalescing(rx).unwrap();
+    let packet = udp_frame(pair.left.mac_addr, pair.right.mac_addr);
+    let mut receiver = socket(rx, pair.right.queue);
+
+    with_flood(&pair.left.name, pair.left.queue, &packet, || {
+        let mut group = c.benchmark_group("packet_rate_coalescing");
+        group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+        for usecs in [0, 8, 32, 128] {
+            set_coalescing(rx, usecs, 0).unwrap();
+            group.bench_function(BenchmarkId::new("rx_usecs", usecs), |b| {
+                let mut frames = Vec::with_capacity(BATCH_SIZE);
+                b.iter_custom(|iters| receive(&mut receiver, &mut frames, iters))
+            });
+        }
+        group.finish();
+    });
+
+    set_coalescing_params(rx, original).unwrap();
+}
+
+criterion_group!(benches, packet_rate_benchmark, coalescing_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use camellia::umem::base::FillQueue;
+use criterion::{criterion_group, criterion_main, Criterion};
+use libxdp_sys::xsk_ring_prod;
+
+const ITERATIONS: u64 = 1 << 20;
+
+// The layout of the ring wrappers before they were cache-line aligned
+struct Packed(xsk_ring_prod);
+
+unsafe impl Send for Packed {}
+
+// Two threads advance the cached producer index of their own ring, like two sockets
+// polled by different cores. Rings sharing a cache line make it bounce between cores.
+fn advance_in_parallel(
+    left: &mut xsk_ring_prod,
+    right: &mut xsk_ring_prod,
+    iters: u64,
+) -> Duration {
+    struct Ring<'a>(&'a mut xsk_ring_prod);
+    unsafe impl Send for Ring<'_> {}
+
+    let (left, right) = (Ring(left), Ring(right));
+    let start = Instant::now();
+    std::thread::scope(|s| {
+        for ring in [left, right] {
+            s.spawn(move || {
+                let ring = ring;
+                for _ in 0..iters * ITERATIONS {
+                    unsafe {
+                        let cached_prod = std::ptr::addr_of_mut!(ring.0.cached_prod);
+                        cached_prod.write_volatile(cached_prod.read_volatile().wrapping_add(1));
+                    }
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn ring_alignment_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ring_index_update");
+
+    group.bench_function("packed", |b| {
+        b.iter_custom(|iters| {
+            let mut rings = [
+                Packed(FillQueue::default().0),
+                Packed(FillQueue::default().0),
+            ];
+            let (left, right) = rings.split_at_mut(1);
+            advance_in_parallel(&mut left[0].0, &mut right[0].0, iters)
+        })
+    });
+
+    group.bench_function("aligned", |b| {
+        b.iter_custom(|iters| {
+            let mut rings = [FillQueue::default(), FillQueue::default()];
+            let (left, right) = rings.split_at_mut(1);
+            advance_in_parallel(&mut left[0].0, &mut right[0].0, iters)
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, ring_alignment_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,48 @@
+use camellia::umem::{base::ChunkLayout, mmap::MMapArea};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const NUM_CHUNKS: usize = 4096;
+const CHUNK_SIZE: u32 = 4096;
+// XDP_PACKET_HEADROOM, where the kernel puts the first byte of a received packet
+const HEADROOM: usize = 256;
+const BATCH_SIZE: usize = 64;
+
+// Touch the Ethernet/IP/UDP headers of a batch of consecutive chunks, like a
+// forwarding loop rewriting headers would do.
+fn touch_headers(area: &MMapArea, layout: &ChunkLayout, first: usize) -> u64 {
+    let mut sum = 0u64;
+    for index in first..first + BATCH_SIZE {
+        let address = area.base_address() + layout.chunk_address(index) + HEADROOM;
+        let header = unsafe { std::slice::from_raw_parts_mut(address as *mut u64, 6) };
+        for word in header.iter_mut() {
+            *word = word.wrapping_add(1);
+            sum = sum.wrapping_add(*word);
+        }
+    }
+    sum
+}
+
+fn stagger_benchmark(c: &mut Criterion) {
+    let area = MMapArea::new(NUM_CHUNKS * CHUNK_SIZE as usize).unwrap();
+    let mut group = c.benchmark_group("header_access");
+
+    for stagger in [false, true] {
+        let layout = ChunkLayout::new(CHUNK_SIZE, stagger);
+        let mut first = 0;
+        group.bench_with_input(
+            BenchmarkId::new("stagger", stagger),
+            &layout,
+            |b, layout| {
+                b.iter(|| {
+                    first = (first + BATCH_SIZE) % NUM_CHUNKS;
+                    touch_headers(&area, layout, first)
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, stagger_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use camellia::umem::{
+    base::{DedicatedAccessorRef, UMemBuilder},
+    AccessorRef,
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use libxdp_sys::{xsk_ring_cons, xsk_ring_prod};
+
+const NUM_CHUNKS: u32 = 4096;
+const BATCH_SIZE: usize = 64;
+
+unsafe fn index<'a>(pointer: *mut u32) -> &'a AtomicU32 {
+    AtomicU32::from_ptr(pointer)
+}
+
+// Takes up to n addresses from the fill ring like the kernel receiving packets
+fn kernel_receive(fill: &xsk_ring_prod, n: usize) -> Vec<u64> {
+    unsafe {
+        let (producer, consumer) = (index(fill.producer), index(fill.consumer));
+        let cons = consumer.load(Ordering::Relaxed);
+        let available = producer.load(Ordering::Acquire).wrapping_sub(cons);
+        let addresses = (0..available.min(n as u32))
+            .map(|i| *(fill.ring as *const u64).add((cons.wrapping_add(i) & fill.mask) as usize))
+            .collect::<Vec<_>>();
+        consumer.store(cons.wrapping_add(addresses.len() as u32), Ordering::Release);
+        addresses
+    }
+}
+
+// Puts addresses on the completion ring like the kernel completing transmissions
+fn kernel_complete(completion: &xsk_ring_cons, addresses: &[u64]) {
+    unsafe {
+        let producer = index(completion.producer);
+        let prod = producer.load(Ordering::Relaxed);
+        for (i, address) in addresses.iter().enumerate() {
+            let slot = prod.wrapping_add(i as u32) & completion.mask;
+            *(completion.ring as *mut u64).add(slot as usize) = *address;
+        }
+        producer.store(prod.wrapping_add(addresses.len() as u32), Ordering::Release);
+    }
+}
+
+// The rings of the UMem and the accessor taking it, the rings are mapped by
+// xsk_umem__create so no socket or device is needed
+fn accessor(track_chunks: bool) -> (DedicatedAccessorRef, xsk_ring_prod, xsk_ring_cons) {
+    let umem = UMemBuilder::new()
+        .num_chunks(NUM_CHUNKS)
+        .track_chunks(track_chunks)
+        .build()
+        .unwrap();
+    let (fill, completion) = (umem.fill.0, umem.completion.0);
+    (umem.into(), fill, completion)
+}
+
+// The operations of the UMem accessor alone, without the kernel and the network. The
+// kernel side of the fill and completion rings is played by the benchmark, so a batch
+// goes through the whole life of chunks: filled, received, sent and recycled.
+fn umem_benchmark(c: &mut Criterion) {
+    let mut group = c.benchmark_group("umem");
+    group.throughput(Throughput::Elements(BATCH_SIZE as u64));
+
+    for track_chunks in [false, true] {
+        let (umem, _, _) = accessor(track_chunks);
+        group.bench_with_input(
+            BenchmarkId::new("allocate_free", track_chunks),
+            &umem,
+            |b, umem| b.iter(|| umem.allocate(BATCH_SIZE).unwrap()),
+        );
+
+        let (umem, fill, completion) = accessor(track_chunks);
+        group.bench_with_input(
+            BenchmarkId::new("fill_recycle", track_chunks),
+            &umem,
+            |b, umem| {
+                b.iter(|| {
+                    assert_eq!(umem.fill(BATCH_SIZE).unwrap(), BATCH_SIZE);
+                    let addresses = kernel_receive(&fill, BATCH_SIZE);
+                    for address in &addresses {
+                        let chunk = umem.extract_recv(*address);
+                        umem.register_send(chunk);
+                    }
+                    kernel_complete(&completion, &addresses);
+                    umem.recycle().unwrap()
+                })
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, umem_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,54 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// the directory of UAPI headers of the target, e.g., asm/types.h, on multiarch systems
+fn multiarch_include() -> Option<PathBuf> {
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").ok()?;
+    let path = PathBuf::from(format!("/usr/include/{}-linux-gnu", arch));
+    path.exists().then_some(path)
+}
+
+fn compile_bpf(source: &Path, out_path: &Path, include_path: &Path) {
+    let stem = source.file_name().unwrap().to_str().unwrap();
+    let object = out_path.join(stem.replace(".bpf.c", ".bpf.o"));
+
+    let mut command = Command::new("clang");
+    command
+        .args(["-g", "-O2", "-Wall", "-target", "bpf", "-c"])
+        .arg(format!("-I{}", include_path.display()));
+    if let Some(path) = multiarch_include() {
+        command.arg(format!("-I{}", path.display()));
+    }
+    let output = command
+        .arg(source)
+        .arg("-o")
+        .arg(&object)
+        .output()
+        .expect("clang is missing");
+
+    if !output.status.success() {
+        panic!(
+            "unable to compile {}\n stdout: {}, stderr: {}",
+            source.display(),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+fn main() {
+    let src_path = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap()).join("src/xdp/bpf");
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let include_path = PathBuf::from(env::var("DEP_XDP_INCLUDE").unwrap());
+
+    println!("cargo:rerun-if-changed={}", src_path.display());
+    for entry in std::fs::read_dir(&src_path).unwrap() {
+        let path = entry.unwrap().path();
+        if path.to_str().unwrap().ends_with(".bpf.c") {
+            compile_bpf(&path, &out_path, &include_path);
+        }
+    }
+}
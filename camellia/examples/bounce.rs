@@ -1,9 +1,13 @@
 use camellia::{
     socket::af_xdp::XskSocketBuilder,
+    stats::StatsReporter,
     umem::{base::UMemBuilder, frame::AppFrame, shared::SharedAccessorRef},
 };
 use clap::Parser;
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -26,6 +30,9 @@ fn main() {
         .enable_cooperate_schedule();
 
     let mut socket = socket_builder.build_shared().unwrap();
+    let mut reporter =
+        StatsReporter::callback(Duration::from_secs(1), |delta| println!("{}", delta));
+
     const BATCH_SIZE: usize = 32;
     loop {
         let frames = socket.recv_bulk(BATCH_SIZE).unwrap();
@@ -44,5 +51,6 @@ fn main() {
         if !frames.is_empty() {
             socket.send_bulk(frames).unwrap();
         }
+        reporter.poll(&socket.stat);
     }
 }
@@ -0,0 +1,297 @@
+// A stateful firewall between two interfaces. Connections are initiated from the inside
+// and tracked by net::firewall, inbound frames are accepted only if they belong to a
+// tracked connection or match an allow rule. Deny rules are rules of the PacketFilter of
+// the interface the frames arrive at, which drops them before they reach the sockets.
+// Rules are updated live by commands read from stdin:
+//
+//   add|del allow in tcp|udp|icmp [port]
+//   add|del deny in|out tcp|udp|icmp [port]
+//   list
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use camellia::{
+    error::CamelliaError,
+    net::{firewall::Firewall, IPPROTO_TCP, IPPROTO_UDP},
+    socket::af_xdp::{XDPMode, XskSocket, XskSocketBuilder},
+    umem::{
+        base::{UMem, UMemBuilder},
+        shared::SharedAccessorRef,
+    },
+    xdp::filter::{FilterAction, FilterMatch, PacketFilter},
+};
+use clap::Parser;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// interface facing the protected network
+    inside: String,
+    /// interface facing the rest of the world
+    outside: String,
+    /// idle timeout of tracked connections in seconds
+    #[arg(long, default_value_t = 60)]
+    timeout: u64,
+    /// connections tracked at most, the least recently used one is evicted beyond
+    #[arg(long, default_value_t = 65536)]
+    connections: usize,
+}
+
+const IPPROTO_ICMP: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Direction {
+    In,
+    Out,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Allow,
+    Deny,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Rule {
+    action: Action,
+    direction: Direction,
+    filter: FilterMatch,
+}
+
+impl Rule {
+    fn parse(words: &[&str]) -> Result<Self, String> {
+        let [action, direction, proto, rest @ ..] = words else {
+            return Err("usage: allow|deny in|out tcp|udp|icmp [port]".to_string());
+        };
+
+        let action = match *action {
+            "allow" => Action::Allow,
+            "deny" => Action::Deny,
+            _ => return Err(format!("unknown action {}", action)),
+        };
+        let direction = match *direction {
+            "in" => Direction::In,
+            "out" => Direction::Out,
+            _ => return Err(format!("unknown direction {}", direction)),
+        };
+        if (action, direction) == (Action::Allow, Direction::Out) {
+            return Err("outbound frames are allowed unless denied".to_string());
+        }
+        let proto = match *proto {
+            "tcp" => IPPROTO_TCP,
+            "udp" => IPPROTO_UDP,
+            "icmp" => IPPROTO_ICMP,
+            _ => return Err(format!("unknown protocol {}", proto)),
+        };
+        let filter = FilterMatch::default().ip_proto(proto);
+        let filter = match rest {
+            [] => filter,
+            [_] if proto == IPPROTO_ICMP => return Err("icmp has no ports".to_string()),
+            [port] => filter.dst_port(port.parse().map_err(|_| format!("invalid port {}", port))?),
+            _ => return Err("too many arguments".to_string()),
+        };
+
+        Ok(Self {
+            action,
+            direction,
+            filter,
+        })
+    }
+}
+
+enum Command {
+    Add(Rule),
+    Delete(Rule),
+    List,
+}
+
+fn control_plane() -> Receiver<Command> {
+    let (sender, receiver) = mpsc::channel();
+
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else { break };
+            let words: Vec<&str> = line.split_whitespace().collect();
+
+            let command = match words.as_slice() {
+                ["add", rule @ ..] => Rule::parse(rule).map(Command::Add),
+                ["del", rule @ ..] => Rule::parse(rule).map(Command::Delete),
+                ["list"] => Ok(Command::List),
+                [] => continue,
+                _ => Err(format!("unknown command: {}", line)),
+            };
+
+            match command {
+                Ok(command) => {
+                    if sender.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    });
+
+    receiver
+}
+
+// An interface whose frames reach the socket through its PacketFilter
+struct Interface {
+    filter: PacketFilter,
+    socket: XskSocket<SharedAccessorRef>,
+}
+
+impl Interface {
+    fn new(ifname: &str, umem: &Arc<Mutex<UMem>>) -> Result<Self, CamelliaError> {
+        let mut filter = PacketFilter::new()?;
+        filter.attach(ifname, XDPMode::Auto)?;
+        let socket = XskSocketBuilder::<SharedAccessorRef>::new()
+            .ifname(ifname)
+            .queue_index(0)
+            .with_umem(umem.clone())
+            .xsks_map(Arc::new(filter.xsks_map()?))
+            .enable_cooperate_schedule()
+            .build_shared()?;
+        Ok(Self { filter, socket })
+    }
+}
+
+struct ControlState {
+    firewall: Firewall,
+    // installed in the PacketFilters, kept to be listed
+    denied: Vec<Rule>,
+}
+
+impl ControlState {
+    fn apply(
+        &mut self,
+        command: Command,
+        inside: &PacketFilter,
+        outside: &PacketFilter,
+    ) -> Result<(), CamelliaError> {
+        // deny rules apply where the frames of their direction arrive
+        let filter = |rule: &Rule| match rule.direction {
+            Direction::In => outside,
+            Direction::Out => inside,
+        };
+
+        match command {
+            Command::Add(rule) if rule.action == Action::Allow => {
+                self.firewall.allow_inbound(rule.filter)
+            }
+            Command::Add(rule) => {
+                filter(&rule).add_rule(rule.filter, FilterAction::Drop)?;
+                if !self.denied.contains(&rule) {
+                    self.denied.push(rule);
+                }
+            }
+            Command::Delete(rule) => {
+                let removed = if rule.action == Action::Allow {
+                    self.firewall.remove_inbound(&rule.filter)
+                } else {
+                    self.denied.retain(|denied| *denied != rule);
+                    filter(&rule).remove_rule(rule.filter)?
+                };
+                if !removed {
+                    eprintln!("no rule {:?}", rule);
+                }
+            }
+            Command::List => {
+                for rule in self.firewall.inbound_rules() {
+                    println!("allow in {:?}", rule);
+                }
+                for rule in &self.denied {
+                    println!("deny {:?} {:?}", rule.direction, rule.filter);
+                }
+                println!(
+                    "{} connections, {:?}",
+                    self.firewall.connections(),
+                    self.firewall.stats()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn forward(
+    from: &mut XskSocket<SharedAccessorRef>,
+    to: &mut XskSocket<SharedAccessorRef>,
+    firewall: &mut Firewall,
+    direction: Direction,
+) {
+    const BATCH_SIZE: usize = 32;
+
+    let now = Instant::now();
+    let mut frames = from.recv_bulk(BATCH_SIZE).unwrap();
+    frames.retain(|frame| match direction {
+        Direction::Out => firewall.accept_outbound(frame.raw_buffer(), now),
+        Direction::In => firewall.accept_inbound(frame.raw_buffer(), now),
+    });
+    if !frames.is_empty() {
+        // frames that don't fit in the TX ring are dropped
+        to.send_bulk(frames).unwrap();
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let cli = Cli::parse();
+
+    let running = Arc::new(AtomicBool::new(true));
+    let running_clone = running.clone();
+    ctrlc::set_handler(move || running_clone.store(false, Ordering::SeqCst)).unwrap();
+
+    let umem = Arc::new(Mutex::new(
+        UMemBuilder::new().num_chunks(16384).build().unwrap(),
+    ));
+    let mut inside = Interface::new(&cli.inside, &umem).unwrap();
+    let mut outside = Interface::new(&cli.outside, &umem).unwrap();
+
+    let commands = control_plane();
+    let mut state = ControlState {
+        firewall: Firewall::new(cli.connections).ttl(Duration::from_secs(cli.timeout)),
+        denied: Vec::new(),
+    };
+
+    let mut last_sweep = Instant::now();
+    while running.load(Ordering::SeqCst) {
+        while let Ok(command) = commands.try_recv() {
+            if let Err(e) = state.apply(command, &inside.filter, &outside.filter) {
+                eprintln!("{}", e);
+            }
+        }
+
+        let firewall = &mut state.firewall;
+        forward(
+            &mut inside.socket,
+            &mut outside.socket,
+            firewall,
+            Direction::Out,
+        );
+        forward(
+            &mut outside.socket,
+            &mut inside.socket,
+            firewall,
+            Direction::In,
+        );
+
+        let now = Instant::now();
+        if now - last_sweep >= Duration::from_secs(1) {
+            firewall.expire(now);
+            last_sweep = now;
+        }
+    }
+
+    println!(
+        "{:?}, connections: {}",
+        state.firewall.stats(),
+        state.firewall.connections()
+    );
+}
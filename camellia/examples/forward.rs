@@ -1,290 +1,250 @@
+// Forwards every frame between two interfaces through sockets sharing one UMem, e.g.,
+//
+//   forward <left> <right> [--left-queue 0] [--right-queue 0] [--batch 32] [--mode spin]
+//           [--num-chunks 16384] [--core 2]
+//
+// runs until Ctrl-C. The interfaces must receive the frames to forward, e.g., by being
+// promiscuous. Without interfaces, it forwards between forward-left and forward-right of
+// the namespaces of test_utils::stdenv and measures the throughput with iperf3.
 use std::{
-    os::fd::{AsFd, AsRawFd},
-    sync::{atomic::AtomicBool, Arc, Mutex},
-    thread::JoinHandle,
-    time::Duration,
+    os::fd::AsFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
 };
 
 use camellia::{
-    socket::af_xdp::XskSocketBuilder,
-    umem::{base::UMemBuilder, shared::SharedAccessorRef},
+    socket::af_xdp::{XskSocket, XskSocketBuilder},
+    umem::{
+        base::{UMem, UMemBuilder},
+        shared::SharedAccessorRef,
+    },
 };
+use clap::{Parser, ValueEnum};
 use humansize::{make_format, DECIMAL};
-use nix::sys::epoll::{self, EpollCreateFlags, EpollEvent};
-use test_utils::{netns::NetNs, stdenv::setup_veth, veth::MacAddr};
-
-fn prepare_env(
-    epoll: bool,
-    busy_polling: bool,
-) -> (Arc<NetNs>, Arc<NetNs>, Arc<AtomicBool>, JoinHandle<()>) {
-    log::warn!(
-        "set up a {} / {} environment",
-        if epoll { "epoll" } else { "polling" },
-        if busy_polling {
-            "preferred busy polling"
-        } else {
-            "normal busy pulling"
-        }
-    );
-    let veth_pair = setup_veth().unwrap();
-
-    let running = Arc::new(AtomicBool::new(true));
-    let ready = Arc::new(AtomicBool::new(false));
-    let running_clone = running.clone();
-    let ready_clone = ready.clone();
-
-    let client_namespace = veth_pair.0.left.namespace.clone();
-    let server_namespace = veth_pair.1.right.namespace.clone();
-
-    let handle = std::thread::spawn(move || {
-        core_affinity::set_for_current(core_affinity::CoreId { id: 2 });
-
-        let broadcase_address = MacAddr::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
-        let mac_address_client = veth_pair.0.left.mac_addr.clone();
-        let mac_address_server = veth_pair.1.right.mac_addr.clone();
-
-        let _guard = veth_pair.0.right.namespace.enter().unwrap();
-
-        let umem = Arc::new(Mutex::new(
-            UMemBuilder::new().num_chunks(16384).build().unwrap(),
-        ));
-
-        let mut left_socket_builder = XskSocketBuilder::<SharedAccessorRef>::new()
-            .ifname("forward-left")
-            .queue_index(0)
-            .with_umem(umem.clone())
-            .enable_cooperate_schedule();
-
-        if busy_polling {
-            left_socket_builder = left_socket_builder.enable_busy_polling();
-        }
-        let mut left_socket = left_socket_builder.build_shared().unwrap();
-
-        let mut right_socket_builder = XskSocketBuilder::<SharedAccessorRef>::new()
-            .ifname("forward-right")
-            .queue_index(0)
-            .with_umem(umem)
-            .enable_cooperate_schedule();
-
-        if busy_polling {
-            right_socket_builder = right_socket_builder.enable_busy_polling();
-        }
-
-        let mut right_socket = right_socket_builder.build_shared().unwrap();
-        let mut total_left_to_right = 0;
-        let mut total_right_to_left = 0;
-        let batch_size = 32;
-
-        ready_clone.store(true, std::sync::atomic::Ordering::SeqCst);
-
-        if !epoll {
-            log::info!("start polling thread");
-            while running_clone.load(std::sync::atomic::Ordering::SeqCst) {
-                let frames = left_socket.recv_bulk(batch_size).unwrap();
-
-                let frames: Vec<_> = frames
-                    .into_iter()
-                    .map(|frame| {
-                        let (ether_header, _remaining) =
-                            etherparse::Ethernet2Header::from_slice(frame.raw_buffer()).unwrap();
-
-                        if ether_header.destination == mac_address_server.bytes()
-                            || ether_header.destination == broadcase_address.bytes()
-                        {
-                            Some(frame)
-                        } else {
-                            None
-                        }
-                    })
-                    .flatten()
-                    .collect();
-
-                total_left_to_right += frames.len();
-
-                if !frames.is_empty() {
-                    let remaining = right_socket.send_bulk(frames).unwrap();
-                    assert_eq!(remaining.len(), 0);
-                }
-
-                let frames = right_socket.recv_bulk(batch_size).unwrap();
-                if frames.len() != 0 {
-                    log::debug!("receive {} frames from right socket", frames.len());
-                }
-
-                let frames: Vec<_> = frames
-                    .into_iter()
-                    .map(|frame| {
-                        let (ether_header, _remaining) =
-                            etherparse::Ethernet2Header::from_slice(frame.raw_buffer()).unwrap();
-
-                        if ether_header.destination == mac_address_client.bytes()
-                            || ether_header.destination == broadcase_address.bytes()
-                        {
-                            Some(frame)
-                        } else {
-                            None
-                        }
-                    })
-                    .flatten()
-                    .collect();
-
-                total_right_to_left += frames.len();
-
-                if !frames.is_empty() {
-                    let remaining = left_socket.send_bulk(frames).unwrap();
-                    assert_eq!(remaining.len(), 0);
-                }
-            }
-            println!(
-                "forward thread exits normally. left=>right: {}, right=>left: {}",
-                total_left_to_right, total_right_to_left
-            );
-        } else {
-            let left_event = epoll::EpollEvent::new(
-                epoll::EpollFlags::EPOLLIN,
-                left_socket.as_fd().as_raw_fd() as u64,
-            );
-            let right_event = epoll::EpollEvent::new(
-                epoll::EpollFlags::EPOLLIN,
-                right_socket.as_fd().as_raw_fd() as u64,
-            );
-
-            let epoll = epoll::Epoll::new(EpollCreateFlags::empty()).unwrap();
-            epoll.add(&left_socket, left_event).unwrap();
-            epoll.add(&right_socket, right_event).unwrap();
+use nix::sys::epoll::{Epoll, EpollCreateFlags, EpollEvent, EpollFlags};
+use test_utils::{iperf::Iperf3, netns::NetNs, stdenv::StdEnvBuilder};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum Mode {
+    /// sleep in epoll until a socket has frames
+    Epoll,
+    /// spin with preferred busy polling, the sockets run the NAPI of their queues
+    BusyPoll,
+    /// spin on the rings, the NAPI runs on interrupts
+    Spin,
+}
 
-            let mut events = [EpollEvent::empty(); 100];
-            let timeout_ms: u16 = 1000;
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// interface to forward from and to, the veth fixture is used without interfaces
+    #[arg(requires = "right")]
+    left: Option<String>,
+    /// the other interface
+    right: Option<String>,
+    /// queue of the left interface
+    #[arg(long, default_value_t = 0)]
+    left_queue: u32,
+    /// queue of the right interface
+    #[arg(long, default_value_t = 0)]
+    right_queue: u32,
+    /// frames received at most per batch
+    #[arg(long, default_value_t = 32)]
+    batch: usize,
+    /// how the forwarding thread waits for frames
+    #[arg(long, value_enum, default_value_t = Mode::Spin)]
+    mode: Mode,
+    /// chunks of the UMem shared by both sockets
+    #[arg(long, default_value_t = 16384)]
+    num_chunks: u32,
+    /// the core of the forwarding thread, 2 with the veth fixture, whose iperf3 runs on 1
+    /// and 3
+    #[arg(long)]
+    core: Option<usize>,
+}
 
-            while running_clone.load(std::sync::atomic::Ordering::SeqCst) {
-                let num_events = epoll.wait(&mut events, timeout_ms).unwrap();
-                // let num_events = epoll::epoll_wait(epfd, &mut events, timeout_ms).unwrap();
-                for i in 0..num_events {
-                    let fd = events[i].data() as i32;
-                    if fd == left_socket.as_fd().as_raw_fd() {
-                        let frames = left_socket.recv_bulk(batch_size).unwrap();
+fn socket(
+    cli: &Cli,
+    ifname: &str,
+    queue_index: u32,
+    umem: &Arc<Mutex<UMem>>,
+) -> XskSocket<SharedAccessorRef> {
+    let builder = XskSocketBuilder::<SharedAccessorRef>::new()
+        .ifname(ifname)
+        .queue_index(queue_index)
+        .with_umem(umem.clone())
+        .enable_cooperate_schedule();
+    match cli.mode {
+        Mode::BusyPoll => builder.enable_busy_polling(),
+        Mode::Epoll | Mode::Spin => builder,
+    }
+    .build_shared()
+    .unwrap()
+}
 
-                        let frames: Vec<_> = frames
-                            .into_iter()
-                            .map(|frame| {
-                                let (ether_header, _remaining) =
-                                    etherparse::Ethernet2Header::from_slice(frame.raw_buffer())
-                                        .unwrap();
+// frames the TX ring has no room for are dropped
+fn forward_batch(
+    from: &mut XskSocket<SharedAccessorRef>,
+    to: &mut XskSocket<SharedAccessorRef>,
+    batch: usize,
+) -> usize {
+    let frames = from.recv_bulk(batch).unwrap();
+    if frames.is_empty() {
+        return 0;
+    }
+    let received = frames.len();
+    received - to.send_bulk(frames).unwrap().len()
+}
 
-                                if ether_header.destination == mac_address_server.bytes()
-                                    || ether_header.destination == broadcase_address.bytes()
-                                {
-                                    Some(frame)
-                                } else {
-                                    None
-                                }
-                            })
-                            .flatten()
-                            .collect();
+fn print_stats(name: &str, socket: &XskSocket<SharedAccessorRef>) {
+    let formatter = make_format(DECIMAL);
+    let stat = &socket.stat;
+    println!(
+        "{}: rx_batch: {}, rx_packets: {}, rx_bytes: {}, rx_wakeup: {}, tx_batch: {}, \
+         tx_packets: {}, tx_bytes: {}, tx_wakeup: {}",
+        name,
+        formatter(stat.rx_batch),
+        formatter(stat.rx_packets),
+        formatter(stat.rx_bytes),
+        formatter(stat.rx_wakeup),
+        formatter(stat.tx_batch),
+        formatter(stat.tx_packets),
+        formatter(stat.tx_bytes),
+        formatter(stat.tx_wakeup)
+    );
+}
 
-                        if !frames.is_empty() {
-                            right_socket.send_bulk(frames).unwrap();
-                        }
-                    } else if fd == right_socket.as_fd().as_raw_fd() {
-                        let frames = right_socket.recv_bulk(batch_size).unwrap();
-                        let frames: Vec<_> = frames
-                            .into_iter()
-                            .map(|frame| {
-                                let (ether_header, _remaining) =
-                                    etherparse::Ethernet2Header::from_slice(frame.raw_buffer())
-                                        .unwrap();
+// Forwards until running is cleared, ready is called once both sockets are bound
+fn forward(cli: &Cli, left: &str, right: &str, running: &AtomicBool, ready: impl FnOnce()) {
+    if let Some(core) = cli.core {
+        core_affinity::set_for_current(core_affinity::CoreId { id: core });
+    }
+    log::info!(
+        "forward between {} and {} in {:?} mode",
+        left,
+        right,
+        cli.mode
+    );
 
-                                if ether_header.destination == mac_address_client.bytes()
-                                    || ether_header.destination == broadcase_address.bytes()
-                                {
-                                    Some(frame)
-                                } else {
-                                    None
-                                }
-                            })
-                            .flatten()
-                            .collect();
+    let umem = Arc::new(Mutex::new(
+        UMemBuilder::new()
+            .num_chunks(cli.num_chunks)
+            .build()
+            .unwrap(),
+    ));
+    let mut left_socket = socket(cli, left, cli.left_queue, &umem);
+    let mut right_socket = socket(cli, right, cli.right_queue, &umem);
+    ready();
+
+    let mut left_to_right = 0;
+    let mut right_to_left = 0;
+    if cli.mode == Mode::Epoll {
+        const LEFT: u64 = 0;
+        const RIGHT: u64 = 1;
+        let epoll = Epoll::new(EpollCreateFlags::empty()).unwrap();
+        epoll
+            .add(
+                left_socket.as_fd(),
+                EpollEvent::new(EpollFlags::EPOLLIN, LEFT),
+            )
+            .unwrap();
+        epoll
+            .add(
+                right_socket.as_fd(),
+                EpollEvent::new(EpollFlags::EPOLLIN, RIGHT),
+            )
+            .unwrap();
 
-                        if !frames.is_empty() {
-                            left_socket.send_bulk(frames).unwrap();
-                        }
-                    } else {
-                        panic!("unexpected fd: {}", fd);
-                    }
+        let mut events = [EpollEvent::empty(); 2];
+        while running.load(Ordering::Relaxed) {
+            // woken up now and then to notice the end
+            let num_events = epoll.wait(&mut events, 1000u16).unwrap();
+            for event in &events[..num_events] {
+                if event.data() == LEFT {
+                    left_to_right += forward_batch(&mut left_socket, &mut right_socket, cli.batch);
+                } else {
+                    right_to_left += forward_batch(&mut right_socket, &mut left_socket, cli.batch);
                 }
             }
         }
+    } else {
+        while running.load(Ordering::Relaxed) {
+            left_to_right += forward_batch(&mut left_socket, &mut right_socket, cli.batch);
+            right_to_left += forward_batch(&mut right_socket, &mut left_socket, cli.batch);
+        }
+    }
 
-        let formatter = make_format(DECIMAL);
-
-        println!(
-            "left: rx_batch: {}, rx_packets: {}, rx_bytes: {}, rx_wakeup: {}, tx_batch: {}, tx_packets: {}, tx_bytes: {}, tx_wakeup: {}",
-            formatter(left_socket.stat.rx_batch), formatter(left_socket.stat.rx_packets), formatter(left_socket.stat.rx_bytes), formatter(left_socket.stat.rx_wakeup), formatter(left_socket.stat.tx_batch), formatter(left_socket.stat.tx_packets), formatter(left_socket.stat.tx_bytes), formatter(left_socket.stat.tx_wakeup)
-        );
-        println!(
-            "left: rx_batch: {}, rx_packets: {}, rx_bytes: {}, rx_wakeup: {}, tx_batch: {}, tx_packets: {}, tx_bytes: {}, tx_wakeup: {}",
-            formatter(right_socket.stat.rx_batch), formatter(right_socket.stat.rx_packets), formatter(right_socket.stat.rx_bytes), formatter(right_socket.stat.rx_wakeup), formatter(right_socket.stat.tx_batch), formatter(right_socket.stat.tx_packets), formatter(right_socket.stat.tx_bytes), formatter(right_socket.stat.tx_wakeup)
-        );
-    });
-
-    while !ready.load(std::sync::atomic::Ordering::SeqCst) {}
-
-    (client_namespace, server_namespace, running, handle)
+    println!(
+        "forwarded {} => {}: {}, {} => {}: {}",
+        left, right, left_to_right, right, left, right_to_left
+    );
+    print_stats(left, &left_socket);
+    print_stats(right, &right_socket);
 }
 
 fn run_iperf(client_ns: &Arc<NetNs>, server_ns: &Arc<NetNs>) {
-    let client_ns = client_ns.clone();
-    let server_ns = server_ns.clone();
-
-    let server_handle = std::thread::spawn(move || {
-        core_affinity::set_for_current(core_affinity::CoreId { id: 3 });
-        let _guarad = server_ns.enter().unwrap();
-
-        let output = std::process::Command::new("taskset")
-            .args(["-c", "3", "iperf3", "-p", "9000", "-s", "-1"])
-            .output()
-            .unwrap();
-
-        if !output.status.success() {
-            panic!("failed to run iperf3 server");
-        };
-    });
-
-    std::thread::sleep(Duration::from_secs(1));
-
-    let client_handle = std::thread::spawn(move || {
-        core_affinity::set_for_current(core_affinity::CoreId { id: 1 });
-        let _guard = client_ns.enter().unwrap();
-
-        let mut output = std::process::Command::new("taskset")
-            .args([
-                "-c",
-                "1",
-                "iperf3",
-                "-c",
-                "192.168.12.1",
-                "-p",
-                "9000",
-                "-t",
-                "10",
-                "-C",
-                "bbr",
-            ])
-            .spawn()
-            .unwrap();
+    let result = Iperf3::new()
+        .port(9000)
+        .congestion("bbr")
+        .server_cpu(3)
+        .client_cpu(1)
+        .run(server_ns, client_ns, "192.168.12.1".parse().unwrap())
+        .unwrap();
+
+    let formatter = make_format(DECIMAL);
+    println!(
+        "sent {}B, received {}B at {}bps, {} retransmits",
+        formatter(result.sent.bytes),
+        formatter(result.received.bytes),
+        formatter(result.received.bits_per_second as u64),
+        result.retransmits
+    );
+}
 
-        if !output.wait().unwrap().success() {
-            panic!("failed to run iperf3 client");
-        };
+// The forwarder runs in the namespace of forward-left and forward-right while iperf3
+// sends from the client to the server namespace through it
+fn run_fixture(mut cli: Cli) {
+    cli.core = cli.core.or(Some(2));
+    let (left, right) = StdEnvBuilder::new()
+        .num_queues(cli.left_queue.max(cli.right_queue) + 1)
+        .build()
+        .unwrap();
+    let client_ns = left.left.namespace.clone();
+    let server_ns = right.right.namespace.clone();
+    let forwarder_ns = left.right.namespace.clone();
+
+    let running = AtomicBool::new(true);
+    let (ready, wait_ready) = mpsc::channel();
+    thread::scope(|s| {
+        let (cli, running) = (&cli, &running);
+        s.spawn(move || {
+            let _guard = forwarder_ns.enter().unwrap();
+            forward(cli, "forward-left", "forward-right", running, || {
+                ready.send(()).unwrap()
+            });
+        });
+
+        wait_ready.recv().unwrap();
+        run_iperf(&client_ns, &server_ns);
+        running.store(false, Ordering::Relaxed);
     });
-
-    client_handle.join().unwrap();
-    server_handle.join().unwrap();
 }
 
 fn main() {
-    let (client_ns, server_ns, stop_signal, handle) = prepare_env(false, false);
-    run_iperf(&client_ns, &server_ns);
-    stop_signal.store(false, std::sync::atomic::Ordering::SeqCst);
-    handle.join().unwrap();
+    env_logger::init();
+    let cli = Cli::parse();
+    let (Some(left), Some(right)) = (cli.left.clone(), cli.right.clone()) else {
+        run_fixture(cli);
+        return;
+    };
+
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let running = running.clone();
+        ctrlc::set_handler(move || running.store(false, Ordering::Relaxed)).unwrap();
+    }
+    forward(&cli, &left, &right, &running, || {});
 }
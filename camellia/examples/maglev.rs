@@ -0,0 +1,188 @@
+// A Maglev load balancer. Flows arriving at the RX queues of the ingress interface are
+// spread over the backends by consistent hashing, and their frames leave the egress
+// interface with the MAC and IPv4 destination of their backend. Each RX queue is served
+// by a thread whose sockets on both interfaces share a UMem, so that frames are sent out
+// without copying.
+//
+//   maglev eth0 eth1 --queues 4 --backend 02:00:00:00:00:01/10.0.1.1 \
+//       --backend 02:00:00:00:00:02/10.0.1.2
+use std::{
+    net::Ipv4Addr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use camellia::{
+    net::{maglev::Maglev, parse_packet, rewrite_ipv4, Endpoint},
+    socket::af_xdp::XskSocketBuilder,
+    umem::{base::UMemBuilder, shared::SharedAccessorRef},
+};
+use clap::Parser;
+use test_utils::veth::MacAddr;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    ingress: String,
+    egress: String,
+    // number of RX queues of the ingress interface to serve, one thread each
+    #[arg(long, default_value_t = 1)]
+    queues: u32,
+    // backends as <MAC>/<IPv4 address>
+    #[arg(long = "backend", required = true)]
+    backends: Vec<String>,
+    // a prime, much larger than the number of backends
+    #[arg(long, default_value_t = camellia::net::maglev::DEFAULT_TABLE_SIZE)]
+    table_size: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Backend {
+    mac: MacAddr,
+    addr: Ipv4Addr,
+}
+
+impl Backend {
+    fn parse(s: &str) -> Result<Self, String> {
+        let (mac, addr) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected <MAC>/<IPv4 address>, got {}", s))?;
+        Ok(Self {
+            mac: mac.parse().map_err(|_| format!("invalid MAC {}", mac))?,
+            addr: addr
+                .parse()
+                .map_err(|_| format!("invalid address {}", addr))?,
+        })
+    }
+}
+
+struct Balancer {
+    maglev: Maglev,
+    backends: Vec<Backend>,
+    source_mac: MacAddr,
+}
+
+impl Balancer {
+    // rewrites the frame towards the backend of its flow, returns false for frames which
+    // aren't IPv4 TCP or UDP
+    fn balance(&self, frame: &mut [u8]) -> bool {
+        let Some(info) = parse_packet(frame).filter(|info| info.is_ipv4()) else {
+            return false;
+        };
+        let backend = &self.backends[self.maglev.lookup_flow(&info.key)];
+
+        frame[0..6].copy_from_slice(&backend.mac.bytes());
+        frame[6..12].copy_from_slice(&self.source_mac.bytes());
+        rewrite_ipv4(
+            frame,
+            &info,
+            Endpoint::Destination,
+            backend.addr,
+            info.key.dst_port,
+        );
+        true
+    }
+}
+
+fn interface_mac(ifname: &str) -> MacAddr {
+    std::fs::read_to_string(format!("/sys/class/net/{}/address", ifname))
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap()
+}
+
+fn serve(
+    ingress: &str,
+    egress: &str,
+    queue_index: u32,
+    balancer: &Balancer,
+    running: &AtomicBool,
+) -> (u64, u64) {
+    const BATCH_SIZE: usize = 32;
+
+    let umem = Arc::new(Mutex::new(
+        UMemBuilder::new().num_chunks(16384).build().unwrap(),
+    ));
+
+    let mut rx = XskSocketBuilder::<SharedAccessorRef>::new()
+        .ifname(ingress)
+        .queue_index(queue_index)
+        .with_umem(umem.clone())
+        .enable_cooperate_schedule()
+        .build_shared()
+        .unwrap();
+
+    let mut tx = XskSocketBuilder::<SharedAccessorRef>::new()
+        .ifname(egress)
+        .queue_index(queue_index)
+        .with_umem(umem)
+        .enable_cooperate_schedule()
+        .build_shared()
+        .unwrap();
+
+    let (mut balanced, mut dropped) = (0, 0);
+    while running.load(Ordering::SeqCst) {
+        let mut frames = rx.recv_bulk(BATCH_SIZE).unwrap();
+        let received = frames.len();
+        frames.retain_mut(|frame| balancer.balance(frame.raw_buffer_mut()));
+        dropped += (received - frames.len()) as u64;
+
+        if !frames.is_empty() {
+            balanced += frames.len() as u64;
+            // frames that don't fit in the TX ring are dropped
+            tx.send_bulk(frames).unwrap();
+        }
+    }
+    (balanced, dropped)
+}
+
+fn main() {
+    env_logger::init();
+    let cli = Cli::parse();
+
+    let backends = cli
+        .backends
+        .iter()
+        .map(|backend| Backend::parse(backend))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    let names: Vec<[u8; 4]> = backends
+        .iter()
+        .map(|backend| backend.addr.octets())
+        .collect();
+    let balancer = Balancer {
+        maglev: Maglev::new(&names, cli.table_size).unwrap(),
+        backends,
+        source_mac: interface_mac(&cli.egress),
+    };
+
+    let running = Arc::new(AtomicBool::new(true));
+    let running_clone = running.clone();
+    ctrlc::set_handler(move || running_clone.store(false, Ordering::SeqCst)).unwrap();
+
+    let balancer = &balancer;
+    let cli = &cli;
+    let running = &*running;
+    let counters: Vec<(u64, u64)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..cli.queues)
+            .map(|queue_index| {
+                scope
+                    .spawn(move || serve(&cli.ingress, &cli.egress, queue_index, balancer, running))
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect()
+    });
+
+    for (queue_index, (balanced, dropped)) in counters.iter().enumerate() {
+        println!(
+            "queue {}: balanced: {}, dropped: {}",
+            queue_index, balanced, dropped
+        );
+    }
+}
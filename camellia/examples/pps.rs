@@ -0,0 +1,151 @@
+// Measures the packet rate of camellia itself. One socket sends minimum-size UDP frames
+// out of the first interface, another counts those arriving at the second interface,
+// e.g., the two ends of a veth pair or two cabled NICs. Unlike iperf, packets never
+// enter the kernel network stack.
+//
+//   pps <tx interface> <rx interface> [--duration 10] [--batch 64] [--zero-copy]
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use camellia::{
+    socket::af_xdp::{XskSocket, XskSocketBuilder},
+    umem::base::{DedicatedAccessorRef, UMemBuilder},
+};
+use clap::Parser;
+use etherparse::PacketBuilder;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    tx: String,
+    rx: String,
+    #[arg(long, default_value_t = 0)]
+    queue: u32,
+    // seconds to run
+    #[arg(long, default_value_t = 10)]
+    duration: u64,
+    #[arg(long, default_value_t = 64)]
+    batch: usize,
+    // on NICs supporting it, veth has no zero-copy mode
+    #[arg(long)]
+    zero_copy: bool,
+}
+
+// Ethernet, IPv4 and UDP headers with padding up to the 60 bytes of a minimum-size frame
+fn udp_frame() -> Vec<u8> {
+    let builder = PacketBuilder::ethernet2(
+        [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+        [0x02, 0x00, 0x00, 0x00, 0x00, 0x02],
+    )
+    .ipv4([10, 0, 0, 1], [10, 0, 0, 2], 64)
+    .udp(9, 9);
+    let payload = [0u8; 18];
+    let mut frame = Vec::with_capacity(builder.size(payload.len()));
+    builder.write(&mut frame, &payload).unwrap();
+    frame
+}
+
+fn socket(cli: &Cli, ifname: &str) -> XskSocket<DedicatedAccessorRef> {
+    let mut builder = XskSocketBuilder::new()
+        .ifname(ifname)
+        .queue_index(cli.queue)
+        .with_umem(UMemBuilder::new().num_chunks(16384).build().unwrap());
+    if cli.zero_copy {
+        builder = builder.enable_zero_copy();
+    }
+    builder.build().unwrap()
+}
+
+fn generate(cli: &Cli, running: &AtomicBool, sent: &AtomicU64) {
+    let mut socket = socket(cli, &cli.tx);
+    let packet = udp_frame();
+    while running.load(Ordering::Relaxed) {
+        // the UMem runs out while the kernel holds the chunks of frames in flight
+        let Ok(mut frames) = socket.allocate(cli.batch) else {
+            continue;
+        };
+        for frame in frames.iter_mut() {
+            frame
+                .raw_buffer_append(packet.len())
+                .unwrap()
+                .copy_from_slice(&packet);
+        }
+        // frames the TX ring is full for go back to the UMem when dropped
+        let unsent = socket.send_bulk(frames).unwrap();
+        sent.fetch_add((cli.batch - unsent.len()) as u64, Ordering::Relaxed);
+    }
+}
+
+fn count(cli: &Cli, running: &AtomicBool, received: &AtomicU64) {
+    let mut socket = socket(cli, &cli.rx);
+    let mut frames = Vec::with_capacity(cli.batch);
+    while running.load(Ordering::Relaxed) {
+        let n = socket.recv_bulk_into(&mut frames, cli.batch).unwrap();
+        received.fetch_add(n as u64, Ordering::Relaxed);
+        frames.clear();
+    }
+}
+
+fn mpps(packets: u64, elapsed: Duration) -> f64 {
+    packets as f64 / elapsed.as_secs_f64() / 1e6
+}
+
+fn main() {
+    let cli = Arc::new(Cli::parse());
+    let running = Arc::new(AtomicBool::new(true));
+    let sent = Arc::new(AtomicU64::new(0));
+    let received = Arc::new(AtomicU64::new(0));
+
+    let counter = {
+        let (cli, running, received) = (cli.clone(), running.clone(), received.clone());
+        thread::spawn(move || count(&cli, &running, &received))
+    };
+    // the counting socket is bound before the first frame is sent
+    thread::sleep(Duration::from_millis(500));
+    let generator = {
+        let (cli, running, sent) = (cli.clone(), running.clone(), sent.clone());
+        thread::spawn(move || generate(&cli, &running, &sent))
+    };
+
+    let start = Instant::now();
+    let mut last = (start, 0, 0);
+    for _ in 0..cli.duration {
+        thread::sleep(Duration::from_secs(1));
+        let now = Instant::now();
+        let (tx, rx) = (
+            sent.load(Ordering::Relaxed),
+            received.load(Ordering::Relaxed),
+        );
+        println!(
+            "tx: {:.3} Mpps, rx: {:.3} Mpps",
+            mpps(tx - last.1, now - last.0),
+            mpps(rx - last.2, now - last.0)
+        );
+        last = (now, tx, rx);
+    }
+
+    running.store(false, Ordering::Relaxed);
+    generator.join().unwrap();
+    counter.join().unwrap();
+
+    let elapsed = last.0 - start;
+    let (tx, rx) = (last.1, last.2);
+    println!(
+        "sent {} ({:.3} Mpps), received {} ({:.3} Mpps), lost {:.2}%",
+        tx,
+        mpps(tx, elapsed),
+        rx,
+        mpps(rx, elapsed),
+        if tx == 0 {
+            0.0
+        } else {
+            tx.saturating_sub(rx) as f64 * 100.0 / tx as f64
+        }
+    );
+}
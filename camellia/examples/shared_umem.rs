@@ -0,0 +1,257 @@
+// Two processes working on the frames of one UMem. The owner creates the UMem backed by a
+// memfd and binds the socket, a worker process maps the same memory and swaps the MAC
+// addresses of every received frame, which the owner then sends back out of the chunk it
+// was received into, e.g.,
+//
+//   shared_umem <interface> [--queue 0] [--batch 64]
+//
+// The owner spawns the worker itself and sends it the memfd over a Unix socket with
+// SCM_RIGHTS, an unrelated process could receive it the same way. Frames are handed over
+// on the same socket as (xdp address, length) descriptors. A chunk belongs to the process
+// which last received its descriptor and only that process touches it, sending and
+// receiving a descriptor orders the accesses of both processes to the chunk, so neither
+// locks nor atomics in the shared memory are needed. Only the owner ever talks to the
+// rings, the worker never sees the UMem but its memory.
+use std::{
+    collections::HashMap,
+    io::{IoSlice, IoSliceMut},
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::process::CommandExt,
+    },
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use camellia::{
+    socket::af_xdp::XskSocketBuilder,
+    umem::{
+        base::{DedicatedAccessorRef, UMemBuilder},
+        frame::{AppFrame, RxFrame},
+        mmap::MMapArea,
+    },
+};
+use clap::Parser;
+use nix::{
+    errno::Errno,
+    sys::socket::{
+        recv, recvmsg, send, sendmsg, socketpair, AddressFamily, ControlMessage,
+        ControlMessageOwned, MsgFlags, SockFlag, SockType,
+    },
+};
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    nic: String,
+    #[arg(long, default_value_t = 0)]
+    queue: u32,
+    #[arg(long, default_value_t = 64)]
+    batch: usize,
+    // run as the worker, stdin is the Unix socket to the owner
+    #[arg(long, hide = true)]
+    worker: bool,
+}
+
+// xdp address and length, both as u64
+const DESCRIPTOR_SIZE: usize = 16;
+// Batches handed over and not returned yet. The worker sends every batch back, so with
+// few enough of them in flight neither process blocks in send while the other does.
+const MAX_BATCHES: usize = 8;
+
+fn encode(descriptors: &[(u64, u64)]) -> Vec<u8> {
+    descriptors
+        .iter()
+        .flat_map(|(address, len)| [address.to_ne_bytes(), len.to_ne_bytes()])
+        .flatten()
+        .collect()
+}
+
+fn decode(message: &[u8]) -> impl Iterator<Item = (u64, u64)> + '_ {
+    message.chunks_exact(DESCRIPTOR_SIZE).map(|descriptor| {
+        let (address, len) = descriptor.split_at(8);
+        (
+            u64::from_ne_bytes(address.try_into().unwrap()),
+            u64::from_ne_bytes(len.try_into().unwrap()),
+        )
+    })
+}
+
+// the size of the UMem as data, the memfd as ancillary data
+fn send_memfd(channel: BorrowedFd, memfd: BorrowedFd, size: usize) {
+    let size = (size as u64).to_ne_bytes();
+    sendmsg::<()>(
+        channel.as_raw_fd(),
+        &[IoSlice::new(&size)],
+        &[ControlMessage::ScmRights(&[memfd.as_raw_fd()])],
+        MsgFlags::empty(),
+        None,
+    )
+    .unwrap();
+}
+
+fn receive_memfd(channel: BorrowedFd) -> (OwnedFd, usize) {
+    let mut size = [0u8; 8];
+    let mut control = nix::cmsg_space!([RawFd; 1]);
+    let mut iov = [IoSliceMut::new(&mut size)];
+    let message = recvmsg::<()>(
+        channel.as_raw_fd(),
+        &mut iov,
+        Some(&mut control),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )
+    .unwrap();
+    let memfd = match message.cmsgs().next() {
+        Some(ControlMessageOwned::ScmRights(fds)) if fds.len() == 1 => unsafe {
+            OwnedFd::from_raw_fd(fds[0])
+        },
+        _ => panic!("the owner sent no memfd"),
+    };
+    (memfd, u64::from_ne_bytes(size) as usize)
+}
+
+fn owner(cli: &Cli) {
+    let umem = UMemBuilder::new()
+        .num_chunks(4096)
+        .memfd(true)
+        .build()
+        .unwrap();
+    let memfd = umem.memfd().unwrap().try_clone_to_owned().unwrap();
+    let size = umem.area.size();
+    let mut socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname(&cli.nic)
+        .queue_index(cli.queue)
+        .with_umem(umem)
+        .build()
+        .unwrap();
+
+    // SOCK_SEQPACKET keeps batches of descriptors apart
+    let (channel, worker_channel) = socketpair(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        None,
+        SockFlag::SOCK_CLOEXEC,
+    )
+    .unwrap();
+    let mut worker = Command::new(std::env::current_exe().unwrap())
+        .arg(&cli.nic)
+        .arg("--batch")
+        .arg(cli.batch.to_string())
+        .arg("--worker")
+        // Ctrl-C stops the owner only, which then stops the worker
+        .process_group(0)
+        .stdin(Stdio::from(worker_channel))
+        .spawn()
+        .unwrap();
+    send_memfd(channel.as_fd(), memfd.as_fd(), size);
+    drop(memfd);
+
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let running = running.clone();
+        ctrlc::set_handler(move || running.store(false, Ordering::Relaxed)).unwrap();
+    }
+
+    // frames the worker holds, by xdp address
+    let mut handed_over: HashMap<u64, RxFrame<DedicatedAccessorRef>> = HashMap::new();
+    let mut message = vec![0u8; cli.batch * DESCRIPTOR_SIZE];
+    let mut batches = 0;
+    let mut forwarded = 0;
+    while running.load(Ordering::Relaxed) {
+        let frames = if batches < MAX_BATCHES {
+            socket.recv_bulk(cli.batch).unwrap()
+        } else {
+            Vec::new()
+        };
+        if !frames.is_empty() {
+            let descriptors: Vec<(u64, u64)> = frames
+                .iter()
+                .map(|frame| (frame.0.xdp_address() as u64, frame.len() as u64))
+                .collect();
+            for (frame, (address, _)) in frames.into_iter().zip(&descriptors) {
+                handed_over.insert(*address, frame);
+            }
+            send(
+                channel.as_raw_fd(),
+                &encode(&descriptors),
+                MsgFlags::empty(),
+            )
+            .unwrap();
+            batches += 1;
+        }
+
+        // the frames the worker is done with are owned here again
+        loop {
+            let len = match recv(channel.as_raw_fd(), &mut message, MsgFlags::MSG_DONTWAIT) {
+                Ok(0) => panic!("the worker exited"),
+                Ok(len) => len,
+                Err(Errno::EAGAIN) => break,
+                Err(e) => panic!("failed to receive frames: {}", e),
+            };
+            batches -= 1;
+            let frames: Vec<_> = decode(&message[..len])
+                .map(|(address, len)| {
+                    let mut frame: AppFrame<_> = handed_over.remove(&address).unwrap().into();
+                    frame.raw_buffer_resize(len as usize).unwrap();
+                    frame
+                })
+                .collect();
+            forwarded += frames.len();
+            socket.send_bulk(frames).unwrap();
+        }
+    }
+
+    // the worker exits once the socket is closed and has released all chunks by then, so
+    // the UMem outlives every access of the worker
+    drop(channel);
+    worker.wait().unwrap();
+    drop(handed_over);
+    println!("forwarded {} frames", forwarded);
+}
+
+fn worker(cli: &Cli) {
+    let channel = std::io::stdin().as_fd().try_clone_to_owned().unwrap();
+    let (memfd, size) = receive_memfd(channel.as_fd());
+    let area = MMapArea::from_memfd(memfd, size).unwrap();
+
+    let mut message = vec![0u8; cli.batch * DESCRIPTOR_SIZE];
+    loop {
+        let len = match recv(channel.as_raw_fd(), &mut message, MsgFlags::empty()) {
+            // the owner closed the socket
+            Ok(0) => return,
+            Ok(len) => len,
+            Err(Errno::EINTR) => continue,
+            Err(e) => panic!("failed to receive frames: {}", e),
+        };
+
+        for (address, len) in decode(&message[..len]) {
+            assert!(address + len <= size as u64);
+            let frame = unsafe {
+                std::slice::from_raw_parts_mut(
+                    (area.base_address() + address as usize) as *mut u8,
+                    len as usize,
+                )
+            };
+            if frame.len() >= 12 {
+                let (destination, source) = frame.split_at_mut(6);
+                destination.swap_with_slice(&mut source[..6]);
+            }
+        }
+        // the owner closed the socket while the batch was processed
+        if send(channel.as_raw_fd(), &message[..len], MsgFlags::empty()).is_err() {
+            return;
+        }
+    }
+}
+
+fn main() {
+    let cli = Cli::parse();
+    if cli.worker {
+        worker(&cli);
+    } else {
+        owner(&cli);
+    }
+}
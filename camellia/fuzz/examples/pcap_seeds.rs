@@ -0,0 +1,44 @@
+// Writes the frames of a pcap capture as seeds of the packet target, one file each:
+//
+//   cargo run --example pcap_seeds -- capture.pcap seeds/packet
+//   cargo fuzz run packet corpus/packet seeds/packet
+//
+// Only the classic pcap format of Ethernet captures is read, tcpdump -w writes it.
+use std::{env, fs, path::Path};
+
+const LINKTYPE_ETHERNET: u32 = 1;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: {} <capture.pcap> <seed directory>", args[0]);
+        std::process::exit(1);
+    }
+    let capture = fs::read(&args[1]).unwrap();
+    let output = Path::new(&args[2]);
+    fs::create_dir_all(output).unwrap();
+
+    assert!(capture.len() >= 24, "truncated pcap header");
+    // microsecond or nanosecond timestamps, in either byte order
+    let read_u32: fn([u8; 4]) -> u32 = match capture[..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => u32::from_le_bytes,
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => u32::from_be_bytes,
+        _ => panic!("not a pcap file, pcapng is not supported"),
+    };
+    let field = |offset: usize| read_u32(capture[offset..offset + 4].try_into().unwrap());
+    assert_eq!(field(20), LINKTYPE_ETHERNET, "not an Ethernet capture");
+
+    let mut offset = 24;
+    let mut count = 0;
+    while offset + 16 <= capture.len() {
+        let len = field(offset + 8) as usize;
+        let start = offset + 16;
+        let Some(frame) = capture.get(start..start + len) else {
+            break;
+        };
+        fs::write(output.join(format!("frame-{:06}", count)), frame).unwrap();
+        offset = start + len;
+        count += 1;
+    }
+    println!("{} seeds written to {}", count, output.display());
+}
@@ -0,0 +1,66 @@
+#![no_main]
+
+use camellia::umem::{
+    frame::{HeadRoom, RxFrame, TxFrame},
+    plain::{PlainAccessorRef, PlainUMem},
+    AccessorRef,
+};
+use libfuzzer_sys::{
+    arbitrary::{self, Arbitrary},
+    fuzz_target,
+};
+
+const CHUNK_SIZE: u32 = 2048;
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    PushHead(usize),
+    PullHead(usize),
+    Append(usize),
+    Truncate(usize),
+    Metadata(usize),
+}
+
+// A descriptor of the RX ring and the operations on the frame received
+#[derive(Arbitrary, Debug)]
+struct Input {
+    offset: usize,
+    len: usize,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| {
+    let umem = PlainAccessorRef::new(PlainUMem::new(1, CHUNK_SIZE).unwrap());
+    let chunk = TxFrame::from(umem.allocate(1).unwrap().pop().unwrap()).take();
+    let xdp_addr = chunk.xdp_address.wrapping_add(input.offset);
+    let Ok(mut frame) = RxFrame::try_from_chunk(chunk, umem.clone(), xdp_addr, input.len) else {
+        assert_eq!(umem.free_chunks(), 1);
+        return;
+    };
+
+    for op in input.ops {
+        match op {
+            Op::PushHead(size) => {
+                let _ = frame.push_head(size);
+            }
+            Op::PullHead(size) => {
+                let _ = frame.pull_head(size);
+            }
+            Op::Append(size) => {
+                let _ = frame.raw_buffer_append(size);
+            }
+            Op::Truncate(len) => frame.truncate(len),
+            Op::Metadata(len) => {
+                if let Some(metadata) = frame.metadata(len) {
+                    assert_eq!(metadata.len(), len);
+                }
+            }
+        }
+        // the packet never leaves its chunk
+        assert_eq!(frame.raw_buffer().len(), frame.len());
+        assert!(frame.head_room() + frame.len() + frame.tail_room() <= CHUNK_SIZE as usize);
+    }
+
+    drop(frame);
+    assert_eq!(umem.free_chunks(), 1);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use test_utils::veth::MacAddr;
+
+fuzz_target!(|input: &str| {
+    if let Ok(mac) = input.parse::<MacAddr>() {
+        assert_eq!(mac.to_string().parse::<MacAddr>().unwrap(), mac);
+    }
+});
@@ -0,0 +1,34 @@
+#![no_main]
+
+use camellia::net::{
+    geneve::parse_geneve, gre::parse_gre, mpls, parse_headers, parse_ip, parse_packet,
+    vxlan::parse_vxlan, FlowKey,
+};
+use libfuzzer_sys::fuzz_target;
+
+const VXLAN_PORT: u16 = 4789;
+const GENEVE_PORT: u16 = 6081;
+
+fuzz_target!(|frame: &[u8]| {
+    if let Some(info) = parse_packet(frame) {
+        assert!(info.l3_offset < info.l4_offset);
+        assert!(info.l4_offset <= info.l3_end && info.l3_end <= frame.len());
+        assert_eq!(FlowKey::from_frame(frame), Some(info.key));
+        assert_eq!(parse_headers(frame), Some(info));
+    }
+    if let Some(ip) = parse_ip(frame) {
+        assert!(ip.l4_offset <= frame.len());
+    }
+
+    let _ = parse_vxlan(frame, VXLAN_PORT);
+    if let Some(geneve) = parse_geneve(frame, GENEVE_PORT) {
+        geneve.options().for_each(drop);
+    }
+    let _ = parse_gre(frame);
+    mpls::label_stack(frame).for_each(drop);
+    let _ = mpls::ip_ttl(frame);
+
+    let mut frame = frame.to_vec();
+    let _ = mpls::swap(&mut frame, 16);
+    let _ = mpls::decrement_ttl(&mut frame);
+});
@@ -0,0 +1,169 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    socket::{
+        af_xdp::{NeedWakeup, XDPMode},
+        timestamp::RxTimestamp,
+    },
+    umem::shared::SharedCacheConfig,
+};
+
+// Declarative counterparts of the builders, e.g., to describe sockets in TOML or YAML.
+// Absent optional fields keep the defaults of the builders.
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UMemConfig {
+    pub num_chunks: u32,
+    pub chunk_size: Option<u32>,
+    pub frame_headroom: Option<u32>,
+    pub fill_queue_size: Option<u32>,
+    pub completion_queue_size: Option<u32>,
+    pub segment_size: Option<usize>,
+    #[serde(default)]
+    pub stagger_headroom: bool,
+    #[serde(default)]
+    pub track_chunks: bool,
+    #[serde(default)]
+    pub refcount_chunks: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct XskConfig {
+    // one of ifname and ifindex
+    pub ifname: Option<String>,
+    pub ifindex: Option<u32>,
+    // one of queue_index and auto_queue
+    pub queue_index: Option<u32>,
+    #[serde(default)]
+    pub auto_queue: bool,
+    pub rx_queue_size: Option<u32>,
+    pub tx_queue_size: Option<u32>,
+    pub mode: Option<XDPMode>,
+    #[serde(default)]
+    pub zero_copy: bool,
+    #[serde(default)]
+    pub copy_mode: bool,
+    // frames larger than a chunk span several chunks, e.g., of a 9000 MTU
+    #[serde(default)]
+    pub multi_buffer: bool,
+    #[serde(default)]
+    pub cooperate_schedule: bool,
+    // need_wakeup per direction, cooperate_schedule enables both
+    #[serde(default)]
+    pub need_wakeup: NeedWakeup,
+    #[serde(default)]
+    pub busy_polling: bool,
+    #[serde(default)]
+    pub no_default_prog: bool,
+    // detach the default program when the last socket on the interface is dropped
+    #[serde(default)]
+    pub unload_default_prog: bool,
+    pub expected_napi_id: Option<u32>,
+    pub max_tx_inflight_bytes: Option<u64>,
+    // e.g., { clock = "tai", metadata = true }
+    pub rx_timestamp: Option<RxTimestamp>,
+    // timeout in milliseconds, e.g., tx_watchdog = 500
+    #[serde(default, deserialize_with = "millis")]
+    pub tx_watchdog: Option<Duration>,
+    // only used by sockets sharing a UMem
+    #[serde(default)]
+    pub cache: SharedCacheConfig,
+}
+
+fn millis<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_millis))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{error::CamelliaError, socket::timestamp::Clock, umem::base::UMemBuilder};
+
+    #[derive(Deserialize)]
+    struct Deployment {
+        umem: UMemConfig,
+        sockets: Vec<XskConfig>,
+    }
+
+    #[test]
+    fn test_parse_config() {
+        let deployment: Deployment = toml::from_str(
+            r#"
+            [umem]
+            num_chunks = 16384
+            chunk_size = 2048
+
+            [[sockets]]
+            ifname = "eth0"
+            queue_index = 0
+            mode = "generic"
+            cooperate_schedule = true
+
+            [[sockets]]
+            ifname = "eth1"
+            queue_index = 3
+            rx_queue_size = 4096
+            need_wakeup = { rx = true }
+            cache = { quota = 1024 }
+            rx_timestamp = { clock = "tai", metadata = true }
+
+            [[sockets]]
+            ifindex = 7
+            auto_queue = true
+            tx_watchdog = 500
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(deployment.umem.num_chunks, 16384);
+        assert_eq!(deployment.umem.chunk_size, Some(2048));
+        assert_eq!(deployment.umem.fill_queue_size, None);
+        assert_eq!(deployment.sockets[0].ifname.as_deref(), Some("eth0"));
+        assert_eq!(deployment.sockets[0].queue_index, Some(0));
+        assert_eq!(deployment.sockets[0].mode, Some(XDPMode::Generic));
+        assert!(deployment.sockets[0].cooperate_schedule);
+        assert!(!deployment.sockets[1].zero_copy);
+        assert_eq!(deployment.sockets[1].rx_queue_size, Some(4096));
+        assert_eq!(deployment.sockets[1].cache.quota, Some(1024));
+        assert_eq!(
+            deployment.sockets[1].rx_timestamp,
+            Some(RxTimestamp::new(Clock::Tai).prefer_metadata())
+        );
+        assert_eq!(
+            deployment.sockets[1].need_wakeup,
+            NeedWakeup {
+                rx: true,
+                tx: false
+            }
+        );
+
+        assert_eq!(deployment.sockets[0].tx_watchdog, None);
+        assert_eq!(deployment.sockets[2].ifname, None);
+        assert_eq!(deployment.sockets[2].ifindex, Some(7));
+        assert_eq!(deployment.sockets[2].queue_index, None);
+        assert!(deployment.sockets[2].auto_queue);
+        assert_eq!(
+            deployment.sockets[2].tx_watchdog,
+            Some(Duration::from_millis(500))
+        );
+
+        // typos are rejected instead of silently ignored
+        assert!(toml::from_str::<XskConfig>("ifname = \"eth0\"\nqueue = 0").is_err());
+
+        let umem = UMemConfig {
+            fill_queue_size: Some(1000),
+            ..deployment.umem
+        };
+        assert!(matches!(
+            UMemBuilder::from_config(&umem).build(),
+            Err(CamelliaError::InvalidConfig(violations)) if violations.len() == 1
+        ));
+    }
+}
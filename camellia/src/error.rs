@@ -1,11 +1,198 @@
+use std::io;
+
+use nix::errno::Errno;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum CamelliaError {
     #[error("system error, {0}")]
     SystemError(#[from] nix::errno::Errno),
+    // a failed system or libxdp call, with the name of the call as context
+    #[error("{call} failed, {errno}")]
+    SyscallFailed { call: &'static str, errno: Errno },
     #[error("invalid argument: {0}")]
     InvalidArgument(String),
+    // every violated constraint of a configuration
+    #[error("invalid configuration: {}", .0.join("; "))]
+    InvalidConfig(Vec<String>),
     #[error("resource exhausted: {0}")]
     ResourceExhausted(String),
+    // no slot is available in a ring, retry after the kernel consumes it
+    #[error("ring is full")]
+    RingFull,
+    #[error("UMem exhausted, {requested} chunks requested, but only {available} chunks available")]
+    UMemExhausted { requested: usize, available: usize },
+    #[error("zero copy is not supported by {ifname}")]
+    ZeroCopyUnsupported { ifname: String },
+    #[error("interface {name} is not found")]
+    InterfaceNotFound { name: String },
+    #[error("queue {queue} is out of range, the interface has {max} queues")]
+    QueueOutOfRange { queue: u32, max: u32 },
+    // bound by a socket of this process with another UMem
+    #[error("queue {queue} of {ifname} is already bound by another socket")]
+    QueueInUse { ifname: String, queue: u32 },
+    // frames of the MTU don't fit in a chunk, the kernel would drop them on RX
+    #[error(
+        "MTU {mtu} of {ifname} exceeds the largest frame of {max_frame_size} bytes fitting in \
+         a chunk, increase the chunk size, enable multi-buffer or reduce the MTU"
+    )]
+    FrameTooLarge {
+        ifname: String,
+        mtu: u32,
+        max_frame_size: u32,
+    },
+    // ENETDOWN, the socket works again after the interface is up or after rebinding it
+    #[error("interface {ifname} is down")]
+    DeviceDown { ifname: String },
+    // ENXIO, the interface is deleted and the socket unbound, e.g., with its namespace.
+    // Rebinding works once an interface of the same name is back.
+    #[error("interface {ifname} is removed")]
+    DeviceRemoved { ifname: String },
+    // I/O errors without an errno, errors with one become SystemError
+    #[error("I/O error, {0}")]
+    Io(#[source] io::Error),
+}
+
+impl CamelliaError {
+    pub fn syscall(call: &'static str, errno: Errno) -> Self {
+        CamelliaError::SyscallFailed { call, errno }
+    }
+
+    pub fn errno(&self) -> Option<Errno> {
+        match self {
+            CamelliaError::SystemError(errno) => Some(*errno),
+            CamelliaError::SyscallFailed { errno, .. } => Some(*errno),
+            _ => None,
+        }
+    }
+
+    // transient conditions which may disappear by retrying later, e.g., once the kernel
+    // drains the rings or the application frees frames
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            CamelliaError::RingFull | CamelliaError::UMemExhausted { .. } => true,
+            CamelliaError::Io(e) => {
+                matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                )
+            }
+            _ => matches!(
+                self.errno(),
+                Some(Errno::EAGAIN | Errno::EBUSY | Errno::ENOBUFS | Errno::EINTR)
+            ),
+        }
+    }
+}
+
+impl From<io::Error> for CamelliaError {
+    fn from(e: io::Error) -> Self {
+        if let Some(code) = e.raw_os_error() {
+            return CamelliaError::SystemError(Errno::from_raw(code));
+        }
+
+        // unwrap errors converted from CamelliaError
+        if e.get_ref().is_some_and(|inner| inner.is::<CamelliaError>()) {
+            let inner = e.into_inner().unwrap();
+            return *inner.downcast::<CamelliaError>().unwrap();
+        }
+
+        CamelliaError::Io(e)
+    }
+}
+
+// Retryable conditions map to WouldBlock, so that camellia sockets can sit behind generic
+// non-blocking I/O interfaces. The original error is kept as the inner error.
+impl From<CamelliaError> for io::Error {
+    fn from(e: CamelliaError) -> Self {
+        let kind = match e {
+            CamelliaError::SystemError(errno) => return io::Error::from_raw_os_error(errno as i32),
+            CamelliaError::Io(e) => return e,
+            CamelliaError::SyscallFailed { errno, .. } => {
+                io::Error::from_raw_os_error(errno as i32).kind()
+            }
+            CamelliaError::RingFull | CamelliaError::UMemExhausted { .. } => {
+                io::ErrorKind::WouldBlock
+            }
+            CamelliaError::InvalidArgument(_)
+            | CamelliaError::InvalidConfig(_)
+            | CamelliaError::QueueOutOfRange { .. }
+            | CamelliaError::FrameTooLarge { .. } => io::ErrorKind::InvalidInput,
+            CamelliaError::ResourceExhausted(_) => io::ErrorKind::OutOfMemory,
+            CamelliaError::ZeroCopyUnsupported { .. } => io::ErrorKind::Unsupported,
+            CamelliaError::InterfaceNotFound { .. } | CamelliaError::DeviceRemoved { .. } => {
+                io::ErrorKind::NotFound
+            }
+            CamelliaError::QueueInUse { .. } => io::ErrorKind::AddrInUse,
+            // the kind std gives ENETDOWN
+            CamelliaError::DeviceDown { .. } => io::Error::from_raw_os_error(libc::ENETDOWN).kind(),
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_classification() {
+        let error = CamelliaError::syscall("xsk_socket__create", Errno::EBUSY);
+        assert_eq!(error.errno(), Some(Errno::EBUSY));
+        assert!(error.is_retryable());
+        assert!(error.to_string().starts_with("xsk_socket__create failed"));
+
+        let error: CamelliaError = Errno::EPERM.into();
+        assert_eq!(error.errno(), Some(Errno::EPERM));
+        assert!(!error.is_retryable());
+
+        let error = CamelliaError::UMemExhausted {
+            requested: 8,
+            available: 2,
+        };
+        assert!(error.is_retryable());
+        assert_eq!(error.errno(), None);
+        assert!(!CamelliaError::InterfaceNotFound {
+            name: "eth0".to_string()
+        }
+        .is_retryable());
+        assert!(!CamelliaError::DeviceRemoved {
+            ifname: "eth0".to_string()
+        }
+        .is_retryable());
+    }
+
+    #[test]
+    fn test_io_error_interop() {
+        let error: io::Error = CamelliaError::SystemError(Errno::EAGAIN).into();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(error.raw_os_error(), Some(Errno::EAGAIN as i32));
+
+        let error: io::Error = CamelliaError::RingFull.into();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        assert!(matches!(
+            CamelliaError::from(error),
+            CamelliaError::RingFull
+        ));
+
+        let error: io::Error = CamelliaError::syscall("sendto", Errno::ENOBUFS).into();
+        assert_eq!(
+            error.kind(),
+            io::Error::from_raw_os_error(libc::ENOBUFS).kind()
+        );
+        assert!(matches!(
+            CamelliaError::from(error),
+            CamelliaError::SyscallFailed {
+                call: "sendto",
+                errno: Errno::ENOBUFS
+            }
+        ));
+
+        let error = CamelliaError::from(io::Error::from_raw_os_error(libc::EPERM));
+        assert_eq!(error.errno(), Some(Errno::EPERM));
+
+        let error = CamelliaError::from(io::Error::new(io::ErrorKind::WouldBlock, "later"));
+        assert!(error.is_retryable());
+        assert_eq!(io::Error::from(error).kind(), io::ErrorKind::WouldBlock);
+    }
 }
@@ -1,3 +1,14 @@
+pub mod config;
 pub mod error;
+pub mod library;
+pub mod net;
+pub mod pipeline;
+pub mod probe;
+pub mod runtime;
 pub mod socket;
+pub mod stats;
+mod sync;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
 pub mod umem;
+pub mod xdp;
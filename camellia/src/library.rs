@@ -0,0 +1,190 @@
+use std::{
+    cell::RefCell,
+    ffi::CStr,
+    os::raw::{c_char, c_int},
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Once,
+    },
+};
+
+use libxdp_sys::{
+    __va_list_tag, libbpf_major_version, libbpf_minor_version, libbpf_print_level,
+    libbpf_print_level_LIBBPF_DEBUG, libbpf_print_level_LIBBPF_INFO, libbpf_set_print,
+    libxdp_print_level, libxdp_set_print, LIBXDP_VERSION,
+};
+use tracing::{event, Level};
+
+extern "C" {
+    fn vsnprintf(
+        buf: *mut c_char,
+        size: usize,
+        format: *const c_char,
+        ap: *mut __va_list_tag,
+    ) -> c_int;
+}
+
+// The verbosity of libbpf and libxdp, whose levels are the same
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LibraryLogLevel {
+    Off = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+}
+
+// libbpf prints warnings and infos to stderr by default
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LibraryLogLevel::Info as u8);
+static INSTALL: Once = Once::new();
+
+thread_local! {
+    // the interface and queue libbpf and libxdp are called for on this thread
+    static CONTEXT: RefCell<Option<(String, Option<u32>)>> = const { RefCell::new(None) };
+}
+
+// major and minor version of the linked libbpf
+pub fn libbpf_version() -> (u32, u32) {
+    unsafe { (libbpf_major_version(), libbpf_minor_version()) }
+}
+
+// version of the bundled libxdp, e.g., 1.3.1
+pub fn libxdp_version() -> &'static str {
+    LIBXDP_VERSION.split('+').next().unwrap()
+}
+
+pub fn library_log_level() -> LibraryLogLevel {
+    match LOG_LEVEL.load(Ordering::Relaxed) {
+        0 => LibraryLogLevel::Off,
+        1 => LibraryLogLevel::Warn,
+        2 => LibraryLogLevel::Info,
+        _ => LibraryLogLevel::Debug,
+    }
+}
+
+// Routes messages of libbpf and libxdp up to the level into tracing events, with the
+// library as the target, instead of printing them to stderr. Off silences them.
+pub fn set_library_log_level(level: LibraryLogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+    install_print();
+}
+
+fn install_print() {
+    INSTALL.call_once(|| unsafe {
+        libbpf_set_print(Some(libbpf_print));
+        libxdp_set_print(Some(libxdp_print));
+    });
+}
+
+// Tags messages of libbpf and libxdp on this thread with the interface and queue until
+// dropped, e.g., while a socket is created
+pub(crate) struct LibraryContext {
+    previous: Option<(String, Option<u32>)>,
+}
+
+impl LibraryContext {
+    pub(crate) fn enter(ifname: &str, queue: Option<u32>) -> Self {
+        // messages are routed into tracing from the first socket or program on
+        install_print();
+        let previous = CONTEXT.with(|context| context.replace(Some((ifname.to_string(), queue))));
+        Self { previous }
+    }
+}
+
+impl Drop for LibraryContext {
+    fn drop(&mut self) {
+        CONTEXT.with(|context| *context.borrow_mut() = self.previous.take());
+    }
+}
+
+// the level of a message, None if it is filtered out
+fn message_level(level: u32) -> Option<LibraryLogLevel> {
+    let level = if level == libbpf_print_level_LIBBPF_DEBUG {
+        LibraryLogLevel::Debug
+    } else if level == libbpf_print_level_LIBBPF_INFO {
+        LibraryLogLevel::Info
+    } else {
+        LibraryLogLevel::Warn
+    };
+    (level <= library_log_level()).then_some(level)
+}
+
+unsafe fn format(format: *const c_char, ap: *mut __va_list_tag) -> String {
+    let mut buf = [0 as c_char; 1024];
+    vsnprintf(buf.as_mut_ptr(), buf.len(), format, ap);
+    let message = CStr::from_ptr(buf.as_ptr()).to_string_lossy();
+    message.trim_end().to_string()
+}
+
+fn emit(library: &'static str, level: LibraryLogLevel, message: &str) {
+    let context = CONTEXT.with(|context| context.borrow().clone());
+    let ifname = context.as_ref().map(|(ifname, _)| ifname.as_str());
+    let queue = context.as_ref().and_then(|(_, queue)| *queue);
+    match level {
+        LibraryLogLevel::Debug => {
+            event!(target: "camellia::library", Level::DEBUG, library, ifname, queue, "{}", message)
+        }
+        LibraryLogLevel::Info => {
+            event!(target: "camellia::library", Level::INFO, library, ifname, queue, "{}", message)
+        }
+        _ => {
+            event!(target: "camellia::library", Level::WARN, library, ifname, queue, "{}", message)
+        }
+    }
+}
+
+unsafe extern "C" fn libbpf_print(
+    level: libbpf_print_level,
+    fmt: *const c_char,
+    ap: *mut __va_list_tag,
+) -> c_int {
+    if let Some(level) = message_level(level) {
+        emit("libbpf", level, &format(fmt, ap));
+    }
+    0
+}
+
+unsafe extern "C" fn libxdp_print(
+    level: libxdp_print_level,
+    fmt: *const c_char,
+    ap: *mut __va_list_tag,
+) -> c_int {
+    if let Some(level) = message_level(level) {
+        emit("libxdp", level, &format(fmt, ap));
+    }
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_message_level() {
+        assert_eq!(libxdp_version(), "1.3.1");
+
+        LOG_LEVEL.store(LibraryLogLevel::Info as u8, Ordering::Relaxed);
+        assert_eq!(library_log_level(), LibraryLogLevel::Info);
+        assert_eq!(message_level(0), Some(LibraryLogLevel::Warn));
+        assert_eq!(message_level(1), Some(LibraryLogLevel::Info));
+        assert_eq!(message_level(2), None);
+
+        LOG_LEVEL.store(LibraryLogLevel::Off as u8, Ordering::Relaxed);
+        assert_eq!(message_level(0), None);
+        LOG_LEVEL.store(LibraryLogLevel::Info as u8, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_context() {
+        let current = || CONTEXT.with(|context| context.borrow().clone());
+        assert_eq!(current(), None);
+        {
+            let _program = LibraryContext::enter("eth0", None);
+            {
+                let _socket = LibraryContext::enter("eth0", Some(3));
+                assert_eq!(current(), Some(("eth0".to_string(), Some(3))));
+            }
+            assert_eq!(current(), Some(("eth0".to_string(), None)));
+        }
+        assert_eq!(current(), None);
+    }
+}
@@ -0,0 +1,89 @@
+use std::net::IpAddr;
+
+// Internet checksums (RFC 1071). Sums are partial one's complement sums folded into 16
+// bits, so that a checksum can be computed over several slices. Every slice but the
+// last must have an even length.
+
+pub fn sum(bytes: &[u8], initial: u32) -> u32 {
+    let mut sum = initial as u64;
+    let mut words = bytes.chunks_exact(2);
+    for word in &mut words {
+        sum += u16::from_be_bytes([word[0], word[1]]) as u64;
+    }
+    // odd lengths are padded with a zero byte
+    if let [last] = words.remainder() {
+        sum += (*last as u64) << 8;
+    }
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u32
+}
+
+pub fn finish(sum: u32) -> u16 {
+    !(sum as u16)
+}
+
+// The checksum after old bytes covered by it are replaced by new ones (RFC 1624), e.g.,
+// addresses or ports rewritten by NAT. Both must have the same even length.
+pub fn adjust(checksum: u16, old: &[u8], new: &[u8]) -> u16 {
+    let old = !(sum(old, 0) as u16);
+    finish(sum(new, sum(&old.to_be_bytes(), !checksum as u32)))
+}
+
+// the checksum field of the header must be zero
+pub fn ipv4_header(header: &[u8]) -> u16 {
+    finish(sum(header, 0))
+}
+
+// sum of the pseudo header of TCP and UDP checksums
+pub fn pseudo_header(src: IpAddr, dst: IpAddr, protocol: u8, len: usize) -> u32 {
+    let mut pseudo = sum(&[0, protocol], 0);
+    match (src, dst) {
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            pseudo = sum(&src.octets(), pseudo);
+            pseudo = sum(&dst.octets(), pseudo);
+            sum(&(len as u16).to_be_bytes(), pseudo)
+        }
+        (src, dst) => {
+            pseudo = sum(&to_octets(src), pseudo);
+            pseudo = sum(&to_octets(dst), pseudo);
+            sum(&(len as u32).to_be_bytes(), pseudo)
+        }
+    }
+}
+
+fn to_octets(addr: IpAddr) -> [u8; 16] {
+    match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped().octets(),
+        IpAddr::V6(addr) => addr.octets(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0x00, 0x00, 0xc0, 0xa8,
+            0x00, 0x01, 0xc0, 0xa8, 0x00, 0xc7,
+        ];
+        assert_eq!(ipv4_header(&header), 0xb861);
+
+        let mut rewritten = header;
+        rewritten[16..20].copy_from_slice(&[10, 1, 2, 3]);
+        assert_eq!(
+            adjust(0xb861, &header[16..20], &rewritten[16..20]),
+            ipv4_header(&rewritten)
+        );
+
+        // summing slices is the same as summing their concatenation
+        let bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(sum(&bytes[100..], sum(&bytes[..100], 0)), sum(&bytes, 0));
+        assert_eq!(sum(&[0xff, 0xff, 0x00, 0x01], 0), 1);
+        assert_eq!(sum(&[0x01], 0), 0x100);
+    }
+}
@@ -0,0 +1,209 @@
+use std::time::{Duration, Instant};
+
+use crate::{
+    net::{flow::FlowTable, parse_ethernet, parse_ip, read_u16, FlowKey, IPPROTO_TCP, IPPROTO_UDP},
+    xdp::filter::FilterMatch,
+};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FirewallStats {
+    pub outbound: u64,
+    pub inbound: u64,
+    // inbound frames neither of a tracked connection nor allowed by a rule
+    pub rejected: u64,
+}
+
+// Connection tracking of a stateful firewall between an inside and an outside network.
+// Connections are initiated from the inside, inbound frames are accepted if they belong
+// to a tracked connection or match an inbound allow rule. Frames which aren't IP, e.g.,
+// ARP, pass in both directions. Stateless deny rules belong in the PacketFilter of each
+// interface, which drops frames before they reach the sockets.
+//
+// Connections are keyed by the 5-tuple of their outbound frames, protocols without ports,
+// e.g., ICMP, by the addresses and the protocol.
+pub struct Firewall {
+    inbound_rules: Vec<FilterMatch>,
+    connections: FlowTable<()>,
+    stats: FirewallStats,
+}
+
+impl Firewall {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inbound_rules: Vec::new(),
+            connections: FlowTable::new(capacity),
+            stats: FirewallStats::default(),
+        }
+    }
+
+    // connections idle for longer than ttl expire
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.connections = self.connections.ttl(ttl);
+        self
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.len()
+    }
+
+    pub fn stats(&self) -> &FirewallStats {
+        &self.stats
+    }
+
+    pub fn inbound_rules(&self) -> &[FilterMatch] {
+        &self.inbound_rules
+    }
+
+    // Accepts inbound frames of new connections matching the rule. Unset fields match
+    // anything, like the rules of PacketFilter.
+    pub fn allow_inbound(&mut self, rule: FilterMatch) {
+        if !self.inbound_rules.contains(&rule) {
+            self.inbound_rules.push(rule);
+        }
+    }
+
+    pub fn remove_inbound(&mut self, rule: &FilterMatch) -> bool {
+        let len = self.inbound_rules.len();
+        self.inbound_rules.retain(|allowed| allowed != rule);
+        self.inbound_rules.len() != len
+    }
+
+    // Tracks the connection of an outbound frame, which is always accepted
+    pub fn accept_outbound(&mut self, frame: &[u8], now: Instant) -> bool {
+        if let Some(key) = connection_key(frame) {
+            if self.connections.get(&key, now).is_none() {
+                self.connections.insert(key, (), now);
+            }
+            self.connections.drain_retired();
+        }
+        self.stats.outbound += 1;
+        true
+    }
+
+    pub fn accept_inbound(&mut self, frame: &[u8], now: Instant) -> bool {
+        let accepted = match connection_key(frame) {
+            Some(key) => {
+                let tracked = self.connections.get(&key.reversed(), now).is_some();
+                self.connections.drain_retired();
+                tracked || self.inbound_rules.iter().any(|rule| matches(rule, frame))
+            }
+            None => true,
+        };
+
+        if accepted {
+            self.stats.inbound += 1;
+        } else {
+            self.stats.rejected += 1;
+        }
+        accepted
+    }
+
+    // forgets connections idle for longer than the TTL, returns the number of them
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let expired = self.connections.expire(now);
+        self.connections.drain_retired();
+        expired
+    }
+}
+
+// the ports of TCP and UDP, None for other protocols or truncated headers
+fn ports(frame: &[u8], protocol: u8, l4_offset: usize) -> Option<(u16, u16)> {
+    (matches!(protocol, IPPROTO_TCP | IPPROTO_UDP) && l4_offset + 4 <= frame.len())
+        .then(|| (read_u16(frame, l4_offset), read_u16(frame, l4_offset + 2)))
+}
+
+fn connection_key(frame: &[u8]) -> Option<FlowKey> {
+    let ip = parse_ip(frame)?;
+    let (src_port, dst_port) = ports(frame, ip.protocol, ip.l4_offset).unwrap_or_default();
+    Some(FlowKey {
+        src: ip.src,
+        dst: ip.dst,
+        src_port,
+        dst_port,
+        protocol: ip.protocol,
+    })
+}
+
+fn matches(rule: &FilterMatch, frame: &[u8]) -> bool {
+    let Some((ethertype, _)) = parse_ethernet(frame) else {
+        return false;
+    };
+    let ip = parse_ip(frame);
+    let dst_port = ip
+        .and_then(|ip| ports(frame, ip.protocol, ip.l4_offset))
+        .map(|(_, dst_port)| dst_port);
+
+    rule.ethertype.is_none_or(|expected| expected == ethertype)
+        && rule
+            .ip_proto
+            .is_none_or(|expected| ip.is_some_and(|ip| ip.protocol == expected))
+        && rule
+            .dst_port
+            .is_none_or(|expected| dst_port == Some(expected))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::{test::tcp_frame, ETH_HLEN};
+
+    // swaps addresses and ports, like a reply of the remote end
+    fn reply(frame: &[u8]) -> Vec<u8> {
+        let mut reply = frame.to_vec();
+        let l3 = ETH_HLEN;
+        reply[l3 + 12..l3 + 16].copy_from_slice(&frame[l3 + 16..l3 + 20]);
+        reply[l3 + 16..l3 + 20].copy_from_slice(&frame[l3 + 12..l3 + 16]);
+        reply[l3 + 20..l3 + 22].copy_from_slice(&frame[l3 + 22..l3 + 24]);
+        reply[l3 + 22..l3 + 24].copy_from_slice(&frame[l3 + 20..l3 + 22]);
+        reply
+    }
+
+    #[test]
+    fn test_established_only() {
+        let now = Instant::now();
+        let mut firewall = Firewall::new(16).ttl(Duration::from_secs(60));
+
+        // replies to a connection from the inside
+        let outbound = tcp_frame(1234, 1, 1, 0x02, b"");
+        assert!(!firewall.accept_inbound(&reply(&outbound), now));
+        assert!(firewall.accept_outbound(&outbound, now));
+        assert!(firewall.accept_inbound(&reply(&outbound), now));
+        assert_eq!(firewall.connections(), 1);
+
+        // a connection from the outside to port 1234 of the inside host
+        let inbound = reply(&tcp_frame(1235, 1, 1, 0x02, b""));
+        assert!(!firewall.accept_inbound(&inbound, now));
+        assert_eq!(firewall.stats().rejected, 2);
+
+        // non-IP frames pass
+        let mut arp = outbound.clone();
+        arp[12..14].copy_from_slice(&0x0806u16.to_be_bytes());
+        assert!(firewall.accept_inbound(&arp, now));
+
+        // idle connections expire
+        let later = now + Duration::from_secs(120);
+        assert_eq!(firewall.expire(later), 1);
+        assert!(!firewall.accept_inbound(&reply(&outbound), later));
+    }
+
+    #[test]
+    fn test_inbound_rules() {
+        let now = Instant::now();
+        let mut firewall = Firewall::new(16);
+        // from port 1234 to port 80, to the port of the rule
+        let inbound = tcp_frame(1234, 1, 1, 0x02, b"");
+        assert!(!firewall.accept_inbound(&inbound, now));
+
+        let rule = FilterMatch::default().ip_proto(IPPROTO_TCP).dst_port(80);
+        firewall.allow_inbound(rule);
+        firewall.allow_inbound(rule);
+        assert_eq!(firewall.inbound_rules(), &[rule]);
+        assert!(firewall.accept_inbound(&inbound, now));
+        assert!(!firewall.accept_inbound(&reply(&inbound), now));
+
+        assert!(firewall.remove_inbound(&rule));
+        assert!(!firewall.remove_inbound(&rule));
+        firewall.allow_inbound(FilterMatch::default().ip_proto(IPPROTO_UDP));
+        assert!(!firewall.accept_inbound(&inbound, now));
+    }
+}
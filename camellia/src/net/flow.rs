@@ -0,0 +1,297 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use crate::net::FlowKey;
+
+const NIL: usize = usize::MAX;
+
+struct Entry<S> {
+    key: FlowKey,
+    state: S,
+    last_seen: Instant,
+    // neighbours in the recency list, towards the most and the least recently used
+    prev: usize,
+    next: usize,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlowTableStats {
+    pub inserted: u64,
+    // least recently used flows removed to make room for new ones
+    pub evicted: u64,
+    // flows idle for longer than the TTL
+    pub expired: u64,
+}
+
+// A table of per-flow state keyed by the 5-tuple, holding at most capacity flows. The
+// least recently used flow is evicted to make room for a new one, and flows idle for
+// longer than the TTL expire. Evicted and expired flows are kept aside until
+// drain_retired, so that applications release their resources, e.g., NAT ports.
+//
+// Every slot is allocated up front and the time is passed in, e.g., once per batch,
+// instead of reading the clock for every frame.
+pub struct FlowTable<S> {
+    index: HashMap<FlowKey, usize>,
+    slots: Vec<Option<Entry<S>>>,
+    free: Vec<usize>,
+    // most and least recently used flows
+    head: usize,
+    tail: usize,
+    ttl: Option<Duration>,
+    retired: Vec<(FlowKey, S)>,
+    stats: FlowTableStats,
+}
+
+impl<S> FlowTable<S> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            index: HashMap::with_capacity(capacity),
+            slots: (0..capacity).map(|_| None).collect(),
+            free: (0..capacity).rev().collect(),
+            head: NIL,
+            tail: NIL,
+            ttl: None,
+            retired: Vec::new(),
+            stats: FlowTableStats::default(),
+        }
+    }
+
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn stats(&self) -> &FlowTableStats {
+        &self.stats
+    }
+
+    fn entry(&self, slot: usize) -> &Entry<S> {
+        self.slots[slot].as_ref().unwrap()
+    }
+
+    fn entry_mut(&mut self, slot: usize) -> &mut Entry<S> {
+        self.slots[slot].as_mut().unwrap()
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = {
+            let entry = self.entry(slot);
+            (entry.prev, entry.next)
+        };
+        match prev {
+            NIL => self.head = next,
+            prev => self.entry_mut(prev).next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            next => self.entry_mut(next).prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, slot: usize) {
+        let head = self.head;
+        {
+            let entry = self.entry_mut(slot);
+            entry.prev = NIL;
+            entry.next = head;
+        }
+        match head {
+            NIL => self.tail = slot,
+            head => self.entry_mut(head).prev = slot,
+        }
+        self.head = slot;
+    }
+
+    fn take(&mut self, slot: usize) -> (FlowKey, S) {
+        self.unlink(slot);
+        let entry = self.slots[slot].take().unwrap();
+        self.index.remove(&entry.key);
+        self.free.push(slot);
+        (entry.key, entry.state)
+    }
+
+    fn is_expired(&self, slot: usize, now: Instant) -> bool {
+        self.ttl
+            .is_some_and(|ttl| now.saturating_duration_since(self.entry(slot).last_seen) > ttl)
+    }
+
+    // the state of the flow without refreshing it, expired flows are returned as well
+    pub fn peek(&self, key: &FlowKey) -> Option<&S> {
+        self.index.get(key).map(|slot| &self.entry(*slot).state)
+    }
+
+    // the state of the flow, which becomes the most recently used one
+    pub fn get(&mut self, key: &FlowKey, now: Instant) -> Option<&mut S> {
+        let slot = *self.index.get(key)?;
+        if self.is_expired(slot, now) {
+            let retired = self.take(slot);
+            self.retired.push(retired);
+            self.stats.expired += 1;
+            return None;
+        }
+
+        self.unlink(slot);
+        self.push_front(slot);
+        let entry = self.entry_mut(slot);
+        entry.last_seen = now;
+        Some(&mut entry.state)
+    }
+
+    // Inserts or replaces the state of the flow. Returns the state replaced, a flow
+    // evicted to make room goes to the retired flows.
+    pub fn insert(&mut self, key: FlowKey, state: S, now: Instant) -> Option<S> {
+        if let Some(slot) = self.index.get(&key).copied() {
+            self.unlink(slot);
+            self.push_front(slot);
+            let entry = self.entry_mut(slot);
+            entry.last_seen = now;
+            return Some(std::mem::replace(&mut entry.state, state));
+        }
+
+        if self.free.is_empty() {
+            let retired = self.take(self.tail);
+            self.retired.push(retired);
+            self.stats.evicted += 1;
+        }
+        let slot = self.free.pop().unwrap();
+        self.slots[slot] = Some(Entry {
+            key,
+            state,
+            last_seen: now,
+            prev: NIL,
+            next: NIL,
+        });
+        self.push_front(slot);
+        self.index.insert(key, slot);
+        self.stats.inserted += 1;
+        None
+    }
+
+    pub fn get_or_insert_with(
+        &mut self,
+        key: FlowKey,
+        now: Instant,
+        state: impl FnOnce() -> S,
+    ) -> &mut S {
+        if self.get(&key, now).is_none() {
+            self.insert(key, state(), now);
+        }
+        let slot = self.index[&key];
+        &mut self.entry_mut(slot).state
+    }
+
+    pub fn remove(&mut self, key: &FlowKey) -> Option<S> {
+        let slot = *self.index.get(key)?;
+        Some(self.take(slot).1)
+    }
+
+    // retires every flow idle for longer than the TTL, returns the number of them
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let mut expired: usize = 0;
+        while self.tail != NIL && self.is_expired(self.tail, now) {
+            let retired = self.take(self.tail);
+            self.retired.push(retired);
+            expired += 1;
+        }
+        self.stats.expired += expired as u64;
+        expired
+    }
+
+    // flows evicted or expired since the last call
+    pub fn drain_retired(&mut self) -> std::vec::Drain<'_, (FlowKey, S)> {
+        self.retired.drain(..)
+    }
+
+    // flows from the most to the least recently used
+    pub fn iter(&self) -> impl Iterator<Item = (&FlowKey, &S)> {
+        let mut slot = self.head;
+        std::iter::from_fn(move || {
+            if slot == NIL {
+                return None;
+            }
+            let entry = self.entry(slot);
+            slot = entry.next;
+            Some((&entry.key, &entry.state))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::IPPROTO_UDP;
+
+    fn key(port: u16) -> FlowKey {
+        FlowKey {
+            src: "10.0.0.1".parse().unwrap(),
+            dst: "10.0.0.2".parse().unwrap(),
+            src_port: port,
+            dst_port: 53,
+            protocol: IPPROTO_UDP,
+        }
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let now = Instant::now();
+        let mut table = FlowTable::new(2);
+        assert!(table.insert(key(1), 1, now).is_none());
+        assert!(table.insert(key(2), 2, now).is_none());
+        // key 1 becomes the most recently used flow
+        *table.get(&key(1), now).unwrap() += 10;
+        assert!(table.insert(key(3), 3, now).is_none());
+
+        assert_eq!(table.len(), 2);
+        assert!(table.peek(&key(2)).is_none());
+        assert_eq!(table.drain_retired().collect::<Vec<_>>(), vec![(key(2), 2)]);
+        let flows: Vec<_> = table
+            .iter()
+            .map(|(key, state)| (key.src_port, *state))
+            .collect();
+        assert_eq!(flows, vec![(3, 3), (1, 11)]);
+
+        assert_eq!(table.insert(key(3), 30, now), Some(3));
+        assert_eq!(*table.get_or_insert_with(key(4), now, || 4), 4);
+        assert_eq!(*table.get_or_insert_with(key(4), now, || 40), 4);
+        assert_eq!(table.remove(&key(4)), Some(4));
+        assert_eq!(table.stats().evicted, 2);
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn test_ttl() {
+        let now = Instant::now();
+        let mut table = FlowTable::new(8).ttl(Duration::from_secs(10));
+        for port in 0..4 {
+            table.insert(key(port), port, now + Duration::from_secs(port as u64));
+        }
+
+        // idle flows are expired from the least recently used one
+        assert_eq!(table.expire(now + Duration::from_secs(12)), 2);
+        let retired: Vec<_> = table.drain_retired().map(|(key, _)| key.src_port).collect();
+        assert_eq!(retired, vec![0, 1]);
+
+        assert!(table.get(&key(2), now + Duration::from_secs(13)).is_none());
+        assert_eq!(
+            table.get(&key(3), now + Duration::from_secs(13)),
+            Some(&mut 3)
+        );
+        assert_eq!(table.stats().expired, 3);
+        assert_eq!(table.len(), 1);
+    }
+}
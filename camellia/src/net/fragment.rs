@@ -0,0 +1,557 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::CamelliaError,
+    net::{checksum, parse_ethernet, read_u16, read_u32, ETHERTYPE_IPV4, ETHERTYPE_IPV6},
+    umem::{frame::TxFrame, AccessorRef},
+};
+
+const IPV4_HLEN: usize = 20;
+const IPV6_HLEN: usize = 40;
+const FRAGMENT_HLEN: usize = 8;
+const NEXTHDR_HOP: u8 = 0;
+const NEXTHDR_FRAGMENT: u8 = 44;
+const IPV4_DF: u16 = 0x4000;
+const IPV4_MF: u16 = 0x2000;
+const IPV4_OFFSET: u16 = 0x1fff;
+// the largest payload of an IP packet
+const MAX_PAYLOAD: usize = 65535;
+
+// the RFC default of ipfrag_time in the kernel
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_PACKETS: usize = 64;
+
+// identifications of IPv6 fragments, IPv4 fragments keep the ID of their packet
+static NEXT_IPV6_ID: AtomicU32 = AtomicU32::new(1);
+
+// Splits an Ethernet frame of an IPv4 or IPv6 packet larger than the MTU into fragments,
+// packets fitting in the MTU are copied into a single frame. IPv4 packets must not have
+// DF set or options, IPv6 packets must not have extension headers before the fragment
+// header, e.g., hop-by-hop options.
+//
+// Chunks of all fragments are allocated at once, no frame is returned if allocation fails.
+pub fn fragment<M: AccessorRef>(
+    umem: &M,
+    packet: &[u8],
+    mtu: usize,
+) -> Result<Vec<TxFrame<M>>, CamelliaError> {
+    let invalid =
+        |reason: &str| CamelliaError::InvalidArgument(format!("can't fragment, {}", reason));
+
+    let (ethertype, l3) = parse_ethernet(packet).ok_or_else(|| invalid("not an Ethernet frame"))?;
+    match ethertype {
+        ETHERTYPE_IPV4 => {
+            if packet.len() < l3 + IPV4_HLEN || packet[l3] != 0x45 {
+                return Err(invalid("not an IPv4 packet without options"));
+            }
+            let end = l3 + read_u16(packet, l3 + 2) as usize;
+            if end > packet.len() {
+                return Err(invalid("the packet is truncated"));
+            }
+            if end - l3 <= mtu {
+                return Ok(vec![TxFrame::copy_from_slice(umem, &packet[..end])?]);
+            }
+
+            let flags = read_u16(packet, l3 + 6);
+            if flags & IPV4_DF != 0 {
+                return Err(invalid("DF is set"));
+            }
+            if flags & (IPV4_MF | IPV4_OFFSET) != 0 {
+                return Err(invalid("the packet is a fragment"));
+            }
+
+            let header = &packet[..l3 + IPV4_HLEN];
+            split(
+                umem,
+                &packet[l3 + IPV4_HLEN..end],
+                block_size(mtu, IPV4_HLEN)?,
+                header.len(),
+                |buffer, offset, len, last| {
+                    buffer.copy_from_slice(header);
+                    let ip = &mut buffer[l3..];
+                    ip[2..4].copy_from_slice(&((IPV4_HLEN + len) as u16).to_be_bytes());
+                    let flags = (offset / 8) as u16 | if last { 0 } else { IPV4_MF };
+                    ip[6..8].copy_from_slice(&flags.to_be_bytes());
+                    ip[10..12].fill(0);
+                    let ip_checksum = checksum::ipv4_header(ip);
+                    ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+                },
+            )
+        }
+        ETHERTYPE_IPV6 => {
+            if packet.len() < l3 + IPV6_HLEN || packet[l3] >> 4 != 6 {
+                return Err(invalid("not an IPv6 packet"));
+            }
+            let end = l3 + IPV6_HLEN + read_u16(packet, l3 + 4) as usize;
+            if end > packet.len() {
+                return Err(invalid("the packet is truncated"));
+            }
+            if end - l3 <= mtu {
+                return Ok(vec![TxFrame::copy_from_slice(umem, &packet[..end])?]);
+            }
+
+            let next_header = packet[l3 + 6];
+            if next_header == NEXTHDR_HOP || next_header == NEXTHDR_FRAGMENT {
+                return Err(invalid("unsupported extension headers"));
+            }
+
+            let id = NEXT_IPV6_ID.fetch_add(1, Ordering::Relaxed);
+            let header = &packet[..l3 + IPV6_HLEN];
+            split(
+                umem,
+                &packet[l3 + IPV6_HLEN..end],
+                block_size(mtu, IPV6_HLEN + FRAGMENT_HLEN)?,
+                header.len() + FRAGMENT_HLEN,
+                |buffer, offset, len, last| {
+                    buffer[..header.len()].copy_from_slice(header);
+                    let ip = &mut buffer[l3..];
+                    ip[4..6].copy_from_slice(&((FRAGMENT_HLEN + len) as u16).to_be_bytes());
+                    ip[6] = NEXTHDR_FRAGMENT;
+
+                    let fragment_header = &mut ip[IPV6_HLEN..];
+                    fragment_header[0] = next_header;
+                    fragment_header[1] = 0;
+                    let offset = offset as u16 | if last { 0 } else { 1 };
+                    fragment_header[2..4].copy_from_slice(&offset.to_be_bytes());
+                    fragment_header[4..8].copy_from_slice(&id.to_be_bytes());
+                },
+            )
+        }
+        _ => Err(invalid("not an IP packet")),
+    }
+}
+
+// the payload of a fragment, fragment offsets count blocks of 8 bytes
+fn block_size(mtu: usize, header_len: usize) -> Result<usize, CamelliaError> {
+    match mtu.saturating_sub(header_len) / 8 * 8 {
+        0 => Err(CamelliaError::InvalidArgument(format!(
+            "MTU {} is too small to fragment",
+            mtu
+        ))),
+        block => Ok(block),
+    }
+}
+
+// writes the headers of each fragment with its offset, its payload length and whether
+// it is the last one
+fn split<M: AccessorRef>(
+    umem: &M,
+    payload: &[u8],
+    block: usize,
+    header_len: usize,
+    mut write_header: impl FnMut(&mut [u8], usize, usize, bool),
+) -> Result<Vec<TxFrame<M>>, CamelliaError> {
+    let count = payload.len().div_ceil(block);
+    umem.allocate(count)?
+        .into_iter()
+        .zip(payload.chunks(block))
+        .enumerate()
+        .map(|(i, (mut frame, piece))| {
+            let buffer = frame.raw_buffer_append(header_len + piece.len())?;
+            write_header(
+                &mut buffer[..header_len],
+                i * block,
+                piece.len(),
+                i + 1 == count,
+            );
+            buffer[header_len..].copy_from_slice(piece);
+            Ok(frame.into())
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct FragmentKey {
+    src: IpAddr,
+    dst: IpAddr,
+    protocol: u8,
+    id: u32,
+}
+
+struct Fragment<'a> {
+    key: FragmentKey,
+    // Ethernet and IP headers, without the fragment header of IPv6
+    header: &'a [u8],
+    l3_offset: usize,
+    offset: usize,
+    more: bool,
+    data: &'a [u8],
+}
+
+// None if the frame is not a fragment or can't be parsed
+fn parse_fragment(frame: &[u8]) -> Option<Fragment<'_>> {
+    let (ethertype, l3) = parse_ethernet(frame)?;
+    match ethertype {
+        ETHERTYPE_IPV4 => {
+            if frame.len() < l3 + IPV4_HLEN || frame[l3] != 0x45 {
+                return None;
+            }
+            let flags = read_u16(frame, l3 + 6);
+            if flags & (IPV4_MF | IPV4_OFFSET) == 0 {
+                return None;
+            }
+            let end = l3 + read_u16(frame, l3 + 2) as usize;
+            if end > frame.len() || end < l3 + IPV4_HLEN {
+                return None;
+            }
+
+            let src: [u8; 4] = frame[l3 + 12..l3 + 16].try_into().unwrap();
+            let dst: [u8; 4] = frame[l3 + 16..l3 + 20].try_into().unwrap();
+            Some(Fragment {
+                key: FragmentKey {
+                    src: Ipv4Addr::from(src).into(),
+                    dst: Ipv4Addr::from(dst).into(),
+                    protocol: frame[l3 + 9],
+                    id: read_u16(frame, l3 + 4) as u32,
+                },
+                header: &frame[..l3 + IPV4_HLEN],
+                l3_offset: l3,
+                offset: (flags & IPV4_OFFSET) as usize * 8,
+                more: flags & IPV4_MF != 0,
+                data: &frame[l3 + IPV4_HLEN..end],
+            })
+        }
+        ETHERTYPE_IPV6 => {
+            if frame.len() < l3 + IPV6_HLEN + FRAGMENT_HLEN
+                || frame[l3] >> 4 != 6
+                || frame[l3 + 6] != NEXTHDR_FRAGMENT
+            {
+                return None;
+            }
+            let end = l3 + IPV6_HLEN + read_u16(frame, l3 + 4) as usize;
+            if end > frame.len() || end < l3 + IPV6_HLEN + FRAGMENT_HLEN {
+                return None;
+            }
+
+            let fragment_header = l3 + IPV6_HLEN;
+            let offset = read_u16(frame, fragment_header + 2);
+            let src: [u8; 16] = frame[l3 + 8..l3 + 24].try_into().unwrap();
+            let dst: [u8; 16] = frame[l3 + 24..l3 + 40].try_into().unwrap();
+            Some(Fragment {
+                key: FragmentKey {
+                    src: Ipv6Addr::from(src).into(),
+                    dst: Ipv6Addr::from(dst).into(),
+                    protocol: frame[fragment_header],
+                    id: read_u32(frame, fragment_header + 4),
+                },
+                header: &frame[..l3 + IPV6_HLEN],
+                l3_offset: l3,
+                offset: (offset & 0xfff8) as usize,
+                more: offset & 1 != 0,
+                data: &frame[fragment_header + FRAGMENT_HLEN..end],
+            })
+        }
+        _ => None,
+    }
+}
+
+// Fragments are copied out of their frames, so that chunks go back to the fill ring
+// instead of being held until the packet is complete
+struct PendingPacket {
+    created: Instant,
+    // headers of the first fragment and the offset of its IP header
+    header: Option<(Vec<u8>, usize)>,
+    fragments: Vec<(usize, Vec<u8>)>,
+    received: usize,
+    total: Option<usize>,
+}
+
+impl PendingPacket {
+    // whether the fragment conflicts with those received, e.g., overlaps them
+    fn conflicts(&self, fragment: &Fragment) -> bool {
+        let start = fragment.offset;
+        let end = start + fragment.data.len();
+        let overlaps = self
+            .fragments
+            .iter()
+            .any(|(offset, data)| start < offset + data.len() && *offset < end);
+        let beyond_end = match self.total {
+            Some(total) => end > total || !fragment.more,
+            None => {
+                !fragment.more
+                    && self
+                        .fragments
+                        .iter()
+                        .any(|(offset, data)| offset + data.len() > end)
+            }
+        };
+        overlaps || beyond_end
+    }
+
+    fn assemble(mut self, protocol: u8) -> Vec<u8> {
+        let (mut packet, l3) = self.header.take().unwrap();
+        let total = self.total.unwrap();
+        self.fragments.sort_unstable_by_key(|(offset, _)| *offset);
+        for (_, data) in self.fragments.iter() {
+            packet.extend_from_slice(data);
+        }
+
+        let ip = &mut packet[l3..];
+        if ip[0] >> 4 == 4 {
+            ip[2..4].copy_from_slice(&((IPV4_HLEN + total) as u16).to_be_bytes());
+            let flags = read_u16(ip, 6) & IPV4_DF;
+            ip[6..8].copy_from_slice(&flags.to_be_bytes());
+            ip[10..12].fill(0);
+            let ip_checksum = checksum::ipv4_header(&ip[..IPV4_HLEN]);
+            ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+        } else {
+            ip[4..6].copy_from_slice(&(total as u16).to_be_bytes());
+            ip[6] = protocol;
+        }
+        packet
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reassembly {
+    // the frame is not a fragment
+    NotFragment,
+    // the fragment is kept until the rest of the packet arrives
+    Pending,
+    // the Ethernet frame of the reassembled packet, with the headers of the first fragment
+    Complete(Vec<u8>),
+    // the fragment is invalid or conflicts with others, the whole packet is dropped
+    Dropped,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReassemblyStats {
+    pub reassembled: u64,
+    // packets dropped for invalid or conflicting fragments
+    pub invalid: u64,
+    // packets not complete before the timeout
+    pub timeouts: u64,
+    // packets dropped to make room for new ones
+    pub evicted: u64,
+}
+
+// Reassembles fragmented IPv4 and IPv6 packets received, e.g., fragmented UDP. At most
+// max_packets packets are reassembled at the same time, the oldest one is dropped to make
+// room for a new one. Fragments overlapping others drop the whole packet, as RFC 5722
+// requires for IPv6.
+pub struct Reassembler {
+    timeout: Duration,
+    max_packets: usize,
+    pending: HashMap<FragmentKey, PendingPacket>,
+    stats: ReassemblyStats,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self {
+            timeout: DEFAULT_TIMEOUT,
+            max_packets: DEFAULT_MAX_PACKETS,
+            pending: HashMap::new(),
+            stats: ReassemblyStats::default(),
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn max_packets(mut self, max_packets: usize) -> Self {
+        assert!(max_packets > 0);
+        self.max_packets = max_packets;
+        self
+    }
+
+    pub fn stats(&self) -> &ReassemblyStats {
+        &self.stats
+    }
+
+    // packets with fragments missing
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // drops packets not complete within the timeout
+    pub fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        self.pending
+            .retain(|_, packet| now.saturating_duration_since(packet.created) < timeout);
+        self.stats.timeouts += (before - self.pending.len()) as u64;
+    }
+
+    pub fn process(&mut self, frame: &[u8], now: Instant) -> Reassembly {
+        let Some(fragment) = parse_fragment(frame) else {
+            return Reassembly::NotFragment;
+        };
+        self.expire(now);
+
+        let end = fragment.offset + fragment.data.len();
+        if end > MAX_PAYLOAD || (fragment.more && fragment.data.len() % 8 != 0) {
+            self.pending.remove(&fragment.key);
+            self.stats.invalid += 1;
+            return Reassembly::Dropped;
+        }
+
+        if !self.pending.contains_key(&fragment.key) && self.pending.len() >= self.max_packets {
+            let oldest = self
+                .pending
+                .iter()
+                .min_by_key(|(_, packet)| packet.created)
+                .map(|(key, _)| *key)
+                .unwrap();
+            self.pending.remove(&oldest);
+            self.stats.evicted += 1;
+        }
+
+        let packet = self
+            .pending
+            .entry(fragment.key)
+            .or_insert_with(|| PendingPacket {
+                created: now,
+                header: None,
+                fragments: Vec::new(),
+                received: 0,
+                total: None,
+            });
+        if packet.conflicts(&fragment) {
+            self.pending.remove(&fragment.key);
+            self.stats.invalid += 1;
+            return Reassembly::Dropped;
+        }
+
+        packet
+            .fragments
+            .push((fragment.offset, fragment.data.to_vec()));
+        packet.received += fragment.data.len();
+        if !fragment.more {
+            packet.total = Some(end);
+        }
+        if fragment.offset == 0 {
+            packet.header = Some((fragment.header.to_vec(), fragment.l3_offset));
+        }
+
+        if packet.header.is_none() || packet.total != Some(packet.received) {
+            return Reassembly::Pending;
+        }
+
+        let packet = self.pending.remove(&fragment.key).unwrap();
+        self.stats.reassembled += 1;
+        Reassembly::Complete(packet.assemble(fragment.key.protocol))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        net::{ETH_HLEN, IPPROTO_UDP},
+        socket::{mock::MockXskSocket, Socket},
+    };
+
+    fn udp_packet(ipv6: bool, payload_len: usize) -> Vec<u8> {
+        let payload: Vec<u8> = (0..payload_len).map(|i| i as u8).collect();
+        let mut packet = vec![0u8; ETH_HLEN];
+        if ipv6 {
+            packet[12..14].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+            let mut ip = [0u8; IPV6_HLEN];
+            ip[0] = 0x60;
+            ip[4..6].copy_from_slice(&(payload_len as u16).to_be_bytes());
+            ip[6] = IPPROTO_UDP;
+            ip[7] = 64;
+            ip[23] = 1;
+            ip[39] = 2;
+            packet.extend_from_slice(&ip);
+        } else {
+            packet[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+            let mut ip = [0u8; IPV4_HLEN];
+            ip[0] = 0x45;
+            ip[2..4].copy_from_slice(&((IPV4_HLEN + payload_len) as u16).to_be_bytes());
+            ip[4..6].copy_from_slice(&0x1234u16.to_be_bytes());
+            ip[8] = 64;
+            ip[9] = IPPROTO_UDP;
+            ip[12..16].copy_from_slice(&[10, 0, 0, 1]);
+            ip[16..20].copy_from_slice(&[10, 0, 0, 2]);
+            let ip_checksum = checksum::ipv4_header(&ip);
+            ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+            packet.extend_from_slice(&ip);
+        }
+        packet.extend_from_slice(&payload);
+        packet
+    }
+
+    fn fragments(packet: &[u8], mtu: usize) -> Vec<Vec<u8>> {
+        let mut socket = MockXskSocket::new(64, 2048).unwrap();
+        let frames = fragment(socket.umem(), packet, mtu).unwrap();
+        assert!(socket.send_bulk(frames).unwrap().is_empty());
+        socket.take_transmitted()
+    }
+
+    #[test]
+    fn test_fragment_and_reassemble() {
+        for ipv6 in [false, true] {
+            let packet = udp_packet(ipv6, 3000);
+            let mut fragments = fragments(&packet, 1500);
+            assert_eq!(fragments.len(), 3);
+            assert!(fragments.iter().all(|frame| frame.len() <= ETH_HLEN + 1500));
+            let first = parse_fragment(&fragments[0]).unwrap();
+            assert!(first.more);
+            assert_eq!(first.offset, 0);
+            let last = parse_fragment(&fragments[2]).unwrap();
+            assert!(!last.more);
+            assert_eq!(last.offset + last.data.len(), 3000);
+
+            // fragments may arrive in any order
+            fragments.reverse();
+            let now = Instant::now();
+            let mut reassembler = Reassembler::new();
+            assert_eq!(reassembler.process(&fragments[0], now), Reassembly::Pending);
+            assert_eq!(reassembler.process(&fragments[1], now), Reassembly::Pending);
+            assert_eq!(
+                reassembler.process(&fragments[2], now),
+                Reassembly::Complete(packet.clone())
+            );
+            assert_eq!(reassembler.pending(), 0);
+            assert_eq!(reassembler.stats().reassembled, 1);
+            assert_eq!(reassembler.process(&packet, now), Reassembly::NotFragment);
+        }
+
+        // packets fitting in the MTU are left alone
+        let packet = udp_packet(false, 1000);
+        assert_eq!(fragments(&packet, 1500), vec![packet]);
+
+        let mut packet = udp_packet(false, 3000);
+        packet[ETH_HLEN + 6] = 0x40;
+        let socket = MockXskSocket::new(64, 2048).unwrap();
+        assert!(fragment(socket.umem(), &packet, 1500).is_err());
+    }
+
+    #[test]
+    fn test_reassembly_limits() {
+        let now = Instant::now();
+        let mut reassembler = Reassembler::new()
+            .max_packets(1)
+            .timeout(Duration::from_secs(1));
+
+        let ipv4 = fragments(&udp_packet(false, 3000), 1500);
+        let ipv6 = fragments(&udp_packet(true, 3000), 1500);
+        assert_eq!(reassembler.process(&ipv4[0], now), Reassembly::Pending);
+        // a duplicate overlaps the fragment
+        assert_eq!(reassembler.process(&ipv4[0], now), Reassembly::Dropped);
+        assert_eq!(reassembler.stats().invalid, 1);
+
+        assert_eq!(reassembler.process(&ipv4[0], now), Reassembly::Pending);
+        assert_eq!(reassembler.process(&ipv6[0], now), Reassembly::Pending);
+        assert_eq!(reassembler.stats().evicted, 1);
+
+        reassembler.expire(now + Duration::from_secs(2));
+        assert_eq!(reassembler.pending(), 0);
+        assert_eq!(reassembler.stats().timeouts, 1);
+    }
+}
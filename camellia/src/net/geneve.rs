@@ -0,0 +1,327 @@
+use std::net::IpAddr;
+
+use crate::{
+    error::CamelliaError,
+    net::{
+        parse_packet, read_u16, read_u32,
+        tunnel::{entropy_port, Underlay, ETHERTYPE_TEB, UDP_HLEN},
+        vxlan::MAX_VNI,
+        ETH_HLEN, IPPROTO_UDP,
+    },
+    umem::frame::HeadRoom,
+};
+
+pub const GENEVE_PORT: u16 = 6081;
+pub const GENEVE_HLEN: usize = 8;
+
+const GENEVE_FLAG_OAM: u8 = 0x80;
+const GENEVE_FLAG_CRITICAL: u8 = 0x40;
+const OPTION_TYPE_CRITICAL: u8 = 0x80;
+const MAX_OPTIONS_LEN: usize = 63 * 4;
+const MAX_OPTION_DATA_LEN: usize = 31 * 4;
+
+// A TLV option of a GENEVE header, whose data is a multiple of 4 bytes long
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GeneveOption<'a> {
+    pub class: u16,
+    pub option_type: u8,
+    pub data: &'a [u8],
+}
+
+impl GeneveOption<'_> {
+    // critical options must be understood by the receiver, or the frame is dropped
+    pub fn is_critical(&self) -> bool {
+        self.option_type & OPTION_TYPE_CRITICAL != 0
+    }
+}
+
+// The outer headers of a GENEVE tunnel (RFC 8926) carrying Ethernet frames, pushed in
+// front of frames in place. The UDP source port is derived from the inner flow.
+#[derive(Clone, Debug)]
+pub struct GeneveEncap {
+    underlay: Underlay,
+    port: u16,
+    udp_checksum: bool,
+}
+
+impl GeneveEncap {
+    pub fn new(
+        src_mac: [u8; 6],
+        dst_mac: [u8; 6],
+        src: IpAddr,
+        dst: IpAddr,
+    ) -> Result<Self, CamelliaError> {
+        Ok(Self {
+            underlay: Underlay::new(src_mac, dst_mac, src, dst)?,
+            port: GENEVE_PORT,
+            udp_checksum: false,
+        })
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.underlay.ttl = ttl;
+        self
+    }
+
+    // UDP checksums over IPv4 are zero by default, over IPv6 they are always computed
+    pub fn udp_checksum(mut self, udp_checksum: bool) -> Self {
+        self.udp_checksum = udp_checksum;
+        self
+    }
+
+    // the head room needed by push with the options
+    pub fn header_len(&self, options: &[GeneveOption]) -> usize {
+        let options_len: usize = options.iter().map(|option| 4 + option.data.len()).sum();
+        self.underlay.header_len() + UDP_HLEN + GENEVE_HLEN + options_len
+    }
+
+    // Encapsulates the Ethernet frame of the packet with the options, the frame must have
+    // header_len bytes of head room
+    pub fn push<F: HeadRoom>(
+        &self,
+        frame: &mut F,
+        vni: u32,
+        options: &[GeneveOption],
+    ) -> Result<(), CamelliaError> {
+        if vni > MAX_VNI {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "VNI {} doesn't fit in 24 bits",
+                vni
+            )));
+        }
+        if let Some(option) = options
+            .iter()
+            .find(|option| option.data.len() % 4 != 0 || option.data.len() > MAX_OPTION_DATA_LEN)
+        {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "option data of {} bytes isn't a multiple of 4 bytes up to {} bytes",
+                option.data.len(),
+                MAX_OPTION_DATA_LEN
+            )));
+        }
+        let header_len = self.header_len(options);
+        let options_len = header_len - self.header_len(&[]);
+        if options_len > MAX_OPTIONS_LEN {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "options of {} bytes are longer than {} bytes",
+                options_len, MAX_OPTIONS_LEN
+            )));
+        }
+
+        let src_port = entropy_port(frame.as_ref());
+        frame.push_head(header_len)?;
+        let packet = frame.as_mut();
+        self.underlay.write(packet, IPPROTO_UDP);
+
+        let geneve = &mut packet[self.underlay.header_len() + UDP_HLEN..header_len];
+        geneve[0] = (options_len / 4) as u8;
+        geneve[1] = if options.iter().any(|option| option.is_critical()) {
+            GENEVE_FLAG_CRITICAL
+        } else {
+            0
+        };
+        geneve[2..4].copy_from_slice(&ETHERTYPE_TEB.to_be_bytes());
+        geneve[4..8].copy_from_slice(&(vni << 8).to_be_bytes());
+
+        let mut offset = GENEVE_HLEN;
+        for option in options {
+            let tlv = &mut geneve[offset..offset + 4 + option.data.len()];
+            tlv[0..2].copy_from_slice(&option.class.to_be_bytes());
+            tlv[2] = option.option_type;
+            tlv[3] = (option.data.len() / 4) as u8;
+            tlv[4..].copy_from_slice(option.data);
+            offset += tlv.len();
+        }
+
+        self.underlay
+            .write_udp(packet, src_port, self.port, self.udp_checksum);
+        Ok(())
+    }
+}
+
+// A GENEVE header of a frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Geneve<'a> {
+    pub vni: u32,
+    // the payload is a control message
+    pub oam: bool,
+    // some options are critical
+    pub critical: bool,
+    // the ethertype of the payload
+    pub protocol: u16,
+    // offset of the payload in the frame
+    pub payload_offset: usize,
+    options: &'a [u8],
+}
+
+impl<'a> Geneve<'a> {
+    pub fn options(&self) -> impl Iterator<Item = GeneveOption<'a>> {
+        let mut options = self.options;
+        std::iter::from_fn(move || {
+            if options.is_empty() {
+                return None;
+            }
+            let len = 4 + (options[3] & 0x1f) as usize * 4;
+            let (tlv, rest) = options.split_at(len);
+            options = rest;
+            Some(GeneveOption {
+                class: read_u16(tlv, 0),
+                option_type: tlv[2],
+                data: &tlv[4..],
+            })
+        })
+    }
+}
+
+// Parses the GENEVE header of a frame to the port, including the TLVs of its options
+pub fn parse_geneve(frame: &[u8], port: u16) -> Option<Geneve<'_>> {
+    let info = parse_packet(frame)?;
+    let geneve = info.l4_offset + UDP_HLEN;
+    if info.key.protocol != IPPROTO_UDP
+        || info.key.dst_port != port
+        || geneve + GENEVE_HLEN > info.l3_end
+        // version 0
+        || frame[geneve] >> 6 != 0
+    {
+        return None;
+    }
+
+    let options_len = (frame[geneve] & 0x3f) as usize * 4;
+    let payload_offset = geneve + GENEVE_HLEN + options_len;
+    if payload_offset > info.l3_end {
+        return None;
+    }
+
+    // every TLV must fit in the options
+    let options = &frame[geneve + GENEVE_HLEN..payload_offset];
+    let mut offset = 0;
+    while offset < options.len() {
+        if offset + 4 > options.len() {
+            return None;
+        }
+        offset += 4 + (options[offset + 3] & 0x1f) as usize * 4;
+    }
+    if offset != options.len() {
+        return None;
+    }
+
+    Some(Geneve {
+        vni: read_u32(frame, geneve + 4) >> 8,
+        oam: frame[geneve + 1] & GENEVE_FLAG_OAM != 0,
+        critical: frame[geneve + 1] & GENEVE_FLAG_CRITICAL != 0,
+        protocol: read_u16(frame, geneve + 2),
+        payload_offset,
+        options,
+    })
+}
+
+// Decapsulates a GENEVE frame to the port carrying an Ethernet frame in place and returns
+// its VNI, other frames are left untouched. Options are parsed by parse_geneve before, and
+// checksums of the outer headers are not verified.
+pub fn pop<F: HeadRoom>(frame: &mut F, port: u16) -> Option<u32> {
+    let geneve = parse_geneve(frame.as_ref(), port)?;
+    if geneve.protocol != ETHERTYPE_TEB || geneve.payload_offset + ETH_HLEN > frame.as_ref().len() {
+        return None;
+    }
+    let (vni, payload_offset) = (geneve.vni, geneve.payload_offset);
+    frame.pull_head(payload_offset).ok()?;
+    Some(vni)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        net::{checksum, test::tcp_frame},
+        socket::{mock::MockXskSocket, Socket},
+    };
+
+    #[test]
+    fn test_geneve() {
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        let inner = tcp_frame(1234, 1, 1, 0x10, b"hello");
+        assert!(socket.inject(&inner));
+        let mut frame = socket.recv_bulk(1).unwrap().pop().unwrap();
+
+        let encap = GeneveEncap::new(
+            [2, 0, 0, 0, 0, 1],
+            [2, 0, 0, 0, 0, 2],
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+        )
+        .unwrap();
+        let options = [
+            GeneveOption {
+                class: 0x0102,
+                option_type: 0x80,
+                data: &[1, 2, 3, 4],
+            },
+            GeneveOption {
+                class: 0xffff,
+                option_type: 1,
+                data: &[],
+            },
+        ];
+        encap.push(&mut frame, 42, &options).unwrap();
+        assert_eq!(frame.len(), encap.header_len(&options) + inner.len());
+
+        let info = parse_packet(&frame).unwrap();
+        let pseudo = checksum::pseudo_header(
+            info.key.src,
+            info.key.dst,
+            IPPROTO_UDP,
+            frame.len() - info.l4_offset,
+        );
+        assert_eq!(checksum::sum(&frame[info.l4_offset..], pseudo), 0xffff);
+
+        let geneve = parse_geneve(&frame, GENEVE_PORT).unwrap();
+        assert_eq!(geneve.vni, 42);
+        assert!(geneve.critical);
+        assert!(!geneve.oam);
+        assert_eq!(geneve.options().collect::<Vec<_>>(), options);
+        assert!(geneve.options().next().unwrap().is_critical());
+
+        assert_eq!(pop(&mut frame, GENEVE_PORT), Some(42));
+        assert_eq!(frame.raw_buffer(), &inner[..]);
+        assert!(pop(&mut frame, GENEVE_PORT).is_none());
+
+        let odd = GeneveOption {
+            class: 1,
+            option_type: 1,
+            data: &[1, 2, 3],
+        };
+        assert!(encap.push(&mut frame, 42, &[odd]).is_err());
+        assert!(encap.push(&mut frame, 1 << 24, &[]).is_err());
+    }
+
+    #[test]
+    fn test_malformed_options() {
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        assert!(socket.inject(&tcp_frame(1234, 1, 1, 0x10, b"hello")));
+        let mut frame = socket.recv_bulk(1).unwrap().pop().unwrap();
+        let encap = GeneveEncap::new(
+            [2, 0, 0, 0, 0, 1],
+            [2, 0, 0, 0, 0, 2],
+            "192.0.2.1".parse().unwrap(),
+            "192.0.2.2".parse().unwrap(),
+        )
+        .unwrap();
+        let option = GeneveOption {
+            class: 1,
+            option_type: 1,
+            data: &[0; 8],
+        };
+        encap.push(&mut frame, 7, &[option]).unwrap();
+        assert!(parse_geneve(&frame, GENEVE_PORT).is_some());
+
+        // the length of the TLV runs past the options
+        let tlv = 14 + 20 + UDP_HLEN + GENEVE_HLEN;
+        frame.raw_buffer_mut()[tlv + 3] = 3;
+        assert!(parse_geneve(&frame, GENEVE_PORT).is_none());
+    }
+}
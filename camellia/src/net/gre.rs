@@ -0,0 +1,282 @@
+use std::net::IpAddr;
+
+use crate::{
+    error::CamelliaError,
+    net::{
+        checksum, parse_ethernet, parse_ip, read_u16, read_u32,
+        tunnel::{Underlay, ETHERTYPE_TEB},
+        ETHERTYPE_IPV4, ETHERTYPE_IPV6, ETH_HLEN,
+    },
+    umem::frame::HeadRoom,
+};
+
+pub const IPPROTO_GRE: u8 = 47;
+
+const GRE_CHECKSUM: u16 = 0x8000;
+const GRE_ROUTING: u16 = 0x4000;
+const GRE_KEY: u16 = 0x2000;
+const GRE_SEQ: u16 = 0x1000;
+const GRE_VERSION: u16 = 0x0007;
+
+// What a GRE tunnel carries
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GreMode {
+    // Ethernet frames, like gretap devices
+    Ethernet,
+    // IP packets, the Ethernet header of a frame is replaced by the outer headers
+    Ip,
+}
+
+// The outer headers of a GRE tunnel (RFC 2784 and RFC 2890), pushed in front of frames
+// in place
+#[derive(Clone, Debug)]
+pub struct GreEncap {
+    underlay: Underlay,
+    mode: GreMode,
+    key: Option<u32>,
+    checksum: bool,
+}
+
+impl GreEncap {
+    pub fn new(
+        src_mac: [u8; 6],
+        dst_mac: [u8; 6],
+        src: IpAddr,
+        dst: IpAddr,
+        mode: GreMode,
+    ) -> Result<Self, CamelliaError> {
+        Ok(Self {
+            underlay: Underlay::new(src_mac, dst_mac, src, dst)?,
+            mode,
+            key: None,
+            checksum: false,
+        })
+    }
+
+    pub fn key(mut self, key: u32) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    pub fn checksum(mut self, checksum: bool) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.underlay.ttl = ttl;
+        self
+    }
+
+    fn gre_len(&self) -> usize {
+        4 + if self.checksum { 4 } else { 0 } + if self.key.is_some() { 4 } else { 0 }
+    }
+
+    // the length of the outer headers in front of the payload
+    pub fn header_len(&self) -> usize {
+        self.underlay.header_len() + self.gre_len()
+    }
+
+    // Encapsulates the frame, or its IP packet in the IP mode. The frame must have
+    // header_len bytes of head room, less the Ethernet header replaced in the IP mode.
+    pub fn push<F: HeadRoom>(&self, frame: &mut F) -> Result<(), CamelliaError> {
+        let (protocol, replaced) = match self.mode {
+            GreMode::Ethernet => (ETHERTYPE_TEB, 0),
+            GreMode::Ip => match parse_ethernet(frame.as_ref()) {
+                Some((ethertype @ (ETHERTYPE_IPV4 | ETHERTYPE_IPV6), l3_offset)) => {
+                    (ethertype, l3_offset)
+                }
+                _ => {
+                    return Err(CamelliaError::InvalidArgument(
+                        "GRE in the IP mode carries IPv4 or IPv6 packets".to_string(),
+                    ))
+                }
+            },
+        };
+        if self.header_len() > frame.head_room() + replaced {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "GRE headers of {} bytes don't fit in the head room of {} bytes",
+                self.header_len() - replaced,
+                frame.head_room()
+            )));
+        }
+
+        frame.pull_head(replaced)?;
+        frame.push_head(self.header_len())?;
+        let packet = frame.as_mut();
+        self.underlay.write(packet, IPPROTO_GRE);
+
+        let gre = &mut packet[self.underlay.header_len()..];
+        let mut flags = 0;
+        let mut offset = 4;
+        if self.checksum {
+            flags |= GRE_CHECKSUM;
+            gre[offset..offset + 4].fill(0);
+            offset += 4;
+        }
+        if let Some(key) = self.key {
+            flags |= GRE_KEY;
+            gre[offset..offset + 4].copy_from_slice(&key.to_be_bytes());
+        }
+        gre[0..2].copy_from_slice(&flags.to_be_bytes());
+        gre[2..4].copy_from_slice(&protocol.to_be_bytes());
+
+        // the checksum covers the GRE header and the payload
+        if self.checksum {
+            let gre_checksum = checksum::finish(checksum::sum(gre, 0));
+            gre[4..6].copy_from_slice(&gre_checksum.to_be_bytes());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GreHeader {
+    // the ethertype of the payload
+    pub protocol: u16,
+    pub key: Option<u32>,
+    pub seq: Option<u32>,
+}
+
+// the GRE header of a frame and the offset of its payload
+pub fn parse_gre(frame: &[u8]) -> Option<(GreHeader, usize)> {
+    let ip = parse_ip(frame)?;
+    let gre = ip.l4_offset;
+    if ip.protocol != IPPROTO_GRE || ip.l3_end > frame.len() || gre + 4 > ip.l3_end {
+        return None;
+    }
+
+    let flags = read_u16(frame, gre);
+    if flags & (GRE_ROUTING | GRE_VERSION) != 0 {
+        return None;
+    }
+    let optional = [GRE_CHECKSUM, GRE_KEY, GRE_SEQ]
+        .iter()
+        .filter(|flag| flags & **flag != 0)
+        .count();
+    let payload = gre + 4 + optional * 4;
+    if payload > ip.l3_end {
+        return None;
+    }
+
+    // the optional fields follow in the order of their flags
+    let mut offset = gre + 4;
+    let mut field = |flag: u16| {
+        if flags & flag == 0 {
+            return None;
+        }
+        offset += 4;
+        Some(read_u32(frame, offset - 4))
+    };
+    field(GRE_CHECKSUM);
+    let key = field(GRE_KEY);
+    let seq = field(GRE_SEQ);
+
+    let header = GreHeader {
+        protocol: read_u16(frame, gre + 2),
+        key,
+        seq,
+    };
+    Some((header, payload))
+}
+
+// Decapsulates a GRE frame in place, other frames are left untouched. Ethernet payloads
+// become the frame, IP payloads get the outer Ethernet addresses. Checksums of the outer
+// headers are not verified.
+pub fn pop<F: HeadRoom>(frame: &mut F) -> Option<GreHeader> {
+    let (header, payload) = parse_gre(frame.as_ref())?;
+    match header.protocol {
+        ETHERTYPE_TEB if payload + ETH_HLEN <= frame.as_ref().len() => {
+            frame.pull_head(payload).ok()?;
+        }
+        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => {
+            let packet = frame.as_mut();
+            packet.copy_within(0..12, payload - ETH_HLEN);
+            packet[payload - 2..payload].copy_from_slice(&header.protocol.to_be_bytes());
+            frame.pull_head(payload - ETH_HLEN).ok()?;
+        }
+        _ => return None,
+    }
+    Some(header)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        net::test::tcp_frame,
+        socket::{mock::MockXskSocket, Socket},
+        umem::AccessorRef,
+    };
+
+    const SRC_MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const DST_MAC: [u8; 6] = [2, 0, 0, 0, 0, 2];
+
+    fn encap(mode: GreMode) -> GreEncap {
+        GreEncap::new(
+            SRC_MAC,
+            DST_MAC,
+            "192.0.2.1".parse().unwrap(),
+            "192.0.2.2".parse().unwrap(),
+            mode,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_gre_ethernet() {
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        let inner = tcp_frame(1234, 1, 1, 0x10, b"hello");
+        assert!(socket.inject(&inner));
+        let mut frame = socket.recv_bulk(1).unwrap().pop().unwrap();
+
+        let encap = encap(GreMode::Ethernet).key(7).checksum(true);
+        encap.push(&mut frame).unwrap();
+        assert_eq!(frame.len(), encap.header_len() + inner.len());
+        let ip = parse_ip(&frame).unwrap();
+        assert_eq!(checksum::sum(&frame[ETH_HLEN..ip.l4_offset], 0), 0xffff);
+        assert_eq!(checksum::sum(&frame[ip.l4_offset..], 0), 0xffff);
+
+        let header = pop(&mut frame).unwrap();
+        assert_eq!(
+            header,
+            GreHeader {
+                protocol: ETHERTYPE_TEB,
+                key: Some(7),
+                seq: None
+            }
+        );
+        assert_eq!(frame.raw_buffer(), &inner[..]);
+        assert!(pop(&mut frame).is_none());
+    }
+
+    #[test]
+    fn test_gre_ip() {
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        let inner = tcp_frame(1234, 1, 1, 0x10, b"hello");
+        assert!(socket.inject(&inner));
+        let mut frame = socket.recv_bulk(1).unwrap().pop().unwrap();
+
+        let encap = encap(GreMode::Ip);
+        encap.push(&mut frame).unwrap();
+        assert_eq!(frame.len(), encap.header_len() + inner.len() - ETH_HLEN);
+        let (header, payload) = parse_gre(&frame).unwrap();
+        assert_eq!(header.protocol, ETHERTYPE_IPV4);
+        assert_eq!(&frame[payload..], &inner[ETH_HLEN..]);
+
+        // the IP packet gets the outer Ethernet addresses
+        pop(&mut frame).unwrap();
+        assert_eq!(&frame[..6], &DST_MAC);
+        assert_eq!(&frame[6..12], &SRC_MAC);
+        assert_eq!(&frame[12..], &inner[12..]);
+
+        let mut arp = socket.umem().allocate(1).unwrap().pop().unwrap();
+        arp.reserve_head(128).unwrap();
+        arp.extend_from_slice(&[0u8; 60]).unwrap();
+        assert!(encap.push(&mut arp).is_err());
+        let mut no_room = socket.umem().allocate(1).unwrap().pop().unwrap();
+        no_room.extend_from_slice(&inner).unwrap();
+        assert!(encap.push(&mut no_room).is_err());
+        assert_eq!(no_room.raw_buffer(), &inner[..]);
+    }
+}
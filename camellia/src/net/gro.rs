@@ -0,0 +1,398 @@
+use std::ops::Range;
+
+use crate::{
+    error::CamelliaError,
+    net::{parse_packet, read_u16, read_u32, FlowKey, PacketInfo, IPPROTO_TCP},
+    pipeline::Stage,
+    umem::{frame::RxFrame, AccessorRef},
+};
+
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+// the most payload a segment coalesces, the limit of GRO in the kernel
+const DEFAULT_MAX_PAYLOAD: usize = 65535 - 40;
+const DEFAULT_MAX_FLOWS: usize = 8;
+
+// The TCP fields of a frame deciding whether it continues a segment
+struct TcpInfo {
+    packet: PacketInfo,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    payload: Range<usize>,
+    // TTL or hop limit, and TOS or traffic class
+    ttl: u8,
+    tos: u8,
+}
+
+impl TcpInfo {
+    fn parse(frame: &[u8]) -> Option<Self> {
+        let packet = parse_packet(frame)?;
+        if packet.key.protocol != IPPROTO_TCP {
+            return None;
+        }
+
+        let tcp = packet.l4_offset;
+        let header_len = (frame[tcp + 12] >> 4) as usize * 4;
+        if header_len < 20 || tcp + header_len > packet.l3_end {
+            return None;
+        }
+
+        let ip = &frame[packet.l3_offset..];
+        let (ttl, tos) = if packet.is_ipv4() {
+            (ip[8], ip[1])
+        } else {
+            (ip[7], (read_u16(ip, 0) >> 4) as u8)
+        };
+
+        Some(Self {
+            seq: read_u32(frame, tcp + 4),
+            ack: read_u32(frame, tcp + 8),
+            flags: frame[tcp + 13],
+            payload: tcp + header_len..packet.l3_end,
+            ttl,
+            tos,
+            packet,
+        })
+    }
+
+    // only segments carrying data with nothing but ACK and PSH set start or continue
+    // a coalesced segment
+    fn coalescible(&self) -> bool {
+        self.flags & !TCP_PSH == TCP_ACK && !self.payload.is_empty()
+    }
+
+    // TCP options, e.g., timestamps, must be the same in every coalesced frame
+    fn options(&self) -> Range<usize> {
+        self.packet.l4_offset + 20..self.payload.start
+    }
+}
+
+// Consecutive TCP segments of a flow, coalesced from several frames. The headers are
+// those of the first frame, the payload spans every frame in order.
+pub struct Segment<M: AccessorRef> {
+    key: FlowKey,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    ttl: u8,
+    tos: u8,
+    // payload length of the first frame, a frame with more payload starts a new segment
+    mss: usize,
+    payload_len: usize,
+    // TCP options in the first frame
+    options: Range<usize>,
+    frames: Vec<RxFrame<M>>,
+    payloads: Vec<Range<usize>>,
+}
+
+impl<M: AccessorRef> Segment<M> {
+    fn new(frame: RxFrame<M>, tcp: TcpInfo) -> Self {
+        Self {
+            key: tcp.packet.key,
+            seq: tcp.seq,
+            ack: tcp.ack,
+            flags: tcp.flags,
+            ttl: tcp.ttl,
+            tos: tcp.tos,
+            mss: tcp.payload.len(),
+            payload_len: tcp.payload.len(),
+            options: tcp.options(),
+            frames: vec![frame],
+            payloads: vec![tcp.payload],
+        }
+    }
+
+    fn next_seq(&self) -> u32 {
+        self.seq.wrapping_add(self.payload_len as u32)
+    }
+
+    // whether later frames may be appended
+    fn is_open(&self) -> bool {
+        self.flags == TCP_ACK && self.payload_len > 0
+    }
+
+    fn continued_by(&self, frame: &[u8], tcp: &TcpInfo) -> bool {
+        tcp.coalescible()
+            && self.is_open()
+            && tcp.seq == self.next_seq()
+            && tcp.ack == self.ack
+            && tcp.ttl == self.ttl
+            && tcp.tos == self.tos
+            && tcp.payload.len() <= self.mss
+            && frame[tcp.options()] == self.frames[0].raw_buffer()[self.options.clone()]
+    }
+
+    fn push(&mut self, frame: RxFrame<M>, tcp: TcpInfo) {
+        self.flags |= tcp.flags;
+        self.payload_len += tcp.payload.len();
+        self.frames.push(frame);
+        self.payloads.push(tcp.payload);
+    }
+
+    pub fn key(&self) -> &FlowKey {
+        &self.key
+    }
+
+    // sequence number of the first payload byte
+    pub fn seq(&self) -> u32 {
+        self.seq
+    }
+
+    pub fn ack(&self) -> u32 {
+        self.ack
+    }
+
+    // flags of every coalesced frame, e.g., PSH if the last one has it
+    pub fn tcp_flags(&self) -> u8 {
+        self.flags
+    }
+
+    // Ethernet, IP and TCP headers of the first frame
+    pub fn headers(&self) -> &[u8] {
+        &self.frames[0].raw_buffer()[..self.payloads[0].start]
+    }
+
+    pub fn payloads(&self) -> impl Iterator<Item = &[u8]> {
+        self.frames
+            .iter()
+            .zip(self.payloads.iter())
+            .map(|(frame, payload)| &frame.raw_buffer()[payload.clone()])
+    }
+
+    pub fn payload_len(&self) -> usize {
+        self.payload_len
+    }
+
+    // the payload copied into a contiguous buffer
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::with_capacity(self.payload_len);
+        self.payloads()
+            .for_each(|bytes| payload.extend_from_slice(bytes));
+        payload
+    }
+
+    pub fn frames(&self) -> &[RxFrame<M>] {
+        &self.frames
+    }
+
+    pub fn into_frames(self) -> Vec<RxFrame<M>> {
+        self.frames
+    }
+}
+
+// Coalesces consecutive TCP segments of a flow in a batch into Segments handed to the
+// sink, like GRO in the kernel. Every TCP frame goes to the sink, those which can't be
+// coalesced, e.g., SYN or FIN, as segments of a single frame, so that segments of a flow
+// reach the sink in order. Other frames stay in the batch.
+//
+// Segments are only coalesced within a batch, segments still being coalesced are handed
+// to the sink at the end of each batch. At most max_flows flows are coalesced at the
+// same time, the oldest flow is handed over to make room for a new one.
+pub struct Gro<M: AccessorRef, F> {
+    sink: F,
+    max_flows: usize,
+    max_payload: usize,
+    flows: Vec<Segment<M>>,
+    coalesced: u64,
+    segments: u64,
+}
+
+impl<M: AccessorRef, F> Gro<M, F> {
+    pub fn new(sink: F) -> Self {
+        Self {
+            sink,
+            max_flows: DEFAULT_MAX_FLOWS,
+            max_payload: DEFAULT_MAX_PAYLOAD,
+            flows: Vec::new(),
+            coalesced: 0,
+            segments: 0,
+        }
+    }
+
+    pub fn max_flows(mut self, max_flows: usize) -> Self {
+        assert!(max_flows > 0);
+        self.max_flows = max_flows;
+        self
+    }
+
+    pub fn max_payload(mut self, max_payload: usize) -> Self {
+        self.max_payload = max_payload;
+        self
+    }
+
+    // frames appended to a segment of preceding frames
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced
+    }
+
+    // segments handed to the sink
+    pub fn segments(&self) -> u64 {
+        self.segments
+    }
+}
+
+impl<M, F> Gro<M, F>
+where
+    M: AccessorRef,
+    F: FnMut(Segment<M>),
+{
+    fn emit(&mut self, segment: Segment<M>) {
+        self.segments += 1;
+        (self.sink)(segment);
+    }
+
+    fn receive(&mut self, frame: RxFrame<M>, tcp: TcpInfo) {
+        let index = self
+            .flows
+            .iter()
+            .position(|segment| segment.key == tcp.packet.key);
+
+        if let Some(index) = index {
+            let segment = &mut self.flows[index];
+            if segment.continued_by(frame.raw_buffer(), &tcp)
+                && segment.payload_len + tcp.payload.len() <= self.max_payload
+            {
+                // a shorter frame ends the segment, as does PSH
+                let last = tcp.payload.len() < segment.mss || tcp.flags & TCP_PSH != 0;
+                segment.push(frame, tcp);
+                self.coalesced += 1;
+                if last {
+                    let segment = self.flows.remove(index);
+                    self.emit(segment);
+                }
+                return;
+            }
+
+            let segment = self.flows.remove(index);
+            self.emit(segment);
+        }
+
+        let segment = Segment::new(frame, tcp);
+        if !segment.is_open() {
+            self.emit(segment);
+            return;
+        }
+
+        if self.flows.len() == self.max_flows {
+            let oldest = self.flows.remove(0);
+            self.emit(oldest);
+        }
+        self.flows.push(segment);
+    }
+
+    // hands every segment being coalesced to the sink
+    pub fn flush(&mut self) {
+        for segment in std::mem::take(&mut self.flows) {
+            self.emit(segment);
+        }
+    }
+}
+
+impl<M, F> Stage<M> for Gro<M, F>
+where
+    M: AccessorRef,
+    F: FnMut(Segment<M>),
+{
+    fn name(&self) -> &'static str {
+        "gro"
+    }
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<M>>) -> Result<(), CamelliaError> {
+        for frame in std::mem::take(frames) {
+            match TcpInfo::parse(frame.raw_buffer()) {
+                Some(tcp) => self.receive(frame, tcp),
+                None => frames.push(frame),
+            }
+        }
+        self.flush();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{net::test::tcp_frame, socket::mock::MockXskSocket, socket::Socket};
+
+    #[test]
+    fn test_gro() {
+        let mut socket = MockXskSocket::new(64, 2048).unwrap();
+        let frames = [
+            tcp_frame(1000, 100, 1, TCP_ACK, &[1; 100]),
+            tcp_frame(2000, 500, 1, TCP_ACK, &[7; 10]),
+            tcp_frame(1000, 200, 1, TCP_ACK, &[2; 100]),
+            // not TCP
+            vec![0; 60],
+            tcp_frame(1000, 300, 1, TCP_ACK | TCP_PSH, &[3; 100]),
+            // a new segment after PSH
+            tcp_frame(1000, 400, 1, TCP_ACK, &[4; 100]),
+            // out of order
+            tcp_frame(1000, 600, 1, TCP_ACK, &[5; 100]),
+            tcp_frame(2000, 510, 1, TCP_ACK | 0x01, &[]),
+        ];
+        for frame in frames.iter() {
+            assert!(socket.inject(frame));
+        }
+
+        let segments = Rc::new(RefCell::new(Vec::new()));
+        let sink = segments.clone();
+        let mut gro = Gro::new(move |segment: Segment<_>| {
+            sink.borrow_mut().push((
+                segment.key().src_port,
+                segment.seq(),
+                segment.frames().len(),
+                segment.to_payload(),
+            ))
+        });
+
+        let mut batch = socket.recv_bulk(32).unwrap();
+        gro.process(&mut batch).unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(gro.coalesced(), 2);
+        assert_eq!(gro.segments(), 5);
+
+        let segments = segments.borrow();
+        let summary: Vec<_> = segments
+            .iter()
+            .map(|(port, seq, frames, payload)| (*port, *seq, *frames, payload.len()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1000, 100, 3, 300),
+                (1000, 400, 1, 100),
+                // the FIN of port 2000 follows its data
+                (2000, 500, 1, 10),
+                (2000, 510, 1, 0),
+                (1000, 600, 1, 100),
+            ]
+        );
+        assert_eq!(&segments[0].3[95..105], &[1, 1, 1, 1, 1, 2, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_gro_limits() {
+        let mut socket = MockXskSocket::new(64, 2048).unwrap();
+        for i in 0..4u32 {
+            assert!(socket.inject(&tcp_frame(1000, i * 100, 1, TCP_ACK, &[0; 100])));
+        }
+        // evicts the flow of port 1000 with a single flow
+        assert!(socket.inject(&tcp_frame(2000, 0, 1, TCP_ACK, &[0; 100])));
+
+        let lens = Rc::new(RefCell::new(Vec::new()));
+        let sink = lens.clone();
+        let mut gro =
+            Gro::new(move |segment: Segment<_>| sink.borrow_mut().push(segment.payload_len()))
+                .max_flows(1)
+                .max_payload(300);
+
+        let mut batch = socket.recv_bulk(32).unwrap();
+        gro.process(&mut batch).unwrap();
+        assert!(batch.is_empty());
+        assert_eq!(lens.borrow().as_slice(), &[300, 100, 100]);
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{
+    error::CamelliaError,
+    net::{checksum, parse_headers, read_u16, read_u32, PacketInfo, IPPROTO_TCP},
+    umem::{frame::TxFrame, AccessorRef},
+};
+
+const TCP_FIN: u8 = 0x01;
+const TCP_PSH: u8 = 0x08;
+const TCP_CWR: u8 = 0x80;
+
+// The most payload of a segment of the headers fitting in the MTU
+pub fn mss_for_mtu(headers: &[u8], mtu: usize) -> Result<usize, CamelliaError> {
+    let (info, header_len) = parse_template(headers)?;
+    let ip_header_len = header_len - info.l3_offset;
+    if mtu <= ip_header_len {
+        return Err(CamelliaError::InvalidArgument(format!(
+            "MTU {} doesn't fit the IP and L4 headers of {} bytes",
+            mtu, ip_header_len
+        )));
+    }
+    Ok(mtu - ip_header_len)
+}
+
+fn parse_template(headers: &[u8]) -> Result<(PacketInfo, usize), CamelliaError> {
+    let invalid = |reason: &str| {
+        CamelliaError::InvalidArgument(format!("invalid header template, {}", reason))
+    };
+
+    let info = parse_headers(headers)
+        .ok_or_else(|| invalid("not TCP or UDP over IPv4 or IPv6 without options"))?;
+    let header_len = match info.key.protocol {
+        IPPROTO_TCP if headers.len() >= info.l4_offset + 20 => {
+            info.l4_offset + (headers[info.l4_offset + 12] >> 4) as usize * 4
+        }
+        IPPROTO_TCP => return Err(invalid("the TCP header is truncated")),
+        _ => info.l4_offset + 8,
+    };
+    if headers.len() != header_len {
+        return Err(invalid("it must end with the L4 header"));
+    }
+    Ok((info, header_len))
+}
+
+// Segments a payload into frames of at most mss bytes of payload each, like GSO in the
+// kernel. headers is the template of the Ethernet, IP and L4 headers, e.g., of the
+// first segment. Every frame gets its own lengths, IPv4 ID and checksums, TCP segments
+// their sequence numbers, and only the last TCP segment keeps FIN and PSH. UDP payloads
+// are split into datagrams with the same headers.
+//
+// Chunks of all segments are allocated at once, no frame is returned if allocation fails.
+pub fn segment<M: AccessorRef>(
+    umem: &M,
+    headers: &[u8],
+    payload: &[u8],
+    mss: usize,
+) -> Result<Vec<TxFrame<M>>, CamelliaError> {
+    if mss == 0 {
+        return Err(CamelliaError::InvalidArgument(
+            "MSS must be positive".to_string(),
+        ));
+    }
+    let (info, header_len) = parse_template(headers)?;
+    let l3 = info.l3_offset;
+    let l4 = info.l4_offset;
+
+    let count = payload.len().div_ceil(mss).max(1);
+    let frames = umem.allocate(count)?;
+
+    let ip_id = read_u16(headers, l3 + 4);
+    let seq = read_u32(headers, l4 + 4);
+    frames
+        .into_iter()
+        .enumerate()
+        .map(|(i, mut frame)| {
+            let chunk = &payload[(i * mss).min(payload.len())..((i + 1) * mss).min(payload.len())];
+            let buffer = frame.raw_buffer_append(header_len + chunk.len())?;
+            buffer[..header_len].copy_from_slice(headers);
+            buffer[header_len..].copy_from_slice(chunk);
+
+            let l4_len = header_len - l4 + chunk.len();
+            if info.is_ipv4() {
+                let ip = &mut buffer[l3..l4];
+                ip[2..4].copy_from_slice(&((l4 - l3 + l4_len) as u16).to_be_bytes());
+                ip[4..6].copy_from_slice(&ip_id.wrapping_add(i as u16).to_be_bytes());
+                ip[10..12].fill(0);
+                let ip_checksum = checksum::ipv4_header(ip);
+                ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+            } else {
+                buffer[l3 + 4..l3 + 6].copy_from_slice(&(l4_len as u16).to_be_bytes());
+            }
+
+            let checksum_offset = if info.key.protocol == IPPROTO_TCP {
+                let tcp = &mut buffer[l4..];
+                let offset = seq.wrapping_add((i * mss) as u32);
+                tcp[4..8].copy_from_slice(&offset.to_be_bytes());
+                if i + 1 < count {
+                    tcp[13] &= !(TCP_FIN | TCP_PSH);
+                }
+                if i > 0 {
+                    tcp[13] &= !TCP_CWR;
+                }
+                l4 + 16
+            } else {
+                buffer[l4 + 4..l4 + 6].copy_from_slice(&(l4_len as u16).to_be_bytes());
+                l4 + 6
+            };
+
+            buffer[checksum_offset..checksum_offset + 2].fill(0);
+            let pseudo =
+                checksum::pseudo_header(info.key.src, info.key.dst, info.key.protocol, l4_len);
+            let mut l4_checksum = checksum::finish(checksum::sum(&buffer[l4..], pseudo));
+            // zero means no checksum for UDP
+            if l4_checksum == 0 && info.key.protocol != IPPROTO_TCP {
+                l4_checksum = 0xffff;
+            }
+            buffer[checksum_offset..checksum_offset + 2]
+                .copy_from_slice(&l4_checksum.to_be_bytes());
+
+            Ok(frame.into())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        net::{parse_packet, test::tcp_frame, ETH_HLEN},
+        socket::{mock::MockXskSocket, Socket},
+    };
+
+    #[test]
+    fn test_segment() {
+        let mut sender = MockXskSocket::new(64, 2048).unwrap();
+        let mut headers = tcp_frame(1000, 5000, 1, 0x10 | TCP_PSH | TCP_FIN, &[]);
+        headers[ETH_HLEN + 4..ETH_HLEN + 6].copy_from_slice(&7u16.to_be_bytes());
+        assert_eq!(mss_for_mtu(&headers, 1500).unwrap(), 1460);
+        assert!(mss_for_mtu(&headers[..40], 1500).is_err());
+
+        let payload: Vec<u8> = (0..250).map(|i| i as u8).collect();
+        let frames = segment(sender.umem(), &headers, &payload, 100).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(sender.send_bulk(frames).unwrap().is_empty());
+
+        let transmitted = sender.take_transmitted();
+        let mut received = Vec::new();
+        for (i, frame) in transmitted.iter().enumerate() {
+            let info = parse_packet(frame).unwrap();
+            assert_eq!(info.l3_end, frame.len());
+            let ip = &frame[ETH_HLEN..info.l4_offset];
+            assert_eq!(read_u16(ip, 4), 7 + i as u16);
+            assert_eq!(checksum::sum(ip, 0), 0xffff);
+
+            let tcp = &frame[info.l4_offset..];
+            let pseudo = checksum::pseudo_header(info.key.src, info.key.dst, 6, tcp.len());
+            assert_eq!(checksum::sum(tcp, pseudo), 0xffff);
+            assert_eq!(read_u32(tcp, 4), 5000 + 100 * i as u32);
+            assert_eq!(tcp[13] & (TCP_PSH | TCP_FIN) != 0, i == 2);
+            received.extend_from_slice(&tcp[20..]);
+        }
+        assert_eq!(received, payload);
+
+        // the template must end with the TCP header
+        assert!(segment(sender.umem(), &tcp_frame(1000, 0, 0, 0x10, b"x"), b"y", 100).is_err());
+    }
+}
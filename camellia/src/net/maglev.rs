@@ -0,0 +1,181 @@
+use std::net::IpAddr;
+
+use crate::{error::CamelliaError, net::FlowKey};
+
+// a prime larger than 100 times the number of backends keeps the disruption low
+pub const DEFAULT_TABLE_SIZE: usize = 65537;
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+// FNV-1a with a final mix, deterministic across processes and hosts unlike the hashers of
+// std, so that every instance of a load balancer maps a flow to the same backend
+pub fn hash_bytes(bytes: &[u8], seed: u64) -> u64 {
+    let mut hash = FNV_OFFSET ^ seed.wrapping_mul(FNV_PRIME);
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+pub fn flow_hash(key: &FlowKey) -> u64 {
+    let mut bytes = [0u8; 37];
+    let octets = |addr| match addr {
+        IpAddr::V4(addr) => addr.to_ipv6_mapped().octets(),
+        IpAddr::V6(addr) => addr.octets(),
+    };
+    bytes[..16].copy_from_slice(&octets(key.src));
+    bytes[16..32].copy_from_slice(&octets(key.dst));
+    bytes[32..34].copy_from_slice(&key.src_port.to_be_bytes());
+    bytes[34..36].copy_from_slice(&key.dst_port.to_be_bytes());
+    bytes[36] = key.protocol;
+    hash_bytes(&bytes, 0)
+}
+
+fn is_prime(n: usize) -> bool {
+    n >= 2
+        && (2..)
+            .take_while(|i| i * i <= n)
+            .map(|i| n % i)
+            .all(|remainder| remainder != 0)
+}
+
+// Consistent hashing of Maglev (Eisenbud et al., NSDI 2016). Each backend fills the
+// lookup table in turns along its own permutation of the entries, so that every backend
+// gets almost the same number of entries, and adding or removing a backend remaps few
+// entries of the others. Backends are identified by their names, e.g., their addresses.
+pub struct Maglev {
+    table: Vec<u32>,
+    backends: usize,
+}
+
+impl Maglev {
+    // table_size must be a prime not smaller than the number of backends
+    pub fn new<B: AsRef<[u8]>>(backends: &[B], table_size: usize) -> Result<Self, CamelliaError> {
+        if backends.is_empty() {
+            return Err(CamelliaError::InvalidArgument(
+                "at least one backend is required".to_string(),
+            ));
+        }
+        if !is_prime(table_size) || table_size < backends.len() {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "table size {} must be a prime not smaller than the {} backends",
+                table_size,
+                backends.len()
+            )));
+        }
+
+        let size = table_size as u64;
+        let permutations: Vec<(u64, u64)> = backends
+            .iter()
+            .map(|backend| {
+                let offset = hash_bytes(backend.as_ref(), 0) % size;
+                let skip = hash_bytes(backend.as_ref(), 1) % (size - 1).max(1) + 1;
+                (offset, skip)
+            })
+            .collect();
+
+        let mut next = vec![0u64; backends.len()];
+        let mut table = vec![u32::MAX; table_size];
+        let mut filled = 0;
+        'fill: loop {
+            for (backend, (offset, skip)) in permutations.iter().enumerate() {
+                let mut entry = ((offset + next[backend] * skip) % size) as usize;
+                while table[entry] != u32::MAX {
+                    next[backend] += 1;
+                    entry = ((offset + next[backend] * skip) % size) as usize;
+                }
+                table[entry] = backend as u32;
+                next[backend] += 1;
+                filled += 1;
+                if filled == table_size {
+                    break 'fill;
+                }
+            }
+        }
+
+        Ok(Self {
+            table,
+            backends: backends.len(),
+        })
+    }
+
+    pub fn backends(&self) -> usize {
+        self.backends
+    }
+
+    // the backend of each entry, as indices of the backends given to new
+    pub fn table(&self) -> &[u32] {
+        &self.table
+    }
+
+    pub fn lookup(&self, hash: u64) -> usize {
+        self.table[(hash % self.table.len() as u64) as usize] as usize
+    }
+
+    pub fn lookup_flow(&self, key: &FlowKey) -> usize {
+        self.lookup(flow_hash(key))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(count: usize) -> Vec<String> {
+        (0..count).map(|i| format!("10.0.1.{}", i)).collect()
+    }
+
+    #[test]
+    fn test_balanced() {
+        assert!(Maglev::new::<String>(&[], 7).is_err());
+        assert!(Maglev::new(&names(3), 8).is_err());
+        assert!(Maglev::new(&names(11), 7).is_err());
+
+        let maglev = Maglev::new(&names(7), DEFAULT_TABLE_SIZE).unwrap();
+        let mut counts = vec![0usize; maglev.backends()];
+        for backend in maglev.table() {
+            counts[*backend as usize] += 1;
+        }
+        let (min, max) = (counts.iter().min().unwrap(), counts.iter().max().unwrap());
+        assert!(max - min <= 1, "{:?}", counts);
+
+        // the same flow always goes to the same backend
+        let key = FlowKey {
+            src: "192.0.2.1".parse().unwrap(),
+            dst: "10.0.0.1".parse().unwrap(),
+            src_port: 1234,
+            dst_port: 80,
+            protocol: 6,
+        };
+        let again = Maglev::new(&names(7), DEFAULT_TABLE_SIZE).unwrap();
+        assert_eq!(maglev.lookup_flow(&key), again.lookup_flow(&key));
+    }
+
+    #[test]
+    fn test_minimal_disruption() {
+        let before = names(10);
+        let mut after = before.clone();
+        let removed = after.remove(3);
+
+        let old = Maglev::new(&before, DEFAULT_TABLE_SIZE).unwrap();
+        let new = Maglev::new(&after, DEFAULT_TABLE_SIZE).unwrap();
+        let moved = old
+            .table()
+            .iter()
+            .zip(new.table())
+            .filter(|(old, new)| {
+                let old = &before[**old as usize];
+                *old != removed && *old != after[**new as usize]
+            })
+            .count();
+        // entries of the removed backend move, few others do
+        assert!(moved < DEFAULT_TABLE_SIZE / 20, "{} entries moved", moved);
+    }
+}
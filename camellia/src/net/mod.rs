@@ -0,0 +1,315 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+pub mod checksum;
+pub mod firewall;
+pub mod flow;
+pub mod fragment;
+pub mod geneve;
+pub mod gre;
+pub mod gro;
+pub mod gso;
+pub mod maglev;
+pub mod mpls;
+pub mod nat;
+pub(crate) mod tunnel;
+pub mod vxlan;
+
+pub const ETH_HLEN: usize = 14;
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_IPV6: u16 = 0x86dd;
+pub const ETHERTYPE_VLAN: u16 = 0x8100;
+pub const IPPROTO_TCP: u8 = 6;
+pub const IPPROTO_UDP: u8 = 17;
+
+pub(crate) fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+pub(crate) fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+// The 5-tuple of a TCP or UDP packet
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FlowKey {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub protocol: u8,
+}
+
+impl FlowKey {
+    pub fn from_frame(frame: &[u8]) -> Option<Self> {
+        parse_packet(frame).map(|info| info.key)
+    }
+
+    // the same key for both directions of a connection
+    pub fn symmetric(&self) -> Self {
+        (*self).min(self.reversed())
+    }
+
+    // the key of the packets flowing in the other direction
+    pub fn reversed(&self) -> Self {
+        Self {
+            src: self.dst,
+            dst: self.src,
+            src_port: self.dst_port,
+            dst_port: self.src_port,
+            protocol: self.protocol,
+        }
+    }
+}
+
+// Where the headers of a TCP or UDP packet in an Ethernet frame are
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PacketInfo {
+    pub key: FlowKey,
+    pub l3_offset: usize,
+    pub l4_offset: usize,
+    // end of the IP packet, the frame may be longer because of Ethernet padding
+    pub l3_end: usize,
+}
+
+impl PacketInfo {
+    pub fn is_ipv4(&self) -> bool {
+        self.key.src.is_ipv4()
+    }
+}
+
+// the ethertype and the offset of the L3 header, after at most one VLAN tag
+pub fn parse_ethernet(frame: &[u8]) -> Option<(u16, usize)> {
+    if frame.len() < ETH_HLEN {
+        return None;
+    }
+    match read_u16(frame, 12) {
+        ETHERTYPE_VLAN if frame.len() >= ETH_HLEN + 4 => Some((read_u16(frame, 16), ETH_HLEN + 4)),
+        ETHERTYPE_VLAN => None,
+        ethertype => Some((ethertype, ETH_HLEN)),
+    }
+}
+
+// Parses Ethernet frames carrying TCP or UDP over IPv4 or IPv6, with at most one VLAN
+// tag. IPv4 fragments, IPv4 options and IPv6 extension headers are not parsed.
+pub fn parse_packet(frame: &[u8]) -> Option<PacketInfo> {
+    let info = parse_headers(frame)?;
+    let min_l4_len = match info.key.protocol {
+        IPPROTO_TCP => 20,
+        _ => 8,
+    };
+    if info.l3_end > frame.len() || info.l4_offset + min_l4_len > info.l3_end {
+        return None;
+    }
+    Some(info)
+}
+
+// The IP header of an Ethernet frame and the protocol it carries
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpInfo {
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub protocol: u8,
+    pub l3_offset: usize,
+    pub l4_offset: usize,
+    pub l3_end: usize,
+}
+
+// Parses the IPv4 or IPv6 header of an Ethernet frame, with at most one VLAN tag. IPv4
+// fragments, IPv4 options and IPv6 extension headers are not parsed, and l3_end is not
+// checked against the frame.
+pub fn parse_ip(frame: &[u8]) -> Option<IpInfo> {
+    let (ethertype, l3_offset) = parse_ethernet(frame)?;
+    let l3 = &frame[l3_offset..];
+
+    match ethertype {
+        ETHERTYPE_IPV4 => {
+            if l3.len() < 20 || l3[0] != 0x45 {
+                return None;
+            }
+            // more fragments or a fragment offset
+            if read_u16(l3, 6) & 0x3fff != 0 {
+                return None;
+            }
+            let src: [u8; 4] = l3[12..16].try_into().unwrap();
+            let dst: [u8; 4] = l3[16..20].try_into().unwrap();
+            Some(IpInfo {
+                src: IpAddr::from(Ipv4Addr::from(src)),
+                dst: IpAddr::from(Ipv4Addr::from(dst)),
+                protocol: l3[9],
+                l3_offset,
+                l4_offset: l3_offset + 20,
+                l3_end: l3_offset + read_u16(l3, 2) as usize,
+            })
+        }
+        ETHERTYPE_IPV6 => {
+            if l3.len() < 40 || l3[0] >> 4 != 6 {
+                return None;
+            }
+            let src: [u8; 16] = l3[8..24].try_into().unwrap();
+            let dst: [u8; 16] = l3[24..40].try_into().unwrap();
+            Some(IpInfo {
+                src: IpAddr::from(Ipv6Addr::from(src)),
+                dst: IpAddr::from(Ipv6Addr::from(dst)),
+                protocol: l3[6],
+                l3_offset,
+                l4_offset: l3_offset + 40,
+                l3_end: l3_offset + 40 + read_u16(l3, 4) as usize,
+            })
+        }
+        _ => None,
+    }
+}
+
+// Like parse_packet, but the lengths in the headers are not checked against the frame,
+// e.g., for header templates. Only the ports of the L4 header are read.
+pub fn parse_headers(frame: &[u8]) -> Option<PacketInfo> {
+    let ip = parse_ip(frame)?;
+    if !matches!(ip.protocol, IPPROTO_TCP | IPPROTO_UDP) || ip.l4_offset + 4 > frame.len() {
+        return None;
+    }
+
+    Some(PacketInfo {
+        key: FlowKey {
+            src: ip.src,
+            dst: ip.dst,
+            src_port: read_u16(frame, ip.l4_offset),
+            dst_port: read_u16(frame, ip.l4_offset + 2),
+            protocol: ip.protocol,
+        },
+        l3_offset: ip.l3_offset,
+        l4_offset: ip.l4_offset,
+        l3_end: ip.l3_end,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Endpoint {
+    Source,
+    Destination,
+}
+
+// Rewrites the address and port of an endpoint of an IPv4 TCP or UDP packet in place, and
+// adjusts the IP and L4 checksums, e.g., for NAT or load balancing
+pub fn rewrite_ipv4(
+    frame: &mut [u8],
+    info: &PacketInfo,
+    endpoint: Endpoint,
+    addr: Ipv4Addr,
+    port: u16,
+) {
+    let l3 = info.l3_offset;
+    let l4 = info.l4_offset;
+    let (addr_offset, port_offset) = match endpoint {
+        Endpoint::Source => (l3 + 12, l4),
+        Endpoint::Destination => (l3 + 16, l4 + 2),
+    };
+    let checksum_offset = if info.key.protocol == IPPROTO_TCP {
+        l4 + 16
+    } else {
+        l4 + 6
+    };
+
+    let old_addr: [u8; 4] = frame[addr_offset..addr_offset + 4].try_into().unwrap();
+    let old_port: [u8; 2] = frame[port_offset..port_offset + 2].try_into().unwrap();
+    let new_addr = addr.octets();
+    let new_port = port.to_be_bytes();
+    frame[addr_offset..addr_offset + 4].copy_from_slice(&new_addr);
+    frame[port_offset..port_offset + 2].copy_from_slice(&new_port);
+
+    let ip_checksum = checksum::adjust(read_u16(frame, l3 + 10), &old_addr, &new_addr);
+    frame[l3 + 10..l3 + 12].copy_from_slice(&ip_checksum.to_be_bytes());
+
+    // UDP datagrams without a checksum have zero
+    let l4_checksum = read_u16(frame, checksum_offset);
+    if info.key.protocol == IPPROTO_TCP || l4_checksum != 0 {
+        // the pseudo header covers the address
+        let l4_checksum = checksum::adjust(l4_checksum, &old_addr, &new_addr);
+        let mut l4_checksum = checksum::adjust(l4_checksum, &old_port, &new_port);
+        if l4_checksum == 0 && info.key.protocol != IPPROTO_TCP {
+            l4_checksum = 0xffff;
+        }
+        frame[checksum_offset..checksum_offset + 2].copy_from_slice(&l4_checksum.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    // an Ethernet frame of a TCP segment over IPv4, without options
+    pub(crate) fn tcp_frame(
+        src_port: u16,
+        seq: u32,
+        ack: u32,
+        flags: u8,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut frame = vec![0u8; ETH_HLEN + 40];
+        frame[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        let ip = &mut frame[ETH_HLEN..];
+        ip[0] = 0x45;
+        ip[2..4].copy_from_slice(&(40 + payload.len() as u16).to_be_bytes());
+        ip[8] = 64;
+        ip[9] = IPPROTO_TCP;
+        ip[12..16].copy_from_slice(&[10, 0, 0, 1]);
+        ip[16..20].copy_from_slice(&[10, 0, 0, 2]);
+        let tcp = &mut ip[20..];
+        tcp[0..2].copy_from_slice(&src_port.to_be_bytes());
+        tcp[2..4].copy_from_slice(&80u16.to_be_bytes());
+        tcp[4..8].copy_from_slice(&seq.to_be_bytes());
+        tcp[8..12].copy_from_slice(&ack.to_be_bytes());
+        tcp[12] = 5 << 4;
+        tcp[13] = flags;
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_parse_packet() {
+        let mut frame = tcp_frame(1234, 0, 0, 0x10, b"hello");
+        // Ethernet padding
+        frame.resize(64, 0);
+        let info = parse_packet(&frame).unwrap();
+        assert_eq!(
+            info.key,
+            FlowKey {
+                src: "10.0.0.1".parse().unwrap(),
+                dst: "10.0.0.2".parse().unwrap(),
+                src_port: 1234,
+                dst_port: 80,
+                protocol: IPPROTO_TCP,
+            }
+        );
+        assert_eq!(info.key.reversed().src_port, 80);
+        assert_eq!(info.key.symmetric(), info.key.reversed().symmetric());
+        assert_eq!(FlowKey::from_frame(&frame), Some(info.key));
+        assert_eq!((info.l4_offset, info.l3_end), (34, 59));
+        assert!(info.is_ipv4());
+
+        // UDP over IPv6 behind a VLAN tag
+        let mut frame = vec![0u8; 18 + 48];
+        frame[12..14].copy_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+        frame[16..18].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+        frame[18] = 0x60;
+        frame[22..24].copy_from_slice(&8u16.to_be_bytes());
+        frame[24] = IPPROTO_UDP;
+        frame[58..60].copy_from_slice(&53u16.to_be_bytes());
+        let info = parse_packet(&frame).unwrap();
+        assert_eq!((info.l3_offset, info.l4_offset), (18, 58));
+        assert_eq!(info.key.src_port, 53);
+        assert!(!info.is_ipv4());
+
+        // fragments don't carry ports
+        let mut frame = tcp_frame(1234, 0, 0, 0x10, b"hello");
+        frame[ETH_HLEN + 6] = 0x20;
+        assert!(parse_packet(&frame).is_none());
+        assert!(parse_packet(&frame[..40]).is_none());
+
+        // IPv4 headers with options, or shorter than 20 bytes
+        for version_ihl in [0x46, 0x44, 0x40] {
+            let mut frame = tcp_frame(1234, 0, 0, 0x10, b"hello");
+            frame[ETH_HLEN] = version_ihl;
+            assert!(parse_ip(&frame).is_none());
+        }
+    }
+}
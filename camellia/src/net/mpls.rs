@@ -0,0 +1,241 @@
+use crate::{
+    error::CamelliaError,
+    net::{checksum, parse_ethernet, read_u16, read_u32, ETHERTYPE_IPV4, ETHERTYPE_IPV6},
+    umem::frame::HeadRoom,
+};
+
+pub const ETHERTYPE_MPLS: u16 = 0x8847;
+pub const ETHERTYPE_MPLS_MULTICAST: u16 = 0x8848;
+pub const MAX_LABEL: u32 = (1 << 20) - 1;
+
+const ENTRY_LEN: usize = 4;
+
+// A label stack entry (RFC 3032)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MplsLabel {
+    pub label: u32,
+    // traffic class
+    pub tc: u8,
+    // bottom of the stack
+    pub bottom: bool,
+    pub ttl: u8,
+}
+
+impl MplsLabel {
+    pub fn new(label: u32, ttl: u8) -> Self {
+        Self {
+            label,
+            tc: 0,
+            bottom: false,
+            ttl,
+        }
+    }
+
+    pub fn from_entry(entry: u32) -> Self {
+        Self {
+            label: entry >> 12,
+            tc: ((entry >> 9) & 0x7) as u8,
+            bottom: entry & 0x100 != 0,
+            ttl: entry as u8,
+        }
+    }
+
+    pub fn entry(&self) -> u32 {
+        (self.label & MAX_LABEL) << 12
+            | ((self.tc & 0x7) as u32) << 9
+            | (self.bottom as u32) << 8
+            | self.ttl as u32
+    }
+}
+
+// the offset of the label stack in an MPLS frame
+fn stack_offset(frame: &[u8]) -> Option<usize> {
+    match parse_ethernet(frame)? {
+        (ETHERTYPE_MPLS | ETHERTYPE_MPLS_MULTICAST, offset)
+            if offset + ENTRY_LEN <= frame.len() =>
+        {
+            Some(offset)
+        }
+        _ => None,
+    }
+}
+
+// the labels of an MPLS frame from the top to the bottom of the stack
+pub fn label_stack(frame: &[u8]) -> impl Iterator<Item = MplsLabel> + '_ {
+    let mut offset = stack_offset(frame);
+    std::iter::from_fn(move || {
+        let current = offset.filter(|offset| offset + ENTRY_LEN <= frame.len())?;
+        let label = MplsLabel::from_entry(read_u32(frame, current));
+        offset = (!label.bottom).then_some(current + ENTRY_LEN);
+        Some(label)
+    })
+}
+
+pub fn top(frame: &[u8]) -> Option<MplsLabel> {
+    label_stack(frame).next()
+}
+
+// The TTL or hop limit of an IP frame, e.g., copied into the label pushed at the ingress
+pub fn ip_ttl(frame: &[u8]) -> Option<u8> {
+    let (ethertype, l3) = parse_ethernet(frame)?;
+    match ethertype {
+        ETHERTYPE_IPV4 => frame.get(l3 + 8).copied(),
+        ETHERTYPE_IPV6 => frame.get(l3 + 7).copied(),
+        _ => None,
+    }
+}
+
+// Pushes a label on the stack of an MPLS frame, or imposes the stack on an IP frame. The
+// bottom of the stack is set by the frame, which must have 4 bytes of head room.
+pub fn push<F: HeadRoom>(frame: &mut F, label: MplsLabel) -> Result<(), CamelliaError> {
+    if label.label > MAX_LABEL {
+        return Err(CamelliaError::InvalidArgument(format!(
+            "label {} doesn't fit in 20 bits",
+            label.label
+        )));
+    }
+    let (ethertype, l2) = parse_ethernet(frame.as_ref()).ok_or_else(|| {
+        CamelliaError::InvalidArgument("labels are pushed on Ethernet frames".to_string())
+    })?;
+    let bottom = !matches!(ethertype, ETHERTYPE_MPLS | ETHERTYPE_MPLS_MULTICAST);
+
+    frame.push_head(ENTRY_LEN)?;
+    let packet = frame.as_mut();
+    packet.copy_within(ENTRY_LEN..ENTRY_LEN + l2, 0);
+    if bottom {
+        packet[l2 - 2..l2].copy_from_slice(&ETHERTYPE_MPLS.to_be_bytes());
+    }
+    let entry = MplsLabel { bottom, ..label }.entry();
+    packet[l2..l2 + ENTRY_LEN].copy_from_slice(&entry.to_be_bytes());
+    Ok(())
+}
+
+// Replaces the top label of an MPLS frame and returns the one replaced. The TTL is left
+// to decrement_ttl.
+pub fn swap(frame: &mut [u8], label: u32) -> Option<MplsLabel> {
+    let offset = stack_offset(frame)?;
+    let old = MplsLabel::from_entry(read_u32(frame, offset));
+    let entry = MplsLabel { label, ..old }.entry();
+    frame[offset..offset + ENTRY_LEN].copy_from_slice(&entry.to_be_bytes());
+    Some(old)
+}
+
+// Decrements the TTL of the top label and returns it, frames whose TTL reaches zero must
+// not be forwarded
+pub fn decrement_ttl(frame: &mut [u8]) -> Option<u8> {
+    let offset = stack_offset(frame)?;
+    let ttl = &mut frame[offset + ENTRY_LEN - 1];
+    *ttl = ttl.saturating_sub(1);
+    Some(*ttl)
+}
+
+// Pops the top label of an MPLS frame and returns it. Popping the bottom of the stack
+// restores the ethertype of the IP packet, whose TTL is lowered to the TTL of the label
+// if propagate_ttl is set, like the uniform model of RFC 3443.
+pub fn pop<F: HeadRoom>(frame: &mut F, propagate_ttl: bool) -> Option<MplsLabel> {
+    let packet = frame.as_mut();
+    let l2 = stack_offset(packet)?;
+    let label = MplsLabel::from_entry(read_u32(packet, l2));
+    let payload = l2 + ENTRY_LEN;
+
+    let ethertype = if label.bottom {
+        // MPLS doesn't tell the protocol of the payload, IP packets start with their version
+        match packet.get(payload).map(|byte| byte >> 4) {
+            Some(4) if packet.len() >= payload + 20 => ETHERTYPE_IPV4,
+            Some(6) if packet.len() >= payload + 40 => ETHERTYPE_IPV6,
+            _ => return None,
+        }
+    } else {
+        read_u16(packet, l2 - 2)
+    };
+
+    if label.bottom && propagate_ttl {
+        let ip = &mut packet[payload..];
+        if ethertype == ETHERTYPE_IPV4 {
+            if label.ttl < ip[8] {
+                let old = [ip[8], ip[9]];
+                ip[8] = label.ttl;
+                let ip_checksum = checksum::adjust(read_u16(ip, 10), &old, &[ip[8], ip[9]]);
+                ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+            }
+        } else {
+            ip[7] = ip[7].min(label.ttl);
+        }
+    }
+
+    packet.copy_within(0..l2, ENTRY_LEN);
+    packet[payload - 2..payload].copy_from_slice(&ethertype.to_be_bytes());
+    frame.pull_head(ENTRY_LEN).ok()?;
+    Some(label)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        net::{parse_packet, test::tcp_frame, ETHERTYPE_VLAN, ETH_HLEN},
+        socket::{mock::MockXskSocket, Socket},
+    };
+
+    #[test]
+    fn test_push_swap_pop() {
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        let mut original = tcp_frame(1234, 1, 1, 0x10, b"hello");
+        let ip_checksum = checksum::ipv4_header(&original[ETH_HLEN..ETH_HLEN + 20]);
+        original[ETH_HLEN + 10..ETH_HLEN + 12].copy_from_slice(&ip_checksum.to_be_bytes());
+        assert!(socket.inject(&original));
+        let mut frame = socket.recv_bulk(1).unwrap().pop().unwrap();
+
+        let ttl = ip_ttl(&frame).unwrap();
+        push(&mut frame, MplsLabel::new(100, ttl - 1)).unwrap();
+        push(&mut frame, MplsLabel::new(200, 30)).unwrap();
+        assert_eq!(read_u16(&frame, 12), ETHERTYPE_MPLS);
+        assert!(parse_packet(&frame).is_none());
+        let stack: Vec<_> = label_stack(&frame)
+            .map(|label| (label.label, label.bottom, label.ttl))
+            .collect();
+        assert_eq!(stack, vec![(200, false, 30), (100, true, 63)]);
+
+        assert_eq!(swap(frame.as_mut(), 300).unwrap().label, 200);
+        assert_eq!(decrement_ttl(frame.as_mut()), Some(29));
+        assert_eq!(top(&frame).unwrap().label, 300);
+
+        assert_eq!(pop(&mut frame, true).unwrap().label, 300);
+        assert_eq!(top(&frame).unwrap().label, 100);
+        let mut bottom = MplsLabel::new(100, 10);
+        bottom.bottom = true;
+        frame.raw_buffer_mut()[ETH_HLEN + 3] = 10;
+        assert_eq!(pop(&mut frame, true), Some(bottom));
+
+        // the IP packet is back with the TTL of the label
+        assert_eq!(frame.len(), original.len());
+        assert_eq!(ip_ttl(&frame), Some(10));
+        assert_eq!(checksum::sum(&frame[ETH_HLEN..ETH_HLEN + 20], 0), 0xffff);
+        assert_eq!(&frame[..ETH_HLEN + 8], &original[..ETH_HLEN + 8]);
+        assert_eq!(&frame[ETH_HLEN + 12..], &original[ETH_HLEN + 12..]);
+        assert!(pop(&mut frame, true).is_none());
+        assert!(swap(frame.raw_buffer_mut(), 1).is_none());
+    }
+
+    #[test]
+    fn test_vlan_tagged() {
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        let untagged = tcp_frame(1234, 1, 1, 0x10, b"hello");
+        let mut original = untagged[..12].to_vec();
+        original.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+        original.extend_from_slice(&[0, 10]);
+        original.extend_from_slice(&untagged[12..]);
+        assert!(socket.inject(&original));
+        let mut frame = socket.recv_bulk(1).unwrap().pop().unwrap();
+
+        push(&mut frame, MplsLabel::new(16, 64)).unwrap();
+        // the label follows the VLAN tag
+        assert_eq!(&frame[..16], &original[..16]);
+        assert_eq!(read_u16(&frame, 16), ETHERTYPE_MPLS);
+        assert!(top(&frame).unwrap().bottom);
+        assert!(push(&mut frame, MplsLabel::new(MAX_LABEL + 1, 64)).is_err());
+
+        pop(&mut frame, false).unwrap();
+        assert_eq!(frame.raw_buffer(), &original[..]);
+    }
+}
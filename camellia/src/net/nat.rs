@@ -0,0 +1,266 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{IpAddr, Ipv4Addr},
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::CamelliaError,
+    net::{flow::FlowTable, parse_packet, rewrite_ipv4, Endpoint, FlowKey, IPPROTO_TCP},
+};
+
+struct Mapping {
+    public_port: u16,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NatStats {
+    pub outbound: u64,
+    pub inbound: u64,
+    // inbound frames without a mapping
+    pub unmatched: u64,
+    // outbound flows without a mapping for lack of free ports
+    pub port_exhausted: u64,
+    pub expired: u64,
+}
+
+// Source NAT of IPv4 TCP and UDP flows to a public address, e.g., a CGNAT data plane.
+// Each outbound flow is mapped to a port of the public address, inbound frames to a
+// mapped port are translated back. Addresses, ports and checksums are rewritten in place.
+// Mappings are flows of a FlowTable, evicted or expired mappings release their ports.
+pub struct Nat44 {
+    public_addr: Ipv4Addr,
+    // free ports of TCP and UDP, released ports are reused last
+    tcp_ports: VecDeque<u16>,
+    udp_ports: VecDeque<u16>,
+    // mappings keyed by outbound flows before translation
+    outbound: FlowTable<Mapping>,
+    // outbound flows by the key of their inbound frames after translation
+    inbound: HashMap<FlowKey, FlowKey>,
+    stats: NatStats,
+}
+
+impl Nat44 {
+    pub fn new(public_addr: Ipv4Addr, ports: RangeInclusive<u16>, capacity: usize) -> Self {
+        Self {
+            public_addr,
+            tcp_ports: ports.clone().collect(),
+            udp_ports: ports.collect(),
+            outbound: FlowTable::new(capacity),
+            inbound: HashMap::with_capacity(capacity),
+            stats: NatStats::default(),
+        }
+    }
+
+    // mappings idle for longer than ttl expire
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.outbound = self.outbound.ttl(ttl);
+        self
+    }
+
+    pub fn public_addr(&self) -> Ipv4Addr {
+        self.public_addr
+    }
+
+    pub fn mappings(&self) -> usize {
+        self.outbound.len()
+    }
+
+    pub fn stats(&self) -> &NatStats {
+        &self.stats
+    }
+
+    fn ports(&mut self, protocol: u8) -> &mut VecDeque<u16> {
+        if protocol == IPPROTO_TCP {
+            &mut self.tcp_ports
+        } else {
+            &mut self.udp_ports
+        }
+    }
+
+    fn translated_key(&self, key: &FlowKey, public_port: u16) -> FlowKey {
+        FlowKey {
+            src: IpAddr::V4(self.public_addr),
+            src_port: public_port,
+            ..*key
+        }
+    }
+
+    // releases the ports of evicted and expired mappings
+    fn release_retired(&mut self) {
+        let retired: Vec<_> = self.outbound.drain_retired().collect();
+        for (key, mapping) in retired {
+            let translated = self.translated_key(&key, mapping.public_port);
+            self.inbound.remove(&translated.reversed());
+            self.ports(key.protocol).push_back(mapping.public_port);
+        }
+    }
+
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let expired = self.outbound.expire(now);
+        self.stats.expired += expired as u64;
+        self.release_retired();
+        expired
+    }
+
+    // Translates the source of an outbound frame, mapping its flow to a public port if
+    // it isn't mapped yet. Returns false for frames which aren't IPv4 TCP or UDP.
+    pub fn translate_outbound(
+        &mut self,
+        frame: &mut [u8],
+        now: Instant,
+    ) -> Result<bool, CamelliaError> {
+        let Some(info) = parse_packet(frame).filter(|info| info.is_ipv4()) else {
+            return Ok(false);
+        };
+
+        let public_port = match self.outbound.get(&info.key, now) {
+            Some(mapping) => mapping.public_port,
+            None => {
+                self.release_retired();
+                let Some(public_port) = self.ports(info.key.protocol).pop_front() else {
+                    self.stats.port_exhausted += 1;
+                    return Err(CamelliaError::ResourceExhausted(format!(
+                        "no free port of {} for {:?}",
+                        self.public_addr, info.key
+                    )));
+                };
+                self.outbound.insert(info.key, Mapping { public_port }, now);
+                let translated = self.translated_key(&info.key, public_port);
+                self.inbound.insert(translated.reversed(), info.key);
+                self.release_retired();
+                public_port
+            }
+        };
+
+        rewrite_ipv4(
+            frame,
+            &info,
+            Endpoint::Source,
+            self.public_addr,
+            public_port,
+        );
+        self.stats.outbound += 1;
+        Ok(true)
+    }
+
+    // Translates the destination of an inbound frame back to the flow mapped to its port.
+    // Returns false for frames without a mapping, which should be dropped.
+    pub fn translate_inbound(&mut self, frame: &mut [u8], now: Instant) -> bool {
+        let Some(info) = parse_packet(frame).filter(|info| info.is_ipv4()) else {
+            return false;
+        };
+        let Some(original) = self.inbound.get(&info.key).copied() else {
+            self.stats.unmatched += 1;
+            return false;
+        };
+        // refreshes the mapping, unless it is expired
+        if self.outbound.get(&original, now).is_none() {
+            self.stats.expired += 1;
+            self.release_retired();
+            self.stats.unmatched += 1;
+            return false;
+        }
+
+        let IpAddr::V4(addr) = original.src else {
+            unreachable!("mappings are only created for IPv4 flows");
+        };
+        rewrite_ipv4(frame, &info, Endpoint::Destination, addr, original.src_port);
+        self.stats.inbound += 1;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::{checksum, read_u16, test::tcp_frame, ETH_HLEN};
+
+    fn checksummed(mut frame: Vec<u8>) -> Vec<u8> {
+        let info = parse_packet(&frame).unwrap();
+        frame[ETH_HLEN + 10..ETH_HLEN + 12].fill(0);
+        frame[info.l4_offset + 16..info.l4_offset + 18].fill(0);
+        let ip_checksum = checksum::ipv4_header(&frame[ETH_HLEN..info.l4_offset]);
+        frame[ETH_HLEN + 10..ETH_HLEN + 12].copy_from_slice(&ip_checksum.to_be_bytes());
+        let l4_checksum = checksum::finish(checksum::sum(
+            &frame[info.l4_offset..],
+            checksum::pseudo_header(
+                info.key.src,
+                info.key.dst,
+                IPPROTO_TCP,
+                frame.len() - info.l4_offset,
+            ),
+        ));
+        frame[info.l4_offset + 16..info.l4_offset + 18].copy_from_slice(&l4_checksum.to_be_bytes());
+        frame
+    }
+
+    fn assert_checksums(frame: &[u8]) {
+        let info = parse_packet(frame).unwrap();
+        assert_eq!(checksum::sum(&frame[ETH_HLEN..info.l4_offset], 0), 0xffff);
+        let pseudo = checksum::pseudo_header(
+            info.key.src,
+            info.key.dst,
+            info.key.protocol,
+            frame.len() - info.l4_offset,
+        );
+        assert_eq!(checksum::sum(&frame[info.l4_offset..], pseudo), 0xffff);
+    }
+
+    // swaps addresses and ports, like a reply of the remote end
+    fn reply(frame: &[u8]) -> Vec<u8> {
+        let mut reply = frame.to_vec();
+        let l3 = ETH_HLEN;
+        reply[l3 + 12..l3 + 16].copy_from_slice(&frame[l3 + 16..l3 + 20]);
+        reply[l3 + 16..l3 + 20].copy_from_slice(&frame[l3 + 12..l3 + 16]);
+        reply[l3 + 20..l3 + 22].copy_from_slice(&frame[l3 + 22..l3 + 24]);
+        reply[l3 + 22..l3 + 24].copy_from_slice(&frame[l3 + 20..l3 + 22]);
+        checksummed(reply)
+    }
+
+    #[test]
+    fn test_nat44() {
+        let now = Instant::now();
+        let public: Ipv4Addr = "192.0.2.1".parse().unwrap();
+        let mut nat = Nat44::new(public, 4000..=4001, 16).ttl(Duration::from_secs(60));
+
+        let original = checksummed(tcp_frame(1234, 1, 1, 0x10, b"hello"));
+        let mut frame = original.clone();
+        assert!(nat.translate_outbound(&mut frame, now).unwrap());
+        let key = FlowKey::from_frame(&frame).unwrap();
+        assert_eq!(key.src, IpAddr::V4(public));
+        assert_eq!(key.src_port, 4000);
+        assert_checksums(&frame);
+
+        // the same flow keeps its port
+        let mut again = original.clone();
+        assert!(nat.translate_outbound(&mut again, now).unwrap());
+        assert_eq!(again, frame);
+
+        let mut inbound = reply(&frame);
+        assert!(nat.translate_inbound(&mut inbound, now));
+        assert_eq!(inbound, reply(&original));
+        assert_checksums(&inbound);
+
+        // unknown flows are not translated
+        let mut unknown = reply(&original);
+        assert!(!nat.translate_inbound(&mut unknown, now));
+        assert_eq!(nat.stats().unmatched, 1);
+
+        let mut other = checksummed(tcp_frame(1235, 1, 1, 0x10, b"hello"));
+        assert!(nat.translate_outbound(&mut other, now).unwrap());
+        let mut third = checksummed(tcp_frame(1236, 1, 1, 0x10, b"hello"));
+        assert!(nat.translate_outbound(&mut third, now).is_err());
+        assert_eq!(nat.stats().port_exhausted, 1);
+
+        // expired mappings give their ports back
+        let later = now + Duration::from_secs(120);
+        assert_eq!(nat.expire(later), 2);
+        assert_eq!(nat.mappings(), 0);
+        let mut inbound = reply(&frame);
+        assert!(!nat.translate_inbound(&mut inbound, later));
+        assert!(nat.translate_outbound(&mut third, later).unwrap());
+        assert_eq!(read_u16(&third, 34), 4000);
+    }
+}
@@ -0,0 +1,134 @@
+use std::net::IpAddr;
+
+use crate::{
+    error::CamelliaError,
+    net::{
+        checksum,
+        maglev::{flow_hash, hash_bytes},
+        FlowKey, ETHERTYPE_IPV4, ETHERTYPE_IPV6, ETH_HLEN, IPPROTO_UDP,
+    },
+};
+
+pub(crate) const UDP_HLEN: usize = 8;
+// the protocol type of Ethernet frames in GRE and GENEVE
+pub(crate) const ETHERTYPE_TEB: u16 = 0x6558;
+
+// source ports of the outer UDP headers are taken from the dynamic range
+const SRC_PORT_BASE: u16 = 49152;
+
+// The outer Ethernet and IP headers shared by the tunnels
+#[derive(Clone, Debug)]
+pub(crate) struct Underlay {
+    src_mac: [u8; 6],
+    dst_mac: [u8; 6],
+    src: IpAddr,
+    dst: IpAddr,
+    pub(crate) ttl: u8,
+}
+
+impl Underlay {
+    pub(crate) fn new(
+        src_mac: [u8; 6],
+        dst_mac: [u8; 6],
+        src: IpAddr,
+        dst: IpAddr,
+    ) -> Result<Self, CamelliaError> {
+        if src.is_ipv4() != dst.is_ipv4() {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "tunnel endpoints {} and {} are of different address families",
+                src, dst
+            )));
+        }
+        Ok(Self {
+            src_mac,
+            dst_mac,
+            src,
+            dst,
+            ttl: 64,
+        })
+    }
+
+    pub(crate) fn is_ipv6(&self) -> bool {
+        self.src.is_ipv6()
+    }
+
+    // the length of the Ethernet and IP headers
+    pub(crate) fn header_len(&self) -> usize {
+        ETH_HLEN + if self.is_ipv6() { 40 } else { 20 }
+    }
+
+    // writes the headers at the head of the packet, carrying the rest of it
+    pub(crate) fn write(&self, packet: &mut [u8], protocol: u8) {
+        let l3 = ETH_HLEN;
+        let l4 = self.header_len();
+        let payload_len = packet.len() - l4;
+
+        packet[0..6].copy_from_slice(&self.dst_mac);
+        packet[6..12].copy_from_slice(&self.src_mac);
+        match (self.src, self.dst) {
+            (IpAddr::V4(src), IpAddr::V4(dst)) => {
+                packet[12..14].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+                let ip = &mut packet[l3..l4];
+                ip[0] = 0x45;
+                ip[1] = 0;
+                ip[2..4].copy_from_slice(&((l4 - l3 + payload_len) as u16).to_be_bytes());
+                ip[4..8].fill(0);
+                ip[8] = self.ttl;
+                ip[9] = protocol;
+                ip[10..12].fill(0);
+                ip[12..16].copy_from_slice(&src.octets());
+                ip[16..20].copy_from_slice(&dst.octets());
+                let ip_checksum = checksum::ipv4_header(ip);
+                ip[10..12].copy_from_slice(&ip_checksum.to_be_bytes());
+            }
+            (IpAddr::V6(src), IpAddr::V6(dst)) => {
+                packet[12..14].copy_from_slice(&ETHERTYPE_IPV6.to_be_bytes());
+                let ip = &mut packet[l3..l4];
+                ip[0..4].copy_from_slice(&[0x60, 0, 0, 0]);
+                ip[4..6].copy_from_slice(&(payload_len as u16).to_be_bytes());
+                ip[6] = protocol;
+                ip[7] = self.ttl;
+                ip[8..24].copy_from_slice(&src.octets());
+                ip[24..40].copy_from_slice(&dst.octets());
+            }
+            _ => unreachable!("tunnel endpoints are of the same address family"),
+        }
+    }
+
+    // Writes the UDP header behind the IP header, once the rest of the packet is written
+    // for the checksum. UDP checksums over IPv6 are always computed.
+    pub(crate) fn write_udp(
+        &self,
+        packet: &mut [u8],
+        src_port: u16,
+        dst_port: u16,
+        checksum: bool,
+    ) {
+        let udp = &mut packet[self.header_len()..];
+        let udp_len = udp.len();
+        udp[0..2].copy_from_slice(&src_port.to_be_bytes());
+        udp[2..4].copy_from_slice(&dst_port.to_be_bytes());
+        udp[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes());
+        udp[6..8].fill(0);
+
+        if checksum || self.is_ipv6() {
+            let pseudo = checksum::pseudo_header(self.src, self.dst, IPPROTO_UDP, udp_len);
+            let mut udp_checksum = checksum::finish(checksum::sum(udp, pseudo));
+            // zero means no checksum
+            if udp_checksum == 0 {
+                udp_checksum = 0xffff;
+            }
+            udp[6..8].copy_from_slice(&udp_checksum.to_be_bytes());
+        }
+    }
+}
+
+// The UDP source port of a tunneled frame, derived from the inner flow so that ECMP and
+// RSS spread the flows of a tunnel
+pub(crate) fn entropy_port(inner: &[u8]) -> u16 {
+    let hash = match FlowKey::from_frame(inner) {
+        Some(key) => flow_hash(&key),
+        None => hash_bytes(&inner[..inner.len().min(ETH_HLEN)], 0),
+    };
+    SRC_PORT_BASE + (hash % (u16::MAX - SRC_PORT_BASE + 1) as u64) as u16
+}
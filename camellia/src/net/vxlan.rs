@@ -0,0 +1,309 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use crate::{
+    error::CamelliaError,
+    net::{
+        parse_packet, read_u32,
+        tunnel::{entropy_port, Underlay, UDP_HLEN},
+        ETH_HLEN, IPPROTO_UDP,
+    },
+    pipeline::Stage,
+    umem::{
+        frame::{HeadRoom, RxFrame},
+        AccessorRef,
+    },
+};
+
+pub const VXLAN_PORT: u16 = 4789;
+pub const VXLAN_HLEN: usize = 8;
+pub const MAX_VNI: u32 = (1 << 24) - 1;
+
+const VXLAN_FLAG_VNI: u8 = 0x08;
+
+// The outer headers of a VXLAN tunnel (RFC 7348), pushed in front of Ethernet frames in
+// place. The UDP source port is derived from the inner flow, so that ECMP and RSS spread
+// the flows of a tunnel.
+#[derive(Clone, Debug)]
+pub struct VxlanEncap {
+    underlay: Underlay,
+    port: u16,
+    udp_checksum: bool,
+}
+
+impl VxlanEncap {
+    pub fn new(
+        src_mac: [u8; 6],
+        dst_mac: [u8; 6],
+        src: IpAddr,
+        dst: IpAddr,
+    ) -> Result<Self, CamelliaError> {
+        Ok(Self {
+            underlay: Underlay::new(src_mac, dst_mac, src, dst)?,
+            port: VXLAN_PORT,
+            udp_checksum: false,
+        })
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn ttl(mut self, ttl: u8) -> Self {
+        self.underlay.ttl = ttl;
+        self
+    }
+
+    // UDP checksums over IPv4 are zero by default, over IPv6 they are always computed
+    pub fn udp_checksum(mut self, udp_checksum: bool) -> Self {
+        self.udp_checksum = udp_checksum;
+        self
+    }
+
+    // the head room needed by push
+    pub fn header_len(&self) -> usize {
+        self.underlay.header_len() + UDP_HLEN + VXLAN_HLEN
+    }
+
+    // Encapsulates the Ethernet frame of the packet, which must have header_len bytes of
+    // head room, e.g., a received frame or an AppFrame with reserved head room.
+    pub fn push<F: HeadRoom>(&self, frame: &mut F, vni: u32) -> Result<(), CamelliaError> {
+        if vni > MAX_VNI {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "VNI {} doesn't fit in 24 bits",
+                vni
+            )));
+        }
+
+        let src_port = entropy_port(frame.as_ref());
+        frame.push_head(self.header_len())?;
+        let packet = frame.as_mut();
+
+        self.underlay.write(packet, IPPROTO_UDP);
+        let vxlan = &mut packet[self.underlay.header_len() + UDP_HLEN..];
+        vxlan[0..4].copy_from_slice(&[VXLAN_FLAG_VNI, 0, 0, 0]);
+        vxlan[4..8].copy_from_slice(&(vni << 8).to_be_bytes());
+        self.underlay
+            .write_udp(packet, src_port, self.port, self.udp_checksum);
+        Ok(())
+    }
+}
+
+// the VNI of a VXLAN frame to the port and the offset of its inner Ethernet frame
+pub fn parse_vxlan(frame: &[u8], port: u16) -> Option<(u32, usize)> {
+    let info = parse_packet(frame)?;
+    let vxlan = info.l4_offset + UDP_HLEN;
+    if info.key.protocol != IPPROTO_UDP
+        || info.key.dst_port != port
+        || vxlan + VXLAN_HLEN + ETH_HLEN > info.l3_end
+        || frame[vxlan] & VXLAN_FLAG_VNI == 0
+    {
+        return None;
+    }
+    Some((read_u32(frame, vxlan + 4) >> 8, vxlan + VXLAN_HLEN))
+}
+
+// Decapsulates a VXLAN frame to the port in place and returns its VNI, other frames are
+// left untouched. Checksums of the outer headers are not verified.
+pub fn pop<F: HeadRoom>(frame: &mut F, port: u16) -> Option<u32> {
+    let (vni, inner) = parse_vxlan(frame.as_ref(), port)?;
+    frame.pull_head(inner).ok()?;
+    Some(vni)
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VxlanDemuxStats {
+    pub decapsulated: u64,
+    // frames of VNIs without a sink, which are dropped
+    pub unknown_vni: u64,
+}
+
+type Sink<M> = Box<dyn FnMut(RxFrame<M>)>;
+
+// A stage terminating VXLAN tunnels. Frames to the VXLAN port are decapsulated and handed
+// to the sink of their VNI, other frames stay in the batch.
+pub struct VxlanDemux<M: AccessorRef> {
+    port: u16,
+    sinks: HashMap<u32, Sink<M>>,
+    stats: VxlanDemuxStats,
+}
+
+impl<M: AccessorRef> VxlanDemux<M> {
+    pub fn new(port: u16) -> Self {
+        Self {
+            port,
+            sinks: HashMap::new(),
+            stats: VxlanDemuxStats::default(),
+        }
+    }
+
+    pub fn route(mut self, vni: u32, sink: impl FnMut(RxFrame<M>) + 'static) -> Self {
+        self.sinks.insert(vni, Box::new(sink));
+        self
+    }
+
+    pub fn stats(&self) -> &VxlanDemuxStats {
+        &self.stats
+    }
+}
+
+impl<M: AccessorRef> Stage<M> for VxlanDemux<M> {
+    fn name(&self) -> &'static str {
+        "vxlan"
+    }
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<M>>) -> Result<(), CamelliaError> {
+        let mut kept = Vec::with_capacity(frames.len());
+        for mut frame in frames.drain(..) {
+            let Some(vni) = pop(&mut frame, self.port) else {
+                kept.push(frame);
+                continue;
+            };
+            match self.sinks.get_mut(&vni) {
+                Some(sink) => {
+                    self.stats.decapsulated += 1;
+                    sink(frame);
+                }
+                None => self.stats.unknown_vni += 1,
+            }
+        }
+        *frames = kept;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{
+        net::{checksum, read_u16, test::tcp_frame},
+        socket::{mock::MockXskSocket, Socket},
+    };
+
+    const SRC_MAC: [u8; 6] = [2, 0, 0, 0, 0, 1];
+    const DST_MAC: [u8; 6] = [2, 0, 0, 0, 0, 2];
+
+    fn encapsulate(socket: &MockXskSocket, encap: &VxlanEncap, inner: &[u8], vni: u32) -> Vec<u8> {
+        let mut frame = socket.umem().allocate(1).unwrap().pop().unwrap();
+        frame.reserve_head(encap.header_len()).unwrap();
+        frame.extend_from_slice(inner).unwrap();
+        encap.push(&mut frame, vni).unwrap();
+        frame.raw_buffer().to_vec()
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let socket = MockXskSocket::new(16, 2048).unwrap();
+        let inner = tcp_frame(1234, 1, 1, 0x10, b"hello");
+
+        let encap = VxlanEncap::new(
+            SRC_MAC,
+            DST_MAC,
+            "192.0.2.1".parse().unwrap(),
+            "192.0.2.2".parse().unwrap(),
+        )
+        .unwrap()
+        .udp_checksum(true);
+        let outer = encapsulate(&socket, &encap, &inner, 42);
+        assert_eq!(outer.len(), encap.header_len() + inner.len());
+        assert_eq!(&outer[..6], &DST_MAC);
+
+        let info = parse_packet(&outer).unwrap();
+        assert_eq!(info.key.dst_port, VXLAN_PORT);
+        assert!(info.key.src_port >= 49152);
+        assert_eq!(info.l3_end, outer.len());
+        assert_eq!(checksum::sum(&outer[ETH_HLEN..info.l4_offset], 0), 0xffff);
+        let pseudo = checksum::pseudo_header(
+            info.key.src,
+            info.key.dst,
+            IPPROTO_UDP,
+            outer.len() - info.l4_offset,
+        );
+        assert_eq!(checksum::sum(&outer[info.l4_offset..], pseudo), 0xffff);
+        assert_eq!(
+            parse_vxlan(&outer, VXLAN_PORT),
+            Some((42, encap.header_len()))
+        );
+        assert!(parse_vxlan(&outer, 8472).is_none());
+
+        // received frames have the XDP headroom to push the outer headers
+        let mut socket = socket;
+        assert!(socket.inject(&inner));
+        let mut frame = socket.recv_bulk(1).unwrap().pop().unwrap();
+        assert!(frame.head_room() >= encap.header_len());
+        encap.push(&mut frame, 42).unwrap();
+        assert_eq!(frame.raw_buffer(), &outer[..]);
+        assert_eq!(pop(&mut frame, VXLAN_PORT), Some(42));
+        assert_eq!(frame.raw_buffer(), &inner[..]);
+        assert!(pop(&mut frame, VXLAN_PORT).is_none());
+
+        let encap6 = VxlanEncap::new(
+            SRC_MAC,
+            DST_MAC,
+            "2001:db8::1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap(),
+        )
+        .unwrap();
+        let outer6 = encapsulate(&socket, &encap6, &inner, MAX_VNI);
+        let info = parse_packet(&outer6).unwrap();
+        assert_ne!(read_u16(&outer6, info.l4_offset + 6), 0);
+        assert_eq!(
+            parse_vxlan(&outer6, VXLAN_PORT),
+            Some((MAX_VNI, 14 + 40 + 16))
+        );
+
+        let mut frame = socket.umem().allocate(1).unwrap().pop().unwrap();
+        assert!(encap.push(&mut frame, 1).is_err());
+        frame.reserve_head(100).unwrap();
+        assert!(encap.push(&mut frame, MAX_VNI + 1).is_err());
+        assert!(VxlanEncap::new(
+            SRC_MAC,
+            DST_MAC,
+            "192.0.2.1".parse().unwrap(),
+            "2001:db8::2".parse().unwrap()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_demux() {
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        let encap = VxlanEncap::new(
+            SRC_MAC,
+            DST_MAC,
+            "192.0.2.1".parse().unwrap(),
+            "192.0.2.2".parse().unwrap(),
+        )
+        .unwrap();
+        let inner = tcp_frame(1234, 1, 1, 0x10, b"hello");
+        let plain = tcp_frame(4321, 1, 1, 0x10, b"plain");
+        for packet in [
+            encapsulate(&socket, &encap, &inner, 1),
+            encapsulate(&socket, &encap, &inner, 2),
+            plain.clone(),
+        ] {
+            assert!(socket.inject(&packet));
+        }
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = received.clone();
+        let mut demux = VxlanDemux::new(VXLAN_PORT).route(1, move |frame: RxFrame<_>| {
+            sink.borrow_mut().push(frame.to_vec())
+        });
+
+        let mut frames = socket.recv_bulk(8).unwrap();
+        demux.process(&mut frames).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].raw_buffer(), &plain[..]);
+        assert_eq!(*received.borrow(), vec![inner]);
+        assert_eq!(
+            demux.stats(),
+            &VxlanDemuxStats {
+                decapsulated: 1,
+                unknown_vni: 1
+            }
+        );
+    }
+}
@@ -0,0 +1,454 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    error::CamelliaError,
+    socket::Socket,
+    umem::{
+        frame::{RxFrame, TxFrame},
+        AccessorRef,
+    },
+};
+
+// A step of batch processing. Stages keep the frames to pass on in the batch, and take
+// out the ones they consume (e.g., by sending or dropping them).
+pub trait Stage<M: AccessorRef> {
+    fn name(&self) -> &'static str;
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<M>>) -> Result<(), CamelliaError>;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StageCounters {
+    pub batches: u64,
+    pub frames_in: u64,
+    pub frames_out: u64,
+}
+
+impl StageCounters {
+    // frames consumed by the stage
+    pub fn consumed(&self) -> u64 {
+        self.frames_in - self.frames_out
+    }
+}
+
+// Stages run in order on each batch, stages after an empty batch are skipped
+pub struct Pipeline<M: AccessorRef> {
+    stages: Vec<(Box<dyn Stage<M>>, StageCounters)>,
+}
+
+impl<M: AccessorRef> Default for Pipeline<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: AccessorRef> Pipeline<M> {
+    pub fn new() -> Self {
+        Self { stages: Vec::new() }
+    }
+
+    pub fn stage(mut self, stage: impl Stage<M> + 'static) -> Self {
+        self.stages
+            .push((Box::new(stage), StageCounters::default()));
+        self
+    }
+
+    pub fn run(&mut self, frames: &mut Vec<RxFrame<M>>) -> Result<(), CamelliaError> {
+        for (stage, counters) in self.stages.iter_mut() {
+            if frames.is_empty() {
+                break;
+            }
+
+            counters.batches += 1;
+            counters.frames_in += frames.len() as u64;
+            stage.process(frames)?;
+            counters.frames_out += frames.len() as u64;
+        }
+        Ok(())
+    }
+
+    pub fn counters(&self) -> impl Iterator<Item = (&'static str, &StageCounters)> {
+        self.stages
+            .iter()
+            .map(|(stage, counters)| (stage.name(), counters))
+    }
+}
+
+impl<M: AccessorRef> Stage<M> for Pipeline<M> {
+    fn name(&self) -> &'static str {
+        "pipeline"
+    }
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<M>>) -> Result<(), CamelliaError> {
+        self.run(frames)
+    }
+}
+
+// Keep frames matching the predicate, drop the others
+pub struct Filter<F> {
+    predicate: F,
+}
+
+impl<F> Filter<F> {
+    pub fn new(predicate: F) -> Self {
+        Self { predicate }
+    }
+}
+
+impl<M, F> Stage<M> for Filter<F>
+where
+    M: AccessorRef,
+    F: FnMut(&RxFrame<M>) -> bool,
+{
+    fn name(&self) -> &'static str {
+        "filter"
+    }
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<M>>) -> Result<(), CamelliaError> {
+        frames.retain(|frame| (self.predicate)(frame));
+        Ok(())
+    }
+}
+
+// Modify every frame in place, e.g., rewrite MAC addresses
+pub struct Rewrite<F> {
+    rewrite: F,
+}
+
+impl<F> Rewrite<F> {
+    pub fn new(rewrite: F) -> Self {
+        Self { rewrite }
+    }
+}
+
+impl<M, F> Stage<M> for Rewrite<F>
+where
+    M: AccessorRef,
+    F: FnMut(&mut RxFrame<M>),
+{
+    fn name(&self) -> &'static str {
+        "rewrite"
+    }
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<M>>) -> Result<(), CamelliaError> {
+        frames.iter_mut().for_each(|frame| (self.rewrite)(frame));
+        Ok(())
+    }
+}
+
+// Dispatch frames to branches by the class the classifier assigns to them. Frames of
+// classes without a branch stay in the batch.
+pub struct Classify<M: AccessorRef, F> {
+    classifier: F,
+    branches: Vec<Pipeline<M>>,
+    batches: Vec<Vec<RxFrame<M>>>,
+}
+
+impl<M: AccessorRef, F> Classify<M, F> {
+    pub fn new(classifier: F) -> Self {
+        Self {
+            classifier,
+            branches: Vec::new(),
+            batches: Vec::new(),
+        }
+    }
+
+    // the pipeline of the next class, starting from class 0
+    pub fn branch(mut self, pipeline: Pipeline<M>) -> Self {
+        self.branches.push(pipeline);
+        self.batches.push(Vec::new());
+        self
+    }
+}
+
+impl<M, F> Stage<M> for Classify<M, F>
+where
+    M: AccessorRef,
+    F: FnMut(&RxFrame<M>) -> usize,
+{
+    fn name(&self) -> &'static str {
+        "classify"
+    }
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<M>>) -> Result<(), CamelliaError> {
+        for frame in std::mem::take(frames) {
+            match self.batches.get_mut((self.classifier)(&frame)) {
+                Some(batch) => batch.push(frame),
+                None => frames.push(frame),
+            }
+        }
+
+        for (branch, batch) in self.branches.iter_mut().zip(self.batches.iter_mut()) {
+            branch.run(batch)?;
+            // frames left by a branch are dropped
+            batch.clear();
+        }
+        Ok(())
+    }
+}
+
+// Send all frames out of a socket sharing their UMem, frames not accepted by the socket
+// are dropped
+pub struct Forward<S> {
+    socket: Rc<RefCell<S>>,
+    rejected: u64,
+}
+
+impl<S> Forward<S> {
+    pub fn new(socket: Rc<RefCell<S>>) -> Self {
+        Self {
+            socket,
+            rejected: 0,
+        }
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+}
+
+impl<S: Socket> Stage<S::Accessor> for Forward<S> {
+    fn name(&self) -> &'static str {
+        "forward"
+    }
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<S::Accessor>>) -> Result<(), CamelliaError> {
+        let remaining = self.socket.borrow_mut().send_bulk(frames.drain(..))?;
+        self.rejected += remaining.len() as u64;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TeeMode {
+    // copy into chunks allocated from the UMem of the target socket
+    Copy,
+    // send a reference to the chunk, the target must share the UMem with reference
+    // counting enabled, and later stages must not modify the frames
+    Shared,
+}
+
+// Send copies of frames matching the predicate out of a secondary socket, e.g., to an
+// analyzer, and pass every frame on. Copies the socket can't take are dropped.
+pub struct Tee<S, F> {
+    socket: Rc<RefCell<S>>,
+    mode: TeeMode,
+    predicate: F,
+    mirrored: u64,
+    dropped: u64,
+}
+
+impl<S, F> Tee<S, F> {
+    pub fn new(socket: Rc<RefCell<S>>, mode: TeeMode, predicate: F) -> Self {
+        Self {
+            socket,
+            mode,
+            predicate,
+            mirrored: 0,
+            dropped: 0,
+        }
+    }
+
+    pub fn mirrored(&self) -> u64 {
+        self.mirrored
+    }
+
+    // copies not sent for lack of chunks or room in the TX ring
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl<S, F> Stage<S::Accessor> for Tee<S, F>
+where
+    S: Socket,
+    F: FnMut(&RxFrame<S::Accessor>) -> bool,
+{
+    fn name(&self) -> &'static str {
+        "tee"
+    }
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<S::Accessor>>) -> Result<(), CamelliaError> {
+        let selected: Vec<&RxFrame<S::Accessor>> = frames
+            .iter()
+            .filter(|frame| (self.predicate)(frame))
+            .collect();
+        if selected.is_empty() {
+            return Ok(());
+        }
+
+        let mut socket = self.socket.borrow_mut();
+        let copies: Vec<TxFrame<S::Accessor>> = match self.mode {
+            TeeMode::Copy => {
+                let chunks = match socket.allocate(selected.len()) {
+                    Ok(chunks) => chunks,
+                    Err(CamelliaError::UMemExhausted { .. }) => {
+                        self.dropped += selected.len() as u64;
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),
+                };
+                selected
+                    .iter()
+                    .zip(chunks)
+                    .map(|(frame, mut copy)| {
+                        copy.raw_buffer_append(frame.len())?
+                            .copy_from_slice(frame.raw_buffer());
+                        Ok(copy.into())
+                    })
+                    .collect::<Result<_, CamelliaError>>()?
+            }
+            TeeMode::Shared => selected
+                .iter()
+                .map(|frame| frame.mirror())
+                .collect::<Result<_, _>>()?,
+        };
+
+        let total = copies.len() as u64;
+        let remaining = socket.send_bulk(copies)?.len() as u64;
+        self.mirrored += total - remaining;
+        self.dropped += remaining;
+        Ok(())
+    }
+}
+
+// Hand the bytes of frames matching the predicate to a sink, e.g., a pcap writer, and
+// pass every frame on
+pub struct Tap<P, F> {
+    predicate: P,
+    sink: F,
+}
+
+impl<P, F> Tap<P, F> {
+    pub fn new(predicate: P, sink: F) -> Self {
+        Self { predicate, sink }
+    }
+}
+
+impl<M, P, F> Stage<M> for Tap<P, F>
+where
+    M: AccessorRef,
+    P: FnMut(&RxFrame<M>) -> bool,
+    F: FnMut(&[u8]),
+{
+    fn name(&self) -> &'static str {
+        "tap"
+    }
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<M>>) -> Result<(), CamelliaError> {
+        for frame in frames.iter() {
+            if (self.predicate)(frame) {
+                (self.sink)(frame.raw_buffer());
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Discard;
+
+impl<M: AccessorRef> Stage<M> for Discard {
+    fn name(&self) -> &'static str {
+        "discard"
+    }
+
+    fn process(&mut self, frames: &mut Vec<RxFrame<M>>) -> Result<(), CamelliaError> {
+        frames.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socket::mock::MockXskSocket;
+
+    #[test]
+    fn test_pipeline() {
+        let (mut left, right) = MockXskSocket::pair(64, 2048).unwrap();
+        let right = Rc::new(RefCell::new(right));
+
+        let frames: Vec<_> = (0..6u8)
+            .map(|i| {
+                let mut frame = left.allocate(1).unwrap().pop().unwrap();
+                frame.raw_buffer_append(60).unwrap().fill(i);
+                frame
+            })
+            .collect();
+        assert!(left.send_bulk(frames).unwrap().is_empty());
+
+        // drop frame 0, bounce odd frames back with the second byte rewritten
+        let mut pipeline = Pipeline::new()
+            .stage(Filter::new(|frame: &RxFrame<_>| frame.raw_buffer()[0] != 0))
+            .stage(
+                Classify::new(|frame: &RxFrame<_>| (frame.raw_buffer()[0] % 2 == 0) as usize)
+                    .branch(
+                        Pipeline::new()
+                            .stage(Rewrite::new(|frame: &mut RxFrame<_>| {
+                                frame.raw_buffer_mut()[1] = 0xff
+                            }))
+                            .stage(Forward::new(right.clone())),
+                    ),
+            )
+            .stage(Discard);
+
+        let mut frames = right.borrow_mut().recv_bulk(32).unwrap();
+        pipeline.run(&mut frames).unwrap();
+        assert!(frames.is_empty());
+
+        let counters: Vec<_> = pipeline.counters().collect();
+        assert_eq!(counters[0].0, "filter");
+        assert_eq!(counters[0].1.consumed(), 1);
+        assert_eq!(counters[1].1.frames_in, 5);
+        assert_eq!(counters[1].1.consumed(), 3);
+        assert_eq!(counters[2].1.consumed(), 2);
+
+        let bounced = left.recv_bulk(32).unwrap();
+        let bounced: Vec<_> = bounced
+            .iter()
+            .map(|frame| (frame.raw_buffer()[0], frame.raw_buffer()[1]))
+            .collect();
+        assert_eq!(bounced, vec![(1, 0xff), (3, 0xff), (5, 0xff)]);
+    }
+
+    #[test]
+    fn test_tee() {
+        let (mut left, right) = MockXskSocket::pair(64, 2048).unwrap();
+        let right = Rc::new(RefCell::new(right));
+        let analyzer = Rc::new(RefCell::new(MockXskSocket::new(4, 2048).unwrap()));
+
+        let frames: Vec<_> = (0..6u8)
+            .map(|i| {
+                let mut frame = left.allocate(1).unwrap().pop().unwrap();
+                frame.raw_buffer_append(60).unwrap().fill(i);
+                frame
+            })
+            .collect();
+        assert!(left.send_bulk(frames).unwrap().is_empty());
+
+        let captured = Rc::new(RefCell::new(Vec::new()));
+        let sink = captured.clone();
+        let mut pipeline = Pipeline::new()
+            .stage(Tee::new(
+                analyzer.clone(),
+                TeeMode::Copy,
+                |frame: &RxFrame<_>| frame.raw_buffer()[0] >= 2,
+            ))
+            .stage(Tap::new(
+                |frame: &RxFrame<_>| frame.raw_buffer()[0] == 1,
+                move |bytes: &[u8]| sink.borrow_mut().push(bytes.to_vec()),
+            ))
+            .stage(Forward::new(right.clone()));
+
+        let mut frames = right.borrow_mut().recv_bulk(32).unwrap();
+        pipeline.run(&mut frames).unwrap();
+
+        // originals are forwarded as they are
+        let bounced = left.recv_bulk(32).unwrap();
+        assert_eq!(bounced.len(), 6);
+        let transmitted = analyzer.borrow_mut().take_transmitted();
+        let firsts: Vec<_> = transmitted.iter().map(|bytes| bytes[0]).collect();
+        assert_eq!(firsts, vec![2, 3, 4, 5]);
+        assert_eq!(captured.borrow().as_slice(), &[vec![1u8; 60]]);
+    }
+}
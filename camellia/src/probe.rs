@@ -0,0 +1,637 @@
+use std::{
+    ffi::CStr,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use nix::errno::Errno;
+use once_cell::sync::OnceCell;
+
+use crate::error::CamelliaError;
+
+// include/uapi/linux/netdev.h
+const NETDEV_FAMILY_NAME: &[u8] = b"netdev\0";
+const NETDEV_CMD_DEV_GET: u8 = 1;
+const NETDEV_A_DEV_IFINDEX: u16 = 1;
+const NETDEV_A_DEV_XDP_FEATURES: u16 = 3;
+const NETDEV_A_DEV_XDP_ZC_MAX_SEGS: u16 = 4;
+const NETDEV_A_DEV_XDP_RX_METADATA_FEATURES: u16 = 5;
+const NETDEV_A_DEV_XSK_FEATURES: u16 = 6;
+
+const NETDEV_XDP_ACT_BASIC: u64 = 1 << 0;
+const NETDEV_XDP_ACT_REDIRECT: u64 = 1 << 1;
+const NETDEV_XDP_ACT_NDO_XMIT: u64 = 1 << 2;
+const NETDEV_XDP_ACT_XSK_ZEROCOPY: u64 = 1 << 3;
+const NETDEV_XDP_ACT_HW_OFFLOAD: u64 = 1 << 4;
+const NETDEV_XDP_ACT_RX_SG: u64 = 1 << 5;
+const NETDEV_XDP_ACT_NDO_XMIT_SG: u64 = 1 << 6;
+
+const NETDEV_XDP_RX_METADATA_TIMESTAMP: u64 = 1 << 0;
+const NETDEV_XDP_RX_METADATA_HASH: u64 = 1 << 1;
+const NETDEV_XDP_RX_METADATA_VLAN_TAG: u64 = 1 << 2;
+
+const NETDEV_XSK_FLAGS_TX_TIMESTAMP: u64 = 1 << 0;
+const NETDEV_XSK_FLAGS_TX_CHECKSUM: u64 = 1 << 1;
+const NETDEV_XSK_FLAGS_TX_LAUNCH_TIME_FIFO: u64 = 1 << 2;
+
+const NLMSG_HDRLEN: usize = 16;
+const GENL_HDRLEN: usize = 4;
+const NLA_HDRLEN: usize = 4;
+
+const ETHTOOL_GDRVINFO: u32 = 0x3;
+
+// include/uapi/linux/if_xdp.h
+const SOL_XDP: libc::c_int = 283;
+const XDP_UMEM_REG: libc::c_int = 4;
+const XDP_USE_NEED_WAKEUP: u16 = 1 << 3;
+const XDP_USE_SG: u16 = 1 << 4;
+const XDP_UMEM_TX_SW_CSUM: u32 = 1 << 1;
+const SO_PREFER_BUSY_POLL: libc::c_int = 69;
+
+static CAPABILITIES: OnceCell<Capabilities> = OnceCell::new();
+
+// XDP features a driver advertises, from the netdev netlink family
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct XdpFeatures {
+    // XDP_PASS, XDP_DROP, XDP_TX and XDP_ABORTED in the native mode
+    pub basic: bool,
+    pub redirect: bool,
+    // the device is a target of XDP_REDIRECT
+    pub ndo_xmit: bool,
+    pub zero_copy: bool,
+    pub hw_offload: bool,
+    // frames spanning several buffers, needed for multi-buffer sockets in the native mode
+    pub multi_buffer: bool,
+    pub ndo_xmit_multi_buffer: bool,
+    // most buffers of a multi-buffer frame in the zero-copy mode
+    pub zero_copy_max_segs: u32,
+    // metadata hints XDP programs read with the bpf_xdp_metadata_rx_* kfuncs
+    pub rx_timestamp: bool,
+    pub rx_hash: bool,
+    pub rx_vlan_tag: bool,
+    // TX metadata offloads of AF_XDP sockets
+    pub tx_timestamp: bool,
+    pub tx_checksum: bool,
+    pub tx_launch_time: bool,
+}
+
+impl XdpFeatures {
+    pub fn from_flags(xdp: u64, rx_metadata: u64, xsk: u64, zero_copy_max_segs: u32) -> Self {
+        Self {
+            basic: xdp & NETDEV_XDP_ACT_BASIC != 0,
+            redirect: xdp & NETDEV_XDP_ACT_REDIRECT != 0,
+            ndo_xmit: xdp & NETDEV_XDP_ACT_NDO_XMIT != 0,
+            zero_copy: xdp & NETDEV_XDP_ACT_XSK_ZEROCOPY != 0,
+            hw_offload: xdp & NETDEV_XDP_ACT_HW_OFFLOAD != 0,
+            multi_buffer: xdp & NETDEV_XDP_ACT_RX_SG != 0,
+            ndo_xmit_multi_buffer: xdp & NETDEV_XDP_ACT_NDO_XMIT_SG != 0,
+            zero_copy_max_segs,
+            rx_timestamp: rx_metadata & NETDEV_XDP_RX_METADATA_TIMESTAMP != 0,
+            rx_hash: rx_metadata & NETDEV_XDP_RX_METADATA_HASH != 0,
+            rx_vlan_tag: rx_metadata & NETDEV_XDP_RX_METADATA_VLAN_TAG != 0,
+            tx_timestamp: xsk & NETDEV_XSK_FLAGS_TX_TIMESTAMP != 0,
+            tx_checksum: xsk & NETDEV_XSK_FLAGS_TX_CHECKSUM != 0,
+            tx_launch_time: xsk & NETDEV_XSK_FLAGS_TX_LAUNCH_TIME_FIFO != 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceFeatures {
+    pub ifname: String,
+    pub ifindex: u32,
+    // e.g., ice or veth, None if the device doesn't support ethtool
+    pub driver: Option<String>,
+    pub rx_queues: u32,
+    pub tx_queues: u32,
+    // None on kernels without the netdev family, before Linux 6.3
+    pub xdp: Option<XdpFeatures>,
+}
+
+impl InterfaceFeatures {
+    // AF_XDP sockets can only bind to queues existing in both directions
+    pub fn queues(&self) -> u32 {
+        self.rx_queues.min(self.tx_queues)
+    }
+}
+
+// Probes what an interface supports without creating sockets or attaching programs,
+// e.g., whether binding in the zero-copy mode will work
+pub fn interface_features(ifname: &str) -> Result<InterfaceFeatures, CamelliaError> {
+    let c_ifname = std::ffi::CString::new(ifname)
+        .map_err(|_| CamelliaError::InvalidArgument(format!("invalid ifname {}", ifname)))?;
+    let ifindex = unsafe { libc::if_nametoindex(c_ifname.as_ptr()) };
+    if ifindex == 0 {
+        return Err(CamelliaError::InterfaceNotFound {
+            name: ifname.to_string(),
+        });
+    }
+
+    let (rx_queues, tx_queues) = queue_counts(ifname);
+    let mut netlink = GenericNetlink::open()?;
+    let xdp = match netlink.family_id(NETDEV_FAMILY_NAME) {
+        Ok(family) => Some(netdev_features(&mut netlink, family, ifindex)?),
+        Err(err) if err.errno() == Some(Errno::ENOENT) => None,
+        Err(err) => return Err(err),
+    };
+
+    Ok(InterfaceFeatures {
+        ifname: ifname.to_string(),
+        ifindex,
+        driver: driver_name(ifname),
+        rx_queues,
+        tx_queues,
+        xdp,
+    })
+}
+
+fn netdev_features(
+    netlink: &mut GenericNetlink,
+    family: u16,
+    ifindex: u32,
+) -> Result<XdpFeatures, CamelliaError> {
+    let reply = netlink.request(
+        family,
+        NETDEV_CMD_DEV_GET,
+        &[(NETDEV_A_DEV_IFINDEX, &ifindex.to_ne_bytes())],
+    )?;
+
+    let (mut xdp, mut rx_metadata, mut xsk, mut zero_copy_max_segs) = (0, 0, 0, 0);
+    for (kind, value) in attributes(&reply) {
+        match kind {
+            NETDEV_A_DEV_XDP_FEATURES => xdp = read_ne(value),
+            NETDEV_A_DEV_XDP_RX_METADATA_FEATURES => rx_metadata = read_ne(value),
+            NETDEV_A_DEV_XSK_FEATURES => xsk = read_ne(value),
+            NETDEV_A_DEV_XDP_ZC_MAX_SEGS => zero_copy_max_segs = read_ne(value) as u32,
+            _ => {}
+        }
+    }
+    Ok(XdpFeatures::from_flags(
+        xdp,
+        rx_metadata,
+        xsk,
+        zero_copy_max_segs,
+    ))
+}
+
+// an unsigned attribute of 1 to 8 bytes
+fn read_ne(value: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    let len = value.len().min(8);
+    if cfg!(target_endian = "little") {
+        bytes[..len].copy_from_slice(&value[..len]);
+    } else {
+        bytes[8 - len..].copy_from_slice(&value[..len]);
+    }
+    u64::from_ne_bytes(bytes)
+}
+
+fn queue_counts(ifname: &str) -> (u32, u32) {
+    let Ok(entries) = std::fs::read_dir(format!("/sys/class/net/{}/queues", ifname)) else {
+        return (0, 0);
+    };
+    let (mut rx, mut tx) = (0, 0);
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("rx-") {
+            rx += 1;
+        } else if name.starts_with("tx-") {
+            tx += 1;
+        }
+    }
+    (rx, tx)
+}
+
+#[repr(C)]
+struct EthtoolDrvinfo {
+    cmd: u32,
+    driver: [libc::c_char; 32],
+    version: [libc::c_char; 32],
+    fw_version: [libc::c_char; 32],
+    bus_info: [libc::c_char; 32],
+    erom_version: [libc::c_char; 32],
+    reserved2: [libc::c_char; 12],
+    n_priv_flags: u32,
+    n_stats: u32,
+    testinfo_len: u32,
+    eedump_len: u32,
+    regdump_len: u32,
+}
+
+#[repr(C)]
+struct IfreqData {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    data: *mut libc::c_void,
+    // the rest of the union in struct ifreq
+    _pad: [u8; 16],
+}
+
+fn driver_name(ifname: &str) -> Option<String> {
+    if ifname.len() >= libc::IFNAMSIZ {
+        return None;
+    }
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return None;
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut drvinfo: EthtoolDrvinfo = unsafe { std::mem::zeroed() };
+    drvinfo.cmd = ETHTOOL_GDRVINFO;
+    let mut ifreq = IfreqData {
+        name: [0; libc::IFNAMSIZ],
+        data: &mut drvinfo as *mut EthtoolDrvinfo as *mut libc::c_void,
+        _pad: [0; 16],
+    };
+    for (dst, src) in ifreq.name.iter_mut().zip(ifname.bytes()) {
+        *dst = src as libc::c_char;
+    }
+
+    let ret = unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCETHTOOL as _, &mut ifreq) };
+    if ret < 0 {
+        return None;
+    }
+    let driver = unsafe { CStr::from_ptr(drvinfo.driver.as_ptr()) };
+    Some(driver.to_string_lossy().into_owned()).filter(|driver| !driver.is_empty())
+}
+
+// Features of AF_XDP the kernel supports, so that sockets degrade gracefully across
+// kernels instead of failing to bind. Probes which can't run, e.g., without CAP_NET_RAW,
+// report the feature as supported and leave the decision to the kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub af_xdp: bool,
+    // XDP_USE_NEED_WAKEUP, Linux 5.4
+    pub need_wakeup: bool,
+    // SO_PREFER_BUSY_POLL, Linux 5.11
+    pub prefer_busy_poll: bool,
+    // XDP_USE_SG, Linux 6.6
+    pub multi_buffer: bool,
+    // TX metadata of the UMem, Linux 6.8
+    pub tx_metadata: bool,
+}
+
+impl Default for Capabilities {
+    // everything supported, like a recent kernel
+    fn default() -> Self {
+        Self {
+            af_xdp: true,
+            need_wakeup: true,
+            prefer_busy_poll: true,
+            multi_buffer: true,
+            tx_metadata: true,
+        }
+    }
+}
+
+// The capabilities of the running kernel, probed once per process
+pub fn capabilities() -> Capabilities {
+    *CAPABILITIES.get_or_init(probe_capabilities)
+}
+
+fn probe_capabilities() -> Capabilities {
+    let prefer_busy_poll = probe_prefer_busy_poll();
+    let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return Capabilities {
+            af_xdp: Errno::last() != Errno::EAFNOSUPPORT,
+            prefer_busy_poll,
+            ..Default::default()
+        };
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    Capabilities {
+        af_xdp: true,
+        need_wakeup: probe_bind_flag(&fd, XDP_USE_NEED_WAKEUP),
+        prefer_busy_poll,
+        multi_buffer: probe_bind_flag(&fd, XDP_USE_SG),
+        tx_metadata: probe_tx_metadata(&fd),
+    }
+}
+
+#[repr(C)]
+struct SockaddrXdp {
+    family: u16,
+    flags: u16,
+    ifindex: u32,
+    queue_id: u32,
+    shared_umem_fd: u32,
+}
+
+// Unknown bind flags are rejected with EINVAL before the interface is looked up, known
+// ones fail later with ENODEV as no interface has index 0
+fn probe_bind_flag(fd: &OwnedFd, flag: u16) -> bool {
+    let addr = SockaddrXdp {
+        family: libc::AF_XDP as u16,
+        flags: flag,
+        ifindex: 0,
+        queue_id: 0,
+        shared_umem_fd: 0,
+    };
+    let ret = unsafe {
+        libc::bind(
+            fd.as_raw_fd(),
+            &addr as *const SockaddrXdp as *const libc::sockaddr,
+            std::mem::size_of::<SockaddrXdp>() as u32,
+        )
+    };
+    ret == 0 || Errno::last() != Errno::EINVAL
+}
+
+#[repr(C)]
+struct XdpUmemReg {
+    addr: u64,
+    len: u64,
+    chunk_size: u32,
+    headroom: u32,
+    flags: u32,
+    tx_metadata_len: u32,
+}
+
+// Registers a UMem with a TX metadata flag, which older kernels reject with EINVAL. The
+// UMem is released with the socket.
+fn probe_tx_metadata(fd: &OwnedFd) -> bool {
+    const LEN: usize = 2 * 4096;
+    let area = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            LEN,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    if area == libc::MAP_FAILED {
+        return true;
+    }
+
+    let reg = XdpUmemReg {
+        addr: area as u64,
+        len: LEN as u64,
+        chunk_size: 2048,
+        headroom: 0,
+        flags: XDP_UMEM_TX_SW_CSUM,
+        tx_metadata_len: 8,
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            SOL_XDP,
+            XDP_UMEM_REG,
+            &reg as *const XdpUmemReg as *const libc::c_void,
+            std::mem::size_of::<XdpUmemReg>() as u32,
+        )
+    };
+    let supported = ret == 0 || Errno::last() != Errno::EINVAL;
+    // the kernel pins the pages of a registered UMem until the socket is closed
+    unsafe {
+        libc::munmap(area, LEN);
+    }
+    supported
+}
+
+fn probe_prefer_busy_poll() -> bool {
+    let fd = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if fd < 0 {
+        return true;
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let disable: libc::c_int = 0;
+    let ret = unsafe {
+        libc::setsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_PREFER_BUSY_POLL,
+            &disable as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as u32,
+        )
+    };
+    ret == 0 || Errno::last() != Errno::ENOPROTOOPT
+}
+
+// A minimal generic netlink client issuing one request at a time
+struct GenericNetlink {
+    fd: OwnedFd,
+    seq: u32,
+}
+
+impl GenericNetlink {
+    fn open() -> Result<Self, CamelliaError> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_GENERIC,
+            )
+        };
+        if fd < 0 {
+            return Err(CamelliaError::syscall("socket", Errno::last()));
+        }
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+            seq: 0,
+        })
+    }
+
+    fn family_id(&mut self, name: &[u8]) -> Result<u16, CamelliaError> {
+        let reply = self.request(
+            libc::GENL_ID_CTRL as u16,
+            libc::CTRL_CMD_GETFAMILY as u8,
+            &[(libc::CTRL_ATTR_FAMILY_NAME as u16, name)],
+        )?;
+        let id = attributes(&reply)
+            .find(|(kind, _)| *kind == libc::CTRL_ATTR_FAMILY_ID as u16)
+            .map(|(_, value)| read_ne(value) as u16);
+        id.ok_or(CamelliaError::SystemError(Errno::ENOENT))
+    }
+
+    // sends a request and returns the attributes of its reply
+    fn request(
+        &mut self,
+        family: u16,
+        cmd: u8,
+        attrs: &[(u16, &[u8])],
+    ) -> Result<Vec<u8>, CamelliaError> {
+        self.seq += 1;
+        let message = encode_request(family, cmd, self.seq, attrs);
+        let sent = unsafe {
+            libc::send(
+                self.fd.as_raw_fd(),
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            return Err(CamelliaError::syscall("send", Errno::last()));
+        }
+
+        let mut buffer = vec![0u8; 32 * 1024];
+        loop {
+            let received = unsafe {
+                libc::recv(
+                    self.fd.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            if received < 0 {
+                return Err(CamelliaError::syscall("recv", Errno::last()));
+            }
+            if let Some(reply) = decode_reply(&buffer[..received as usize], family, self.seq)? {
+                return Ok(reply.to_vec());
+            }
+        }
+    }
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+fn encode_request(family: u16, cmd: u8, seq: u32, attrs: &[(u16, &[u8])]) -> Vec<u8> {
+    let mut message = vec![0u8; NLMSG_HDRLEN];
+    message.extend_from_slice(&[cmd, 1, 0, 0]);
+    for (kind, value) in attrs {
+        let start = message.len();
+        message.extend_from_slice(&((NLA_HDRLEN + value.len()) as u16).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(value);
+        message.resize(start + align(NLA_HDRLEN + value.len()), 0);
+    }
+
+    let len = message.len() as u32;
+    message[0..4].copy_from_slice(&len.to_ne_bytes());
+    message[4..6].copy_from_slice(&family.to_ne_bytes());
+    message[6..8].copy_from_slice(&(libc::NLM_F_REQUEST as u16).to_ne_bytes());
+    message[8..12].copy_from_slice(&seq.to_ne_bytes());
+    message
+}
+
+// The attributes of the reply of the family to the request seq in the datagram, None if
+// the datagram holds no reply to it
+fn decode_reply(datagram: &[u8], family: u16, seq: u32) -> Result<Option<&[u8]>, CamelliaError> {
+    let mut offset = 0;
+    while offset + NLMSG_HDRLEN <= datagram.len() {
+        let header = &datagram[offset..];
+        let len = read_ne(&header[0..4]) as usize;
+        if len < NLMSG_HDRLEN || offset + len > datagram.len() {
+            return Err(CamelliaError::SystemError(Errno::EBADMSG));
+        }
+        let kind = read_ne(&header[4..6]) as u16;
+        let message_seq = read_ne(&header[8..12]) as u32;
+
+        if message_seq == seq {
+            if kind == libc::NLMSG_ERROR as u16 && len >= NLMSG_HDRLEN + 4 {
+                let error =
+                    i32::from_ne_bytes(header[NLMSG_HDRLEN..NLMSG_HDRLEN + 4].try_into().unwrap());
+                if error < 0 {
+                    return Err(CamelliaError::SystemError(Errno::from_raw(-error)));
+                }
+            } else if kind == family && len >= NLMSG_HDRLEN + GENL_HDRLEN {
+                return Ok(Some(&header[NLMSG_HDRLEN + GENL_HDRLEN..len]));
+            }
+        }
+        offset += align(len);
+    }
+    Ok(None)
+}
+
+// the type and value of netlink attributes, nested and byte order flags are cleared
+fn attributes(mut data: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < NLA_HDRLEN {
+            return None;
+        }
+        let len = read_ne(&data[0..2]) as usize;
+        if len < NLA_HDRLEN || len > data.len() {
+            return None;
+        }
+        let kind = read_ne(&data[2..4]) as u16 & 0x3fff;
+        let value = &data[NLA_HDRLEN..len];
+        data = &data[align(len).min(data.len())..];
+        Some((kind, value))
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let request = encode_request(
+            30,
+            NETDEV_CMD_DEV_GET,
+            7,
+            &[(NETDEV_A_DEV_IFINDEX, &3u32.to_ne_bytes()), (9, &[1, 2])],
+        );
+        assert_eq!(request.len(), NLMSG_HDRLEN + GENL_HDRLEN + 8 + 8);
+        assert_eq!(read_ne(&request[0..4]), request.len() as u64);
+        let attrs: Vec<_> = attributes(&request[NLMSG_HDRLEN + GENL_HDRLEN..]).collect();
+        assert_eq!(
+            attrs,
+            vec![
+                (NETDEV_A_DEV_IFINDEX, &3u32.to_ne_bytes()[..]),
+                (9, &[1u8, 2][..])
+            ]
+        );
+
+        // the reply of the kernel has the same layout
+        assert_eq!(
+            decode_reply(&request, 30, 7).unwrap(),
+            Some(&request[NLMSG_HDRLEN + GENL_HDRLEN..])
+        );
+        assert_eq!(decode_reply(&request, 30, 8).unwrap(), None);
+
+        let mut error = request[..NLMSG_HDRLEN].to_vec();
+        error[4..6].copy_from_slice(&(libc::NLMSG_ERROR as u16).to_ne_bytes());
+        error.extend_from_slice(&(-libc::EOPNOTSUPP).to_ne_bytes());
+        error.extend_from_slice(&request);
+        let len = error.len() as u32;
+        error[0..4].copy_from_slice(&len.to_ne_bytes());
+        assert_eq!(
+            decode_reply(&error, 30, 7).unwrap_err().errno(),
+            Some(Errno::EOPNOTSUPP)
+        );
+    }
+
+    #[test]
+    fn test_features() {
+        let features = XdpFeatures::from_flags(
+            NETDEV_XDP_ACT_BASIC | NETDEV_XDP_ACT_XSK_ZEROCOPY | NETDEV_XDP_ACT_RX_SG,
+            NETDEV_XDP_RX_METADATA_TIMESTAMP,
+            NETDEV_XSK_FLAGS_TX_CHECKSUM,
+            17,
+        );
+        assert!(features.basic && features.zero_copy && features.multi_buffer);
+        assert!(!features.redirect && !features.hw_offload);
+        assert!(features.rx_timestamp && !features.rx_hash);
+        assert!(features.tx_checksum && !features.tx_launch_time);
+        assert_eq!(features.zero_copy_max_segs, 17);
+
+        assert!(matches!(
+            interface_features("nonexistent0"),
+            Err(CamelliaError::InterfaceNotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let probed = capabilities();
+        assert_eq!(capabilities(), probed);
+        // every kernel with multi-buffer has need_wakeup
+        assert!(!probed.multi_buffer || probed.need_wakeup);
+        assert!(!probed.tx_metadata || probed.multi_buffer);
+
+        // flags unknown to any kernel are rejected
+        let fd = unsafe { libc::socket(libc::AF_XDP, libc::SOCK_RAW | libc::SOCK_CLOEXEC, 0) };
+        if fd >= 0 {
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            assert!(!probe_bind_flag(&fd, 1 << 15));
+        }
+    }
+}
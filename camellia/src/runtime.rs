@@ -0,0 +1,480 @@
+use std::{
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    error::CamelliaError,
+    socket::{
+        af_xdp::{XskSocketBuilder, XskStat},
+        napi, Socket,
+    },
+    stats::{Stat, StatsSource},
+    umem::{
+        base::{DedicatedAccessorRef, UMemBuilder},
+        frame::{RxFrame, TxFrame},
+    },
+};
+
+// How an idle polling loop backs off. It pauses the CPU for the first spin_limit idle
+// rounds (doubling the pauses each round), then yields for yield_limit rounds and parks
+// the thread for park_timeout afterwards. Any received frame resets the backoff.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    batch_size: usize,
+    spin_limit: u32,
+    yield_limit: u32,
+    park_timeout: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Backoff {
+    pub fn new() -> Self {
+        Self {
+            batch_size: 32,
+            spin_limit: 64,
+            yield_limit: 64,
+            park_timeout: Duration::from_micros(100),
+        }
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    pub fn spin_limit(mut self, spin_limit: u32) -> Self {
+        self.spin_limit = spin_limit;
+        self
+    }
+
+    pub fn yield_limit(mut self, yield_limit: u32) -> Self {
+        self.yield_limit = yield_limit;
+        self
+    }
+
+    pub fn park_timeout(mut self, park_timeout: Duration) -> Self {
+        self.park_timeout = park_timeout;
+        self
+    }
+
+    pub(crate) fn idle(&self, idle_rounds: u32, stat: &mut SpinStat) {
+        if idle_rounds < self.spin_limit {
+            for _ in 0..1u32 << idle_rounds.min(6) {
+                std::hint::spin_loop();
+            }
+            stat.spins += 1;
+        } else if idle_rounds < self.spin_limit + self.yield_limit {
+            thread::yield_now();
+            stat.yields += 1;
+        } else {
+            thread::park_timeout(self.park_timeout);
+            stat.parks += 1;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpinStat {
+    // rounds over all sockets
+    pub rounds: u64,
+    // rounds receiving at least one frame
+    pub productive: u64,
+    pub spins: u64,
+    pub yields: u64,
+    pub parks: u64,
+}
+
+impl StatsSource for SpinStat {
+    fn stats_id(&self) -> String {
+        "spin_loop".to_string()
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        visit(Stat::counter("rounds", self.rounds));
+        visit(Stat::counter("productive", self.productive));
+        visit(Stat::counter("spins", self.spins));
+        visit(Stat::counter("yields", self.yields));
+        visit(Stat::counter("parks", self.parks));
+    }
+}
+
+// Busy-poll sockets until the handler breaks. In each round, the handler is called for
+// every socket with its index, all sockets (e.g., to forward frames to another one), and
+// the frames received from it, possibly none, so that it can check exit conditions.
+pub fn spin_loop<S, F>(sockets: &mut [S], handler: F) -> Result<SpinStat, CamelliaError>
+where
+    S: Socket,
+    F: FnMut(usize, &mut [S], Vec<RxFrame<S::Accessor>>) -> Result<ControlFlow<()>, CamelliaError>,
+{
+    spin_loop_with(sockets, &Backoff::default(), handler)
+}
+
+pub fn spin_loop_with<S, F>(
+    sockets: &mut [S],
+    backoff: &Backoff,
+    mut handler: F,
+) -> Result<SpinStat, CamelliaError>
+where
+    S: Socket,
+    F: FnMut(usize, &mut [S], Vec<RxFrame<S::Accessor>>) -> Result<ControlFlow<()>, CamelliaError>,
+{
+    let mut stat = SpinStat::default();
+    let mut idle_rounds = 0;
+
+    loop {
+        let mut received = 0;
+        let mut flow = ControlFlow::Continue(());
+
+        for index in 0..sockets.len() {
+            let frames = sockets[index].recv_bulk(backoff.batch_size)?;
+            received += frames.len();
+            if handler(index, sockets, frames)?.is_break() {
+                flow = ControlFlow::Break(());
+            }
+        }
+
+        stat.rounds += 1;
+        if flow.is_break() {
+            return Ok(stat);
+        }
+
+        if received > 0 {
+            stat.productive += 1;
+            idle_rounds = 0;
+        } else {
+            backoff.idle(idle_rounds, &mut stat);
+            idle_rounds = idle_rounds.saturating_add(1);
+        }
+    }
+}
+
+type Configure =
+    fn(XskSocketBuilder<DedicatedAccessorRef>) -> XskSocketBuilder<DedicatedAccessorRef>;
+
+pub struct XskRuntimeBuilder {
+    queues: Vec<(String, u32)>,
+    cores: Vec<usize>,
+    num_chunks: u32,
+    backoff: Backoff,
+    close_timeout: Duration,
+    configure: Configure,
+}
+
+impl Default for XskRuntimeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl XskRuntimeBuilder {
+    pub fn new() -> Self {
+        Self {
+            queues: Vec::new(),
+            cores: Vec::new(),
+            num_chunks: 4096,
+            backoff: Backoff::default(),
+            close_timeout: Duration::from_millis(100),
+            configure: |builder| builder,
+        }
+    }
+
+    // Add a worker polling a queue of an interface
+    pub fn queue(mut self, ifname: &str, queue_index: u32) -> Self {
+        self.queues.push((ifname.to_string(), queue_index));
+        self
+    }
+
+    // Pin the i-th worker to the i-th core, workers beyond the list are not pinned
+    pub fn cores(mut self, cores: &[usize]) -> Self {
+        self.cores = cores.to_vec();
+        self
+    }
+
+    // chunks of the UMem of each worker, whose chunks are reference counted, see spawn
+    pub fn num_chunks(mut self, num_chunks: u32) -> Self {
+        self.num_chunks = num_chunks;
+        self
+    }
+
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    // how long a stopping worker waits for its TX frames to complete
+    pub fn close_timeout(mut self, close_timeout: Duration) -> Self {
+        self.close_timeout = close_timeout;
+        self
+    }
+
+    // Customize the socket of every worker, e.g., to enable zero copy. The interface,
+    // queue and UMem are set by the runtime.
+    pub fn configure(mut self, configure: Configure) -> Self {
+        self.configure = configure;
+        self
+    }
+
+    // Start the workers, each runs a clone of the handler. The handler may modify the
+    // received frames in place, the frames it returns are sent out of the socket they
+    // are received from. Received frames are sent back without copying by returning
+    // RxFrame::mirror of them, the UMem of every worker counts chunk references for it.
+    pub fn spawn<H>(self, handler: H) -> Result<XskRuntime, CamelliaError>
+    where
+        H: FnMut(&mut [RxFrame<DedicatedAccessorRef>]) -> Vec<TxFrame<DedicatedAccessorRef>>
+            + Clone
+            + Send
+            + 'static,
+    {
+        if self.queues.is_empty() {
+            return Err(CamelliaError::InvalidArgument(
+                "no queue is added to the runtime".to_string(),
+            ));
+        }
+
+        let mut runtime = XskRuntime {
+            running: Arc::new(AtomicBool::new(true)),
+            workers: Vec::new(),
+        };
+        let (ready_sender, ready_receiver) = mpsc::channel();
+
+        for (index, (ifname, queue_index)) in self.queues.iter().enumerate() {
+            let worker = Worker {
+                ifname: ifname.clone(),
+                queue_index: *queue_index,
+                core: self.cores.get(index).copied(),
+                num_chunks: self.num_chunks,
+                backoff: self.backoff.clone(),
+                close_timeout: self.close_timeout,
+                configure: self.configure,
+                running: runtime.running.clone(),
+            };
+            let handler = handler.clone();
+            let ready = ready_sender.clone();
+
+            let handle = thread::Builder::new()
+                .name(format!("xsk-{}-{}", ifname, queue_index))
+                .spawn(move || worker.run(handler, ready))
+                .map_err(|e| {
+                    CamelliaError::ResourceExhausted(format!("failed to spawn worker: {}", e))
+                })?;
+            runtime.workers.push(handle);
+        }
+
+        drop(ready_sender);
+
+        // wait for all sockets, so that failures are reported here instead of by stop
+        for _ in 0..runtime.workers.len() {
+            match ready_receiver.recv() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => return Err(e),
+                Err(_) => {
+                    return Err(CamelliaError::InvalidArgument(
+                        "worker exited during setup".to_string(),
+                    ))
+                }
+            }
+        }
+
+        Ok(runtime)
+    }
+}
+
+// Counters of a worker when it stops. The packet, byte and batch counters of xsk are
+// only counted with the trace feature.
+#[derive(Clone, Debug)]
+pub struct WorkerStat {
+    pub ifname: String,
+    pub queue_index: u32,
+    pub xsk: XskStat,
+    pub spin: SpinStat,
+    // frames returned by the handler but not accepted by the TX ring
+    pub tx_dropped: u64,
+}
+
+struct Worker {
+    ifname: String,
+    queue_index: u32,
+    core: Option<usize>,
+    num_chunks: u32,
+    backoff: Backoff,
+    close_timeout: Duration,
+    configure: Configure,
+    running: Arc<AtomicBool>,
+}
+
+impl Worker {
+    fn run<H>(
+        self,
+        mut handler: H,
+        ready: mpsc::Sender<Result<(), CamelliaError>>,
+    ) -> Result<WorkerStat, CamelliaError>
+    where
+        H: FnMut(&mut [RxFrame<DedicatedAccessorRef>]) -> Vec<TxFrame<DedicatedAccessorRef>>,
+    {
+        let setup = || {
+            if let Some(core) = self.core {
+                napi::pin_current_thread(&[core])?;
+            }
+            // counted references let the handler return mirrors of received frames
+            let umem = UMemBuilder::new()
+                .num_chunks(self.num_chunks)
+                .refcount_chunks(true)
+                .build()?;
+            (self.configure)(XskSocketBuilder::new())
+                .ifname(&self.ifname)
+                .queue_index(self.queue_index)
+                .with_umem(umem)
+                .build()
+        };
+
+        let socket = match setup() {
+            Ok(socket) => {
+                let _ = ready.send(Ok(()));
+                socket
+            }
+            Err(e) => {
+                let _ = ready.send(Err(e));
+                self.running.store(false, Ordering::SeqCst);
+                return Err(CamelliaError::InvalidArgument(format!(
+                    "{} (queue {}) is not set up",
+                    self.ifname, self.queue_index
+                )));
+            }
+        };
+
+        let mut sockets = [socket];
+        let mut tx_dropped = 0;
+        let spin = spin_loop_with(&mut sockets, &self.backoff, |_, sockets, mut frames| {
+            if !self.running.load(Ordering::Relaxed) {
+                return Ok(ControlFlow::Break(()));
+            }
+
+            if !frames.is_empty() {
+                let frames = handler(&mut frames);
+                if !frames.is_empty() {
+                    tx_dropped += sockets[0].send_bulk(frames)?.len() as u64;
+                }
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+
+        let [socket] = sockets;
+        let xsk = socket.stat.clone();
+        socket.close(self.close_timeout)?;
+
+        Ok(WorkerStat {
+            ifname: self.ifname,
+            queue_index: self.queue_index,
+            xsk,
+            spin,
+            tx_dropped,
+        })
+    }
+}
+
+// Worker threads polling AF_XDP sockets, one per queue. Workers keep running until the
+// runtime is stopped or dropped.
+pub struct XskRuntime {
+    running: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<Result<WorkerStat, CamelliaError>>>,
+}
+
+impl XskRuntime {
+    // false once any worker fails to set up or stop is called
+    pub fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    fn shutdown(&mut self) -> Vec<Result<WorkerStat, CamelliaError>> {
+        self.running.store(false, Ordering::SeqCst);
+        self.workers
+            .iter()
+            .for_each(|worker| worker.thread().unpark());
+
+        self.workers
+            .drain(..)
+            .map(|worker| match worker.join() {
+                Ok(result) => result,
+                Err(panic) => std::panic::resume_unwind(panic),
+            })
+            .collect()
+    }
+
+    // Stop all workers after they drain their TX rings
+    pub fn stop(mut self) -> Result<Vec<WorkerStat>, CamelliaError> {
+        self.shutdown().into_iter().collect()
+    }
+}
+
+impl Drop for XskRuntime {
+    fn drop(&mut self) {
+        for result in self.shutdown() {
+            if let Err(e) = result {
+                log::error!("worker exits with error: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socket::mock::MockXskSocket;
+
+    #[test]
+    fn test_spin_loop() {
+        let (mut left, right) = MockXskSocket::pair(64, 2048).unwrap();
+
+        let frames = left.allocate(3).unwrap();
+        let frames: Vec<_> = frames
+            .into_iter()
+            .map(|mut frame| {
+                frame.raw_buffer_append(60).unwrap();
+                frame
+            })
+            .collect();
+        assert!(left.send_bulk(frames).unwrap().is_empty());
+
+        let backoff = Backoff::new()
+            .spin_limit(2)
+            .yield_limit(2)
+            .park_timeout(Duration::from_micros(10));
+        let mut sockets = [left, right];
+        let mut bounced = 0;
+        let mut idle = 0;
+
+        // bounce frames received by the right socket back once, then stop when idle
+        let stat = spin_loop_with(&mut sockets, &backoff, |index, sockets, frames| {
+            if index == 1 && !frames.is_empty() {
+                bounced += frames.len();
+                sockets[1].send_bulk(frames)?;
+            } else if index == 0 && frames.is_empty() {
+                idle += 1;
+            }
+            Ok(if idle > 8 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            })
+        })
+        .unwrap();
+
+        assert_eq!(bounced, 3);
+        assert_eq!(sockets[0].stat().rx_packets, 3);
+        assert_eq!(stat.productive, 2);
+        assert_eq!(stat.spins, 2);
+        assert_eq!(stat.yields, 2);
+        assert!(stat.parks > 0);
+    }
+}
@@ -0,0 +1,467 @@
+use std::{
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+    sync::atomic::{fence, Ordering},
+};
+
+use libc::{c_int, c_void, sockaddr_ll, AF_PACKET, ETH_P_ALL, SOCK_RAW, SOL_PACKET};
+use nix::errno::Errno;
+
+use crate::{
+    error::CamelliaError,
+    socket::{af_xdp::XskStat, Socket},
+    stats::{Stat, StatsSource},
+    umem::{
+        frame::{AppFrame, RxFrame, TxFrame},
+        plain::{PlainAccessorRef, PlainUMem},
+        AccessorRef,
+    },
+};
+
+// libc doesn't give us the TPACKET_V2 definitions on all versions we support
+const PACKET_RX_RING: c_int = 5;
+const PACKET_VERSION: c_int = 10;
+const PACKET_TX_RING: c_int = 13;
+// since Linux 4.20
+const PACKET_IGNORE_OUTGOING: c_int = 23;
+// sll_pkttype of packets sent by the host
+const PACKET_OUTGOING: u8 = 4;
+const TPACKET_V2: c_int = 1;
+
+const TP_STATUS_KERNEL: u32 = 0;
+const TP_STATUS_USER: u32 = 1;
+const TP_STATUS_SEND_REQUEST: u32 = 1;
+const TP_STATUS_SENDING: u32 = 2;
+
+const TPACKET_ALIGNMENT: usize = 16;
+const PAGE_SIZE: usize = 4096;
+
+#[repr(C)]
+struct TPacketReq {
+    tp_block_size: u32,
+    tp_block_nr: u32,
+    tp_frame_size: u32,
+    tp_frame_nr: u32,
+}
+
+#[repr(C)]
+#[allow(dead_code)]
+struct TPacket2Hdr {
+    tp_status: u32,
+    tp_len: u32,
+    tp_snaplen: u32,
+    tp_mac: u16,
+    tp_net: u16,
+    tp_sec: u32,
+    tp_nsec: u32,
+    tp_vlan_tci: u16,
+    tp_vlan_tpid: u16,
+    tp_padding: [u8; 4],
+}
+
+// without PACKET_TX_HAS_OFF, the kernel expects TX payload right after the aligned header
+const TX_DATA_OFFSET: usize =
+    (std::mem::size_of::<TPacket2Hdr>() + TPACKET_ALIGNMENT - 1) & !(TPACKET_ALIGNMENT - 1);
+// the sockaddr_ll of a received packet follows the aligned header as well
+const RX_ADDR_OFFSET: usize = TX_DATA_OFFSET;
+
+pub struct PacketSocketBuilder {
+    ifname: Option<String>,
+    ring_frames: u32,
+    frame_size: u32,
+    num_chunks: u32,
+    chunk_size: u32,
+}
+
+impl Default for PacketSocketBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PacketSocketBuilder {
+    pub fn new() -> Self {
+        Self {
+            ifname: None,
+            ring_frames: 1024,
+            frame_size: 2048,
+            num_chunks: 4096,
+            chunk_size: 2048,
+        }
+    }
+
+    pub fn ifname(mut self, ifname: &str) -> Self {
+        self.ifname = Some(ifname.to_string());
+        self
+    }
+
+    // number of frames in each of the RX and TX rings
+    pub fn ring_frames(mut self, ring_frames: u32) -> Self {
+        self.ring_frames = ring_frames;
+        self
+    }
+
+    pub fn frame_size(mut self, frame_size: u32) -> Self {
+        self.frame_size = frame_size;
+        self
+    }
+
+    pub fn num_chunks(mut self, num_chunks: u32) -> Self {
+        self.num_chunks = num_chunks;
+        self
+    }
+
+    pub fn chunk_size(mut self, chunk_size: u32) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub fn build(self) -> Result<PacketSocket, CamelliaError> {
+        let ifname = self.ifname.ok_or_else(|| {
+            CamelliaError::InvalidArgument("Interface name is not set".to_string())
+        })?;
+
+        let frame_size = self.frame_size as usize;
+        if frame_size & (TPACKET_ALIGNMENT - 1) != 0 || frame_size <= TX_DATA_OFFSET {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "frame size {} must be a multiple of {} and larger than {}",
+                frame_size, TPACKET_ALIGNMENT, TX_DATA_OFFSET
+            )));
+        }
+        // received packets are copied into chunks
+        if frame_size - TX_DATA_OFFSET > self.chunk_size as usize {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "packets of up to {} bytes in frames of {} bytes do not fit in chunks of {} bytes",
+                frame_size - TX_DATA_OFFSET,
+                frame_size,
+                self.chunk_size
+            )));
+        }
+
+        let block_size = PAGE_SIZE.max(frame_size);
+        let frames_per_block = block_size / frame_size;
+        let block_nr = (self.ring_frames as usize).div_ceil(frames_per_block);
+        let frame_nr = block_nr * frames_per_block;
+
+        let ifindex =
+            nix::net::if_::if_nametoindex(ifname.as_str()).map_err(|errno| match errno {
+                Errno::ENODEV => CamelliaError::InterfaceNotFound {
+                    name: ifname.clone(),
+                },
+                _ => errno.into(),
+            })?;
+
+        // Without a protocol, nothing is received until bind, so that the RX ring only sees
+        // packets of the interface
+        let fd =
+            unsafe { OwnedFd::from_raw_fd(Errno::result(libc::socket(AF_PACKET, SOCK_RAW, 0))?) };
+
+        let version: c_int = TPACKET_V2;
+        set_packet_option(fd.as_fd(), PACKET_VERSION, &version)?;
+        // AF_XDP sockets see ingress packets only, older kernels deliver packets sent by
+        // the host as well, which recv_bulk skips
+        let ignore_outgoing: c_int = 1;
+        if let Err(e) = set_packet_option(fd.as_fd(), PACKET_IGNORE_OUTGOING, &ignore_outgoing) {
+            if e.errno() != Some(Errno::ENOPROTOOPT) {
+                return Err(e);
+            }
+        }
+
+        let req = TPacketReq {
+            tp_block_size: block_size as u32,
+            tp_block_nr: block_nr as u32,
+            tp_frame_size: frame_size as u32,
+            tp_frame_nr: frame_nr as u32,
+        };
+        set_packet_option(fd.as_fd(), PACKET_RX_RING, &req)?;
+        set_packet_option(fd.as_fd(), PACKET_TX_RING, &req)?;
+
+        // RX and TX rings are mapped back to back
+        let ring_size = 2 * block_size * block_nr;
+        let ring = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                ring_size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ring == libc::MAP_FAILED {
+            return Err(Errno::last().into());
+        }
+
+        let mut addr: sockaddr_ll = unsafe { std::mem::zeroed() };
+        addr.sll_family = AF_PACKET as u16;
+        addr.sll_protocol = (ETH_P_ALL as u16).to_be();
+        addr.sll_ifindex = ifindex as c_int;
+
+        let socket = PacketSocket {
+            fd,
+            ring: ring as *mut u8,
+            ring_size,
+            frame_size,
+            frame_nr,
+            rx_index: 0,
+            tx_index: 0,
+            ifname,
+            umem_accessor: PlainAccessorRef::new(PlainUMem::new(self.num_chunks, self.chunk_size)?),
+            stat: XskStat::default(),
+        };
+
+        unsafe {
+            Errno::result(libc::bind(
+                socket.fd.as_raw_fd(),
+                &addr as *const sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<sockaddr_ll>() as u32,
+            ))?;
+        }
+
+        log::info!(
+            "create AF_PACKET socket on device {} ({} frames per ring)",
+            socket.ifname,
+            frame_nr
+        );
+
+        Ok(socket)
+    }
+}
+
+fn set_packet_option<T>(fd: BorrowedFd, option: c_int, value: &T) -> Result<(), CamelliaError> {
+    unsafe {
+        Errno::result(libc::setsockopt(
+            fd.as_raw_fd(),
+            SOL_PACKET,
+            option,
+            value as *const T as *const c_void,
+            std::mem::size_of::<T>() as u32,
+        ))?;
+    }
+    Ok(())
+}
+
+// A fallback backend over AF_PACKET with PACKET_MMAP rings, for kernels or NICs where
+// AF_XDP is unavailable. Packets are copied between the rings and a plain UMem. Like
+// AF_XDP, it receives packets arriving at the interface only, not those the host sends.
+pub struct PacketSocket {
+    fd: OwnedFd,
+    ring: *mut u8,
+    ring_size: usize,
+    frame_size: usize,
+    frame_nr: usize,
+    rx_index: usize,
+    tx_index: usize,
+    ifname: String,
+    umem_accessor: PlainAccessorRef,
+    pub stat: XskStat,
+}
+
+unsafe impl Send for PacketSocket {}
+
+impl PacketSocket {
+    pub fn ifname(&self) -> &str {
+        &self.ifname
+    }
+
+    pub fn umem(&self) -> &PlainAccessorRef {
+        &self.umem_accessor
+    }
+
+    fn rx_header(&self, index: usize) -> *mut TPacket2Hdr {
+        unsafe { self.ring.add(index * self.frame_size) as *mut TPacket2Hdr }
+    }
+
+    fn tx_header(&self, index: usize) -> *mut TPacket2Hdr {
+        unsafe { self.ring.add((self.frame_nr + index) * self.frame_size) as *mut TPacket2Hdr }
+    }
+
+    fn kick_tx(&self) -> Result<(), CamelliaError> {
+        unsafe {
+            Errno::result(libc::sendto(
+                self.fd.as_raw_fd(),
+                std::ptr::null(),
+                0,
+                libc::MSG_DONTWAIT,
+                std::ptr::null(),
+                0,
+            ))
+            .or_else(|e| match e {
+                Errno::EAGAIN | Errno::ENOBUFS => Ok(0),
+                _ => Err(e),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Socket for PacketSocket {
+    type Accessor = PlainAccessorRef;
+
+    fn recv_bulk(&mut self, size: usize) -> Result<Vec<RxFrame<PlainAccessorRef>>, CamelliaError> {
+        let mut frames = Vec::new();
+
+        while frames.len() < size {
+            let header = self.rx_header(self.rx_index);
+            let status = unsafe { std::ptr::read_volatile(&(*header).tp_status) };
+            if status & TP_STATUS_USER == 0 {
+                break;
+            }
+            fence(Ordering::Acquire);
+
+            let pkttype = unsafe {
+                (*((header as *const u8).add(RX_ADDR_OFFSET) as *const sockaddr_ll)).sll_pkttype
+            };
+            if pkttype == PACKET_OUTGOING {
+                fence(Ordering::Release);
+                unsafe { std::ptr::write_volatile(&mut (*header).tp_status, TP_STATUS_KERNEL) };
+                self.rx_index = (self.rx_index + 1) % self.frame_nr;
+                continue;
+            }
+
+            // leave the packet in the ring if the UMem is exhausted
+            let Ok(mut allocated) = self.umem_accessor.allocate(1) else {
+                break;
+            };
+            let mut frame = allocated.pop().unwrap();
+
+            let (offset, len) = unsafe { ((*header).tp_mac as usize, (*header).tp_snaplen) };
+            let data = unsafe {
+                std::slice::from_raw_parts((header as *const u8).add(offset), len as usize)
+            };
+            let copied = frame
+                .raw_buffer_append(data.len())
+                .map(|buffer| buffer.copy_from_slice(data));
+
+            // the slot goes back to the kernel even if the packet could not be copied
+            fence(Ordering::Release);
+            unsafe { std::ptr::write_volatile(&mut (*header).tp_status, TP_STATUS_KERNEL) };
+            self.rx_index = (self.rx_index + 1) % self.frame_nr;
+            copied?;
+
+            let chunk = frame.0.take_chunk();
+            let xdp_address = chunk.xdp_address();
+            self.stat.rx_bytes += len as u64;
+            frames.push(RxFrame::from_chunk(
+                chunk,
+                self.umem_accessor.clone(),
+                xdp_address,
+                len as usize,
+            ));
+        }
+
+        if !frames.is_empty() {
+            self.stat.rx_batch += 1;
+            self.stat.rx_packets += frames.len() as u64;
+        }
+
+        Ok(frames)
+    }
+
+    fn send_bulk<Iter, T>(&mut self, frames: Iter) -> Result<Vec<T>, CamelliaError>
+    where
+        T: Into<TxFrame<PlainAccessorRef>>,
+        Iter: IntoIterator<Item = T>,
+        Iter::IntoIter: ExactSizeIterator,
+    {
+        let mut remaining = Vec::new();
+        let mut sent = 0;
+        let mut oversized = None;
+
+        for frame in frames {
+            let header = self.tx_header(self.tx_index);
+            let status = unsafe { std::ptr::read_volatile(&(*header).tp_status) };
+
+            // keep frames in order once the ring is full
+            if !remaining.is_empty() || status & (TP_STATUS_SEND_REQUEST | TP_STATUS_SENDING) != 0 {
+                remaining.push(frame);
+                continue;
+            }
+
+            let frame: TxFrame<PlainAccessorRef> = frame.into();
+            let data = frame.0.raw_buffer();
+
+            // the frames queued so far are still sent, the following ones are dropped
+            if data.len() > self.frame_size - TX_DATA_OFFSET {
+                oversized = Some(data.len());
+                break;
+            }
+
+            unsafe {
+                std::ptr::copy_nonoverlapping(
+                    data.as_ptr(),
+                    (header as *mut u8).add(TX_DATA_OFFSET),
+                    data.len(),
+                );
+                (*header).tp_len = data.len() as u32;
+            }
+            fence(Ordering::Release);
+            unsafe { std::ptr::write_volatile(&mut (*header).tp_status, TP_STATUS_SEND_REQUEST) };
+
+            self.tx_index = (self.tx_index + 1) % self.frame_nr;
+            self.stat.tx_bytes += data.len() as u64;
+            sent += 1;
+            // the payload now lives in the ring, the chunk goes back to its UMem on drop
+        }
+
+        if sent > 0 {
+            self.stat.tx_batch += 1;
+            self.stat.tx_packets += sent;
+            self.stat.tx_wakeup += 1;
+            self.kick_tx()?;
+        }
+
+        if let Some(len) = oversized {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "frame of {} bytes does not fit in a ring slot of {} bytes, {} frames before it \
+                 were sent",
+                len,
+                self.frame_size - TX_DATA_OFFSET,
+                sent
+            )));
+        }
+
+        Ok(remaining)
+    }
+
+    fn allocate(&mut self, n: usize) -> Result<Vec<AppFrame<PlainAccessorRef>>, CamelliaError> {
+        self.umem_accessor.allocate(n)
+    }
+
+    fn stat(&self) -> &XskStat {
+        &self.stat
+    }
+}
+
+impl StatsSource for PacketSocket {
+    fn stats_id(&self) -> String {
+        format!("af_packet/{}", self.ifname)
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.stat.visit_stats(visit)
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        if unsafe { libc::munmap(self.ring as *mut c_void, self.ring_size) } != 0 {
+            eprintln!(
+                "unable to munmap AF_PACKET ring of {}: {}",
+                self.ifname,
+                Errno::last()
+            );
+        }
+    }
+}
+
+impl AsFd for PacketSocket {
+    fn as_fd(&self) -> BorrowedFd {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for PacketSocket {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.fd.as_raw_fd()
+    }
+}
@@ -1,40 +1,94 @@
 use std::cmp::min;
+use std::collections::VecDeque;
 use std::ffi::CString;
+use std::future::Future;
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
+use std::path::Path;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use libbpf_rs::libbpf_sys;
 use libc::c_int;
 use libc::c_void;
 use libc::SOL_SOCKET;
 
-use tracing::Level;
+use tracing::Span;
 
 use libxdp_sys::{
-    xsk_ring_cons, xsk_ring_cons__peek, xsk_ring_cons__release, xsk_ring_cons__rx_desc,
-    xsk_ring_prod, xsk_ring_prod__needs_wakeup, xsk_ring_prod__reserve, xsk_ring_prod__submit,
-    xsk_ring_prod__tx_desc, xsk_socket, xsk_socket__create, xsk_socket__create_shared,
-    xsk_socket__delete, xsk_socket__fd, xsk_socket_config, xsk_socket_config__bindgen_ty_1,
+    xdp_attach_mode, xsk_ring_cons, xsk_ring_cons__cancel, xsk_ring_cons__peek,
+    xsk_ring_cons__release, xsk_ring_cons__rx_desc, xsk_ring_prod, xsk_ring_prod__needs_wakeup,
+    xsk_ring_prod__reserve, xsk_ring_prod__submit, xsk_ring_prod__tx_desc, xsk_socket,
+    xsk_socket__create, xsk_socket__create_shared, xsk_socket__delete, xsk_socket__fd,
+    xsk_socket__update_xskmap, xsk_socket_config, xsk_socket_config__bindgen_ty_1,
     XSK_RING_CONS__DEFAULT_NUM_DESCS, XSK_RING_PROD__DEFAULT_NUM_DESCS,
 };
+// descriptors of the raw ring API
+pub use libxdp_sys::xdp_desc;
 use nix::errno::Errno;
-use tracing::event;
+use serde::Deserialize;
+#[cfg(feature = "trace")]
+use tracing::{event, Level};
 
+use crate::config::XskConfig;
 use crate::error::CamelliaError;
-use crate::umem::base::DedicatedAccessorRef;
+use crate::library::LibraryContext;
+use crate::probe::{capabilities, Capabilities};
+use crate::runtime::{Backoff, SpinStat};
+use crate::socket::frames::Frames;
+use crate::socket::hooks::{Hooks, WakeupDirection};
+use crate::socket::napi;
+use crate::socket::queues::{bound_queues, QueueClaim};
+use crate::socket::timestamp::RxTimestamp;
+use crate::socket::warnings::{TxStall, Warning, Warnings};
+use crate::socket::Socket;
+use crate::stats::{Stat, StatsSource};
+use crate::sync::{self, Mutex};
+use crate::umem::base::{decode_desc_addr, DedicatedAccessorRef};
+use crate::umem::libxdp::pending_entries;
+use crate::umem::libxdp::try_wakeup_tx;
 use crate::umem::libxdp::wakeup_rx;
-use crate::umem::libxdp::wakeup_tx;
 use crate::umem::shared::SharedAccessorRef;
 use crate::umem::{
     base::{CompletionQueue, FillQueue, UMem},
     frame::{AppFrame, RxFrame, TxFrame},
-    shared::SharedAccessor,
-    AccessorRef,
+    shared::{SharedAccessor, SharedCacheConfig},
+    AccessorRef, RingState, UMemStat,
 };
+use crate::xdp::{
+    map::BpfMap,
+    program::{check_ret, detach_xsk_default},
+};
+
+// multi-buffer flags of linux/if_xdp.h, missing from older headers
+const XDP_USE_SG: u32 = 1 << 4;
+// set on every descriptor of a packet but the last one
+const XDP_PKT_CONTD: u32 = 1 << 0;
+
+// Enters a span of an operation under the span of the socket until the end of the block,
+// compiled out without the trace feature
+macro_rules! socket_span {
+    ($socket:expr, $name:literal) => {
+        #[cfg(feature = "trace")]
+        let _span = tracing::trace_span!(parent: &$socket.span, $name).entered();
+    };
+}
+
+// Counts frames and batches of the hot paths into the stat of the socket, compiled out
+// without the trace feature. The counters other code depends on are always updated.
+macro_rules! hot_stat {
+    ($socket:expr, $($field:ident += $value:expr),+) => {
+        #[cfg(feature = "trace")]
+        {
+            $($socket.stat.$field += $value;)+
+        }
+    };
+}
 
 #[derive(Debug)]
+#[repr(align(64))]
 pub struct RxQueue {
     inner: xsk_ring_cons,
 }
@@ -57,6 +111,7 @@ impl Default for RxQueue {
 }
 
 #[derive(Debug)]
+#[repr(align(64))]
 pub struct TxQueue {
     inner: xsk_ring_prod,
 }
@@ -80,15 +135,109 @@ impl Default for TxQueue {
 
 pub struct TxDescriptor {}
 
+// A read-only view of a pending RX descriptor, the descriptor stays in the RX ring
+// until it is consumed by recv_commit or recv_bulk.
+#[derive(Debug)]
+pub struct RxDescView<'a> {
+    pub xdp_address: u64,
+    pub options: u32,
+    data: &'a [u8],
+}
+
+impl<'a> RxDescView<'a> {
+    pub fn raw_buffer(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum XDPMode {
     Generic,
     Driver,
     Hardware,
+    // the first of hardware, driver and generic mode the program attaches in
+    Auto,
+}
+
+impl XDPMode {
+    fn flags(self) -> u32 {
+        match self {
+            XDPMode::Generic => libbpf_sys::XDP_FLAGS_SKB_MODE,
+            XDPMode::Driver => libbpf_sys::XDP_FLAGS_DRV_MODE,
+            XDPMode::Hardware => libbpf_sys::XDP_FLAGS_HW_MODE,
+            XDPMode::Auto => 0,
+        }
+    }
+
+    pub(crate) fn attach_mode(self) -> xdp_attach_mode {
+        match self {
+            XDPMode::Generic => libxdp_sys::xdp_attach_mode_XDP_MODE_SKB,
+            XDPMode::Driver => libxdp_sys::xdp_attach_mode_XDP_MODE_NATIVE,
+            XDPMode::Hardware => libxdp_sys::xdp_attach_mode_XDP_MODE_HW,
+            XDPMode::Auto => libxdp_sys::xdp_attach_mode_XDP_MODE_UNSPEC,
+        }
+    }
+
+    fn candidates(self) -> &'static [XDPMode] {
+        match self {
+            XDPMode::Generic => &[XDPMode::Generic],
+            XDPMode::Driver => &[XDPMode::Driver],
+            XDPMode::Hardware => &[XDPMode::Hardware],
+            XDPMode::Auto => &[XDPMode::Hardware, XDPMode::Driver, XDPMode::Generic],
+        }
+    }
+}
+
+// Creates the socket in each candidate mode in turn. Without the default program no
+// program is attached by the socket, so the mode is left as requested.
+fn create_in_mode(
+    mode: XDPMode,
+    config: &mut xsk_socket_config,
+    mut create: impl FnMut(&xsk_socket_config) -> c_int,
+) -> Result<XDPMode, Errno> {
+    let inhibit_prog_load = unsafe { config.__bindgen_anon_1.libxdp_flags }
+        & libxdp_sys::XSK_LIBXDP_FLAGS__INHIBIT_PROG_LOAD
+        != 0;
+    if inhibit_prog_load {
+        config.xdp_flags |= mode.flags();
+        return match create(config) {
+            0 => Ok(mode),
+            errno => Err(Errno::from_raw(-errno)),
+        };
+    }
+
+    let raw_xdp_flags = config.xdp_flags;
+    let mut result = Err(Errno::EINVAL);
+    for candidate in mode.candidates() {
+        config.xdp_flags = raw_xdp_flags | candidate.flags();
+        let errno = match create(config) {
+            0 => return Ok(*candidate),
+            errno => Errno::from_raw(-errno),
+        };
+        if mode == XDPMode::Auto {
+            log::info!(
+                "failed to attach XDP program in {:?} mode: {}",
+                candidate,
+                errno
+            );
+        }
+        result = Err(errno);
+    }
+    result
 }
 
 pub enum XSKUMem {
     Dedicated(UMem),
-    Shared(Arc<Mutex<UMem>>),
+    Shared(sync::Arc<Mutex<UMem>>),
 }
 
 pub struct XskSocketBuilder<M>
@@ -96,14 +245,29 @@ where
     M: AccessorRef,
 {
     ifname: Option<String>,
+    ifindex: Option<u32>,
     queue_index: Option<u32>,
+    auto_queue: bool,
     rx_queue_size: u32,
     tx_queue_size: u32,
     no_default_prog: bool,
+    unload_default_prog: bool,
     zero_copy: bool,
-    cooperate_schedule: bool,
+    copy_mode: bool,
+    multi_buffer: bool,
+    need_wakeup: NeedWakeup,
     busy_polling: bool,
+    expected_napi_id: Option<u32>,
+    max_tx_inflight_bytes: Option<u64>,
+    rx_timestamp: Option<RxTimestamp>,
+    tx_watchdog: Option<Duration>,
+    shared_cache: SharedCacheConfig,
+    raw_bind_flags: u16,
+    raw_xdp_flags: u32,
+    warnings: Option<Warnings>,
+    hooks: Option<Box<dyn Hooks>>,
     mode: XDPMode,
+    xsks_map: Option<Arc<BpfMap>>,
     umem: Option<M::UMemRef>,
 }
 
@@ -123,76 +287,320 @@ where
     pub fn new() -> Self {
         Self {
             ifname: None,
+            ifindex: None,
             queue_index: None,
+            auto_queue: false,
             rx_queue_size: XSK_RING_CONS__DEFAULT_NUM_DESCS,
             tx_queue_size: XSK_RING_PROD__DEFAULT_NUM_DESCS,
             mode: XDPMode::Driver,
+            xsks_map: None,
             umem: None,
             no_default_prog: false,
+            unload_default_prog: false,
             zero_copy: false,
-            cooperate_schedule: false,
+            copy_mode: false,
+            multi_buffer: false,
+            need_wakeup: NeedWakeup::default(),
             busy_polling: false,
+            expected_napi_id: None,
+            max_tx_inflight_bytes: None,
+            rx_timestamp: None,
+            tx_watchdog: None,
+            shared_cache: SharedCacheConfig::default(),
+            raw_bind_flags: 0,
+            raw_xdp_flags: 0,
+            warnings: None,
+            hooks: None,
+        }
+    }
+
+    // everything but the UMem, which is built separately as it may be shared
+    pub fn from_config(config: &XskConfig) -> Self {
+        let mut builder = Self::new();
+        builder.ifname = config.ifname.clone();
+        builder.ifindex = config.ifindex;
+        builder.queue_index = config.queue_index;
+        builder.auto_queue = config.auto_queue;
+        if let Some(rx_queue_size) = config.rx_queue_size {
+            builder.rx_queue_size = rx_queue_size;
+        }
+        if let Some(tx_queue_size) = config.tx_queue_size {
+            builder.tx_queue_size = tx_queue_size;
         }
+        if let Some(mode) = config.mode {
+            builder.mode = mode;
+        }
+        builder.zero_copy = config.zero_copy;
+        builder.copy_mode = config.copy_mode;
+        builder.multi_buffer = config.multi_buffer;
+        builder.need_wakeup = if config.cooperate_schedule {
+            NeedWakeup::BOTH
+        } else {
+            config.need_wakeup
+        };
+        builder.busy_polling = config.busy_polling;
+        builder.no_default_prog = config.no_default_prog;
+        builder.unload_default_prog = config.unload_default_prog;
+        builder.expected_napi_id = config.expected_napi_id;
+        builder.max_tx_inflight_bytes = config.max_tx_inflight_bytes;
+        builder.rx_timestamp = config.rx_timestamp;
+        builder.shared_cache = config.cache;
+        builder.tx_watchdog = config.tx_watchdog;
+        builder
     }
 
-    fn construct_config(&self) -> Result<xsk_socket_config, CamelliaError> {
+    // Collects every violated constraint instead of stopping at the first one. The UMem
+    // is passed separately as shared ones are behind a lock.
+    fn validate(&self, umem: Option<&UMem>) -> Vec<String> {
+        let mut violations = Vec::new();
+
         if self.umem.is_none() {
-            return Err(CamelliaError::InvalidArgument(
-                "UMem is not set".to_string(),
-            ));
+            violations.push("UMem is not set, call with_umem".to_string());
+        }
+        let ifname = self.resolve_ifname();
+        match (&self.ifname, self.ifindex) {
+            (None, None) => {
+                violations.push("interface is not set, call ifname or ifindex".to_string())
+            }
+            (None, Some(ifindex)) if ifname.is_none() => {
+                violations.push(format!("no interface has index {}", ifindex))
+            }
+            (Some(ifname), Some(ifindex))
+                if nix::net::if_::if_nametoindex(ifname.as_str()).is_ok_and(|i| i != ifindex) =>
+            {
+                violations.push(format!(
+                    "interface {} doesn't have index {}, set only one of ifname and ifindex",
+                    ifname, ifindex
+                ))
+            }
+            _ => {}
+        }
+        if self.queue_index.is_none() && !self.auto_queue {
+            violations.push("queue index is not set, call queue_index or auto_queue".to_string());
+        }
+        if self.zero_copy && self.copy_mode {
+            violations.push("zero copy and copy mode are both requested".to_string());
+        }
+        if self.unload_default_prog && (self.no_default_prog || self.xsks_map.is_some()) {
+            violations.push(
+                "unload_default_prog is requested, but no_default_prog or xsks_map keep the \
+                 socket from loading the default program"
+                    .to_string(),
+            );
         }
 
-        if self.ifname.is_none() {
-            return Err(CamelliaError::InvalidArgument(
-                "Interface name is not set".to_string(),
-            ));
+        for (name, size) in [("RX", self.rx_queue_size), ("TX", self.tx_queue_size)] {
+            if !size.is_power_of_two() {
+                violations.push(format!(
+                    "{} queue size {} must be a power of two",
+                    name, size
+                ));
+            }
         }
 
-        if self.queue_index.is_none() {
-            return Err(CamelliaError::InvalidArgument(
-                "Queue index is not set".to_string(),
-            ));
+        if let Some(ifname) = &ifname {
+            if !Path::new("/sys/class/net").join(ifname).exists() {
+                violations.push(format!("interface {} does not exist", ifname));
+            } else {
+                if let Some(kind) = interface_kind(ifname) {
+                    if self.zero_copy && !kind.supports_zero_copy() {
+                        violations.push(format!(
+                            "{} interface {} doesn't support zero copy",
+                            kind, ifname
+                        ));
+                    }
+                    if self.mode == XDPMode::Hardware && !kind.supports_native_xdp() {
+                        violations.push(format!(
+                            "{} interface {} doesn't support XDP offload, use the generic mode",
+                            kind, ifname
+                        ));
+                    }
+                }
+
+                if !interface_is_up(ifname) {
+                    violations.push(format!(
+                        "interface {} is down, bring it up with ip link set {} up",
+                        ifname, ifname
+                    ));
+                }
+
+                if let (Some(queue_index), Some(max)) = (self.queue_index, queue_count(ifname)) {
+                    if max > 0 && queue_index >= max {
+                        violations.push(format!(
+                            "queue {} is out of range, {} has {} combined queues, \
+                             see ethtool -l {}",
+                            queue_index, ifname, max, ifname
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(umem) = umem {
+            if self.rx_queue_size > umem.fill_size() {
+                violations.push(format!(
+                    "RX queue size {} exceeds the fill queue size {} of the UMem, the RX ring \
+                     can never be filled",
+                    self.rx_queue_size,
+                    umem.fill_size()
+                ));
+            }
+        }
+
+        if let (Some(low), Some(high)) = (
+            self.shared_cache.low_watermark,
+            self.shared_cache.high_watermark,
+        ) {
+            if low >= high {
+                violations.push(format!(
+                    "low watermark {} of the chunk cache is not below the high watermark {}",
+                    low, high
+                ));
+            }
+        }
+
+        violations
+    }
+
+    fn construct_config(&self, umem: Option<&UMem>) -> Result<xsk_socket_config, CamelliaError> {
+        let violations = self.validate(umem);
+        if !violations.is_empty() {
+            return Err(CamelliaError::InvalidConfig(violations));
         }
+        self.check_mtu(umem)?;
 
-        let libxdp_flags = if self.no_default_prog {
+        let libxdp_flags = if self.no_default_prog || self.xsks_map.is_some() {
             libxdp_sys::XSK_LIBXDP_FLAGS__INHIBIT_PROG_LOAD
         } else {
             0
         };
 
-        let xdp_flags = match self.mode {
-            XDPMode::Generic => libbpf_sys::XDP_FLAGS_SKB_MODE,
-            XDPMode::Driver => libbpf_sys::XDP_FLAGS_DRV_MODE,
-            XDPMode::Hardware => libbpf_sys::XDP_FLAGS_HW_MODE,
-        };
+        // the mode flags are added when the socket is created
+        let xdp_flags = self.raw_xdp_flags;
 
-        let bind_flags = match self.zero_copy {
-            true => libxdp_sys::XDP_ZEROCOPY,
-            false => 0,
-        } | match self.cooperate_schedule {
+        let bind_flags = match (self.zero_copy, self.copy_mode) {
+            (true, _) => libxdp_sys::XDP_ZEROCOPY,
+            (false, true) => libxdp_sys::XDP_COPY,
+            (false, false) => 0,
+        } | match self.need_wakeup.any() {
             true => libxdp_sys::XDP_USE_NEED_WAKEUP,
             false => 0,
+        } | match self.multi_buffer {
+            true => XDP_USE_SG,
+            false => 0,
         };
 
         Ok(xsk_socket_config {
             rx_size: self.rx_queue_size,
             tx_size: self.tx_queue_size,
             __bindgen_anon_1: xsk_socket_config__bindgen_ty_1 { libxdp_flags },
-            bind_flags: bind_flags as u16,
+            bind_flags: bind_flags as u16 | self.raw_bind_flags,
             xdp_flags,
         })
     }
 
+    // Drops options the kernel doesn't support with a warning instead of failing to bind,
+    // e.g., on 5.4 kernels without preferred busy polling or multi-buffer
+    pub(crate) fn degrade(&mut self, capabilities: &Capabilities) {
+        if self.need_wakeup.any() && !capabilities.need_wakeup {
+            log::warn!("the kernel doesn't support need_wakeup, disabling it");
+            self.need_wakeup = NeedWakeup::default();
+        }
+        if self.busy_polling && !capabilities.prefer_busy_poll {
+            log::warn!("the kernel doesn't support preferred busy polling, disabling it");
+            self.busy_polling = false;
+        }
+        if self.multi_buffer && !capabilities.multi_buffer {
+            log::warn!("the kernel doesn't support multi-buffer sockets, disabling it");
+            self.multi_buffer = false;
+        }
+    }
+
+    // Without multi-buffer, a frame must fit in a single chunk, or the kernel drops it
+    // silently as an invalid descriptor
+    fn check_mtu(&self, umem: Option<&UMem>) -> Result<(), CamelliaError> {
+        if self.multi_buffer {
+            return Ok(());
+        }
+
+        let (Some(umem), Some(ifname)) = (umem, self.resolve_ifname()) else {
+            return Ok(());
+        };
+        match interface_mtu(&ifname) {
+            // the Ethernet header is not part of the MTU
+            Some(mtu) if mtu + libc::ETH_HLEN as u32 > umem.max_frame_size() => {
+                Err(CamelliaError::FrameTooLarge {
+                    ifname,
+                    mtu,
+                    max_frame_size: umem.max_frame_size(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn ifname(mut self, ifname: &str) -> Self {
         self.ifname = Some(ifname.to_string());
         self
     }
 
+    // an alternative to ifname, resolved to the name when the socket is built
+    pub fn ifindex(mut self, ifindex: u32) -> Self {
+        self.ifindex = Some(ifindex);
+        self
+    }
+
+    // Sockets of different UMems can't bind the same queue, umem identifies the UMem
+    fn claim_queue(&self, ifname: &str, umem: usize) -> Result<QueueClaim, CamelliaError> {
+        let ifindex = nix::net::if_::if_nametoindex(ifname)?;
+        match self.queue_index {
+            Some(queue_index) => QueueClaim::acquire(ifname, ifindex, queue_index, umem),
+            // virtual interfaces may have no queues in sysfs but still one queue
+            None => QueueClaim::acquire_free(
+                ifname,
+                ifindex,
+                queue_count(ifname).unwrap_or(0).max(1),
+                umem,
+            ),
+        }
+    }
+
+    // Stacked devices without native XDP fall back to the generic mode, otherwise binding
+    // fails with EOPNOTSUPP
+    fn resolve_mode(&self, ifname: &str) -> XDPMode {
+        match (self.mode, interface_kind(ifname)) {
+            (XDPMode::Driver | XDPMode::Auto, Some(kind)) if !kind.supports_native_xdp() => {
+                if self.mode == XDPMode::Driver {
+                    log::warn!(
+                        "{} interface {} doesn't support native XDP, falling back to the generic mode",
+                        kind,
+                        ifname
+                    );
+                }
+                XDPMode::Generic
+            }
+            (mode, _) => mode,
+        }
+    }
+
+    fn resolve_ifname(&self) -> Option<String> {
+        self.ifname
+            .clone()
+            .or_else(|| self.ifindex.and_then(interface_name))
+    }
+
     pub fn queue_index(mut self, queue_index: u32) -> Self {
         self.queue_index = Some(queue_index);
         self
     }
 
+    // Without a queue index, bind the first queue not bound by other sockets of this
+    // process yet
+    pub fn auto_queue(mut self) -> Self {
+        self.auto_queue = true;
+        self
+    }
+
     pub fn rx_queue_size(mut self, rx_queue_size: u32) -> Self {
         self.rx_queue_size = rx_queue_size;
         self
@@ -208,6 +616,21 @@ where
         self
     }
 
+    // Detach the default program from the interface when the last socket of this process
+    // on it is dropped, instead of leaving it to redirect traffic to sockets that are
+    // gone. Sockets of other processes registered in the program stop receiving.
+    pub fn unload_default_prog(mut self) -> Self {
+        self.unload_default_prog = true;
+        self
+    }
+
+    // Register the socket in the xsks_map of an attached program, e.g., re-opened from
+    // bpffs after a restart, instead of loading the default program.
+    pub fn xsks_map(mut self, map: Arc<BpfMap>) -> Self {
+        self.xsks_map = Some(map);
+        self
+    }
+
     pub fn xdp_mode(mut self, mode: XDPMode) -> Self {
         self.mode = mode;
         self
@@ -218,8 +641,28 @@ where
         self
     }
 
+    // XDP_COPY, without it the kernel picks zero copy if the driver supports it
+    pub fn force_copy_mode(mut self) -> Self {
+        self.copy_mode = true;
+        self
+    }
+
+    // XDP_USE_SG, frames larger than a chunk, e.g., of a 9000 MTU, span several chunks.
+    // Receive them with recv_packets and send them with send_fragments.
+    pub fn enable_multi_buffer(mut self) -> Self {
+        self.multi_buffer = true;
+        self
+    }
+
     pub fn enable_cooperate_schedule(mut self) -> Self {
-        self.cooperate_schedule = true;
+        self.need_wakeup = NeedWakeup::BOTH;
+        self
+    }
+
+    // need_wakeup semantics per direction, the kernel has a single flag for both rings so
+    // it is set if any direction enables it
+    pub fn need_wakeup(mut self, need_wakeup: NeedWakeup) -> Self {
+        self.need_wakeup = need_wakeup;
         self
     }
 
@@ -228,6 +671,54 @@ where
         self
     }
 
+    // Flags passed to the kernel as is, for bind/XDP flags not modeled by the builder yet
+    pub fn raw_bind_flags(mut self, flags: u16) -> Self {
+        self.raw_bind_flags |= flags;
+        self
+    }
+
+    pub fn raw_xdp_flags(mut self, flags: u32) -> Self {
+        self.raw_xdp_flags |= flags;
+        self
+    }
+
+    pub fn warnings(mut self, warnings: Warnings) -> Self {
+        self.warnings = Some(warnings);
+        self
+    }
+
+    pub fn hooks(mut self, hooks: impl Hooks + 'static) -> Self {
+        self.hooks = Some(Box::new(hooks));
+        self
+    }
+
+    pub fn expected_napi_id(mut self, napi_id: u32) -> Self {
+        self.expected_napi_id = Some(napi_id);
+        self
+    }
+
+    // Bound the bytes submitted to the TX ring but not completed yet. Frames are submitted
+    // while in-flight bytes are below the cap, so it is exceeded by at most one frame.
+    // The rest are returned by send_bulk as if the TX ring were full.
+    pub fn max_tx_inflight_bytes(mut self, bytes: u64) -> Self {
+        self.max_tx_inflight_bytes = Some(bytes);
+        self
+    }
+
+    // Stamp received frames when they are dequeued, see RxFrame::timestamp. Frames taken
+    // from the RawRxRing aren't stamped.
+    pub fn rx_timestamp(mut self, timestamp: RxTimestamp) -> Self {
+        self.rx_timestamp = Some(timestamp);
+        self
+    }
+
+    // Report Warning::TxStalled if TX descriptors are outstanding but none is completed
+    // for the timeout, see XskSocket::check_tx_stall.
+    pub fn tx_watchdog(mut self, timeout: Duration) -> Self {
+        self.tx_watchdog = Some(timeout);
+        self
+    }
+
     pub fn with_umem(mut self, umem: M::UMemRef) -> Self {
         if self.umem.is_some() {
             panic!("UMem is already set");
@@ -276,23 +767,43 @@ where
 }
 
 impl XskSocketBuilder<DedicatedAccessorRef> {
-    pub fn build(self) -> Result<XskSocket<DedicatedAccessorRef>, CamelliaError> {
-        let config = self.construct_config()?;
+    pub fn build(mut self) -> Result<XskSocket<DedicatedAccessorRef>, CamelliaError> {
+        self.degrade(&capabilities());
+        let config = self.construct_config(self.umem.as_ref())?;
         let schedule_mode = if self.busy_polling {
             ScheduleMode::BusyPolling
-        } else if self.cooperate_schedule {
+        } else if self.need_wakeup.any() {
             ScheduleMode::Cooperative
         } else {
             ScheduleMode::Legacy
         };
 
-        let xsk_socket = XskSocket::<DedicatedAccessorRef>::new(
-            &self.ifname.unwrap(),
-            self.queue_index.unwrap(),
+        let ifname = self.resolve_ifname().unwrap();
+        let umem = self.umem.as_ref().unwrap().inner() as usize;
+        let queue_claim = self.claim_queue(&ifname, umem)?;
+        let mode = self.resolve_mode(&ifname);
+
+        let mut xsk_socket = XskSocket::<DedicatedAccessorRef>::new(
+            &ifname,
+            queue_claim.queue_index(),
             self.umem.unwrap(),
             config,
+            mode,
             schedule_mode,
         )?;
+        xsk_socket.queue_claim = Some(queue_claim);
+        xsk_socket.need_wakeup = self.need_wakeup;
+        xsk_socket.expected_napi_id = self.expected_napi_id;
+        xsk_socket.max_tx_inflight_bytes = self.max_tx_inflight_bytes;
+        xsk_socket.rx_timestamp = self.rx_timestamp;
+        xsk_socket.tx_watchdog = self.tx_watchdog.map(TxWatchdog::new);
+        xsk_socket.unload_default_prog = self.unload_default_prog;
+        xsk_socket.xsks_map = self.xsks_map;
+        xsk_socket.update_xsks_map()?;
+        xsk_socket.hooks = self.hooks;
+        if let Some(warnings) = self.warnings {
+            xsk_socket.warnings = warnings;
+        }
         if self.busy_polling {
             Self::set_busy_polling(xsk_socket.as_fd())?;
         }
@@ -301,23 +812,64 @@ impl XskSocketBuilder<DedicatedAccessorRef> {
 }
 
 impl XskSocketBuilder<SharedAccessorRef> {
-    pub fn build_shared(self) -> Result<XskSocket<SharedAccessorRef>, CamelliaError> {
-        let config = self.construct_config()?;
+    // Refill the chunk cache of the socket up to low cached chunks beyond the requested
+    // ones, and return chunks to the other sockets once more than high are cached.
+    pub fn cache_watermarks(mut self, low: usize, high: usize) -> Self {
+        self.shared_cache.low_watermark = Some(low);
+        self.shared_cache.high_watermark = Some(high);
+        self
+    }
+
+    // Bound the chunks held by the fill ring and the cache of the socket. Chunks owned by
+    // the application or in flight in the TX ring don't count.
+    pub fn chunk_quota(mut self, quota: usize) -> Self {
+        self.shared_cache.quota = Some(quota);
+        self
+    }
+
+    pub fn build_shared(mut self) -> Result<XskSocket<SharedAccessorRef>, CamelliaError> {
+        self.degrade(&capabilities());
+        // the UMem is locked again while creating the socket
+        let config = match &self.umem {
+            Some(umem) => self.construct_config(Some(&umem.lock().unwrap()))?,
+            None => self.construct_config(None)?,
+        };
+
         let schedule_mode = if self.busy_polling {
             ScheduleMode::BusyPolling
-        } else if self.cooperate_schedule {
+        } else if self.need_wakeup.any() {
             ScheduleMode::Cooperative
         } else {
             ScheduleMode::Legacy
         };
 
-        let xsk_socket = XskSocket::<SharedAccessorRef>::new(
-            &self.ifname.unwrap(),
-            self.queue_index.unwrap(),
+        let ifname = self.resolve_ifname().unwrap();
+        let umem = self.umem.as_ref().unwrap().lock().unwrap().inner() as usize;
+        let queue_claim = self.claim_queue(&ifname, umem)?;
+        let mode = self.resolve_mode(&ifname);
+
+        let mut xsk_socket = XskSocket::<SharedAccessorRef>::new(
+            &ifname,
+            queue_claim.queue_index(),
             self.umem.unwrap(),
             config,
+            mode,
             schedule_mode,
+            self.shared_cache,
         )?;
+        xsk_socket.queue_claim = Some(queue_claim);
+        xsk_socket.need_wakeup = self.need_wakeup;
+        xsk_socket.expected_napi_id = self.expected_napi_id;
+        xsk_socket.max_tx_inflight_bytes = self.max_tx_inflight_bytes;
+        xsk_socket.rx_timestamp = self.rx_timestamp;
+        xsk_socket.tx_watchdog = self.tx_watchdog.map(TxWatchdog::new);
+        xsk_socket.unload_default_prog = self.unload_default_prog;
+        xsk_socket.xsks_map = self.xsks_map;
+        xsk_socket.update_xsks_map()?;
+        xsk_socket.hooks = self.hooks;
+        if let Some(warnings) = self.warnings {
+            xsk_socket.warnings = warnings;
+        }
 
         if self.busy_polling {
             Self::set_busy_polling(xsk_socket.as_fd())?;
@@ -326,12 +878,168 @@ impl XskSocketBuilder<SharedAccessorRef> {
     }
 }
 
+fn interface_name(ifindex: u32) -> Option<String> {
+    let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+    let ret = unsafe { libc::if_indextoname(ifindex, name.as_mut_ptr()) };
+    if ret.is_null() {
+        return None;
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(name.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+fn interface_flags(ifname: &str) -> Option<u32> {
+    let flags = std::fs::read_to_string(format!("/sys/class/net/{}/flags", ifname)).ok()?;
+    u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok()
+}
+
+fn interface_is_up(ifname: &str) -> bool {
+    interface_flags(ifname).is_some_and(|flags| flags & libc::IFF_UP as u32 != 0)
+}
+
+fn interface_mtu(ifname: &str) -> Option<u32> {
+    std::fs::read_to_string(format!("/sys/class/net/{}/mtu", ifname))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+// Interfaces differ in their XDP support, stacked devices only run generic XDP
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InterfaceKind {
+    Physical,
+    Vlan,
+    Macvlan,
+    Bond,
+    // other software devices, e.g., veth or tun
+    Virtual,
+}
+
+impl InterfaceKind {
+    // bonds run native XDP if all their slaves do
+    pub fn supports_native_xdp(&self) -> bool {
+        !matches!(self, InterfaceKind::Vlan | InterfaceKind::Macvlan)
+    }
+
+    pub fn supports_zero_copy(&self) -> bool {
+        !matches!(
+            self,
+            InterfaceKind::Vlan | InterfaceKind::Macvlan | InterfaceKind::Bond
+        )
+    }
+}
+
+impl std::fmt::Display for InterfaceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            InterfaceKind::Physical => "physical",
+            InterfaceKind::Vlan => "vlan",
+            InterfaceKind::Macvlan => "macvlan",
+            InterfaceKind::Bond => "bond",
+            InterfaceKind::Virtual => "virtual",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub fn interface_kind(ifname: &str) -> Option<InterfaceKind> {
+    let sysfs = Path::new("/sys/class/net").join(ifname);
+    let uevent = std::fs::read_to_string(sysfs.join("uevent")).ok()?;
+    let devtype = uevent
+        .lines()
+        .find_map(|line| line.strip_prefix("DEVTYPE="));
+
+    Some(match devtype {
+        Some("vlan") => InterfaceKind::Vlan,
+        Some("macvlan") => InterfaceKind::Macvlan,
+        Some("bond") => InterfaceKind::Bond,
+        _ if sysfs.join("bonding").exists() => InterfaceKind::Bond,
+        _ if sysfs.join("device").exists() => InterfaceKind::Physical,
+        // macvlan and alike, e.g., ipvlan, are stacked on their lower device
+        None if has_lower_device(&sysfs) => InterfaceKind::Macvlan,
+        _ => InterfaceKind::Virtual,
+    })
+}
+
+fn has_lower_device(sysfs: &Path) -> bool {
+    std::fs::read_dir(sysfs).is_ok_and(|entries| {
+        entries
+            .flatten()
+            .any(|entry| entry.file_name().to_string_lossy().starts_with("lower_"))
+    })
+}
+
+// AF_XDP sockets can only bind to queues existing in both directions
+fn queue_count(ifname: &str) -> Option<u32> {
+    let entries = std::fs::read_dir(format!("/sys/class/net/{}/queues", ifname)).ok()?;
+    let (mut rx, mut tx) = (0, 0);
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("rx-") {
+            rx += 1;
+        } else if name.starts_with("tx-") {
+            tx += 1;
+        }
+    }
+    Some(min(rx, tx))
+}
+
+fn socket_create_error(
+    call: &'static str,
+    ifname: &CString,
+    queue_index: u32,
+    config: &xsk_socket_config,
+    errno: Errno,
+) -> CamelliaError {
+    let ifname = ifname.to_string_lossy().into_owned();
+    match errno {
+        Errno::ENODEV => CamelliaError::InterfaceNotFound { name: ifname },
+        Errno::ENETDOWN => CamelliaError::DeviceDown { ifname },
+        Errno::EOPNOTSUPP if config.bind_flags & libxdp_sys::XDP_ZEROCOPY as u16 != 0 => {
+            CamelliaError::ZeroCopyUnsupported { ifname }
+        }
+        Errno::EINVAL => match queue_count(&ifname) {
+            Some(max) if queue_index >= max => CamelliaError::QueueOutOfRange {
+                queue: queue_index,
+                max,
+            },
+            _ => CamelliaError::syscall(call, errno),
+        },
+        _ => CamelliaError::syscall(call, errno),
+    }
+}
+
+// Which rings follow the need_wakeup flag of the kernel. The kernel is woken up on
+// every batch of a direction which doesn't.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NeedWakeup {
+    #[serde(default)]
+    pub rx: bool,
+    #[serde(default)]
+    pub tx: bool,
+}
+
+impl NeedWakeup {
+    pub const BOTH: NeedWakeup = NeedWakeup { rx: true, tx: true };
+
+    pub fn any(&self) -> bool {
+        self.rx || self.tx
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 enum ScheduleMode {
     Legacy,
     Cooperative,
     BusyPolling,
 }
 
+// Counters of a socket. Packets, bytes and batches are counted by the hot paths only with
+// the trace feature (on by default) and stay zero without it, wakeups and in-flight
+// bytes are always counted.
 #[derive(Clone, Debug, Default)]
 pub struct XskStat {
     pub rx_packets: u64,
@@ -343,74 +1051,358 @@ pub struct XskStat {
     pub tx_bytes: u64,
     pub tx_wakeup: u64,
     pub tx_batch: u64,
+    // bytes submitted to the TX ring and not completed yet
+    pub tx_inflight_bytes: u64,
 }
 
-pub struct XskSocket<M: AccessorRef> {
-    inner: *mut xsk_socket,
-    umem_accessor: M,
-    rx: Pin<Box<RxQueue>>,
-    tx: Pin<Box<TxQueue>>,
-    schedule_mode: ScheduleMode,
-    pub stat: XskStat,
-}
+impl StatsSource for XskStat {
+    fn stats_id(&self) -> String {
+        "socket".to_string()
+    }
 
-unsafe impl<M> Send for XskSocket<M> where M: AccessorRef {}
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        visit(Stat::counter("rx_packets", self.rx_packets));
+        visit(Stat::counter("rx_bytes", self.rx_bytes));
+        visit(Stat::counter("rx_wakeup", self.rx_wakeup));
+        visit(Stat::counter("rx_batch", self.rx_batch));
+        visit(Stat::counter("tx_packets", self.tx_packets));
+        visit(Stat::counter("tx_bytes", self.tx_bytes));
+        visit(Stat::counter("tx_wakeup", self.tx_wakeup));
+        visit(Stat::counter("tx_batch", self.tx_batch));
+        visit(Stat::gauge("tx_inflight_bytes", self.tx_inflight_bytes));
+    }
+}
 
-impl XskSocket<SharedAccessorRef> {
-    fn new(
-        ifname: &str,
-        queue_index: u32,
-        umem: <SharedAccessorRef as AccessorRef>::UMemRef,
-        config: xsk_socket_config,
-        schedule_mode: ScheduleMode,
-    ) -> Result<Self, CamelliaError> {
-        let mut raw_socket: *mut xsk_socket = std::ptr::null_mut();
-        let mut rx_queue = Box::pin(RxQueue::default());
-        let mut tx_queue = Box::pin(TxQueue::default());
-        let mut fill_queue = Box::pin(FillQueue::default());
-        let mut completion_queue = Box::pin(CompletionQueue::default());
+// A copy of the counters of a socket readable from other threads, e.g., by a monitoring
+// thread. The socket stores its counters with relaxed ordering after every batch, so
+// counters read together may come from consecutive batches. Without the trace feature
+// nothing is stored after batches and the handle keeps the counters of its creation.
+#[derive(Debug, Default)]
+pub struct XskStatHandle {
+    rx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    rx_wakeup: AtomicU64,
+    rx_batch: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+    tx_wakeup: AtomicU64,
+    tx_batch: AtomicU64,
+    tx_inflight_bytes: AtomicU64,
+}
 
-        let ifname = CString::new(ifname).unwrap();
-        log::info!(
-            "create AF_XDP socket on device {:?} (queue {})",
-            ifname,
-            queue_index
-        );
+impl XskStatHandle {
+    pub fn store(&self, stat: &XskStat) {
+        self.rx_packets.store(stat.rx_packets, Ordering::Relaxed);
+        self.rx_bytes.store(stat.rx_bytes, Ordering::Relaxed);
+        self.rx_wakeup.store(stat.rx_wakeup, Ordering::Relaxed);
+        self.rx_batch.store(stat.rx_batch, Ordering::Relaxed);
+        self.tx_packets.store(stat.tx_packets, Ordering::Relaxed);
+        self.tx_bytes.store(stat.tx_bytes, Ordering::Relaxed);
+        self.tx_wakeup.store(stat.tx_wakeup, Ordering::Relaxed);
+        self.tx_batch.store(stat.tx_batch, Ordering::Relaxed);
+        self.tx_inflight_bytes
+            .store(stat.tx_inflight_bytes, Ordering::Relaxed);
+    }
 
-        unsafe {
-            match xsk_socket__create_shared(
-                &mut raw_socket,
-                ifname.as_ptr(),
-                queue_index,
-                umem.lock().unwrap().inner(),
-                &mut rx_queue.inner,
-                &mut tx_queue.inner,
-                &mut fill_queue.0,
-                &mut completion_queue.0,
-                &config,
-            ) {
-                0 => {}
-                errno => {
-                    return Err(Errno::from_raw(-errno).into());
-                }
-            }
+    pub fn load(&self) -> XskStat {
+        XskStat {
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            rx_wakeup: self.rx_wakeup.load(Ordering::Relaxed),
+            rx_batch: self.rx_batch.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            tx_wakeup: self.tx_wakeup.load(Ordering::Relaxed),
+            tx_batch: self.tx_batch.load(Ordering::Relaxed),
+            tx_inflight_bytes: self.tx_inflight_bytes.load(Ordering::Relaxed),
         }
+    }
+}
 
-        let umem_accessor = SharedAccessorRef::new(Arc::new(Mutex::new(SharedAccessor::new(
-            umem.clone(),
-            fill_queue,
-            completion_queue,
-        )?)));
-
-        // TODO: validate that the RX ring is fulfilled
-        umem_accessor.fill(config.rx_size as usize).unwrap();
+impl StatsSource for XskStatHandle {
+    fn stats_id(&self) -> String {
+        "socket".to_string()
+    }
 
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.load().visit_stats(visit)
+    }
+}
+
+impl XskStat {
+    pub fn snapshot(&self) -> XskStatSnapshot {
+        XskStatSnapshot {
+            stat: self.clone(),
+            taken_at: Instant::now(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct XskStatSnapshot {
+    pub stat: XskStat,
+    pub taken_at: Instant,
+}
+
+impl XskStatSnapshot {
+    // counters accumulated since prev, gauges keep their current values
+    pub fn delta(&self, prev: &XskStatSnapshot) -> XskStatDelta {
+        let (now, prev_stat) = (&self.stat, &prev.stat);
+        XskStatDelta {
+            elapsed: self.taken_at.saturating_duration_since(prev.taken_at),
+            stat: XskStat {
+                rx_packets: now.rx_packets.saturating_sub(prev_stat.rx_packets),
+                rx_bytes: now.rx_bytes.saturating_sub(prev_stat.rx_bytes),
+                rx_wakeup: now.rx_wakeup.saturating_sub(prev_stat.rx_wakeup),
+                rx_batch: now.rx_batch.saturating_sub(prev_stat.rx_batch),
+                tx_packets: now.tx_packets.saturating_sub(prev_stat.tx_packets),
+                tx_bytes: now.tx_bytes.saturating_sub(prev_stat.tx_bytes),
+                tx_wakeup: now.tx_wakeup.saturating_sub(prev_stat.tx_wakeup),
+                tx_batch: now.tx_batch.saturating_sub(prev_stat.tx_batch),
+                tx_inflight_bytes: now.tx_inflight_bytes,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct XskStatDelta {
+    pub elapsed: Duration,
+    pub stat: XskStat,
+}
+
+impl XskStatDelta {
+    fn rate(&self, value: u64) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds > 0.0 {
+            value as f64 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    pub fn rx_pps(&self) -> f64 {
+        self.rate(self.stat.rx_packets)
+    }
+
+    pub fn rx_bps(&self) -> f64 {
+        self.rate(self.stat.rx_bytes * 8)
+    }
+
+    pub fn tx_pps(&self) -> f64 {
+        self.rate(self.stat.tx_packets)
+    }
+
+    pub fn tx_bps(&self) -> f64 {
+        self.rate(self.stat.tx_bytes * 8)
+    }
+
+    pub fn rx_wakeups_per_sec(&self) -> f64 {
+        self.rate(self.stat.rx_wakeup)
+    }
+
+    pub fn tx_wakeups_per_sec(&self) -> f64 {
+        self.rate(self.stat.tx_wakeup)
+    }
+
+    // packets per non-empty batch
+    pub fn avg_rx_batch(&self) -> f64 {
+        if self.stat.rx_batch == 0 {
+            0.0
+        } else {
+            self.stat.rx_packets as f64 / self.stat.rx_batch as f64
+        }
+    }
+
+    pub fn avg_tx_batch(&self) -> f64 {
+        if self.stat.tx_batch == 0 {
+            0.0
+        } else {
+            self.stat.tx_packets as f64 / self.stat.tx_batch as f64
+        }
+    }
+}
+
+impl std::fmt::Display for XskStatDelta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "rx: {:.0} pps, {:.0} bps, {:.0} wakeups/s, batch {:.1}; \
+             tx: {:.0} pps, {:.0} bps, {:.0} wakeups/s, batch {:.1}",
+            self.rx_pps(),
+            self.rx_bps(),
+            self.rx_wakeups_per_sec(),
+            self.avg_rx_batch(),
+            self.tx_pps(),
+            self.tx_bps(),
+            self.tx_wakeups_per_sec(),
+            self.avg_tx_batch()
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DeadlineSendStat {
+    pub sent: usize,
+    pub attempts: usize,
+    pub elapsed: Duration,
+    // time left before the deadline when we stop submitting, zero if we overshot it
+    pub slack: Duration,
+    pub overshoot: Duration,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NapiBinding {
+    // the kernel has not associated any NAPI context with the socket yet,
+    // i.e., no packet has been received
+    Unbound,
+    Bound(u32),
+    Mismatch { expected: u32, actual: u32 },
+}
+
+struct TxWatchdog {
+    timeout: Duration,
+    // the last time a descriptor is completed or none is outstanding
+    progress_at: Instant,
+    reported: bool,
+}
+
+impl TxWatchdog {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            progress_at: Instant::now(),
+            reported: false,
+        }
+    }
+}
+
+pub struct XskSocket<M: AccessorRef> {
+    inner: *mut xsk_socket,
+    ifname: String,
+    queue_index: u32,
+    // released after the socket is deleted
+    queue_claim: Option<QueueClaim>,
+    umem_accessor: M,
+    rx: Pin<Box<RxQueue>>,
+    tx: Pin<Box<TxQueue>>,
+    schedule_mode: ScheduleMode,
+    need_wakeup: NeedWakeup,
+    // wakeups are left to flush_tx and kick_rx
+    manual_wakeup: bool,
+    expected_napi_id: Option<u32>,
+    max_tx_inflight_bytes: Option<u64>,
+    rx_timestamp: Option<RxTimestamp>,
+    // lengths of in-flight TX frames in submission order, completions come back in order
+    tx_inflight_lens: VecDeque<u32>,
+    // leading fragments of a multi-buffer packet whose last fragment isn't received yet
+    rx_partial: Vec<RxFrame<M>>,
+    // any packet received, unlike stat.rx_packets also without the trace feature
+    has_received: bool,
+    warnings: Warnings,
+    hooks: Option<Box<dyn Hooks>>,
+    stat_handle: Option<Arc<XskStatHandle>>,
+    tx_watchdog: Option<TxWatchdog>,
+    config: xsk_socket_config,
+    xdp_mode: XDPMode,
+    // detach the default program once no socket of this process uses the interface
+    unload_default_prog: bool,
+    xsks_map: Option<Arc<BpfMap>>,
+    // entries added by register_in_xskmap, updated again on rebind
+    xskmap_entries: Vec<(BpfMap, u32)>,
+    // ifname/queue, the parent of the spans of the socket
+    id: String,
+    span: Span,
+    pub stat: XskStat,
+}
+
+unsafe impl<M> Send for XskSocket<M> where M: AccessorRef {}
+
+impl XskSocket<SharedAccessorRef> {
+    fn new(
+        ifname: &str,
+        queue_index: u32,
+        umem: <SharedAccessorRef as AccessorRef>::UMemRef,
+        mut config: xsk_socket_config,
+        mode: XDPMode,
+        schedule_mode: ScheduleMode,
+        cache: SharedCacheConfig,
+    ) -> Result<Self, CamelliaError> {
+        let mut raw_socket: *mut xsk_socket = std::ptr::null_mut();
+        let mut rx_queue = Box::pin(RxQueue::default());
+        let mut tx_queue = Box::pin(TxQueue::default());
+        let mut fill_queue = Box::pin(FillQueue::default());
+        let mut completion_queue = Box::pin(CompletionQueue::default());
+
+        let ifname = CString::new(ifname).unwrap();
+        log::info!(
+            "create AF_XDP socket on device {:?} (queue {})",
+            ifname,
+            queue_index
+        );
+
+        let context = LibraryContext::enter(ifname.to_str().unwrap(), Some(queue_index));
+        let xdp_mode = create_in_mode(mode, &mut config, |config| unsafe {
+            xsk_socket__create_shared(
+                &mut raw_socket,
+                ifname.as_ptr(),
+                queue_index,
+                umem.lock().unwrap().inner(),
+                &mut rx_queue.inner,
+                &mut tx_queue.inner,
+                &mut fill_queue.0,
+                &mut completion_queue.0,
+                config,
+            )
+        })
+        .map_err(|errno| {
+            socket_create_error(
+                "xsk_socket__create_shared",
+                &ifname,
+                queue_index,
+                &config,
+                errno,
+            )
+        })?;
+        drop(context);
+
+        let umem_accessor = SharedAccessorRef::new(sync::Arc::new(Mutex::new(
+            SharedAccessor::new(umem.clone(), fill_queue, completion_queue, cache)?,
+        )));
+
+        // TODO: validate that the RX ring is fulfilled
+        umem_accessor.fill(config.rx_size as usize).unwrap();
+
+        let id = format!("{}/{}", ifname.to_string_lossy(), queue_index);
+        let span = tracing::info_span!("xsk", id = %id);
         Ok(XskSocket {
             inner: raw_socket,
+            ifname: ifname.into_string().unwrap(),
+            queue_index,
+            queue_claim: None,
             umem_accessor,
             rx: rx_queue,
             tx: tx_queue,
             schedule_mode,
+            need_wakeup: NeedWakeup::default(),
+            manual_wakeup: false,
+            expected_napi_id: None,
+            max_tx_inflight_bytes: None,
+            rx_timestamp: None,
+            tx_inflight_lens: VecDeque::new(),
+            rx_partial: Vec::new(),
+            has_received: false,
+            warnings: Warnings::default(),
+            hooks: None,
+            stat_handle: None,
+            tx_watchdog: None,
+            config,
+            xdp_mode,
+            unload_default_prog: false,
+            xsks_map: None,
+            xskmap_entries: Vec::new(),
+            id,
+            span,
             stat: XskStat::default(),
         })
     }
@@ -421,7 +1413,8 @@ impl XskSocket<DedicatedAccessorRef> {
         ifname: &str,
         queue_index: u32,
         umem: <DedicatedAccessorRef as AccessorRef>::UMemRef,
-        config: xsk_socket_config,
+        mut config: xsk_socket_config,
+        mode: XDPMode,
         schedule_mode: ScheduleMode,
     ) -> Result<Self, CamelliaError> {
         let mut raw_socket: *mut xsk_socket = std::ptr::null_mut();
@@ -435,41 +1428,293 @@ impl XskSocket<DedicatedAccessorRef> {
             queue_index
         );
 
-        unsafe {
-            match xsk_socket__create(
+        let context = LibraryContext::enter(ifname.to_str().unwrap(), Some(queue_index));
+        let xdp_mode = create_in_mode(mode, &mut config, |config| unsafe {
+            xsk_socket__create(
                 &mut raw_socket,
                 ifname.as_ptr(),
                 queue_index,
                 umem.inner() as *mut _,
                 &mut rx_queue.inner,
                 &mut tx_queue.inner,
-                &config,
-            ) {
-                0 => {}
-                errno => {
-                    return Err(Errno::from_raw(-errno).into());
-                }
-            }
-        }
+                config,
+            )
+        })
+        .map_err(|errno| {
+            socket_create_error("xsk_socket__create", &ifname, queue_index, &config, errno)
+        })?;
+        drop(context);
 
         let umem_accessor: DedicatedAccessorRef = umem.into();
         umem_accessor.fill(config.rx_size as usize).unwrap();
 
+        let id = format!("{}/{}", ifname.to_string_lossy(), queue_index);
+        let span = tracing::info_span!("xsk", id = %id);
         Ok(XskSocket {
             inner: raw_socket,
+            ifname: ifname.into_string().unwrap(),
+            queue_index,
+            queue_claim: None,
             umem_accessor,
             rx: rx_queue,
             tx: tx_queue,
             schedule_mode,
+            need_wakeup: NeedWakeup::default(),
+            manual_wakeup: false,
+            expected_napi_id: None,
+            max_tx_inflight_bytes: None,
+            rx_timestamp: None,
+            tx_inflight_lens: VecDeque::new(),
+            rx_partial: Vec::new(),
+            has_received: false,
+            warnings: Warnings::default(),
+            hooks: None,
+            stat_handle: None,
+            tx_watchdog: None,
+            config,
+            xdp_mode,
+            unload_default_prog: false,
+            xsks_map: None,
+            xskmap_entries: Vec::new(),
+            id,
+            span,
             stat: XskStat::default(),
         })
     }
+
+    // Re-creates the kernel socket on the same UMem, e.g., after DeviceDown once the
+    // interface is up again. Frames owned by the application stay valid, chunks held by
+    // the rings are reclaimed and packets in the TX ring are dropped. The old socket is
+    // deleted first, if the new one can't be created the socket stays unbound and fails
+    // with DeviceDown until a later rebind succeeds.
+    pub fn rebind(&mut self) -> Result<(), CamelliaError> {
+        match interface_flags(&self.ifname) {
+            None => {
+                return Err(CamelliaError::InterfaceNotFound {
+                    name: self.ifname.clone(),
+                })
+            }
+            Some(flags) if flags & libc::IFF_UP as u32 == 0 => {
+                return Err(self.device_down());
+            }
+            Some(_) => {}
+        }
+
+        // the rings of a socket left unbound by a failed rebind are reclaimed already
+        let (mut received, mut sent) = (Vec::new(), Vec::new());
+        if self.is_bound() {
+            let (rx, tx) = (&self.rx.inner, &self.tx.inner);
+            received =
+                unsafe { pending_entries::<xdp_desc>(rx.producer, rx.consumer, rx.ring, rx.mask) }
+                    .iter()
+                    .map(|desc| desc.addr)
+                    .collect();
+            sent =
+                unsafe { pending_entries::<xdp_desc>(tx.producer, tx.consumer, tx.ring, tx.mask) }
+                    .iter()
+                    .map(|desc| desc.addr)
+                    .collect();
+
+            unsafe { xsk_socket__delete(self.inner) };
+            self.inner = std::ptr::null_mut();
+            self.rx = Box::pin(RxQueue::default());
+            self.tx = Box::pin(TxQueue::default());
+            self.tx_inflight_lens.clear();
+            self.stat.tx_inflight_bytes = 0;
+            // the rest of a partial packet is gone with the old RX ring
+            self.rx_partial.clear();
+        }
+
+        let lost = self.umem_accessor.borrow_mut().reregister(received, sent)?;
+        if lost > 0 {
+            log::warn!("{} chunks held by the driver are lost by rebinding", lost);
+        }
+
+        let ifname = CString::new(self.ifname.as_str()).unwrap();
+        let umem_inner = self.umem_accessor.borrow().inner();
+        let mut raw_socket: *mut xsk_socket = std::ptr::null_mut();
+        // the rings are installed only with the socket they belong to
+        let mut rx = Box::pin(RxQueue::default());
+        let mut tx = Box::pin(TxQueue::default());
+        let (rx_queue, tx_queue) = (&mut rx.inner, &mut tx.inner);
+        create_in_mode(self.xdp_mode, &mut self.config, |config| unsafe {
+            xsk_socket__create(
+                &mut raw_socket,
+                ifname.as_ptr(),
+                self.queue_index,
+                umem_inner,
+                rx_queue,
+                tx_queue,
+                config,
+            )
+        })
+        .map_err(|errno| {
+            socket_create_error(
+                "xsk_socket__create",
+                &ifname,
+                self.queue_index,
+                &self.config,
+                errno,
+            )
+        })?;
+        self.inner = raw_socket;
+        self.rx = rx;
+        self.tx = tx;
+        // the kernel removes closed sockets from the map
+        self.update_xsks_map()?;
+        for (map, key) in &self.xskmap_entries {
+            map.update(key, &self.as_raw_fd())?;
+        }
+
+        self.umem_accessor.fill(self.config.rx_size as usize)?;
+        Ok(())
+    }
 }
 
 impl<M> XskSocket<M>
 where
     M: AccessorRef,
 {
+    pub fn ifname(&self) -> &str {
+        &self.ifname
+    }
+
+    // false while a failed rebind left the socket without a kernel socket and rings
+    pub fn is_bound(&self) -> bool {
+        !self.inner.is_null()
+    }
+
+    fn ensure_bound(&self) -> Result<(), CamelliaError> {
+        if self.is_bound() {
+            Ok(())
+        } else {
+            Err(self.device_down())
+        }
+    }
+
+    fn update_xsks_map(&self) -> Result<(), CamelliaError> {
+        let Some(map) = &self.xsks_map else {
+            return Ok(());
+        };
+        check_ret("xsk_socket__update_xskmap", unsafe {
+            xsk_socket__update_xskmap(self.inner, map.as_raw_fd())
+        })
+    }
+
+    // Registers the socket at key of the XSKMAP of a program loaded elsewhere, e.g., with
+    // no_default_prog. The kernel removes the socket from the map once it is closed, an
+    // entry replaced by another socket meanwhile stays.
+    pub fn register_in_xskmap(
+        &mut self,
+        map_fd: BorrowedFd,
+        key: u32,
+    ) -> Result<(), CamelliaError> {
+        self.ensure_bound()?;
+        let map = BpfMap::from_fd(map_fd)?;
+        map.update(&key, &self.as_raw_fd())?;
+        self.xskmap_entries.push((map, key));
+        Ok(())
+    }
+
+    // the mode XDPMode::Auto resolved to
+    pub fn xdp_mode(&self) -> XDPMode {
+        self.xdp_mode
+    }
+
+    pub fn queue_index(&self) -> u32 {
+        self.queue_index
+    }
+
+    // Identifies the socket among others of the process, e.g., eth0/3
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    // The span of the socket, the parent of spans of its operations with the trace
+    // feature. Entering it tags events of the application with the socket as well.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    pub fn warnings(&mut self) -> &mut Warnings {
+        &mut self.warnings
+    }
+
+    pub fn napi_id(&self) -> Result<u32, CamelliaError> {
+        // libc and nix don't give us this option yet
+        const SO_INCOMING_NAPI_ID: c_int = 56;
+
+        self.ensure_bound()?;
+        let mut napi_id: u32 = 0;
+        let mut len = std::mem::size_of::<u32>() as libc::socklen_t;
+
+        unsafe {
+            Errno::result(libc::getsockopt(
+                self.as_fd().as_raw_fd(),
+                SOL_SOCKET,
+                SO_INCOMING_NAPI_ID,
+                &mut napi_id as *mut u32 as *mut c_void,
+                &mut len,
+            ))?;
+        }
+
+        Ok(napi_id)
+    }
+
+    // CPUs serving the NAPI instance of the socket, known once a packet is received
+    pub fn napi_cpus(&self) -> Result<Vec<usize>, CamelliaError> {
+        let napi_id = self.napi_id()?;
+        if napi_id == 0 {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "no NAPI context is associated with the socket on {} (queue {}) yet",
+                self.ifname, self.queue_index
+            )));
+        }
+        napi::napi_cpus(&self.ifname, self.queue_index, napi_id)
+    }
+
+    // Pins the calling thread to the CPUs serving the NAPI instance of the socket, so
+    // that busy polling and softirq processing share caches with the application.
+    pub fn pin_to_napi_cpus(&self) -> Result<Vec<usize>, CamelliaError> {
+        let cpus = self.napi_cpus()?;
+        napi::pin_current_thread(&cpus)?;
+        Ok(cpus)
+    }
+
+    pub fn verify_napi_binding(&mut self) -> Result<NapiBinding, CamelliaError> {
+        let actual = self.napi_id()?;
+
+        if actual == 0 {
+            if self.schedule_mode == ScheduleMode::BusyPolling && self.has_received {
+                log::warn!(
+                    "busy polling is enabled on {} (queue {}) but no NAPI context is associated with the socket",
+                    self.ifname,
+                    self.queue_index
+                );
+            }
+            return Ok(NapiBinding::Unbound);
+        }
+
+        match self.expected_napi_id {
+            Some(expected) if expected != actual => {
+                log::warn!(
+                    "RX of {} (queue {}) is handled by NAPI {}, but NAPI {} is expected",
+                    self.ifname,
+                    self.queue_index,
+                    actual,
+                    expected
+                );
+                Ok(NapiBinding::Mismatch { expected, actual })
+            }
+            Some(_) => Ok(NapiBinding::Bound(actual)),
+            None => {
+                // pin the first NAPI context we observe, later migrations are reported
+                self.expected_napi_id = Some(actual);
+                Ok(NapiBinding::Bound(actual))
+            }
+        }
+    }
+
     pub fn recv(&mut self) -> Result<Option<RxFrame<M>>, CamelliaError> {
         let mut received = self.recv_bulk(1)?;
         assert!(received.len() <= 1);
@@ -477,75 +1722,283 @@ where
     }
 
     pub fn recv_bulk(&mut self, size: usize) -> Result<Vec<RxFrame<M>>, CamelliaError> {
+        let mut frames = Vec::new();
+        self.recv_bulk_into(&mut frames, size)?;
+        Ok(frames)
+    }
+
+    // Appends up to size received frames to frames, so that one buffer serves every batch.
+    // Returns the number of frames received.
+    pub fn recv_bulk_into(
+        &mut self,
+        frames: &mut Vec<RxFrame<M>>,
+        size: usize,
+    ) -> Result<usize, CamelliaError> {
+        socket_span!(self, "recv");
+        self.ensure_bound()?;
         let mut start_index = 0;
 
         let received: u32 =
             unsafe { xsk_ring_cons__peek(&mut self.rx.inner, size as u32, &mut start_index) };
 
         if received == 0 {
-            match self.schedule_mode {
-                ScheduleMode::Cooperative | ScheduleMode::Legacy => {
-                    if M::need_wakeup(&self.umem_accessor) {
-                        self.stat.rx_wakeup += 1;
-                        wakeup_rx(self.as_fd())?;
-                    }
-                }
-                ScheduleMode::BusyPolling => {
-                    self.stat.rx_wakeup += 1;
-                    wakeup_rx(self.as_fd())?;
-                }
+            if !self.manual_wakeup {
+                self.kick_rx()?;
             }
         } else {
-            self.stat.rx_batch += 1;
+            hot_stat!(self, rx_batch += 1);
         }
 
         assert!((received as usize) <= size);
 
-        let frames = (0..received as usize)
+        self.consume_rx(start_index, received, frames)?;
+        Ok(received as usize)
+    }
+
+    // Receives up to size descriptors and groups the fragments of multi-buffer packets,
+    // each packet is a Vec of its frames in order. Fragments of a packet not complete yet
+    // are kept until its last fragment arrives.
+    pub fn recv_packets(&mut self, size: usize) -> Result<Vec<Vec<RxFrame<M>>>, CamelliaError> {
+        socket_span!(self, "recv_packets");
+        self.ensure_bound()?;
+        let mut start_index = 0;
+
+        let received: u32 =
+            unsafe { xsk_ring_cons__peek(&mut self.rx.inner, size as u32, &mut start_index) };
+
+        if received == 0 {
+            if !self.manual_wakeup {
+                self.kick_rx()?;
+            }
+        } else {
+            hot_stat!(self, rx_batch += 1);
+        }
+
+        let continued: Vec<bool> = (0..received)
+            .map(|i| unsafe {
+                let rx_desp = xsk_ring_cons__rx_desc(&self.rx.inner, start_index + i);
+                (*rx_desp).options & XDP_PKT_CONTD != 0
+            })
+            .collect();
+
+        let mut frames = Vec::with_capacity(received as usize);
+        self.consume_rx(start_index, received, &mut frames)?;
+
+        let mut packets = Vec::new();
+        for (frame, continued) in frames.into_iter().zip(continued) {
+            self.rx_partial.push(frame);
+            if !continued {
+                packets.push(std::mem::take(&mut self.rx_partial));
+            }
+        }
+        Ok(packets)
+    }
+
+    pub fn frames(&mut self, batch_size: usize) -> Frames<'_, Self> {
+        Frames::new(self, batch_size)
+    }
+
+    // nothing is pending while the socket is unbound
+    pub fn recv_peek_bulk(&mut self, size: usize) -> Vec<RxDescView<'_>> {
+        if !self.is_bound() {
+            return Vec::new();
+        }
+        let mut start_index = 0;
+
+        let peeked: u32 =
+            unsafe { xsk_ring_cons__peek(&mut self.rx.inner, size as u32, &mut start_index) };
+
+        let views = (0..peeked)
             .map(|i| {
-                let (addr, len) = unsafe {
-                    let rx_desp = xsk_ring_cons__rx_desc(&self.rx.inner, start_index + i as u32);
-                    ((*rx_desp).addr, (*rx_desp).len)
+                let (addr, len, options) = unsafe {
+                    let rx_desp = xsk_ring_cons__rx_desc(&self.rx.inner, start_index + i);
+                    (
+                        decode_desc_addr((*rx_desp).addr),
+                        (*rx_desp).len,
+                        (*rx_desp).options,
+                    )
                 };
 
-                self.stat.rx_bytes += len as u64;
-                let chunk = M::extract_recv(&self.umem_accessor, addr);
-                RxFrame::from_chunk(
-                    chunk,
-                    self.umem_accessor.clone(),
-                    addr as usize,
-                    len as usize,
-                )
+                // the chunk stays in the RX ring
+                let address = M::translate(&self.umem_accessor, addr);
+
+                RxDescView {
+                    xdp_address: addr,
+                    options,
+                    data: unsafe { std::slice::from_raw_parts(address as *const u8, len as usize) },
+                }
             })
             .collect();
 
+        // rewind the cached consumer index so that descriptors are still pending
+        unsafe {
+            xsk_ring_cons__cancel(&mut self.rx.inner, peeked);
+        }
+
+        views
+    }
+
+    pub fn recv_commit(&mut self, size: usize) -> Result<Vec<RxFrame<M>>, CamelliaError> {
+        self.ensure_bound()?;
+        let mut start_index = 0;
+
+        let received: u32 =
+            unsafe { xsk_ring_cons__peek(&mut self.rx.inner, size as u32, &mut start_index) };
+
+        if received > 0 {
+            hot_stat!(self, rx_batch += 1);
+        }
+
+        let mut frames = Vec::new();
+        self.consume_rx(start_index, received, &mut frames)?;
+        Ok(frames)
+    }
+
+    fn consume_rx(
+        &mut self,
+        start_index: u32,
+        received: u32,
+        frames: &mut Vec<RxFrame<M>>,
+    ) -> Result<(), CamelliaError> {
+        let mut bytes = 0;
+        let first = frames.len();
+        frames.extend((0..received as usize).map(|i| {
+            let (addr, len) = unsafe {
+                let rx_desp = xsk_ring_cons__rx_desc(&self.rx.inner, start_index + i as u32);
+                (decode_desc_addr((*rx_desp).addr), (*rx_desp).len)
+            };
+
+            bytes += len as u64;
+            let chunk = M::extract_recv(&self.umem_accessor, addr);
+            RxFrame::from_chunk(
+                chunk,
+                self.umem_accessor.clone(),
+                addr as usize,
+                len as usize,
+            )
+        }));
+
         unsafe {
             xsk_ring_cons__release(&mut self.rx.inner, received);
         }
+        if let Some(timestamp) = &self.rx_timestamp {
+            timestamp.stamp(&mut frames[first..]);
+        }
 
-        self.stat.rx_packets += received as u64;
+        hot_stat!(self, rx_packets += received as u64, rx_bytes += bytes);
+        self.has_received |= received > 0;
 
         // TODO: add an option controlling whether to fill the umem eagerly
-        let filled = M::fill(&self.umem_accessor, received as usize)?;
+        let filled = {
+            socket_span!(self, "fill");
+            M::fill(&self.umem_accessor, received as usize)?
+        };
+
+        if let Some(hooks) = self.hooks.as_mut() {
+            if received > 0 {
+                hooks.on_rx_batch(received as usize, bytes);
+            }
+            hooks.on_fill(received as usize, filled);
+        }
 
         if filled < (received as usize) {
-            log::warn!("fill failed, filled: {}, received: {}", filled, received);
+            self.warnings.report(Warning::PartialFill {
+                requested: received as usize,
+                filled,
+            });
         }
 
-        event!(
-            Level::TRACE,
-            event = "recv",
-            frames = received,
-            filled = filled
-        );
+        #[cfg(feature = "trace")]
+        {
+            event!(
+                Level::TRACE,
+                event = "recv",
+                frames = received,
+                filled = filled
+            );
+            self.publish_stat();
+        }
 
-        Ok(frames)
+        Ok(())
     }
 
     pub fn allocate(&mut self, n: usize) -> Result<Vec<AppFrame<M>>, CamelliaError> {
         AccessorRef::allocate(&self.umem_accessor, n)
     }
 
+    // Waits up to timeout for chunks to come back, e.g., from completed TX descriptors,
+    // instead of failing at once. The last UMemExhausted is returned on timeout. Between
+    // attempts, the thread backs off like an idle spin_loop, from spinning to parking.
+    pub fn allocate_blocking(
+        &mut self,
+        n: usize,
+        timeout: Duration,
+    ) -> Result<Vec<AppFrame<M>>, CamelliaError> {
+        let deadline = Instant::now() + timeout;
+        let backoff = Backoff::default();
+        let mut spin_stat = SpinStat::default();
+        let mut idle_rounds = 0u32;
+        loop {
+            match self.allocate_recycling(n) {
+                Err(e @ CamelliaError::UMemExhausted { .. })
+                    if Instant::now() >= deadline || !self.may_allocate(n) =>
+                {
+                    return Err(e)
+                }
+                Err(CamelliaError::UMemExhausted { .. }) => {
+                    backoff.idle(idle_rounds, &mut spin_stat);
+                    idle_rounds = idle_rounds.saturating_add(1);
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // The counterpart of allocate_blocking for async code, it yields to the executor
+    // between attempts as completions don't wake up any task. The task is woken again at
+    // once, so it busy-polls the executor until chunks come back and never times out,
+    // bound the wait with a timer of the executor, e.g., tokio::time::timeout.
+    pub async fn allocate_async(&mut self, n: usize) -> Result<Vec<AppFrame<M>>, CamelliaError> {
+        loop {
+            match self.allocate_recycling(n) {
+                Err(CamelliaError::UMemExhausted { .. }) if self.may_allocate(n) => {
+                    YieldNow(false).await
+                }
+                result => return result,
+            }
+        }
+    }
+
+    // recycles completed chunks and pushes the TX ring on if the UMem is exhausted
+    fn allocate_recycling(&mut self, n: usize) -> Result<Vec<AppFrame<M>>, CamelliaError> {
+        match self.allocate(n) {
+            Err(CamelliaError::UMemExhausted { .. }) => {
+                self.recycle_tx()?;
+                if M::tx_inflight(&self.umem_accessor) > 0 && self.tx_wakeup_required() {
+                    self.stat.tx_wakeup += 1;
+                    self.wakeup_tx()?;
+                }
+                self.allocate(n)
+            }
+            result => result,
+        }
+    }
+
+    // requests for more chunks than the UMem has never succeed
+    fn may_allocate(&self, n: usize) -> bool {
+        let stat = self.umem_stat();
+        n <= stat.free_chunks + stat.fill_ring + stat.tx_pending + stat.app_owned
+    }
+
+    pub fn umem_stat(&self) -> UMemStat {
+        M::umem_stat(&self.umem_accessor)
+    }
+
+    // the accessor frames of the socket are allocated from, e.g., for TxFrame::copy_from_slice
+    pub fn umem(&self) -> &M {
+        &self.umem_accessor
+    }
+
     pub fn send<T>(&mut self, frame: T) -> Result<Option<T>, CamelliaError>
     where
         T: Into<TxFrame<M>>,
@@ -566,71 +2019,679 @@ where
         Iter: IntoIterator<Item = T>,
         Iter::IntoIter: ExactSizeIterator,
     {
-        let mut start_index = 0;
-        let mut remaining = Vec::new();
+        let mut iter = frames.into_iter();
+        let len = iter.len();
+        self.send_from(&mut iter, len)?;
+        Ok(iter.collect())
+    }
 
-        M::recycle(&self.umem_accessor)?;
+    // Sends frames from the front of frames, those left behind stay in it in order. Unlike
+    // send_bulk, no Vec is allocated for the remaining frames. Returns the number of frames
+    // taken out of frames.
+    pub fn send_bulk_drain(
+        &mut self,
+        frames: &mut Vec<TxFrame<M>>,
+    ) -> Result<usize, CamelliaError> {
+        let len = frames.len();
+        // taking frames from the back is cheap
+        frames.reverse();
+        let result = self.send_from(&mut std::iter::from_fn(|| frames.pop()), len);
+        frames.reverse();
+        result?;
+        Ok(len - frames.len())
+    }
 
-        let iter = frames.into_iter();
+    // takes frames out of the iterator only as long as they fit in the TX ring
+    fn send_from<T>(
+        &mut self,
+        frames: &mut impl Iterator<Item = T>,
+        len: usize,
+    ) -> Result<(), CamelliaError>
+    where
+        T: Into<TxFrame<M>>,
+    {
+        socket_span!(self, "send");
+        self.ensure_bound()?;
+        let mut start_index = 0;
 
-        let reserved_desp = unsafe {
-            xsk_ring_prod__reserve(&mut self.tx.inner, iter.len() as u32, &mut start_index)
-        };
+        self.recycle_tx()?;
+
+        let reserved_desp =
+            unsafe { xsk_ring_prod__reserve(&mut self.tx.inner, len as u32, &mut start_index) };
 
-        let actual_sent = min(reserved_desp, iter.len() as u32);
+        let actual_sent = min(reserved_desp, len as u32);
 
         if actual_sent > 0 {
-            self.stat.tx_batch += 1;
+            hot_stat!(self, tx_batch += 1);
         }
 
-        for (send_index, frame) in iter.enumerate() {
-            if (send_index as u32) < actual_sent {
-                let frame: TxFrame<M> = frame.into();
+        let mut written: u32 = 0;
+        let mut bytes = 0;
 
-                if !M::equal(frame.umem(), &self.umem_accessor) {
-                    return Err(CamelliaError::InvalidArgument(
-                        "Frame does not belong to this socket".to_string(),
-                    ));
-                }
+        for _ in 0..actual_sent {
+            let over_cap = self
+                .max_tx_inflight_bytes
+                .is_some_and(|max_bytes| self.stat.tx_inflight_bytes >= max_bytes);
+            if over_cap {
+                break;
+            }
 
-                unsafe {
-                    let tx_desc = xsk_ring_prod__tx_desc(
-                        &mut self.tx.inner,
-                        start_index + (send_index as u32),
-                    );
-                    (*tx_desc).addr = frame.xdp_address() as u64;
-                    (*tx_desc).len = frame.len() as u32;
-                    (*tx_desc).options = 0;
-                };
-                self.stat.tx_bytes += frame.len() as u64;
-                M::register_send(&self.umem_accessor, frame.take());
-            } else {
-                remaining.push(frame);
+            let Some(frame) = frames.next() else {
+                break;
+            };
+            let frame: TxFrame<M> = frame.into();
+
+            if !M::equal(frame.umem(), &self.umem_accessor) {
+                // the frame goes back to its own UMem when dropped
+                self.warnings.report(Warning::ForeignFrameRejected);
+                continue;
             }
+
+            unsafe {
+                let tx_desc = xsk_ring_prod__tx_desc(&mut self.tx.inner, start_index + written);
+                (*tx_desc).addr = frame.xdp_address() as u64;
+                (*tx_desc).len = frame.len() as u32;
+                (*tx_desc).options = 0;
+            };
+            written += 1;
+            bytes += frame.len() as u64;
+            self.stat.tx_inflight_bytes += frame.len() as u64;
+            self.tx_inflight_lens.push_back(frame.len() as u32);
+            M::register_send(&self.umem_accessor, frame.take());
+        }
+
+        // give back descriptors reserved for rejected frames
+        self.tx.inner.cached_prod -= actual_sent - written;
+
+        self.submit_tx(written, bytes)
+    }
+
+    // Sends the fragments of a multi-buffer packet, which go into the TX ring all
+    // together or not at all. The fragments are given back if the TX ring doesn't have
+    // room for all of them.
+    pub fn send_fragments(
+        &mut self,
+        fragments: Vec<TxFrame<M>>,
+    ) -> Result<Option<Vec<TxFrame<M>>>, CamelliaError> {
+        if fragments.is_empty() {
+            return Err(CamelliaError::InvalidArgument(
+                "a packet has at least one fragment".to_string(),
+            ));
+        }
+        if fragments.len() > 1 && self.config.bind_flags & XDP_USE_SG as u16 == 0 {
+            return Err(CamelliaError::InvalidArgument(
+                "packets of several fragments require multi-buffer, call enable_multi_buffer"
+                    .to_string(),
+            ));
         }
+        if fragments
+            .iter()
+            .any(|fragment| !M::equal(fragment.umem(), &self.umem_accessor))
+        {
+            return Err(CamelliaError::InvalidArgument(
+                "fragments must be allocated from the UMem of the socket".to_string(),
+            ));
+        }
+        self.ensure_bound()?;
+
+        self.recycle_tx()?;
 
-        self.stat.tx_packets += actual_sent as u64;
+        let over_cap = self
+            .max_tx_inflight_bytes
+            .is_some_and(|max_bytes| self.stat.tx_inflight_bytes >= max_bytes);
+        if over_cap {
+            return Ok(Some(fragments));
+        }
+
+        let len = fragments.len() as u32;
+        let mut start_index = 0;
+        let reserved = unsafe { xsk_ring_prod__reserve(&mut self.tx.inner, len, &mut start_index) };
+        if reserved < len {
+            return Ok(Some(fragments));
+        }
+
+        hot_stat!(self, tx_batch += 1);
+        let mut bytes = 0;
+        for (i, fragment) in fragments.into_iter().enumerate() {
+            unsafe {
+                let tx_desc = xsk_ring_prod__tx_desc(&mut self.tx.inner, start_index + i as u32);
+                (*tx_desc).addr = fragment.xdp_address() as u64;
+                (*tx_desc).len = fragment.len() as u32;
+                (*tx_desc).options = if i as u32 + 1 < len { XDP_PKT_CONTD } else { 0 };
+            };
+            bytes += fragment.len() as u64;
+            self.stat.tx_inflight_bytes += fragment.len() as u64;
+            self.tx_inflight_lens.push_back(fragment.len() as u32);
+            M::register_send(&self.umem_accessor, fragment.take());
+        }
+
+        self.submit_tx(len, bytes)?;
+        Ok(None)
+    }
+
+    fn submit_tx(&mut self, written: u32, bytes: u64) -> Result<(), CamelliaError> {
+        hot_stat!(self, tx_packets += written as u64, tx_bytes += bytes);
 
         unsafe {
-            xsk_ring_prod__submit(&mut self.tx.inner, actual_sent);
+            xsk_ring_prod__submit(&mut self.tx.inner, written);
+        }
+
+        if written > 0 {
+            if let Some(hooks) = self.hooks.as_mut() {
+                hooks.on_tx_batch(written as usize, bytes);
+            }
+        }
+
+        if !self.manual_wakeup {
+            self.flush_tx()?;
         }
+        #[cfg(feature = "trace")]
+        self.publish_stat();
+
+        Ok(())
+    }
+
+    // With manual wakeup, send_bulk and recv_bulk never wake the kernel up. Frames sent
+    // by several send_bulk calls are then kicked off by a single flush_tx.
+    pub fn set_manual_wakeup(&mut self, manual_wakeup: bool) {
+        self.manual_wakeup = manual_wakeup;
+    }
+
+    // wakes the TX queue up if the schedule mode requires it
+    pub fn flush_tx(&mut self) -> Result<(), CamelliaError> {
+        if self.tx_wakeup_required() {
+            self.stat.tx_wakeup += 1;
+            self.wakeup_tx()?;
+        }
+        Ok(())
+    }
+
+    // wakes the RX queue up if the schedule mode requires it, e.g., to process the fill
+    // ring in cooperative mode
+    pub fn kick_rx(&mut self) -> Result<(), CamelliaError> {
+        self.ensure_bound()?;
+        let required = match self.schedule_mode {
+            ScheduleMode::Legacy => M::need_wakeup(&self.umem_accessor),
+            ScheduleMode::Cooperative => !self.need_wakeup.rx || self.rx_needs_wakeup(),
+            ScheduleMode::BusyPolling => true,
+        };
+        if required {
+            self.wakeup_rx()?;
+        }
+        Ok(())
+    }
+
+    pub fn need_wakeup(&self) -> NeedWakeup {
+        self.need_wakeup
+    }
+
+    // the need_wakeup flag of the fill ring, set by the kernel when it waits for a wakeup
+    // to process the fill ring again
+    pub fn rx_needs_wakeup(&self) -> bool {
+        self.is_bound() && M::need_wakeup(&self.umem_accessor)
+    }
+
+    // the need_wakeup flag of the TX ring
+    pub fn tx_needs_wakeup(&self) -> bool {
+        self.is_bound() && unsafe { xsk_ring_prod__needs_wakeup(&self.tx.inner) != 0 }
+    }
 
+    fn tx_wakeup_required(&self) -> bool {
         match self.schedule_mode {
             // When cooperate schedule is disabled, we always need to wake up the TX queue
             // https://lore.kernel.org/bpf/20201130185205.196029-5-bjorn.topel@gmail.com/
-            ScheduleMode::Legacy | ScheduleMode::BusyPolling => {
+            ScheduleMode::Legacy | ScheduleMode::BusyPolling => true,
+            ScheduleMode::Cooperative => !self.need_wakeup.tx || self.tx_needs_wakeup(),
+        }
+    }
+
+    // A handle to read the counters of the socket from other threads, the counters are
+    // published after every RX and TX batch once a handle is requested. Publishing
+    // requires the trace feature.
+    pub fn stat_handle(&mut self) -> Arc<XskStatHandle> {
+        let stat = &self.stat;
+        self.stat_handle
+            .get_or_insert_with(|| {
+                let handle = XskStatHandle::default();
+                handle.store(stat);
+                Arc::new(handle)
+            })
+            .clone()
+    }
+
+    #[cfg(feature = "trace")]
+    fn publish_stat(&self) {
+        if let Some(handle) = &self.stat_handle {
+            handle.store(&self.stat);
+        }
+    }
+
+    // Stop receiving and wait for outstanding TX descriptors to complete so that their
+    // chunks go back to the UMem before the socket is torn down. Returns the number of
+    // descriptors still in flight when the timeout expires, their chunks are lost.
+    pub fn close(mut self, timeout: Duration) -> Result<usize, CamelliaError> {
+        // the rings of an unbound socket are reclaimed already
+        if !self.is_bound() {
+            return Ok(0);
+        }
+        let deadline = Instant::now() + timeout;
+
+        // return pending RX descriptors without refilling the fill ring
+        let mut start_index = 0;
+        let pending =
+            unsafe { xsk_ring_cons__peek(&mut self.rx.inner, u32::MAX, &mut start_index) };
+        for i in 0..pending {
+            let addr = decode_desc_addr(unsafe {
+                (*xsk_ring_cons__rx_desc(&self.rx.inner, start_index + i)).addr
+            });
+            let chunk = M::extract_recv(&self.umem_accessor, addr);
+            M::free(&self.umem_accessor, chunk);
+        }
+        unsafe {
+            xsk_ring_cons__release(&mut self.rx.inner, pending);
+        }
+
+        loop {
+            self.recycle_tx()?;
+            let inflight = M::tx_inflight(&self.umem_accessor);
+
+            if inflight == 0 {
+                break;
+            }
+
+            if Instant::now() >= deadline {
+                log::warn!(
+                    "close {} (queue {}) with {} TX descriptors in flight",
+                    self.ifname,
+                    self.queue_index,
+                    inflight
+                );
+                return Ok(inflight);
+            }
+
+            if self.tx_wakeup_required() {
                 self.stat.tx_wakeup += 1;
-                wakeup_tx(self.as_fd())?;
+                self.wakeup_tx()?;
             }
-            ScheduleMode::Cooperative => {
-                if unsafe { xsk_ring_prod__needs_wakeup(&self.tx.inner) != 0 } {
-                    self.stat.tx_wakeup += 1;
-                    wakeup_tx(self.as_fd())?;
-                }
+            std::hint::spin_loop();
+        }
+
+        Ok(0)
+    }
+
+    fn recycle_tx(&mut self) -> Result<(), CamelliaError> {
+        socket_span!(self, "recycle");
+        // nothing is in flight while unbound, and the rings of the UMem may be gone
+        if !self.is_bound() {
+            return Ok(());
+        }
+        let completed = M::recycle(&self.umem_accessor)?;
+        if completed > 0 {
+            if let Some(hooks) = self.hooks.as_mut() {
+                hooks.on_recycle(completed);
+            }
+        }
+        for len in self
+            .tx_inflight_lens
+            .drain(..completed.min(self.tx_inflight_lens.len()))
+        {
+            self.stat.tx_inflight_bytes -= len as u64;
+        }
+
+        let Some(watchdog) = self.tx_watchdog.as_mut() else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        if completed > 0 || self.tx_inflight_lens.is_empty() {
+            watchdog.progress_at = now;
+            watchdog.reported = false;
+        } else if !watchdog.reported && now - watchdog.progress_at >= watchdog.timeout {
+            // reported once per stall
+            watchdog.reported = true;
+            let stall = self.tx_stall(now);
+            self.warnings.report(Warning::TxStalled(stall));
+        }
+        Ok(())
+    }
+
+    fn tx_stall(&self, now: Instant) -> TxStall {
+        let progress_at = self
+            .tx_watchdog
+            .as_ref()
+            .map_or(now, |watchdog| watchdog.progress_at);
+        TxStall {
+            stalled_for: now - progress_at,
+            outstanding: self.tx_inflight_lens.len(),
+            outstanding_bytes: self.stat.tx_inflight_bytes,
+            tx_ring: RingState::of_prod(&self.tx.inner),
+            completion_ring: self.umem_accessor.completion_ring(),
+        }
+    }
+
+    // Recycles completed descriptors and returns the state of the rings if the watchdog
+    // sees no progress for longer than its timeout. Sockets which stop sending don't
+    // recycle on their own, so the watchdog relies on calls of this or send_bulk.
+    pub fn check_tx_stall(&mut self) -> Result<Option<TxStall>, CamelliaError> {
+        self.recycle_tx()?;
+        let now = Instant::now();
+        Ok(match &self.tx_watchdog {
+            Some(watchdog)
+                if !self.tx_inflight_lens.is_empty()
+                    && now - watchdog.progress_at >= watchdog.timeout =>
+            {
+                Some(self.tx_stall(now))
+            }
+            _ => None,
+        })
+    }
+
+    fn wakeup_rx(&mut self) -> Result<(), CamelliaError> {
+        self.ensure_bound()?;
+        self.stat.rx_wakeup += 1;
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.on_wakeup(WakeupDirection::Rx);
+        }
+        wakeup_rx(self.as_fd()).map_err(|e| match e.errno() {
+            Some(Errno::ENETDOWN) => self.device_down(),
+            Some(Errno::ENXIO) => self.device_removed(),
+            _ => e,
+        })
+    }
+
+    fn wakeup_tx(&mut self) -> Result<(), CamelliaError> {
+        self.ensure_bound()?;
+        if let Some(hooks) = self.hooks.as_mut() {
+            hooks.on_wakeup(WakeupDirection::Tx);
+        }
+        match try_wakeup_tx(self.as_fd()).map_err(|e| match e.errno() {
+            Some(Errno::ENXIO) => self.device_removed(),
+            _ => e,
+        })? {
+            Some(Errno::ENETDOWN) => return Err(self.device_down()),
+            // the kernel is still busy with the ring, e.g., sending from it
+            Some(Errno::EAGAIN | Errno::EBUSY) | None => {}
+            Some(errno) => self.warnings.report(Warning::WakeupFailed { errno }),
+        }
+        Ok(())
+    }
+
+    fn device_down(&self) -> CamelliaError {
+        CamelliaError::DeviceDown {
+            ifname: self.ifname.clone(),
+        }
+    }
+
+    fn device_removed(&self) -> CamelliaError {
+        CamelliaError::DeviceRemoved {
+            ifname: self.ifname.clone(),
+        }
+    }
+
+    /// Raw access to the RX ring, for experiments with descriptor fields camellia
+    /// doesn't model yet.
+    ///
+    /// # Safety
+    ///
+    /// The socket must be bound, see [`XskSocket::is_bound`]. The frame of every
+    /// descriptor released must be taken with [`RawRxRing::take_frame`] first, otherwise
+    /// its chunk is handed out twice.
+    pub unsafe fn rx_ring_raw(&mut self) -> RawRxRing<'_, M> {
+        RawRxRing { socket: self }
+    }
+
+    /// Raw access to the TX ring, the counterpart of [`XskSocket::rx_ring_raw`].
+    ///
+    /// # Safety
+    ///
+    /// The socket must be bound. Every descriptor submitted must be written by
+    /// [`RawTxRing::write_frame`] and keep
+    /// its address and a length within the chunk, otherwise the kernel reads buffers
+    /// owned by the application and their chunks are recycled while still in use.
+    pub unsafe fn tx_ring_raw(&mut self) -> RawTxRing<'_, M> {
+        RawTxRing { socket: self }
+    }
+
+    pub fn send_bulk_before<Iter, T>(
+        &mut self,
+        frames: Iter,
+        deadline: Instant,
+    ) -> Result<(Vec<T>, DeadlineSendStat), CamelliaError>
+    where
+        T: Into<TxFrame<M>>,
+        Iter: IntoIterator<Item = T>,
+    {
+        // Instant is backed by CLOCK_MONOTONIC on Linux
+        let start = Instant::now();
+        let mut remaining: Vec<T> = frames.into_iter().collect();
+        let total = remaining.len();
+        let mut attempts = 0;
+
+        while !remaining.is_empty() && Instant::now() < deadline {
+            let before = remaining.len();
+            attempts += 1;
+            remaining = self.send_bulk(remaining)?;
+
+            if remaining.len() == before {
+                // TX ring is full, wait for the kernel to drain it
+                std::hint::spin_loop();
             }
         }
 
-        Ok(remaining)
+        let finished = Instant::now();
+        let sent = total - remaining.len();
+
+        Ok((
+            remaining,
+            DeadlineSendStat {
+                sent,
+                attempts,
+                elapsed: finished - start,
+                slack: deadline.saturating_duration_since(finished),
+                overshoot: finished.saturating_duration_since(deadline),
+            },
+        ))
+    }
+}
+
+// ready on the second poll, so that other tasks run in between
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+// Raw access to the RX ring for descriptor fields camellia doesn't model yet, e.g., new
+// option flags. Descriptors are peeked and released as with the xsk_ring_cons__* calls,
+// bypassing statistics, hooks and refilling the fill ring.
+pub struct RawRxRing<'a, M: AccessorRef> {
+    socket: &'a mut XskSocket<M>,
+}
+
+impl<M> RawRxRing<'_, M>
+where
+    M: AccessorRef,
+{
+    // returns the index of the first and the number of descriptors available
+    pub fn peek(&mut self, max: u32) -> (u32, u32) {
+        let mut start_index = 0;
+        let peeked =
+            unsafe { xsk_ring_cons__peek(&mut self.socket.rx.inner, max, &mut start_index) };
+        (start_index, peeked)
+    }
+
+    // the descriptor as written by the kernel, decode_desc_addr gives the address of its
+    // packet in unaligned chunk mode
+    pub fn desc(&self, index: u32) -> &xdp_desc {
+        unsafe { &*xsk_ring_cons__rx_desc(&self.socket.rx.inner, index) }
+    }
+
+    pub fn data(&self, index: u32) -> &[u8] {
+        let desc = self.desc(index);
+        let address = M::translate(&self.socket.umem_accessor, decode_desc_addr(desc.addr));
+        unsafe { std::slice::from_raw_parts(address as *const u8, desc.len as usize) }
+    }
+
+    // takes the chunk of a peeked descriptor, exactly once before it is released
+    pub fn take_frame(&mut self, index: u32) -> RxFrame<M> {
+        let (addr, len) = {
+            let desc = self.desc(index);
+            (decode_desc_addr(desc.addr), desc.len)
+        };
+        let chunk = M::extract_recv(&self.socket.umem_accessor, addr);
+        RxFrame::from_chunk(
+            chunk,
+            self.socket.umem_accessor.clone(),
+            addr as usize,
+            len as usize,
+        )
+    }
+
+    pub fn release(&mut self, n: u32) {
+        unsafe { xsk_ring_cons__release(&mut self.socket.rx.inner, n) }
+    }
+
+    // gives back the last n peeked descriptors, they are peeked again next time
+    pub fn cancel(&mut self, n: u32) {
+        unsafe { xsk_ring_cons__cancel(&mut self.socket.rx.inner, n) }
+    }
+
+    // puts up to n chunks into the fill ring, returns how many are put
+    pub fn fill(&mut self, n: usize) -> Result<usize, CamelliaError> {
+        M::fill(&self.socket.umem_accessor, n)
+    }
+
+    pub fn state(&self) -> RingState {
+        RingState::of_cons(&self.socket.rx.inner)
+    }
+}
+
+// Raw access to the TX ring, the counterpart of RawRxRing. Frames are written through
+// write_frame so that their chunks are recycled once completed, the descriptors can be
+// adjusted before they are submitted.
+pub struct RawTxRing<'a, M: AccessorRef> {
+    socket: &'a mut XskSocket<M>,
+}
+
+impl<M> RawTxRing<'_, M>
+where
+    M: AccessorRef,
+{
+    // recycles completed descriptors and returns the index of the first and the number
+    // of descriptors reserved
+    pub fn reserve(&mut self, n: u32) -> Result<(u32, u32), CamelliaError> {
+        self.socket.recycle_tx()?;
+        let mut start_index = 0;
+        let reserved =
+            unsafe { xsk_ring_prod__reserve(&mut self.socket.tx.inner, n, &mut start_index) };
+        Ok((start_index, reserved))
+    }
+
+    // Fills a reserved descriptor with the frame, options are zero until changed through
+    // the returned descriptor. Frames of other UMems are returned.
+    pub fn write_frame(
+        &mut self,
+        index: u32,
+        frame: impl Into<TxFrame<M>>,
+    ) -> Result<&mut xdp_desc, TxFrame<M>> {
+        let frame: TxFrame<M> = frame.into();
+        let socket = &mut *self.socket;
+        if !M::equal(frame.umem(), &socket.umem_accessor) {
+            socket.warnings.report(Warning::ForeignFrameRejected);
+            return Err(frame);
+        }
+
+        let desc = unsafe { &mut *xsk_ring_prod__tx_desc(&mut socket.tx.inner, index) };
+        desc.addr = frame.xdp_address() as u64;
+        desc.len = frame.len() as u32;
+        desc.options = 0;
+        socket.stat.tx_inflight_bytes += frame.len() as u64;
+        socket.tx_inflight_lens.push_back(frame.len() as u32);
+        M::register_send(&socket.umem_accessor, frame.take());
+        Ok(desc)
+    }
+
+    // gives back the last n reserved descriptors, none of which is written
+    pub fn cancel(&mut self, n: u32) {
+        self.socket.tx.inner.cached_prod -= n;
+    }
+
+    pub fn submit(&mut self, n: u32) {
+        unsafe { xsk_ring_prod__submit(&mut self.socket.tx.inner, n) }
+    }
+
+    pub fn needs_wakeup(&self) -> bool {
+        unsafe { xsk_ring_prod__needs_wakeup(&self.socket.tx.inner) != 0 }
+    }
+
+    pub fn wakeup(&mut self) -> Result<(), CamelliaError> {
+        self.socket.stat.tx_wakeup += 1;
+        self.socket.wakeup_tx()
+    }
+
+    pub fn state(&self) -> RingState {
+        RingState::of_prod(&self.socket.tx.inner)
+    }
+}
+
+impl<M> Socket for XskSocket<M>
+where
+    M: AccessorRef,
+{
+    type Accessor = M;
+
+    fn recv_bulk(&mut self, size: usize) -> Result<Vec<RxFrame<M>>, CamelliaError> {
+        XskSocket::recv_bulk(self, size)
+    }
+
+    fn recv_bulk_into(
+        &mut self,
+        frames: &mut Vec<RxFrame<M>>,
+        size: usize,
+    ) -> Result<usize, CamelliaError> {
+        XskSocket::recv_bulk_into(self, frames, size)
+    }
+
+    fn send_bulk<Iter, T>(&mut self, frames: Iter) -> Result<Vec<T>, CamelliaError>
+    where
+        T: Into<TxFrame<M>>,
+        Iter: IntoIterator<Item = T>,
+        Iter::IntoIter: ExactSizeIterator,
+    {
+        XskSocket::send_bulk(self, frames)
+    }
+
+    fn send_bulk_drain(&mut self, frames: &mut Vec<TxFrame<M>>) -> Result<usize, CamelliaError> {
+        XskSocket::send_bulk_drain(self, frames)
+    }
+
+    fn allocate(&mut self, n: usize) -> Result<Vec<AppFrame<M>>, CamelliaError> {
+        XskSocket::allocate(self, n)
+    }
+
+    fn stat(&self) -> &XskStat {
+        &self.stat
+    }
+}
+
+impl<M> StatsSource for XskSocket<M>
+where
+    M: AccessorRef,
+{
+    fn stats_id(&self) -> String {
+        format!("xsk/{}", self.id)
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.stat.visit_stats(visit)
     }
 }
 
@@ -640,6 +2701,22 @@ where
 {
     fn drop(&mut self) {
         unsafe { xsk_socket__delete(self.inner) }
+
+        // released first, so that the socket itself doesn't count
+        let Some(claim) = self.queue_claim.take() else {
+            return;
+        };
+        let ifindex = claim.ifindex();
+        drop(claim);
+        if self.unload_default_prog && bound_queues(ifindex).is_empty() {
+            if let Err(e) = detach_xsk_default(&self.ifname, self.xdp_mode) {
+                log::warn!(
+                    "failed to unload the default XDP program of {}: {}",
+                    self.ifname,
+                    e
+                );
+            }
+        }
     }
 }
 
@@ -647,7 +2724,23 @@ impl<M> AsFd for XskSocket<M>
 where
     M: AccessorRef,
 {
+    // panics while the socket is unbound, it has no file descriptor then
     fn as_fd(&self) -> BorrowedFd {
+        assert!(
+            self.is_bound(),
+            "{} (queue {}) is unbound after a failed rebind",
+            self.ifname,
+            self.queue_index
+        );
         unsafe { BorrowedFd::borrow_raw(xsk_socket__fd(self.inner)) }
     }
 }
+
+impl<M> AsRawFd for XskSocket<M>
+where
+    M: AccessorRef,
+{
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        unsafe { xsk_socket__fd(self.inner) }
+    }
+}
@@ -0,0 +1,123 @@
+#[cfg(feature = "async")]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use crate::{error::CamelliaError, socket::Socket, umem::frame::RxFrame};
+
+// Received frames one by one, frames are still received in batches of batch_size
+// behind the scenes. Iteration stops when no frame is pending, while the stream waits
+// for frames to arrive.
+pub struct Frames<'a, S: Socket> {
+    socket: &'a mut S,
+    batch_size: usize,
+    // the current batch in reverse order, so that frames are popped from the back
+    batch: Vec<RxFrame<S::Accessor>>,
+}
+
+impl<'a, S: Socket> Frames<'a, S> {
+    pub fn new(socket: &'a mut S, batch_size: usize) -> Self {
+        assert!(batch_size > 0);
+        Self {
+            socket,
+            batch_size,
+            batch: Vec::with_capacity(batch_size),
+        }
+    }
+
+    // frames received but not yielded yet
+    pub fn buffered(&self) -> usize {
+        self.batch.len()
+    }
+
+    fn next_frame(&mut self) -> Result<Option<RxFrame<S::Accessor>>, CamelliaError> {
+        if self.batch.is_empty() {
+            self.socket
+                .recv_bulk_into(&mut self.batch, self.batch_size)?;
+            self.batch.reverse();
+        }
+        Ok(self.batch.pop())
+    }
+}
+
+// frames are owned through the batch Vec, nothing is pinned
+impl<S: Socket> Unpin for Frames<'_, S> {}
+
+impl<S: Socket> Iterator for Frames<'_, S> {
+    type Item = Result<RxFrame<S::Accessor>, CamelliaError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+// There is no reactor behind the socket, so the stream yields to the executor and
+// polls again when the RX ring is empty, like busy polling.
+#[cfg(feature = "async")]
+impl<S: Socket> futures_core::Stream for Frames<'_, S> {
+    type Item = Result<RxFrame<S::Accessor>, CamelliaError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.get_mut().next_frame().transpose() {
+            Some(item) => Poll::Ready(Some(item)),
+            None => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socket::mock::MockXskSocket;
+
+    #[test]
+    fn test_frames() {
+        let mut socket = MockXskSocket::new(64, 2048).unwrap();
+        for i in 0..10u8 {
+            socket.inject(&[i; 60]);
+        }
+
+        let mut frames = socket.frames(4);
+        let first = frames.next().unwrap().unwrap();
+        assert_eq!(first.raw_buffer()[0], 0);
+        assert_eq!(frames.buffered(), 3);
+
+        let rest: Vec<u8> = frames.map(|frame| frame.unwrap().raw_buffer()[0]).collect();
+        assert_eq!(rest, (1..10).collect::<Vec<_>>());
+        drop(first);
+
+        assert!(socket.frames(4).next().is_none());
+        assert_eq!(socket.stat().rx_packets, 10);
+        assert_eq!(socket.umem().free_chunks(), 64);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_frame_stream() {
+        use futures_core::Stream;
+        use std::{sync::Arc, task::Wake};
+
+        struct Noop;
+
+        impl Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        let waker = Arc::new(Noop).into();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut socket = MockXskSocket::new(64, 2048).unwrap();
+        socket.inject(&[7; 60]);
+
+        let mut frames = socket.frames(4);
+        match Pin::new(&mut frames).poll_next(&mut cx) {
+            Poll::Ready(Some(Ok(frame))) => assert_eq!(frame.raw_buffer()[0], 7),
+            _ => panic!("a frame is pending"),
+        }
+        assert!(Pin::new(&mut frames).poll_next(&mut cx).is_pending());
+    }
+}
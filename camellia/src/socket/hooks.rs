@@ -0,0 +1,24 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WakeupDirection {
+    Rx,
+    Tx,
+}
+
+// User-defined instrumentation invoked on the datapath of a socket, e.g., to sample batch
+// sizes into histograms. Hooks run inline, so they should be cheap. All hooks do nothing
+// by default.
+pub trait Hooks: Send {
+    // a non-empty batch is taken from the RX ring
+    fn on_rx_batch(&mut self, _frames: usize, _bytes: u64) {}
+
+    // a non-empty batch is submitted to the TX ring
+    fn on_tx_batch(&mut self, _frames: usize, _bytes: u64) {}
+
+    // chunks are put back to the fill ring after receiving
+    fn on_fill(&mut self, _requested: usize, _filled: usize) {}
+
+    // completed TX descriptors are taken from the completion ring
+    fn on_recycle(&mut self, _completed: usize) {}
+
+    fn on_wakeup(&mut self, _direction: WakeupDirection) {}
+}
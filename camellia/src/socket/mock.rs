@@ -0,0 +1,380 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use libxdp_sys::XSK_RING_CONS__DEFAULT_NUM_DESCS;
+
+use crate::{
+    error::CamelliaError,
+    socket::{
+        af_xdp::XskStat,
+        timestamp::RxTimestamp,
+        warnings::{Warning, Warnings},
+        Socket,
+    },
+    stats::{Stat, StatsSource},
+    umem::{
+        base::XDP_PACKET_HEADROOM,
+        frame::{AppFrame, RxFrame, TxFrame},
+        plain::{PlainAccessorRef, PlainUMem},
+        AccessorRef,
+    },
+};
+
+type Wire = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+// An in-memory socket for unit testing application logic without privileges or NICs.
+// Frames sent by one end of a pair are copied into the UMem of the other end, like a
+// NIC in copy mode would do.
+pub struct MockXskSocket {
+    umem_accessor: PlainAccessorRef,
+    rx: Wire,
+    tx: Wire,
+    rx_queue_size: usize,
+    warnings: Warnings,
+    rx_timestamp: Option<RxTimestamp>,
+    // frames dropped because the receiving queue is full
+    pub dropped: u64,
+    pub stat: XskStat,
+}
+
+impl MockXskSocket {
+    fn with_wires(
+        num_chunks: u32,
+        chunk_size: u32,
+        rx: Wire,
+        tx: Wire,
+    ) -> Result<Self, CamelliaError> {
+        Ok(Self {
+            umem_accessor: PlainAccessorRef::new(PlainUMem::new(num_chunks, chunk_size)?),
+            rx,
+            tx,
+            rx_queue_size: XSK_RING_CONS__DEFAULT_NUM_DESCS as usize,
+            warnings: Warnings::default(),
+            rx_timestamp: None,
+            dropped: 0,
+            stat: XskStat::default(),
+        })
+    }
+
+    // A standalone socket, packets are injected with inject() and transmitted
+    // frames are collected with take_transmitted()
+    pub fn new(num_chunks: u32, chunk_size: u32) -> Result<Self, CamelliaError> {
+        Self::with_wires(num_chunks, chunk_size, Wire::default(), Wire::default())
+    }
+
+    // Two sockets connected back to back
+    pub fn pair(num_chunks: u32, chunk_size: u32) -> Result<(Self, Self), CamelliaError> {
+        let left_to_right = Wire::default();
+        let right_to_left = Wire::default();
+
+        Ok((
+            Self::with_wires(
+                num_chunks,
+                chunk_size,
+                right_to_left.clone(),
+                left_to_right.clone(),
+            )?,
+            Self::with_wires(num_chunks, chunk_size, left_to_right, right_to_left)?,
+        ))
+    }
+
+    pub fn rx_queue_size(mut self, rx_queue_size: usize) -> Self {
+        self.rx_queue_size = rx_queue_size;
+        self
+    }
+
+    pub fn rx_timestamp(mut self, timestamp: RxTimestamp) -> Self {
+        self.rx_timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn umem(&self) -> &PlainAccessorRef {
+        &self.umem_accessor
+    }
+
+    pub fn warnings(&mut self) -> &mut Warnings {
+        &mut self.warnings
+    }
+
+    // Returns false if the packet is dropped because the RX queue is full
+    pub fn inject(&mut self, data: &[u8]) -> bool {
+        let mut rx = self.rx.lock().unwrap();
+        if rx.len() >= self.rx_queue_size {
+            self.dropped += 1;
+            return false;
+        }
+        rx.push_back(data.to_vec());
+        true
+    }
+
+    // Drain frames sent on a standalone socket. For a pair, this steals frames from the peer.
+    pub fn take_transmitted(&mut self) -> Vec<Vec<u8>> {
+        self.tx.lock().unwrap().drain(..).collect()
+    }
+}
+
+impl Socket for MockXskSocket {
+    type Accessor = PlainAccessorRef;
+
+    fn recv_bulk(&mut self, size: usize) -> Result<Vec<RxFrame<PlainAccessorRef>>, CamelliaError> {
+        let mut rx = self.rx.lock().unwrap();
+        // packets stay on the wire if the UMem runs out of chunks, like a starved fill ring
+        let received = size.min(rx.len()).min(self.umem_accessor.free_chunks());
+
+        if received == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut frames = self
+            .umem_accessor
+            .allocate(received)?
+            .into_iter()
+            .map(|mut frame| {
+                let data = rx.pop_front().unwrap();
+                // packets start behind the XDP headroom if they fit, like in the kernel
+                let headroom = if data.len() + XDP_PACKET_HEADROOM as usize <= frame.tail_room() {
+                    XDP_PACKET_HEADROOM as usize
+                } else {
+                    0
+                };
+                frame.reserve_head(headroom)?;
+                frame
+                    .raw_buffer_append(data.len())
+                    .map(|buffer| buffer.copy_from_slice(&data))?;
+
+                let chunk = frame.0.take_chunk();
+                let xdp_address = chunk.xdp_address() + headroom;
+                self.stat.rx_bytes += data.len() as u64;
+                Ok(RxFrame::from_chunk(
+                    chunk,
+                    self.umem_accessor.clone(),
+                    xdp_address,
+                    data.len(),
+                ))
+            })
+            .collect::<Result<Vec<_>, CamelliaError>>()?;
+        if let Some(timestamp) = &self.rx_timestamp {
+            timestamp.stamp(&mut frames);
+        }
+
+        self.stat.rx_batch += 1;
+        self.stat.rx_packets += received as u64;
+
+        Ok(frames)
+    }
+
+    fn send_bulk<Iter, T>(&mut self, frames: Iter) -> Result<Vec<T>, CamelliaError>
+    where
+        T: Into<TxFrame<PlainAccessorRef>>,
+        Iter: IntoIterator<Item = T>,
+        Iter::IntoIter: ExactSizeIterator,
+    {
+        let mut tx = self.tx.lock().unwrap();
+        let mut sent = 0;
+
+        for frame in frames {
+            let frame: TxFrame<PlainAccessorRef> = frame.into();
+
+            if !frame.umem().equal(&self.umem_accessor) {
+                self.warnings.report(Warning::ForeignFrameRejected);
+                continue;
+            }
+
+            sent += 1;
+            self.stat.tx_bytes += frame.len() as u64;
+
+            if tx.len() >= self.rx_queue_size {
+                self.dropped += 1;
+            } else {
+                tx.push_back(frame.0.raw_buffer().to_vec());
+            }
+
+            self.umem_accessor.register_send(frame.take());
+        }
+
+        if sent > 0 {
+            self.stat.tx_batch += 1;
+            self.stat.tx_packets += sent;
+        }
+
+        Ok(Vec::new())
+    }
+
+    fn allocate(&mut self, n: usize) -> Result<Vec<AppFrame<PlainAccessorRef>>, CamelliaError> {
+        self.umem_accessor.allocate(n)
+    }
+
+    fn stat(&self) -> &XskStat {
+        &self.stat
+    }
+}
+
+impl StatsSource for MockXskSocket {
+    fn stats_id(&self) -> String {
+        format!("mock/{}", self.umem_accessor.stats_id())
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.stat.visit_stats(visit);
+        visit(Stat::counter("dropped", self.dropped));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_mock_pair() {
+        let (mut left, mut right) = MockXskSocket::pair(64, 2048).unwrap();
+
+        let mut frame = left.allocate(1).unwrap().pop().unwrap();
+        frame
+            .raw_buffer_append(5)
+            .unwrap()
+            .copy_from_slice(b"hello");
+        assert!(left.send(frame).unwrap().is_none());
+        assert_eq!(left.umem().free_chunks(), 64);
+
+        let frame = right.recv().unwrap().unwrap();
+        assert_eq!(frame.raw_buffer(), b"hello");
+        assert_eq!(right.umem().free_chunks(), 63);
+
+        // bounce it back without copying
+        assert!(right.send(frame).unwrap().is_none());
+        assert_eq!(right.umem().free_chunks(), 64);
+
+        let frames = left.recv_bulk(32).unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].raw_buffer(), b"hello");
+        assert_eq!(left.stat().tx_packets, 1);
+        assert_eq!(left.stat().rx_packets, 1);
+    }
+
+    #[test]
+    fn test_mock_standalone() {
+        let mut socket = MockXskSocket::new(2, 2048).unwrap().rx_queue_size(4);
+
+        for i in 0..5u8 {
+            socket.inject(&[i; 60]);
+        }
+        assert_eq!(socket.dropped, 1);
+
+        // only two chunks are available, the rest stays queued
+        let frames = socket.recv_bulk(8).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(socket.recv_bulk(8).unwrap().len(), 0);
+
+        socket.send_bulk(frames).unwrap();
+        let transmitted = socket.take_transmitted();
+        assert_eq!(transmitted, vec![vec![0u8; 60], vec![1u8; 60]]);
+        assert_eq!(socket.recv_bulk(8).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_mock_foreign_frame() {
+        let (mut left, mut right) = MockXskSocket::pair(4, 2048).unwrap();
+        *left.warnings() = Warnings::bounded(4);
+
+        let frame = right.allocate(1).unwrap().pop().unwrap();
+        left.send(frame).unwrap();
+
+        assert_eq!(left.warnings().drain(), vec![Warning::ForeignFrameRejected]);
+        assert_eq!(right.umem().free_chunks(), 4);
+        assert_eq!(right.recv_bulk(1).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_mock_umem_stat() {
+        let mut socket = MockXskSocket::new(8, 2048).unwrap();
+
+        let frames = socket.allocate(3).unwrap();
+        let stat = socket.umem().umem_stat();
+        assert_eq!(stat.free_chunks, 5);
+        assert_eq!(stat.app_owned, 3);
+
+        socket.send_bulk(frames).unwrap();
+        assert_eq!(socket.umem().umem_stat().app_owned, 0);
+    }
+
+    #[test]
+    fn test_frame_resize() {
+        let (mut left, mut right) = MockXskSocket::pair(4, 2048).unwrap();
+
+        let mut frame = left.allocate(1).unwrap().pop().unwrap();
+        assert_eq!(frame.tail_room(), 2048);
+        frame.extend_from_slice(b"hello, world").unwrap();
+        frame.truncate(5);
+        frame.truncate(100);
+        assert_eq!(frame.raw_buffer(), b"hello");
+        assert_eq!(frame.tail_room(), 2043);
+        assert!(frame.extend_from_slice(&[0; 2044]).is_err());
+        assert!(left.send(frame).unwrap().is_none());
+
+        let mut frame = right.recv().unwrap().unwrap();
+        frame.extend_from_slice(b"!").unwrap();
+        assert_eq!(frame.raw_buffer(), b"hello!");
+        frame.truncate(0);
+        assert!(frame.is_empty());
+    }
+
+    #[test]
+    fn test_frame_bytes() {
+        fn checksum(bytes: impl AsRef<[u8]>) -> u32 {
+            bytes.as_ref().iter().map(|byte| *byte as u32).sum()
+        }
+
+        let (mut left, mut right) = MockXskSocket::pair(4, 2048).unwrap();
+        let mut frame = left.allocate(1).unwrap().pop().unwrap();
+        frame.extend_from_slice(&[1, 2, 3]).unwrap();
+        frame.as_mut()[0] = 4;
+        assert_eq!(&frame[..], &[4, 2, 3]);
+        assert_eq!(checksum(&frame), 9);
+        assert!(left.send(frame).unwrap().is_none());
+
+        let frame = right.recv().unwrap().unwrap();
+        assert!(frame.starts_with(&[4, 2]));
+        assert_eq!(checksum(frame), 9);
+    }
+
+    #[test]
+    fn test_copy_out() {
+        let mut socket = MockXskSocket::new(4, 2048).unwrap();
+        socket.inject(b"hello");
+
+        let frame = socket.recv().unwrap().unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(frame.copy_into(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert!(frame.copy_into(&mut buf[..4]).is_err());
+
+        let packet = frame.to_vec();
+        drop(frame);
+        assert_eq!(packet, b"hello");
+        assert_eq!(socket.umem().free_chunks(), 4);
+    }
+
+    #[test]
+    fn test_copy_from_slice() {
+        let mut socket = MockXskSocket::new(4, 2048).unwrap();
+
+        let frame = TxFrame::copy_from_slice(socket.umem(), b"hello").unwrap();
+        assert_eq!(frame.len(), 5);
+        let frames = TxFrame::copy_from_slices(socket.umem(), [&b"a"[..], &b"bc"[..]]).unwrap();
+        assert!(socket.send(frame).unwrap().is_none());
+        assert!(socket.send_bulk(frames).unwrap().is_empty());
+        assert_eq!(
+            socket.take_transmitted(),
+            vec![b"hello".to_vec(), b"a".to_vec(), b"bc".to_vec()]
+        );
+
+        // chunks of the packets fitting are returned as well
+        assert!(
+            TxFrame::copy_from_slices(socket.umem(), [vec![0u8; 64], vec![0u8; 4096]]).is_err()
+        );
+        assert!(TxFrame::copy_from_slices(socket.umem(), vec![vec![0u8; 64]; 5]).is_err());
+        assert_eq!(socket.umem().free_chunks(), 4);
+    }
+}
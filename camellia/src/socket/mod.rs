@@ -1 +1,84 @@
+use crate::{
+    error::CamelliaError,
+    umem::{
+        frame::{AppFrame, RxFrame, TxFrame},
+        AccessorRef,
+    },
+};
+
+use self::{af_xdp::XskStat, frames::Frames};
+
+pub mod af_packet;
 pub mod af_xdp;
+pub mod frames;
+pub mod hooks;
+pub mod mock;
+pub mod napi;
+pub mod queues;
+pub mod shaper;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod timestamp;
+pub mod warnings;
+
+// Common interface of socket backends, so that application logic can be written once
+// and run on AF_XDP sockets or the in-memory mock.
+pub trait Socket {
+    type Accessor: AccessorRef;
+
+    fn recv_bulk(&mut self, size: usize) -> Result<Vec<RxFrame<Self::Accessor>>, CamelliaError>;
+
+    fn send_bulk<Iter, T>(&mut self, frames: Iter) -> Result<Vec<T>, CamelliaError>
+    where
+        T: Into<TxFrame<Self::Accessor>>,
+        Iter: IntoIterator<Item = T>,
+        Iter::IntoIter: ExactSizeIterator;
+
+    fn allocate(&mut self, n: usize) -> Result<Vec<AppFrame<Self::Accessor>>, CamelliaError>;
+
+    fn stat(&self) -> &XskStat;
+
+    // appends the received frames to frames and returns how many were received
+    fn recv_bulk_into(
+        &mut self,
+        frames: &mut Vec<RxFrame<Self::Accessor>>,
+        size: usize,
+    ) -> Result<usize, CamelliaError> {
+        let mut received = self.recv_bulk(size)?;
+        let n = received.len();
+        frames.append(&mut received);
+        Ok(n)
+    }
+
+    // sends frames from the front of frames and returns how many were taken out of it
+    fn send_bulk_drain(
+        &mut self,
+        frames: &mut Vec<TxFrame<Self::Accessor>>,
+    ) -> Result<usize, CamelliaError> {
+        let len = frames.len();
+        *frames = self.send_bulk(std::mem::take(frames))?;
+        Ok(len - frames.len())
+    }
+
+    fn frames(&mut self, batch_size: usize) -> Frames<'_, Self>
+    where
+        Self: Sized,
+    {
+        Frames::new(self, batch_size)
+    }
+
+    fn recv(&mut self) -> Result<Option<RxFrame<Self::Accessor>>, CamelliaError> {
+        let mut received = self.recv_bulk(1)?;
+        assert!(received.len() <= 1);
+        Ok(received.pop())
+    }
+
+    fn send<T>(&mut self, frame: T) -> Result<Option<T>, CamelliaError>
+    where
+        T: Into<TxFrame<Self::Accessor>>,
+    {
+        let mut remaining = self.send_bulk([frame])?;
+        assert!(remaining.len() <= 1);
+        Ok(remaining.pop())
+    }
+}
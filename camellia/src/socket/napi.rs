@@ -0,0 +1,174 @@
+use std::{fs, io};
+
+use nix::{
+    sched::{sched_setaffinity, CpuSet},
+    unistd::Pid,
+};
+
+use crate::error::CamelliaError;
+
+// Finds the CPUs serving a NAPI instance. Threaded NAPI runs in the kthread
+// napi/<ifname>-<napi_id>, otherwise NAPI runs in softirq context on the CPUs handling
+// the interrupts of the queue, whose names end with the queue index, e.g., eth0-TxRx-3.
+pub fn napi_cpus(
+    ifname: &str,
+    queue_index: u32,
+    napi_id: u32,
+) -> Result<Vec<usize>, CamelliaError> {
+    if let Some(cpus) = napi_thread_cpus(ifname, napi_id) {
+        return Ok(cpus);
+    }
+
+    let mut cpus = Vec::new();
+    for irq in queue_irqs(
+        &fs::read_to_string("/proc/interrupts")?,
+        ifname,
+        queue_index,
+    ) {
+        let list = fs::read_to_string(format!("/proc/irq/{}/effective_affinity_list", irq))
+            .or_else(|_| fs::read_to_string(format!("/proc/irq/{}/smp_affinity_list", irq)))?;
+        cpus.extend(parse_cpu_list(&list)?);
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+
+    if cpus.is_empty() {
+        return Err(CamelliaError::Io(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!(
+                "no CPU serving NAPI {} of {} (queue {}) is found",
+                napi_id, ifname, queue_index
+            ),
+        )));
+    }
+    Ok(cpus)
+}
+
+pub fn pin_current_thread(cpus: &[usize]) -> Result<(), CamelliaError> {
+    let mut cpu_set = CpuSet::new();
+    for cpu in cpus {
+        cpu_set.set(*cpu)?;
+    }
+    // pid 0 is the calling thread
+    sched_setaffinity(Pid::from_raw(0), &cpu_set)?;
+    Ok(())
+}
+
+fn napi_thread_cpus(ifname: &str, napi_id: u32) -> Option<Vec<usize>> {
+    let comm = format!("napi/{}-{}", ifname, napi_id);
+    // comm is truncated to 15 bytes, longer names are ambiguous
+    if comm.len() > 15 {
+        return None;
+    }
+
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let path = entry.path();
+        let name = fs::read_to_string(path.join("comm")).unwrap_or_default();
+        if name.trim_end() != comm {
+            continue;
+        }
+
+        let status = fs::read_to_string(path.join("status")).ok()?;
+        let list = status
+            .lines()
+            .find_map(|line| line.strip_prefix("Cpus_allowed_list:"))?;
+        return parse_cpu_list(list).ok();
+    }
+    None
+}
+
+// IRQs of /proc/interrupts whose action names belong to the queue of the interface
+fn queue_irqs(interrupts: &str, ifname: &str, queue_index: u32) -> Vec<u32> {
+    interrupts
+        .lines()
+        .filter_map(|line| {
+            let (irq, rest) = line.trim_start().split_once(':')?;
+            let irq = irq.parse().ok()?;
+            let name = rest.split_whitespace().last()?;
+            name.split(',')
+                .any(|action| {
+                    action.strip_prefix(ifname).is_some_and(|suffix| {
+                        suffix.starts_with(['-', '_'])
+                            && suffix
+                                .rsplit(['-', '_'])
+                                .next()
+                                .and_then(|q| q.parse().ok())
+                                == Some(queue_index)
+                    })
+                })
+                .then_some(irq)
+        })
+        .collect()
+}
+
+// e.g., 0-3,8,10-11
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>, CamelliaError> {
+    let invalid = || CamelliaError::InvalidArgument(format!("invalid CPU list {}", list.trim()));
+
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                let first: usize = first.parse().map_err(|_| invalid())?;
+                let last: usize = last.parse().map_err(|_| invalid())?;
+                if first > last {
+                    return Err(invalid());
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(range.parse().map_err(|_| invalid())?),
+        }
+    }
+    Ok(cpus)
+}
+
+#[cfg(test)]
+mod test {
+    use nix::sched::sched_getaffinity;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,8\n").unwrap(), vec![0, 1, 2, 3, 8]);
+        assert_eq!(parse_cpu_list("5").unwrap(), vec![5]);
+        assert!(parse_cpu_list("").unwrap().is_empty());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+
+    #[test]
+    fn test_queue_irqs() {
+        let interrupts = "           CPU0       CPU1
+  24:          0          0  IR-PCI-MSI 1048576-edge      eth0
+  25:        812          0  IR-PCI-MSI 1048577-edge      eth0-TxRx-0
+  26:          0        317  IR-PCI-MSI 1048578-edge      eth0-TxRx-1
+  27:          0         12  IR-PCI-MSI 1048579-edge      eth01-TxRx-1
+  28:          3          0  IR-PCI-MSI 1048580-edge      eth0-rx-11
+ NMI:          0          0   Non-maskable interrupts
+";
+        assert_eq!(queue_irqs(interrupts, "eth0", 0), vec![25]);
+        assert_eq!(queue_irqs(interrupts, "eth0", 1), vec![26]);
+        assert_eq!(queue_irqs(interrupts, "eth0", 11), vec![28]);
+        assert!(queue_irqs(interrupts, "eth0", 2).is_empty());
+    }
+
+    #[test]
+    fn test_pin_current_thread() {
+        std::thread::spawn(|| {
+            let allowed = sched_getaffinity(Pid::from_raw(0)).unwrap();
+            let cpu = (0..CpuSet::count())
+                .find(|cpu| allowed.is_set(*cpu).unwrap())
+                .unwrap();
+            pin_current_thread(&[cpu]).unwrap();
+            assert_eq!(sched_getaffinity(Pid::from_raw(0)).unwrap(), {
+                let mut cpu_set = CpuSet::new();
+                cpu_set.set(cpu).unwrap();
+                cpu_set
+            });
+            assert!(pin_current_thread(&[CpuSet::count()]).is_err());
+        })
+        .join()
+        .unwrap();
+    }
+}
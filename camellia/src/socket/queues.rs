@@ -0,0 +1,136 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use once_cell::sync::Lazy;
+
+use crate::error::CamelliaError;
+
+// Queues bound by the sockets of this process, keyed by (ifindex, queue index). Sockets
+// sharing a UMem may bind the same queue, sockets with different UMems can't, which the
+// kernel only reports as EBUSY.
+static BOUND_QUEUES: Lazy<Mutex<HashMap<(u32, u32), BoundQueue>>> = Lazy::new(Default::default);
+
+struct BoundQueue {
+    // address of the xsk_umem, identifies the UMem
+    umem: usize,
+    sockets: usize,
+}
+
+// The claim of a socket on its queue, released when dropped
+#[derive(Debug)]
+pub struct QueueClaim {
+    ifindex: u32,
+    queue_index: u32,
+}
+
+impl QueueClaim {
+    pub(crate) fn acquire(
+        ifname: &str,
+        ifindex: u32,
+        queue_index: u32,
+        umem: usize,
+    ) -> Result<Self, CamelliaError> {
+        let mut bound = BOUND_QUEUES.lock().unwrap();
+        let entry = bound
+            .entry((ifindex, queue_index))
+            .or_insert(BoundQueue { umem, sockets: 0 });
+        if entry.umem != umem {
+            return Err(CamelliaError::QueueInUse {
+                ifname: ifname.to_string(),
+                queue: queue_index,
+            });
+        }
+        entry.sockets += 1;
+
+        Ok(Self {
+            ifindex,
+            queue_index,
+        })
+    }
+
+    // claims the first queue below num_queues which is not bound yet
+    pub(crate) fn acquire_free(
+        ifname: &str,
+        ifindex: u32,
+        num_queues: u32,
+        umem: usize,
+    ) -> Result<Self, CamelliaError> {
+        let queue_index = {
+            let bound = BOUND_QUEUES.lock().unwrap();
+            (0..num_queues).find(|queue_index| !bound.contains_key(&(ifindex, *queue_index)))
+        };
+
+        match queue_index {
+            Some(queue_index) => Self::acquire(ifname, ifindex, queue_index, umem),
+            None => Err(CamelliaError::ResourceExhausted(format!(
+                "all {} queues of {} are bound",
+                num_queues, ifname
+            ))),
+        }
+    }
+
+    pub fn queue_index(&self) -> u32 {
+        self.queue_index
+    }
+
+    pub fn ifindex(&self) -> u32 {
+        self.ifindex
+    }
+}
+
+impl Drop for QueueClaim {
+    fn drop(&mut self) {
+        let mut bound = BOUND_QUEUES.lock().unwrap();
+        let key = (self.ifindex, self.queue_index);
+        if let Some(entry) = bound.get_mut(&key) {
+            entry.sockets -= 1;
+            if entry.sockets == 0 {
+                bound.remove(&key);
+            }
+        }
+    }
+}
+
+// queues of the interface bound by sockets of this process
+pub fn bound_queues(ifindex: u32) -> Vec<u32> {
+    let mut queues: Vec<u32> = BOUND_QUEUES
+        .lock()
+        .unwrap()
+        .keys()
+        .filter(|(index, _)| *index == ifindex)
+        .map(|(_, queue_index)| *queue_index)
+        .collect();
+    queues.sort_unstable();
+    queues
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_queue_claims() {
+        // an ifindex no other test uses
+        let ifindex = u32::MAX;
+
+        let first = QueueClaim::acquire_free("test0", ifindex, 2, 1).unwrap();
+        assert_eq!(first.queue_index(), 0);
+        // the same UMem may share the queue, another one can't
+        let shared = QueueClaim::acquire("test0", ifindex, 0, 1).unwrap();
+        assert!(matches!(
+            QueueClaim::acquire("test0", ifindex, 0, 2),
+            Err(CamelliaError::QueueInUse { queue: 0, .. })
+        ));
+
+        let second = QueueClaim::acquire_free("test0", ifindex, 2, 2).unwrap();
+        assert_eq!(second.queue_index(), 1);
+        assert!(QueueClaim::acquire_free("test0", ifindex, 2, 3).is_err());
+        assert_eq!(bound_queues(ifindex), vec![0, 1]);
+
+        drop(first);
+        assert_eq!(bound_queues(ifindex), vec![0, 1]);
+        drop(shared);
+        assert_eq!(bound_queues(ifindex), vec![1]);
+        drop(second);
+        assert!(bound_queues(ifindex).is_empty());
+    }
+}
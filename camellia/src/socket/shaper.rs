@@ -0,0 +1,254 @@
+use std::{cmp::Ordering, collections::BinaryHeap, time::Duration};
+
+use crate::{
+    error::CamelliaError,
+    socket::{timestamp::Clock, Socket},
+    umem::{frame::TxFrame, AccessorRef},
+};
+
+// waits longer than this sleep, shorter ones spin for accuracy
+const SPIN_THRESHOLD: Duration = Duration::from_micros(50);
+
+// nanoseconds of CLOCK_TAI, the clock of launch times
+pub fn clock_ns() -> u64 {
+    Clock::Tai.now()
+}
+
+struct Scheduled<M: AccessorRef> {
+    launch_time: u64,
+    // frames of the same launch time leave in the order they are enqueued
+    seq: u64,
+    frame: TxFrame<M>,
+}
+
+impl<M: AccessorRef> PartialEq for Scheduled<M> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<M: AccessorRef> Eq for Scheduled<M> {}
+
+impl<M: AccessorRef> PartialOrd for Scheduled<M> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<M: AccessorRef> Ord for Scheduled<M> {
+    // the earliest frame is the greatest, on top of the heap
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.launch_time, other.seq).cmp(&(self.launch_time, self.seq))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShaperStat {
+    pub sent: u64,
+    // frames sent after their launch time, by how much the latest one was late
+    pub late: u64,
+    pub max_lateness_ns: u64,
+}
+
+// Paces frames by their launch times in software, for microsecond pacing without launch
+// time offload of the NIC. Frames are held until their launch time and sent by poll or
+// run_until, frames without a launch time are sent at once. The pacing is as accurate as
+// the thread calling them is scheduled, run_until spins shortly before each launch time.
+pub struct Shaper<M: AccessorRef> {
+    queue: BinaryHeap<Scheduled<M>>,
+    seq: u64,
+    // lateness tolerated before a frame counts as late
+    tolerance: u64,
+    stat: ShaperStat,
+}
+
+impl<M: AccessorRef> Default for Shaper<M> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<M: AccessorRef> Shaper<M> {
+    pub fn new() -> Self {
+        Self {
+            queue: BinaryHeap::new(),
+            seq: 0,
+            tolerance: 1_000,
+            stat: ShaperStat::default(),
+        }
+    }
+
+    pub fn tolerance(mut self, tolerance: Duration) -> Self {
+        self.tolerance = tolerance.as_nanos() as u64;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    pub fn stat(&self) -> &ShaperStat {
+        &self.stat
+    }
+
+    pub fn enqueue(&mut self, frame: TxFrame<M>) {
+        self.queue.push(Scheduled {
+            launch_time: frame.launch_time().unwrap_or(0),
+            seq: self.seq,
+            frame,
+        });
+        self.seq += 1;
+    }
+
+    // the launch time of the next frame
+    pub fn next_launch_time(&self) -> Option<u64> {
+        self.queue.peek().map(|scheduled| scheduled.launch_time)
+    }
+
+    // Sends the frames due at now and returns how many were sent. Frames the TX ring
+    // doesn't take stay queued.
+    pub fn poll<S>(&mut self, socket: &mut S, now: u64) -> Result<usize, CamelliaError>
+    where
+        S: Socket<Accessor = M>,
+    {
+        let mut due = Vec::new();
+        while self
+            .queue
+            .peek()
+            .is_some_and(|scheduled| scheduled.launch_time <= now)
+        {
+            due.push(self.queue.pop().unwrap());
+        }
+        if due.is_empty() {
+            return Ok(0);
+        }
+
+        let times: Vec<(u64, u64)> = due
+            .iter()
+            .map(|scheduled| (scheduled.launch_time, scheduled.seq))
+            .collect();
+        let unsent = socket.send_bulk(due.into_iter().map(|scheduled| scheduled.frame))?;
+        let sent = times.len() - unsent.len();
+
+        for (launch_time, _) in &times[..sent] {
+            let lateness = now - launch_time;
+            // frames without launch time are never late
+            if *launch_time > 0 && lateness > self.tolerance {
+                self.stat.late += 1;
+                self.stat.max_lateness_ns = self.stat.max_lateness_ns.max(lateness);
+            }
+        }
+        self.stat.sent += sent as u64;
+
+        for ((launch_time, seq), frame) in times[sent..].iter().zip(unsent) {
+            self.queue.push(Scheduled {
+                launch_time: *launch_time,
+                seq: *seq,
+                frame,
+            });
+        }
+        Ok(sent)
+    }
+
+    // Sends frames at their launch times until the queue is empty or the clock reaches
+    // until, and returns how many were sent
+    pub fn run_until<S>(&mut self, socket: &mut S, until: u64) -> Result<usize, CamelliaError>
+    where
+        S: Socket<Accessor = M>,
+    {
+        let mut sent = 0;
+        loop {
+            let now = clock_ns();
+            sent += self.poll(socket, now)?;
+            let Some(next) = self.next_launch_time() else {
+                break;
+            };
+            if now >= until {
+                break;
+            }
+
+            let wait = Duration::from_nanos(next.min(until).saturating_sub(now));
+            if wait > SPIN_THRESHOLD {
+                std::thread::sleep(wait - SPIN_THRESHOLD);
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{socket::mock::MockXskSocket, umem::plain::PlainAccessorRef};
+
+    fn frame(
+        socket: &MockXskSocket,
+        byte: u8,
+        launch_time: Option<u64>,
+    ) -> TxFrame<PlainAccessorRef> {
+        let mut frame = TxFrame::copy_from_slice(socket.umem(), &[byte; 60]).unwrap();
+        if let Some(launch_time) = launch_time {
+            frame.set_launch_time(launch_time);
+        }
+        frame
+    }
+
+    #[test]
+    fn test_shaper_order() {
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        let mut shaper = Shaper::new();
+        shaper.enqueue(frame(&socket, 3, Some(3_000)));
+        shaper.enqueue(frame(&socket, 1, Some(1_000)));
+        shaper.enqueue(frame(&socket, 2, Some(2_000)));
+        shaper.enqueue(frame(&socket, 0, None));
+        shaper.enqueue(frame(&socket, 4, Some(3_000)));
+        assert_eq!(shaper.next_launch_time(), Some(0));
+
+        assert_eq!(shaper.poll(&mut socket, 500).unwrap(), 1);
+        assert_eq!(shaper.poll(&mut socket, 2_500).unwrap(), 2);
+        assert_eq!(shaper.next_launch_time(), Some(3_000));
+        assert_eq!(shaper.poll(&mut socket, 2_999).unwrap(), 0);
+        assert_eq!(shaper.poll(&mut socket, 10_000).unwrap(), 2);
+        assert!(shaper.is_empty());
+
+        let order: Vec<u8> = socket
+            .take_transmitted()
+            .iter()
+            .map(|packet| packet[0])
+            .collect();
+        assert_eq!(order, vec![0, 1, 2, 3, 4]);
+        // frames 1 and both of 3 us are sent more than 1 us late
+        assert_eq!(shaper.stat().late, 3);
+        assert_eq!(shaper.stat().max_lateness_ns, 7_000);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        let mut shaper = Shaper::new();
+        let start = clock_ns();
+        for i in 0..4u8 {
+            shaper.enqueue(frame(&socket, i, Some(start + (4 - i as u64) * 200_000)));
+        }
+
+        assert_eq!(
+            shaper
+                .run_until(&mut socket, start + 1_000_000_000)
+                .unwrap(),
+            4
+        );
+        assert!(clock_ns() >= start + 800_000);
+        let order: Vec<u8> = socket
+            .take_transmitted()
+            .iter()
+            .map(|packet| packet[0])
+            .collect();
+        assert_eq!(order, vec![3, 2, 1, 0]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use futures_sink::Sink;
+
+use crate::{
+    error::CamelliaError,
+    socket::Socket,
+    umem::frame::{RxFrame, TxFrame},
+};
+
+// A socket driven by an executor, received frames are a Stream and frames to send go
+// into a Sink. Frames are batched in both directions: the sink buffers up to batch_size
+// frames before sending them, or until it is flushed. There is no reactor behind the
+// socket, so the task yields and polls again when the socket isn't ready, like busy
+// polling.
+pub struct AsyncSocket<S: Socket> {
+    socket: S,
+    batch_size: usize,
+    // received frames in reverse order, popped from the back
+    rx_batch: Vec<RxFrame<S::Accessor>>,
+    tx_batch: Vec<TxFrame<S::Accessor>>,
+}
+
+impl<S: Socket> AsyncSocket<S> {
+    pub fn new(socket: S, batch_size: usize) -> Self {
+        assert!(batch_size > 0);
+        Self {
+            socket,
+            batch_size,
+            rx_batch: Vec::with_capacity(batch_size),
+            tx_batch: Vec::with_capacity(batch_size),
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.socket
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.socket
+    }
+
+    // frames buffered in the sink are dropped, flush or close the sink first
+    pub fn into_inner(self) -> S {
+        self.socket
+    }
+
+    fn poll_send(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), CamelliaError>> {
+        if self.tx_batch.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+
+        self.socket.send_bulk_drain(&mut self.tx_batch)?;
+        if self.tx_batch.is_empty() {
+            Poll::Ready(Ok(()))
+        } else {
+            // the TX ring is full, wait for the kernel to drain it
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+// frames are owned through the batch Vecs, nothing is pinned
+impl<S: Socket> Unpin for AsyncSocket<S> {}
+
+impl<S: Socket> Stream for AsyncSocket<S> {
+    type Item = Result<RxFrame<S::Accessor>, CamelliaError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+
+        if this.rx_batch.is_empty() {
+            if let Err(error) = this
+                .socket
+                .recv_bulk_into(&mut this.rx_batch, this.batch_size)
+            {
+                return Poll::Ready(Some(Err(error)));
+            }
+            this.rx_batch.reverse();
+        }
+
+        match this.rx_batch.pop() {
+            Some(frame) => Poll::Ready(Some(Ok(frame))),
+            None => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S: Socket> Sink<TxFrame<S::Accessor>> for AsyncSocket<S> {
+    type Error = CamelliaError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if this.tx_batch.len() < this.batch_size {
+            Poll::Ready(Ok(()))
+        } else {
+            this.poll_send(cx)
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, frame: TxFrame<S::Accessor>) -> Result<(), Self::Error> {
+        self.get_mut().tx_batch.push(frame);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_send(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_send(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, task::Wake};
+
+    use super::*;
+    use crate::socket::mock::MockXskSocket;
+
+    struct Noop;
+
+    impl Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    #[test]
+    fn test_async_socket() {
+        let waker = Arc::new(Noop).into();
+        let mut cx = Context::from_waker(&waker);
+
+        let (left, right) = MockXskSocket::pair(64, 2048).unwrap();
+        let mut left = AsyncSocket::new(left, 2);
+        let mut right = AsyncSocket::new(right, 2);
+
+        for i in 0..3u8 {
+            assert!(Pin::new(&mut left).poll_ready(&mut cx).is_ready());
+            let mut frame = left.get_mut().allocate(1).unwrap().pop().unwrap();
+            frame.extend_from_slice(&[i; 60]).unwrap();
+            Pin::new(&mut left).start_send(frame.into()).unwrap();
+        }
+        // two frames went out when the batch was full
+        assert_eq!(left.get_ref().stat().tx_packets, 2);
+
+        assert!(matches!(
+            Pin::new(&mut left).poll_flush(&mut cx),
+            Poll::Ready(Ok(()))
+        ));
+        assert_eq!(left.get_ref().stat().tx_packets, 3);
+
+        for i in 0..3u8 {
+            match Pin::new(&mut right).poll_next(&mut cx) {
+                Poll::Ready(Some(Ok(frame))) => assert_eq!(frame.raw_buffer(), &[i; 60]),
+                _ => panic!("frame {} is pending", i),
+            }
+        }
+        assert!(Pin::new(&mut right).poll_next(&mut cx).is_pending());
+    }
+}
@@ -0,0 +1,133 @@
+use serde::Deserialize;
+
+use crate::umem::{frame::RxFrame, AccessorRef};
+
+// bytes of the hardware timestamp at the end of the XDP metadata
+const METADATA_TIMESTAMP_LEN: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Clock {
+    Monotonic,
+    // the clock of launch times and of PTP synchronized NICs
+    Tai,
+}
+
+impl Clock {
+    // nanoseconds of the clock
+    pub fn now(&self) -> u64 {
+        let clock_id = match self {
+            Clock::Monotonic => libc::CLOCK_MONOTONIC,
+            Clock::Tai => libc::CLOCK_TAI,
+        };
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        unsafe {
+            libc::clock_gettime(clock_id, &mut ts);
+        }
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+}
+
+// How received frames are stamped, see XskSocketBuilder::rx_timestamp
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RxTimestamp {
+    pub clock: Clock,
+    // Prefer the hardware timestamp an XDP program stored in the last 8 bytes of the
+    // metadata in front of the packet, e.g., from bpf_xdp_metadata_rx_timestamp. The
+    // program must adjust the metadata of every packet and store zero if the NIC has no
+    // timestamp, otherwise stale bytes of the head room are taken as timestamps.
+    #[serde(default)]
+    pub metadata: bool,
+}
+
+impl RxTimestamp {
+    pub fn new(clock: Clock) -> Self {
+        Self {
+            clock,
+            metadata: false,
+        }
+    }
+
+    pub fn prefer_metadata(mut self) -> Self {
+        self.metadata = true;
+        self
+    }
+
+    // Stamps the frames of a batch, all dequeued at the same time so the clock is read
+    // once. Hardware timestamps are in the clock of the NIC, usually synchronized to TAI.
+    pub fn stamp<M: AccessorRef>(&self, frames: &mut [RxFrame<M>]) {
+        if frames.is_empty() {
+            return;
+        }
+        let now = self.clock.now();
+        for frame in frames {
+            let hardware = if self.metadata {
+                frame
+                    .metadata(METADATA_TIMESTAMP_LEN)
+                    .map(|bytes| u64::from_ne_bytes(bytes.try_into().unwrap()))
+                    .filter(|timestamp| *timestamp != 0)
+            } else {
+                None
+            };
+            frame.set_timestamp(hardware.unwrap_or(now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socket::{mock::MockXskSocket, Socket};
+
+    #[test]
+    fn test_software_timestamp() {
+        let mut socket = MockXskSocket::new(16, 2048)
+            .unwrap()
+            .rx_timestamp(RxTimestamp::new(Clock::Monotonic));
+        assert!(socket.inject(&[1; 60]));
+        assert!(socket.inject(&[2; 60]));
+
+        let before = Clock::Monotonic.now();
+        let frames = socket.recv_bulk(2).unwrap();
+        let after = Clock::Monotonic.now();
+        let timestamp = frames[0].timestamp().unwrap();
+        assert!(before <= timestamp && timestamp <= after);
+        assert_eq!(frames[1].timestamp(), Some(timestamp));
+
+        // sockets don't stamp frames by default
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        assert!(socket.inject(&[1; 60]));
+        assert_eq!(socket.recv().unwrap().unwrap().timestamp(), None);
+    }
+
+    #[test]
+    fn test_metadata_timestamp() {
+        let mut socket = MockXskSocket::new(16, 2048).unwrap();
+        assert!(socket.inject(&[1; 60]));
+        assert!(socket.inject(&[2; 60]));
+        let mut frames = socket.recv_bulk(2).unwrap();
+
+        // an XDP program stored the timestamp of the first packet in the metadata
+        let hardware = 1_700_000_000_123_456_789u64;
+        for (frame, timestamp) in frames.iter_mut().zip([hardware, 0]) {
+            frame
+                .0
+                .push_head(METADATA_TIMESTAMP_LEN)
+                .unwrap()
+                .copy_from_slice(&timestamp.to_ne_bytes());
+            frame.0.pull_head(METADATA_TIMESTAMP_LEN).unwrap();
+        }
+
+        let timestamp = RxTimestamp::new(Clock::Tai).prefer_metadata();
+        let before = Clock::Tai.now();
+        timestamp.stamp(&mut frames);
+        assert_eq!(frames[0].timestamp(), Some(hardware));
+        // packets without hardware timestamp fall back to the clock
+        assert!(frames[1].timestamp().unwrap() >= before);
+        assert_eq!(frames[0].raw_buffer(), &[1; 60]);
+    }
+}
@@ -0,0 +1,263 @@
+use std::{collections::VecDeque, time::Duration};
+
+use nix::errno::Errno;
+
+use crate::umem::RingState;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    // fewer chunks than received frames are put back to the fill ring
+    PartialFill { requested: usize, filled: usize },
+    // wakeup syscall failed with a transient error, e.g., ENOBUFS. EAGAIN and EBUSY only
+    // mean that the kernel is busy with the ring and are not reported.
+    WakeupFailed { errno: Errno },
+    // a frame allocated from another UMem is dropped instead of being sent
+    ForeignFrameRejected,
+    // no TX descriptor is completed for longer than the watchdog timeout
+    TxStalled(TxStall),
+}
+
+// Ring state of a socket whose completion ring makes no progress, e.g., because the
+// driver stopped transmitting. Sent chunks are not returned to the UMem meanwhile.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxStall {
+    pub stalled_for: Duration,
+    pub outstanding: usize,
+    pub outstanding_bytes: u64,
+    pub tx_ring: RingState,
+    pub completion_ring: Option<RingState>,
+}
+
+impl std::fmt::Display for TxStall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no TX completion for {:?}, {} descriptors ({} bytes) outstanding, TX ring: {}",
+            self.stalled_for, self.outstanding, self.outstanding_bytes, self.tx_ring
+        )?;
+        if let Some(completion_ring) = &self.completion_ring {
+            write!(f, ", completion ring: {}", completion_ring)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Warning::PartialFill { requested, filled } => {
+                write!(
+                    f,
+                    "fill failed, filled: {}, received: {}",
+                    filled, requested
+                )
+            }
+            Warning::WakeupFailed { errno } => write!(f, "wakeup failed: {}", errno),
+            Warning::ForeignFrameRejected => {
+                write!(f, "frame does not belong to this socket, dropped")
+            }
+            Warning::TxStalled(stall) => write!(f, "TX stalled: {}", stall),
+        }
+    }
+}
+
+type WarningCallback = Box<dyn FnMut(&Warning) + Send>;
+
+// warnings kept by default until the application drains them
+const DEFAULT_CAPACITY: usize = 64;
+
+enum Sink {
+    Log,
+    Queue {
+        queue: VecDeque<Warning>,
+        capacity: usize,
+    },
+    Callback(WarningCallback),
+}
+
+// Collects non-fatal datapath conditions so that applications decide how to handle
+// them instead of logging on the hot path.
+pub struct Warnings {
+    sink: Sink,
+    dropped: u64,
+}
+
+// nothing is logged on the datapath unless asked for with Warnings::log
+impl Default for Warnings {
+    fn default() -> Self {
+        Self::bounded(DEFAULT_CAPACITY)
+    }
+}
+
+impl std::fmt::Debug for Warnings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sink = match &self.sink {
+            Sink::Log => "log",
+            Sink::Queue { .. } => "queue",
+            Sink::Callback(_) => "callback",
+        };
+        f.debug_struct("Warnings")
+            .field("sink", &sink)
+            .field("dropped", &self.dropped)
+            .finish()
+    }
+}
+
+impl Warnings {
+    pub fn log() -> Self {
+        Self {
+            sink: Sink::Log,
+            dropped: 0,
+        }
+    }
+
+    // the oldest warning is discarded when the queue is full
+    pub fn bounded(capacity: usize) -> Self {
+        Self {
+            sink: Sink::Queue {
+                queue: VecDeque::with_capacity(capacity),
+                capacity,
+            },
+            dropped: 0,
+        }
+    }
+
+    pub fn callback<F>(callback: F) -> Self
+    where
+        F: FnMut(&Warning) + Send + 'static,
+    {
+        Self {
+            sink: Sink::Callback(Box::new(callback)),
+            dropped: 0,
+        }
+    }
+
+    pub fn report(&mut self, warning: Warning) {
+        match &mut self.sink {
+            Sink::Log => log::warn!("{}", warning),
+            Sink::Queue { queue, capacity } => {
+                if *capacity == 0 {
+                    self.dropped += 1;
+                    return;
+                }
+                if queue.len() == *capacity {
+                    queue.pop_front();
+                    self.dropped += 1;
+                }
+                queue.push_back(warning);
+            }
+            Sink::Callback(callback) => callback(&warning),
+        }
+    }
+
+    pub fn drain(&mut self) -> Vec<Warning> {
+        match &mut self.sink {
+            Sink::Queue { queue, .. } => queue.drain(..).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.sink {
+            Sink::Queue { queue, .. } => queue.len(),
+            _ => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // number of warnings discarded because the queue is full
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn test_bounded_queue() {
+        let mut warnings = Warnings::bounded(2);
+
+        warnings.report(Warning::ForeignFrameRejected);
+        warnings.report(Warning::PartialFill {
+            requested: 32,
+            filled: 16,
+        });
+        warnings.report(Warning::WakeupFailed {
+            errno: Errno::EAGAIN,
+        });
+
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(warnings.dropped(), 1);
+        assert_eq!(
+            warnings.drain(),
+            vec![
+                Warning::PartialFill {
+                    requested: 32,
+                    filled: 16
+                },
+                Warning::WakeupFailed {
+                    errno: Errno::EAGAIN
+                }
+            ]
+        );
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_default_queue() {
+        let mut warnings = Warnings::default();
+        for _ in 0..DEFAULT_CAPACITY + 1 {
+            warnings.report(Warning::ForeignFrameRejected);
+        }
+
+        assert_eq!(warnings.len(), DEFAULT_CAPACITY);
+        assert_eq!(warnings.dropped(), 1);
+    }
+
+    #[test]
+    fn test_callback() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen_clone = seen.clone();
+        let mut warnings = Warnings::callback(move |warning| {
+            seen_clone.lock().unwrap().push(warning.clone());
+        });
+
+        warnings.report(Warning::ForeignFrameRejected);
+
+        assert_eq!(*seen.lock().unwrap(), vec![Warning::ForeignFrameRejected]);
+        assert!(warnings.drain().is_empty());
+    }
+
+    #[test]
+    fn test_tx_stall_report() {
+        let stall = TxStall {
+            stalled_for: Duration::from_secs(2),
+            outstanding: 3,
+            outstanding_bytes: 180,
+            tx_ring: RingState {
+                producer: 10,
+                consumer: 10,
+                size: 2048,
+            },
+            completion_ring: Some(RingState {
+                producer: 7,
+                consumer: 7,
+                size: 2048,
+            }),
+        };
+
+        assert_eq!(
+            Warning::TxStalled(stall).to_string(),
+            "TX stalled: no TX completion for 2s, 3 descriptors (180 bytes) outstanding, \
+             TX ring: producer 10, consumer 10, 0/2048 pending, \
+             completion ring: producer 7, consumer 7, 0/2048 pending"
+        );
+    }
+}
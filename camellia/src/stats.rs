@@ -0,0 +1,339 @@
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::socket::af_xdp::{XskStat, XskStatDelta, XskStatSnapshot};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatKind {
+    // monotonically increasing, exporters usually report rates of them
+    Counter,
+    Gauge,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Stat {
+    pub name: &'static str,
+    pub kind: StatKind,
+    pub value: u64,
+}
+
+impl Stat {
+    pub fn counter(name: &'static str, value: u64) -> Self {
+        Self {
+            name,
+            kind: StatKind::Counter,
+            value,
+        }
+    }
+
+    pub fn gauge(name: &'static str, value: u64) -> Self {
+        Self {
+            name,
+            kind: StatKind::Gauge,
+            value,
+        }
+    }
+}
+
+// Anything exposing statistics, so that exporters enumerate them uniformly instead of
+// reading struct fields of each type.
+pub trait StatsSource {
+    // identifies the source among others of the same process, e.g., "xsk/eth0/0"
+    fn stats_id(&self) -> String;
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat));
+
+    fn stats(&self) -> Vec<Stat> {
+        let mut stats = Vec::new();
+        self.visit_stats(&mut |stat| stats.push(stat));
+        stats
+    }
+}
+
+impl<T: StatsSource + ?Sized> StatsSource for Rc<T> {
+    fn stats_id(&self) -> String {
+        self.as_ref().stats_id()
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.as_ref().visit_stats(visit)
+    }
+}
+
+impl<T: StatsSource + ?Sized> StatsSource for Arc<T> {
+    fn stats_id(&self) -> String {
+        self.as_ref().stats_id()
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.as_ref().visit_stats(visit)
+    }
+}
+
+impl<T: StatsSource + ?Sized> StatsSource for RefCell<T> {
+    fn stats_id(&self) -> String {
+        self.borrow().stats_id()
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.borrow().visit_stats(visit)
+    }
+}
+
+impl<T: StatsSource + ?Sized> StatsSource for Mutex<T> {
+    fn stats_id(&self) -> String {
+        self.lock().unwrap().stats_id()
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.lock().unwrap().visit_stats(visit)
+    }
+}
+
+type ReportCallback = Box<dyn FnMut(&XskStatDelta) + Send>;
+
+// Reports rates of socket counters once per interval. It doesn't own a thread, the
+// polling loop calls poll with the current counters, e.g., after each batch. The packet
+// and byte counters it reports on need the trace feature, all rates are zero without it.
+pub struct StatsReporter {
+    interval: Duration,
+    last: Option<XskStatSnapshot>,
+    callback: ReportCallback,
+}
+
+impl StatsReporter {
+    // log rates with the given prefix at info level
+    pub fn log(interval: Duration, prefix: &str) -> Self {
+        let prefix = prefix.to_string();
+        Self::callback(interval, move |delta| log::info!("{}: {}", prefix, delta))
+    }
+
+    pub fn callback(
+        interval: Duration,
+        callback: impl FnMut(&XskStatDelta) + Send + 'static,
+    ) -> Self {
+        Self {
+            interval,
+            last: None,
+            callback: Box::new(callback),
+        }
+    }
+
+    // returns the delta if an interval has passed since the last report
+    pub fn poll(&mut self, stat: &XskStat) -> Option<XskStatDelta> {
+        let now = stat.snapshot();
+        let Some(last) = &self.last else {
+            self.last = Some(now);
+            return None;
+        };
+
+        if now.taken_at.saturating_duration_since(last.taken_at) < self.interval {
+            return None;
+        }
+
+        let delta = now.delta(last);
+        (self.callback)(&delta);
+        self.last = Some(now);
+        Some(delta)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rates {
+    pub rx_pps: f64,
+    pub rx_bps: f64,
+    pub tx_pps: f64,
+    pub tx_bps: f64,
+}
+
+// Smoothed rates of socket counters, an exponentially weighted moving average of the rates
+// of deltas, e.g., for logging or adapting batch sizes. The weight of a rate decays with
+// time rather than with updates, so that deltas of irregular intervals are averaged
+// correctly: a rate measured half_life ago weighs half as much as a current one. Like
+// StatsReporter, it measures nothing but zeros without the trace feature.
+pub struct Meter {
+    half_life: Duration,
+    rates: Option<Rates>,
+    last: Option<XskStatSnapshot>,
+}
+
+impl Meter {
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life,
+            rates: None,
+            last: None,
+        }
+    }
+
+    // the smoothed rates, zero until the first delta
+    pub fn rates(&self) -> Rates {
+        self.rates.unwrap_or_default()
+    }
+
+    pub fn update(&mut self, delta: &XskStatDelta) -> Rates {
+        if delta.elapsed.is_zero() {
+            return self.rates();
+        }
+        let current = Rates {
+            rx_pps: delta.rx_pps(),
+            rx_bps: delta.rx_bps(),
+            tx_pps: delta.tx_pps(),
+            tx_bps: delta.tx_bps(),
+        };
+        let Some(rates) = self.rates.as_mut() else {
+            self.rates = Some(current);
+            return current;
+        };
+
+        // the weight of the previous average
+        let decay = if self.half_life.is_zero() {
+            0.0
+        } else {
+            0.5f64.powf(delta.elapsed.as_secs_f64() / self.half_life.as_secs_f64())
+        };
+        let average = |old: f64, new: f64| old * decay + new * (1.0 - decay);
+        rates.rx_pps = average(rates.rx_pps, current.rx_pps);
+        rates.rx_bps = average(rates.rx_bps, current.rx_bps);
+        rates.tx_pps = average(rates.tx_pps, current.tx_pps);
+        rates.tx_bps = average(rates.tx_bps, current.tx_bps);
+        *rates
+    }
+
+    // updates the rates with the counters accumulated since the last poll
+    pub fn poll(&mut self, stat: &XskStat) -> Rates {
+        let now = stat.snapshot();
+        let rates = match &self.last {
+            Some(last) => self.update(&now.delta(last)),
+            None => self.rates(),
+        };
+        self.last = Some(now);
+        rates
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::socket::{af_xdp::XskStatHandle, mock::MockXskSocket, Socket};
+
+    #[test]
+    fn test_enumerate_sources() {
+        let (mut left, mut right) = MockXskSocket::pair(8, 2048).unwrap();
+
+        let mut frame = left.allocate(1).unwrap().pop().unwrap();
+        frame.raw_buffer_append(60).unwrap();
+        left.send(frame).unwrap();
+        let _received = right.recv().unwrap().unwrap();
+
+        let sources: Vec<&dyn StatsSource> = vec![&left, left.umem(), &right, right.umem()];
+        let collected: Vec<_> = sources
+            .iter()
+            .map(|source| (source.stats_id(), source.stats()))
+            .collect();
+
+        assert!(collected[0].0.starts_with("mock/plain_umem/"));
+        assert_ne!(collected[0].0, collected[2].0);
+        assert!(collected[0].1.contains(&Stat::counter("tx_packets", 1)));
+        assert!(collected[0].1.contains(&Stat::counter("tx_bytes", 60)));
+        assert!(collected[1].1.contains(&Stat::gauge("free_chunks", 8)));
+        assert!(collected[2].1.contains(&Stat::counter("rx_packets", 1)));
+        assert!(collected[3].1.contains(&Stat::gauge("free_chunks", 7)));
+        assert!(collected[3].1.contains(&Stat::gauge("chunk_size", 2048)));
+    }
+
+    #[test]
+    fn test_stat_handle() {
+        let handle = Arc::new(XskStatHandle::default());
+        let stat = XskStat {
+            rx_packets: 3,
+            tx_bytes: 180,
+            tx_inflight_bytes: 60,
+            ..Default::default()
+        };
+
+        let reader = handle.clone();
+        handle.store(&stat);
+        let loaded = std::thread::spawn(move || reader.load()).join().unwrap();
+        assert_eq!(loaded.rx_packets, 3);
+        assert_eq!(loaded.tx_bytes, 180);
+        assert!(handle
+            .stats()
+            .contains(&Stat::gauge("tx_inflight_bytes", 60)));
+    }
+
+    #[test]
+    fn test_stat_delta() {
+        let mut stat = XskStat {
+            rx_packets: 100,
+            rx_bytes: 6400,
+            rx_batch: 10,
+            ..Default::default()
+        };
+        let prev = stat.snapshot();
+
+        stat.rx_packets += 300;
+        stat.rx_bytes += 19200;
+        stat.rx_batch += 10;
+        stat.tx_inflight_bytes = 128;
+        let mut now = stat.snapshot();
+        now.taken_at = prev.taken_at + Duration::from_millis(500);
+
+        let delta = now.delta(&prev);
+        assert_eq!(delta.stat.rx_packets, 300);
+        assert_eq!(delta.stat.tx_inflight_bytes, 128);
+        assert_eq!(delta.rx_pps(), 600.0);
+        assert_eq!(delta.rx_bps(), 19200.0 * 8.0 * 2.0);
+        assert_eq!(delta.avg_rx_batch(), 30.0);
+        assert_eq!(delta.avg_tx_batch(), 0.0);
+
+        let reports = Arc::new(Mutex::new(0));
+        let reports_clone = reports.clone();
+        let mut reporter =
+            StatsReporter::callback(Duration::ZERO, move |_| *reports_clone.lock().unwrap() += 1);
+        assert!(reporter.poll(&stat).is_none());
+        assert!(reporter.poll(&stat).is_some());
+        assert_eq!(*reports.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_meter() {
+        let delta = |rx_packets: u64, millis: u64| XskStatDelta {
+            elapsed: Duration::from_millis(millis),
+            stat: XskStat {
+                rx_packets,
+                rx_bytes: rx_packets * 64,
+                ..Default::default()
+            },
+        };
+
+        let mut meter = Meter::new(Duration::from_secs(1));
+        assert_eq!(meter.rates(), Rates::default());
+        // the first rate is taken as is
+        assert_eq!(meter.update(&delta(1000, 1000)).rx_pps, 1000.0);
+        // a rate of one half-life ago weighs as much as the current one
+        let rates = meter.update(&delta(3000, 1000));
+        assert_eq!(rates.rx_pps, 2000.0);
+        assert_eq!(rates.rx_bps, 2000.0 * 64.0 * 8.0);
+        assert_eq!(rates.tx_pps, 0.0);
+        // two deltas of half the interval decay the average as much as one
+        meter.update(&delta(0, 500));
+        let rx_pps = meter.update(&delta(0, 500)).rx_pps;
+        assert!((rx_pps - 1000.0).abs() < 1e-6);
+        assert_eq!(meter.update(&delta(5, 0)).rx_pps, rx_pps);
+
+        let mut meter = Meter::new(Duration::ZERO);
+        meter.update(&delta(1000, 1000));
+        assert_eq!(meter.update(&delta(10, 1000)).rx_pps, 10.0);
+
+        let mut stat = XskStat::default();
+        assert_eq!(meter.poll(&stat).rx_pps, 10.0);
+        stat.tx_packets = 100;
+        std::thread::sleep(Duration::from_millis(1));
+        assert!(meter.poll(&stat).tx_pps > 0.0);
+    }
+}
@@ -0,0 +1,14 @@
+// Synchronization primitives shared by the accessors of a UMem. The models checking
+// their interleavings replace them with the ones of loom:
+//
+//   RUSTFLAGS="--cfg loom" cargo test -p camellia --release --lib loom
+#[cfg(loom)]
+pub(crate) use loom::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+#[cfg(not(loom))]
+pub(crate) use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
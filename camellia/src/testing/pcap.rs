@@ -0,0 +1,381 @@
+use std::{fmt::Display, path::Path, time::Duration};
+
+use crate::{
+    error::CamelliaError,
+    umem::{frame::AppFrame, AccessorRef},
+};
+
+const PCAP_MAGIC_MICROS: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const PCAP_GLOBAL_HEADER_LEN: usize = 24;
+const PCAP_RECORD_HEADER_LEN: usize = 16;
+const LINKTYPE_ETHERNET: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PcapPacket {
+    pub timestamp: Duration,
+    // original length on the wire, may be larger than data if the capture is truncated
+    pub orig_len: u32,
+    pub data: Vec<u8>,
+}
+
+pub fn parse_pcap(bytes: &[u8]) -> Result<Vec<PcapPacket>, CamelliaError> {
+    if bytes.len() < PCAP_GLOBAL_HEADER_LEN {
+        return Err(CamelliaError::InvalidArgument(format!(
+            "pcap file is too short: {} bytes",
+            bytes.len()
+        )));
+    }
+
+    let magic = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    let (big_endian, nanos) = match (u32::from_le_bytes(magic), u32::from_be_bytes(magic)) {
+        (PCAP_MAGIC_MICROS, _) => (false, false),
+        (PCAP_MAGIC_NANOS, _) => (false, true),
+        (_, PCAP_MAGIC_MICROS) => (true, false),
+        (_, PCAP_MAGIC_NANOS) => (true, true),
+        _ => {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "unknown pcap magic number: {:02x?}",
+                magic
+            )))
+        }
+    };
+
+    let read_u32 = |offset: usize| {
+        let field = [
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ];
+        if big_endian {
+            u32::from_be_bytes(field)
+        } else {
+            u32::from_le_bytes(field)
+        }
+    };
+
+    let link_type = read_u32(20);
+    if link_type != LINKTYPE_ETHERNET {
+        return Err(CamelliaError::InvalidArgument(format!(
+            "unsupported pcap link type: {}",
+            link_type
+        )));
+    }
+
+    let mut packets = Vec::new();
+    let mut offset = PCAP_GLOBAL_HEADER_LEN;
+
+    while offset < bytes.len() {
+        if offset + PCAP_RECORD_HEADER_LEN > bytes.len() {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "truncated pcap record header at offset {}",
+                offset
+            )));
+        }
+
+        let ts_sec = read_u32(offset);
+        let ts_frac = read_u32(offset + 4);
+        let incl_len = read_u32(offset + 8) as usize;
+        let orig_len = read_u32(offset + 12);
+        offset += PCAP_RECORD_HEADER_LEN;
+
+        if offset + incl_len > bytes.len() {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "truncated pcap record at offset {}: {} bytes expected, {} bytes left",
+                offset,
+                incl_len,
+                bytes.len() - offset
+            )));
+        }
+
+        let timestamp = Duration::from_secs(ts_sec as u64)
+            + if nanos {
+                Duration::from_nanos(ts_frac as u64)
+            } else {
+                Duration::from_micros(ts_frac as u64)
+            };
+
+        packets.push(PcapPacket {
+            timestamp,
+            orig_len,
+            data: bytes[offset..offset + incl_len].to_vec(),
+        });
+        offset += incl_len;
+    }
+
+    Ok(packets)
+}
+
+pub fn read_pcap<P: AsRef<Path>>(path: P) -> Result<Vec<PcapPacket>, CamelliaError> {
+    let bytes = std::fs::read(path.as_ref()).map_err(|e| {
+        CamelliaError::InvalidArgument(format!(
+            "unable to read pcap file {}: {}",
+            path.as_ref().display(),
+            e
+        ))
+    })?;
+    parse_pcap(&bytes)
+}
+
+pub fn encode_pcap<I, B>(packets: I) -> Vec<u8>
+where
+    I: IntoIterator<Item = B>,
+    B: AsRef<[u8]>,
+{
+    let mut bytes = Vec::with_capacity(PCAP_GLOBAL_HEADER_LEN);
+    bytes.extend_from_slice(&PCAP_MAGIC_MICROS.to_le_bytes());
+    // version 2.4
+    bytes.extend_from_slice(&2u16.to_le_bytes());
+    bytes.extend_from_slice(&4u16.to_le_bytes());
+    // thiszone and sigfigs
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    // snaplen
+    bytes.extend_from_slice(&65535u32.to_le_bytes());
+    bytes.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+
+    for packet in packets {
+        let data = packet.as_ref();
+        // timestamps are irrelevant for golden files, keep them zero for stable output
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+    }
+
+    bytes
+}
+
+pub fn write_pcap<P, I, B>(path: P, packets: I) -> Result<(), CamelliaError>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = B>,
+    B: AsRef<[u8]>,
+{
+    std::fs::write(path.as_ref(), encode_pcap(packets)).map_err(|e| {
+        CamelliaError::InvalidArgument(format!(
+            "unable to write pcap file {}: {}",
+            path.as_ref().display(),
+            e
+        ))
+    })
+}
+
+// Copy packets into freshly allocated frames, ready to be sent or fed to the application
+pub fn load_frames<M>(
+    accessor: &M,
+    packets: &[PcapPacket],
+) -> Result<Vec<AppFrame<M>>, CamelliaError>
+where
+    M: AccessorRef,
+{
+    let mut frames = accessor.allocate(packets.len())?;
+
+    for (frame, packet) in frames.iter_mut().zip(packets) {
+        frame
+            .raw_buffer_append(packet.data.len())?
+            .copy_from_slice(&packet.data);
+    }
+
+    Ok(frames)
+}
+
+// A byte range ignored when comparing frames, e.g., IP ID or checksums
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mask {
+    pub offset: usize,
+    pub len: usize,
+}
+
+const ETHERNET_HEADER_LEN: usize = 14;
+
+impl Mask {
+    pub fn new(offset: usize, len: usize) -> Self {
+        Self { offset, len }
+    }
+
+    // field offsets assume an untagged Ethernet frame carrying IPv4 without options
+    pub fn ipv4_id() -> Self {
+        Self::new(ETHERNET_HEADER_LEN + 4, 2)
+    }
+
+    pub fn ipv4_ttl() -> Self {
+        Self::new(ETHERNET_HEADER_LEN + 8, 1)
+    }
+
+    pub fn ipv4_checksum() -> Self {
+        Self::new(ETHERNET_HEADER_LEN + 10, 2)
+    }
+
+    pub fn l4_checksum_udp() -> Self {
+        Self::new(ETHERNET_HEADER_LEN + 20 + 6, 2)
+    }
+
+    pub fn l4_checksum_tcp() -> Self {
+        Self::new(ETHERNET_HEADER_LEN + 20 + 16, 2)
+    }
+
+    fn covers(&self, offset: usize) -> bool {
+        offset >= self.offset && offset < self.offset + self.len
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GoldenMismatch {
+    Count {
+        expected: usize,
+        actual: usize,
+    },
+    Length {
+        index: usize,
+        expected: usize,
+        actual: usize,
+    },
+    Content {
+        index: usize,
+        offset: usize,
+        expected: u8,
+        actual: u8,
+    },
+}
+
+impl Display for GoldenMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GoldenMismatch::Count { expected, actual } => {
+                write!(f, "{} packets expected, {} packets found", expected, actual)
+            }
+            GoldenMismatch::Length {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "packet {}: length {} expected, {} found",
+                index, expected, actual
+            ),
+            GoldenMismatch::Content {
+                index,
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "packet {}: byte at offset {} is {:#04x}, {:#04x} expected",
+                index, offset, actual, expected
+            ),
+        }
+    }
+}
+
+pub fn compare_golden<B>(
+    actual: &[B],
+    golden: &[PcapPacket],
+    masks: &[Mask],
+) -> Result<(), GoldenMismatch>
+where
+    B: AsRef<[u8]>,
+{
+    if actual.len() != golden.len() {
+        return Err(GoldenMismatch::Count {
+            expected: golden.len(),
+            actual: actual.len(),
+        });
+    }
+
+    for (index, (actual, expected)) in actual.iter().zip(golden).enumerate() {
+        let actual = actual.as_ref();
+        let expected = expected.data.as_slice();
+
+        if actual.len() != expected.len() {
+            return Err(GoldenMismatch::Length {
+                index,
+                expected: expected.len(),
+                actual: actual.len(),
+            });
+        }
+
+        if let Some(offset) = (0..actual.len())
+            .find(|&i| actual[i] != expected[i] && !masks.iter().any(|mask| mask.covers(i)))
+        {
+            return Err(GoldenMismatch::Content {
+                index,
+                offset,
+                expected: expected[offset],
+                actual: actual[offset],
+            });
+        }
+    }
+
+    Ok(())
+}
+
+pub fn assert_golden<B, P>(actual: &[B], golden: P, masks: &[Mask])
+where
+    B: AsRef<[u8]>,
+    P: AsRef<Path>,
+{
+    let packets = read_pcap(golden.as_ref()).unwrap();
+    if let Err(mismatch) = compare_golden(actual, &packets, masks) {
+        panic!(
+            "frames do not match golden file {}: {}",
+            golden.as_ref().display(),
+            mismatch
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn packet(ip_id: u16) -> Vec<u8> {
+        let mut data = vec![0u8; 42];
+        data[12] = 0x08;
+        data[14] = 0x45;
+        data[18..20].copy_from_slice(&ip_id.to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn test_pcap_roundtrip() {
+        let packets = vec![packet(1), packet(2)];
+        let parsed = parse_pcap(&encode_pcap(&packets)).unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].data, packets[0]);
+        assert_eq!(parsed[1].orig_len, 42);
+    }
+
+    #[test]
+    fn test_pcap_truncated() {
+        let bytes = encode_pcap([packet(1)]);
+        assert!(parse_pcap(&bytes[..bytes.len() - 1]).is_err());
+        assert!(parse_pcap(&bytes[..10]).is_err());
+    }
+
+    #[test]
+    fn test_compare_with_mask() {
+        let golden = parse_pcap(&encode_pcap([packet(1)])).unwrap();
+
+        assert_eq!(
+            compare_golden(&[packet(2)], &golden, &[]),
+            Err(GoldenMismatch::Content {
+                index: 0,
+                offset: 19,
+                expected: 1,
+                actual: 2
+            })
+        );
+        assert!(compare_golden(&[packet(2)], &golden, &[Mask::ipv4_id()]).is_ok());
+        assert_eq!(
+            compare_golden(&[packet(1), packet(1)], &golden, &[]),
+            Err(GoldenMismatch::Count {
+                expected: 1,
+                actual: 2
+            })
+        );
+    }
+}
@@ -3,7 +3,10 @@ use std::{
     cmp::min,
     fmt::Display,
     ops::{AddAssign, SubAssign},
-    os::{fd::AsRawFd, raw::c_void},
+    os::{
+        fd::{AsRawFd, BorrowedFd},
+        raw::c_void,
+    },
     pin::Pin,
     rc::Rc,
     sync::{Arc, Mutex},
@@ -12,24 +15,125 @@ use std::{
 use libxdp_sys::{
     xsk_ring_cons, xsk_ring_cons__comp_addr, xsk_ring_cons__peek, xsk_ring_cons__release,
     xsk_ring_prod, xsk_ring_prod__needs_wakeup, xsk_umem, xsk_umem__create, xsk_umem__delete,
-    xsk_umem__fd, xsk_umem_config, XSK_RING_CONS__DEFAULT_NUM_DESCS,
+    xsk_umem__fd, xsk_umem_config, XDP_UMEM_UNALIGNED_CHUNK_FLAG, XSK_RING_CONS__DEFAULT_NUM_DESCS,
     XSK_RING_PROD__DEFAULT_NUM_DESCS, XSK_UMEM__DEFAULT_FRAME_HEADROOM,
     XSK_UMEM__DEFAULT_FRAME_SIZE,
 };
 use nix::errno::Errno;
 
-use crate::error::CamelliaError;
+use crate::{
+    config::UMemConfig,
+    error::CamelliaError,
+    stats::{Stat, StatsSource},
+    sync,
+};
 
 use super::{
     frame::{AppFrame, Chunk},
-    libxdp::populate_fill_ring,
+    libxdp::{pending_entries, populate_fill_ring},
     mmap::MMapArea,
-    AccessorRef,
+    refcount::{ChunkRefs, ChunkRefsRef},
+    shared::{ChunkSegments, SharedAccessorCounters},
+    tracker::{ChunkTracker, ChunkTrackerRef},
+    AccessorRef, RingState, UMemStat,
 };
 
+// the kernel refuses chunks smaller than this
+const XDP_UMEM_MIN_CHUNK_SIZE: u32 = 2048;
+// reserved by the kernel in front of every received packet
+pub const XDP_PACKET_HEADROOM: u32 = 256;
+
+// Packets are received at chunk base + headroom, i.e., the same page offset in every chunk
+// when chunks are page sized, so headers of consecutive packets compete for the same cache
+// sets. Staggering shifts each chunk by a varying multiple of the cache line size.
+const STAGGER_STEP: u32 = 64;
+const STAGGER_SLOTS: u32 = 8;
+// the largest stagger offset, staggered UMems need at least this much frame headroom
+pub const STAGGER_HEADROOM: u32 = (STAGGER_SLOTS - 1) * STAGGER_STEP;
+
+// In unaligned chunk mode, the kernel reports RX addresses as the address of the fill
+// descriptor with the offset of the packet from it in the upper bits
+const XSK_UNALIGNED_BUF_OFFSET_SHIFT: u64 = 48;
+const XSK_UNALIGNED_BUF_ADDR_MASK: u64 = (1 << XSK_UNALIGNED_BUF_OFFSET_SHIFT) - 1;
+
+// xdp address of the packet described by an RX or completion descriptor, addresses of
+// aligned UMems are returned unchanged, see xsk_umem__add_offset_to_addr
+pub fn decode_desc_addr(addr: u64) -> u64 {
+    (addr & XSK_UNALIGNED_BUF_ADDR_MASK) + (addr >> XSK_UNALIGNED_BUF_OFFSET_SHIFT)
+}
+
+const DEFAULT_SEGMENT_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkLayout {
+    chunk_size: u32,
+    // chunk indexes are computed by shifts instead of divisions for power of two sizes
+    chunk_shift: Option<u32>,
+    stagger: bool,
+}
+
+impl ChunkLayout {
+    pub fn new(chunk_size: u32, stagger: bool) -> Self {
+        Self {
+            chunk_size,
+            chunk_shift: chunk_size
+                .is_power_of_two()
+                .then(|| chunk_size.trailing_zeros()),
+            stagger,
+        }
+    }
+
+    // distance between two consecutive chunks
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size
+    }
+
+    pub fn is_staggered(&self) -> bool {
+        self.stagger
+    }
+
+    // bytes from the chunk address to the end of the chunk, the kernel headroom of staggered
+    // UMems shrinks by the same STAGGER_HEADROOM, so packets get the room they would get
+    // without staggering
+    pub fn usable_size(&self) -> u32 {
+        if self.stagger {
+            self.chunk_size - STAGGER_HEADROOM
+        } else {
+            self.chunk_size
+        }
+    }
+
+    pub fn chunk_address(&self, index: usize) -> usize {
+        let offset = if self.stagger {
+            (index % STAGGER_SLOTS as usize) * STAGGER_STEP as usize
+        } else {
+            0
+        };
+        index * self.chunk_size as usize + offset
+    }
+
+    // index of the chunk containing xdp_addr
+    pub fn chunk_index(&self, xdp_addr: u64) -> usize {
+        match self.chunk_shift {
+            Some(shift) => (xdp_addr >> shift) as usize,
+            None => xdp_addr as usize / self.chunk_size as usize,
+        }
+    }
+
+    // xdp address of the chunk containing xdp_addr
+    pub fn chunk_base(&self, xdp_addr: u64) -> usize {
+        self.chunk_address(self.chunk_index(xdp_addr))
+    }
+}
+
 pub struct UMemBuilder {
     chunk_size: u32,
     num_chunks: Option<u32>,
+    stagger_headroom: bool,
+    track_chunks: bool,
+    refcount_chunks: bool,
+    memfd: bool,
+    segment_size: usize,
     frame_headroom: u32,
     fill_queue_size: u32,
     completion_queue_size: u32,
@@ -46,12 +150,35 @@ impl UMemBuilder {
         UMemBuilder {
             chunk_size: XSK_UMEM__DEFAULT_FRAME_SIZE,
             num_chunks: None,
+            stagger_headroom: false,
+            track_chunks: false,
+            refcount_chunks: false,
+            memfd: false,
+            segment_size: DEFAULT_SEGMENT_SIZE,
             frame_headroom: XSK_UMEM__DEFAULT_FRAME_HEADROOM,
             fill_queue_size: XSK_RING_PROD__DEFAULT_NUM_DESCS,
             completion_queue_size: XSK_RING_CONS__DEFAULT_NUM_DESCS,
         }
     }
 
+    pub fn from_config(config: &UMemConfig) -> Self {
+        let defaults = Self::new();
+        UMemBuilder {
+            chunk_size: config.chunk_size.unwrap_or(defaults.chunk_size),
+            num_chunks: Some(config.num_chunks),
+            stagger_headroom: config.stagger_headroom,
+            track_chunks: config.track_chunks,
+            refcount_chunks: config.refcount_chunks,
+            memfd: false,
+            segment_size: config.segment_size.unwrap_or(defaults.segment_size),
+            frame_headroom: config.frame_headroom.unwrap_or(defaults.frame_headroom),
+            fill_queue_size: config.fill_queue_size.unwrap_or(defaults.fill_queue_size),
+            completion_queue_size: config
+                .completion_queue_size
+                .unwrap_or(defaults.completion_queue_size),
+        }
+    }
+
     pub fn chunk_size(mut self, chunk_size: u32) -> Self {
         self.chunk_size = chunk_size;
         self
@@ -62,6 +189,43 @@ impl UMemBuilder {
         self
     }
 
+    // Offset the start of consecutive chunks by a varying stride to avoid cache aliasing.
+    // This switches the UMem to unaligned chunk mode, the offset is taken out of the frame
+    // headroom, which must be at least STAGGER_HEADROOM bytes.
+    pub fn stagger_headroom(mut self, stagger_headroom: bool) -> Self {
+        self.stagger_headroom = stagger_headroom;
+        self
+    }
+
+    // Debug aid recording the state of every chunk, leaks and double frees are
+    // reported when the UMem is dropped
+    pub fn track_chunks(mut self, track_chunks: bool) -> Self {
+        self.track_chunks = track_chunks;
+        self
+    }
+
+    // Count references to chunks so that frames can be shared by TxFrame::share, e.g., to
+    // send a packet on several sockets of a shared UMem without copying it. Freeing and
+    // completing chunks then takes an atomic operation each.
+    pub fn refcount_chunks(mut self, refcount_chunks: bool) -> Self {
+        self.refcount_chunks = refcount_chunks;
+        self
+    }
+
+    // Back the chunks with a memfd instead of anonymous memory, so that other processes
+    // can map them through UMem::memfd, see examples/shared_umem.rs
+    pub fn memfd(mut self, memfd: bool) -> Self {
+        self.memfd = memfd;
+        self
+    }
+
+    // Number of chunks moved at once between a shared accessor's cache and the chunk
+    // segments shared by all accessors. Each accessor caches up to two segments.
+    pub fn segment_size(mut self, segment_size: usize) -> Self {
+        self.segment_size = segment_size;
+        self
+    }
+
     pub fn frame_headroom(mut self, frame_headroom: u32) -> Self {
         self.frame_headroom = frame_headroom;
         self
@@ -77,26 +241,113 @@ impl UMemBuilder {
         self
     }
 
-    pub fn build(self) -> Result<UMem, CamelliaError> {
-        if self.num_chunks.is_none() {
-            return Err(CamelliaError::InvalidArgument(
-                "number of chunks must be specified".to_string(),
+    fn validate(&self) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        if !matches!(self.num_chunks, Some(num_chunks) if num_chunks > 0) {
+            violations.push("number of chunks must be specified and positive".to_string());
+        }
+
+        let layout = ChunkLayout::new(self.chunk_size, self.stagger_headroom);
+        if layout.is_staggered() {
+            let min_chunk_size = XDP_UMEM_MIN_CHUNK_SIZE + STAGGER_HEADROOM;
+            if self.chunk_size < min_chunk_size {
+                violations.push(format!(
+                    "chunk size {} is too small to stagger, at least {} bytes are required",
+                    self.chunk_size, min_chunk_size
+                ));
+            }
+            if self.frame_headroom < STAGGER_HEADROOM {
+                violations.push(format!(
+                    "frame headroom {} is too small to stagger, at least {} bytes are required",
+                    self.frame_headroom, STAGGER_HEADROOM
+                ));
+            }
+        } else {
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u32;
+            if !self.chunk_size.is_power_of_two()
+                || self.chunk_size < XDP_UMEM_MIN_CHUNK_SIZE
+                || self.chunk_size > page_size
+            {
+                violations.push(format!(
+                    "chunk size {} must be a power of two between {} and the page size {}, \
+                     enable stagger_headroom for other sizes",
+                    self.chunk_size, XDP_UMEM_MIN_CHUNK_SIZE, page_size
+                ));
+            }
+        }
+
+        // the stagger offset comes out of the frame headroom, so it doesn't shrink packets
+        if self.frame_headroom.saturating_add(XDP_PACKET_HEADROOM) >= self.chunk_size {
+            violations.push(format!(
+                "frame headroom {} leaves no room for packets in chunks of {} bytes",
+                self.frame_headroom, self.chunk_size
             ));
         }
 
+        for (name, size) in [
+            ("fill", self.fill_queue_size),
+            ("completion", self.completion_queue_size),
+        ] {
+            if !size.is_power_of_two() {
+                violations.push(format!(
+                    "{} queue size {} must be a power of two",
+                    name, size
+                ));
+            }
+        }
+
+        if self.segment_size == 0 {
+            violations.push("segment size must be positive".to_string());
+        }
+
+        violations
+    }
+
+    pub fn build(self) -> Result<UMem, CamelliaError> {
+        let violations = self.validate();
+        if !violations.is_empty() {
+            return Err(CamelliaError::InvalidConfig(violations));
+        }
+
+        let layout = ChunkLayout::new(self.chunk_size, self.stagger_headroom);
+
         let xsk_config = xsk_umem_config {
-            frame_size: self.chunk_size,
-            frame_headroom: self.frame_headroom,
+            frame_size: layout.usable_size(),
+            // chunks start up to STAGGER_HEADROOM bytes late, packets still start within
+            // the frame headroom
+            frame_headroom: if layout.is_staggered() {
+                self.frame_headroom - STAGGER_HEADROOM
+            } else {
+                self.frame_headroom
+            },
             fill_size: self.fill_queue_size,
             comp_size: self.completion_queue_size,
-            flags: 0,
+            flags: if layout.is_staggered() {
+                XDP_UMEM_UNALIGNED_CHUNK_FLAG
+            } else {
+                0
+            },
         };
 
-        UMem::new(self.chunk_size, self.num_chunks.unwrap(), xsk_config)
+        let mut umem = UMem::new(layout, self.num_chunks.unwrap(), xsk_config, self.memfd)?;
+        umem.segment_size = self.segment_size;
+        if self.track_chunks {
+            umem.tracker = Some(Arc::new(Mutex::new(ChunkTracker::new(
+                umem.chunks.iter().copied(),
+            ))));
+        }
+        if self.refcount_chunks {
+            umem.refs = Some(Arc::new(ChunkRefs::new(layout, umem.num_chunks())));
+        }
+        Ok(umem)
     }
 }
 
+// Ring wrappers are written on every batch, each takes whole cache lines so that the
+// rings of different sockets and threads don't share them
 #[derive(Debug)]
+#[repr(align(64))]
 pub struct FillQueue(pub xsk_ring_prod);
 
 unsafe impl Send for FillQueue {}
@@ -117,6 +368,7 @@ impl Default for FillQueue {
 }
 
 #[derive(Debug)]
+#[repr(align(64))]
 pub struct CompletionQueue(pub xsk_ring_cons);
 
 unsafe impl Send for CompletionQueue {}
@@ -136,6 +388,56 @@ impl Default for CompletionQueue {
     }
 }
 
+// Ring indexes and entries in plain memory, leaked, instead of being mapped from the
+// kernel. Tests play the kernel side of such rings.
+#[cfg(test)]
+fn detached_ring(size: u32) -> (*mut u32, *mut u32, *mut c_void, *mut u32) {
+    let indexes = Box::leak(Box::new([0u32; 3]));
+    let ring = Box::leak(vec![0u64; size as usize].into_boxed_slice());
+    let (producer, rest) = indexes.split_first_mut().unwrap();
+    let (consumer, flags) = rest.split_first_mut().unwrap();
+    (
+        producer,
+        consumer,
+        ring.as_mut_ptr() as *mut c_void,
+        &mut flags[0],
+    )
+}
+
+#[cfg(test)]
+impl FillQueue {
+    pub(crate) fn detached(size: u32) -> Self {
+        let (producer, consumer, ring, flags) = detached_ring(size);
+        FillQueue(xsk_ring_prod {
+            cached_prod: 0,
+            cached_cons: size,
+            mask: size - 1,
+            size,
+            producer,
+            consumer,
+            ring,
+            flags,
+        })
+    }
+}
+
+#[cfg(test)]
+impl CompletionQueue {
+    pub(crate) fn detached(size: u32) -> Self {
+        let (producer, consumer, ring, flags) = detached_ring(size);
+        CompletionQueue(xsk_ring_cons {
+            cached_prod: 0,
+            cached_cons: 0,
+            mask: size - 1,
+            size,
+            producer,
+            consumer,
+            ring,
+            flags,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct UMem {
     pub area: Arc<MMapArea>,
@@ -144,8 +446,16 @@ pub struct UMem {
     pub fill: Pin<Box<FillQueue>>,
     pub completion: Pin<Box<CompletionQueue>>,
     pub chunk_size: u32,
+    pub layout: ChunkLayout,
     _num_chunks: u32,
     pub inner: *mut xsk_umem,
+    tracker: Option<ChunkTrackerRef>,
+    refs: Option<ChunkRefsRef>,
+    // published by the accessors of a shared UMem
+    pub(crate) shared_counters: Vec<sync::Arc<SharedAccessorCounters>>,
+    pub(crate) segments: sync::Arc<ChunkSegments>,
+    pub(crate) segment_size: usize,
+    config: xsk_umem_config,
 }
 
 unsafe impl Send for UMem {}
@@ -154,13 +464,19 @@ static LOCKED_IO_MEMORY: Mutex<u64> = Mutex::new(0);
 
 impl UMem {
     fn new(
-        chunk_size: u32,
+        layout: ChunkLayout,
         num_chunks: u32,
         config: xsk_umem_config,
+        memfd: bool,
     ) -> Result<Self, CamelliaError> {
+        let chunk_size = layout.chunk_size();
         let mmap_size = chunk_size * num_chunks;
         let mut umem_inner: *mut xsk_umem = std::ptr::null_mut();
-        let area = Arc::new(MMapArea::new((chunk_size * num_chunks) as usize)?);
+        let area = Arc::new(if memfd {
+            MMapArea::memfd("camellia-umem", mmap_size as usize)?
+        } else {
+            MMapArea::new(mmap_size as usize)?
+        });
         let mut fill_queue = Box::pin(FillQueue::default());
         let mut completion_queue = Box::pin(CompletionQueue::default());
 
@@ -188,7 +504,12 @@ impl UMem {
                 &config,
             ) {
                 0 => {}
-                errno => return Err(Errno::from_raw(-errno).into()),
+                errno => {
+                    return Err(CamelliaError::syscall(
+                        "xsk_umem__create",
+                        Errno::from_raw(-errno),
+                    ))
+                }
             }
         }
 
@@ -198,12 +519,19 @@ impl UMem {
             fill: fill_queue,
             completion: completion_queue,
             chunk_size,
+            layout,
             _num_chunks: num_chunks,
             inner: umem_inner,
+            tracker: None,
+            refs: None,
+            shared_counters: Vec::new(),
+            segments: sync::Arc::new(ChunkSegments::default()),
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            config,
         };
 
         for i in 0..num_chunks {
-            umem.chunks.push((i * chunk_size) as usize)
+            umem.chunks.push(layout.chunk_address(i as usize))
         }
 
         Ok(umem)
@@ -213,20 +541,81 @@ impl UMem {
         self.inner
     }
 
+    // The memfd backing the chunks of a UMem built with memfd. Another process maps it
+    // with MMapArea::from_memfd and finds a frame at its xdp_address, but only touches
+    // chunks this process hands over, the UMem stays owned here.
+    pub fn memfd(&self) -> Option<BorrowedFd> {
+        self.area.fd()
+    }
+
+    pub fn tracker(&self) -> Option<&ChunkTrackerRef> {
+        self.tracker.as_ref()
+    }
+
+    pub fn refs(&self) -> Option<&ChunkRefsRef> {
+        self.refs.as_ref()
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self._num_chunks as usize
+    }
+
+    pub fn fill_size(&self) -> u32 {
+        self.fill.0.size
+    }
+
+    // the largest packet the kernel can receive into a chunk
+    pub fn max_frame_size(&self) -> u32 {
+        self.layout.usable_size() - self.config.frame_headroom - XDP_PACKET_HEADROOM
+    }
+
+    // The kernel refuses to bind the socket owning a UMem again, so the UMem outlives its
+    // socket only by registering the memory area anew. The fill and completion rings are
+    // recreated empty.
+    pub(crate) fn reregister(&mut self) -> Result<(), CamelliaError> {
+        let errno = unsafe { xsk_umem__delete(self.inner) };
+        if errno < 0 {
+            return Err(CamelliaError::syscall(
+                "xsk_umem__delete",
+                Errno::from_raw(-errno),
+            ));
+        }
+
+        self.inner = std::ptr::null_mut();
+        self.fill = Box::pin(FillQueue::default());
+        self.completion = Box::pin(CompletionQueue::default());
+        match unsafe {
+            xsk_umem__create(
+                &mut self.inner,
+                self.area.base_address() as *mut c_void,
+                self._num_chunks as u64 * self.chunk_size as u64,
+                &mut self.fill.as_mut().0,
+                &mut self.completion.as_mut().0,
+                &self.config,
+            )
+        } {
+            0 => Ok(()),
+            errno => Err(CamelliaError::syscall(
+                "xsk_umem__create",
+                Errno::from_raw(-errno),
+            )),
+        }
+    }
+
     pub fn allocate(&mut self, n: usize) -> Result<Vec<Chunk>, CamelliaError> {
         if self.chunks.len() < n {
-            return Err(CamelliaError::InvalidArgument(format!(
-                "SharedUMem::allocate: {} chunks requested, but only {} chunks available",
-                n,
-                self.chunks.len()
-            )));
+            return Err(CamelliaError::UMemExhausted {
+                requested: n,
+                available: self.chunks.len(),
+            });
         }
+        // free chunks are a stack, the most recently freed ones are likely still cached
         Ok(self
             .chunks
-            .drain(0..n)
+            .drain(self.chunks.len() - n..)
             .map(|address| Chunk {
                 xdp_address: address,
-                size: self.chunk_size as usize,
+                size: self.layout.usable_size() as usize,
                 mmap_area: self.area.clone(),
             })
             .collect())
@@ -239,13 +628,12 @@ impl UMem {
 
     pub fn allocate_raw(&mut self, n: usize) -> Result<Vec<usize>, CamelliaError> {
         if self.chunks.len() < n {
-            return Err(CamelliaError::InvalidArgument(format!(
-                "SharedUMem::allocate: {} chunks requested, but only {} chunks available",
-                n,
-                self.chunks.len()
-            )));
+            return Err(CamelliaError::UMemExhausted {
+                requested: n,
+                available: self.chunks.len(),
+            });
         }
-        Ok(self.chunks.drain(0..n).collect())
+        Ok(self.chunks.drain(self.chunks.len() - n..).collect())
     }
 
     pub fn free_raw(&mut self, chunks: impl IntoIterator<Item = usize>) {
@@ -253,6 +641,44 @@ impl UMem {
     }
 }
 
+#[cfg(test)]
+impl UMem {
+    // A UMem not registered to the kernel, whose rings are detached
+    pub(crate) fn detached(num_chunks: u32, ring_size: u32) -> Self {
+        let layout = ChunkLayout::new(XSK_UMEM__DEFAULT_FRAME_SIZE, false);
+        let mmap_size = layout.chunk_size() * num_chunks;
+        LOCKED_IO_MEMORY
+            .lock()
+            .unwrap()
+            .add_assign(mmap_size as u64);
+
+        UMem {
+            area: Arc::new(MMapArea::new(mmap_size as usize).unwrap()),
+            chunks: (0..num_chunks as usize)
+                .map(|index| layout.chunk_address(index))
+                .collect(),
+            fill: Box::pin(FillQueue::detached(ring_size)),
+            completion: Box::pin(CompletionQueue::detached(ring_size)),
+            chunk_size: layout.chunk_size(),
+            layout,
+            _num_chunks: num_chunks,
+            inner: std::ptr::null_mut(),
+            tracker: None,
+            refs: None,
+            shared_counters: Vec::new(),
+            segments: sync::Arc::new(ChunkSegments::default()),
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            config: xsk_umem_config {
+                fill_size: ring_size,
+                comp_size: ring_size,
+                frame_size: layout.usable_size(),
+                frame_headroom: 0,
+                flags: 0,
+            },
+        }
+    }
+}
+
 impl Display for UMem {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -271,6 +697,10 @@ impl Drop for UMem {
         }
         let mut locked_memory = LOCKED_IO_MEMORY.lock().unwrap();
         locked_memory.sub_assign(self._num_chunks as u64 * self.chunk_size as u64);
+
+        if let Some(tracker) = &self.tracker {
+            tracker.lock().unwrap().report();
+        }
     }
 }
 
@@ -284,12 +714,14 @@ impl AsRawFd for UMem {
 pub struct DedicatedAccessor {
     base: UMem,
     tx_issued_num: u32,
+    filled_num: usize,
 }
 
 impl DedicatedAccessor {
     pub fn new(base: UMem) -> Result<Self, CamelliaError> {
         let umem = DedicatedAccessor {
             tx_issued_num: 0,
+            filled_num: 0,
             base,
         };
 
@@ -301,14 +733,107 @@ impl DedicatedAccessor {
     }
 
     pub fn fill(&mut self, n: usize) -> Result<usize, CamelliaError> {
+        // chunks are taken from the tail of the pool
+        let candidates: Vec<usize> = match &self.base.tracker {
+            Some(_) => self.base.chunks[self.base.chunks.len().saturating_sub(n)..].to_vec(),
+            None => Vec::new(),
+        };
+
         let actual_filled = populate_fill_ring(&mut self.base.fill.0, n, &mut self.base.chunks);
+
+        if let Some(tracker) = &self.base.tracker {
+            let mut tracker = tracker.lock().unwrap();
+            candidates[candidates.len() - actual_filled..]
+                .iter()
+                .for_each(|address| tracker.fill(*address));
+        }
+        self.filled_num += actual_filled;
         Ok(actual_filled)
     }
 
     pub fn free(&mut self, chunk: Chunk) {
+        if let Some(refs) = &self.base.refs {
+            if !refs.release(chunk.xdp_address as u64) {
+                return;
+            }
+        }
+        if let Some(tracker) = &self.base.tracker {
+            tracker.lock().unwrap().free(chunk.xdp_address);
+        }
         self.base.free([chunk]);
     }
 
+    // Registers the UMem again after its socket is deleted. Chunks left in the rings of
+    // the UMem and the given ones taken from the RX and TX rings of the socket return to
+    // the pool, chunks held by a zero-copy driver are lost. Returns the number of them.
+    // The chunks are reclaimed before registering, so that a failed registration leaves
+    // a UMem without rings but with consistent accounting for the next attempt.
+    pub(crate) fn reregister(
+        &mut self,
+        rx: Vec<u64>,
+        tx: Vec<u64>,
+    ) -> Result<usize, CamelliaError> {
+        let layout = self.base.layout;
+        let (fill, completion) = (&self.base.fill.0, &self.base.completion.0);
+        // the rings are gone after a failed registration
+        let registered = !self.base.inner.is_null();
+        let mut filled: Vec<usize> = if registered {
+            unsafe { pending_entries::<u64>(fill.producer, fill.consumer, fill.ring, fill.mask) }
+        } else {
+            Vec::new()
+        }
+        .into_iter()
+        .chain(rx)
+        .map(|xdp_addr| layout.chunk_base(decode_desc_addr(xdp_addr)))
+        .collect();
+        let mut sent: Vec<usize> = if registered {
+            unsafe {
+                pending_entries::<u64>(
+                    completion.producer,
+                    completion.consumer,
+                    completion.ring,
+                    completion.mask,
+                )
+            }
+        } else {
+            Vec::new()
+        }
+        .into_iter()
+        .chain(tx)
+        .map(|xdp_addr| layout.chunk_base(decode_desc_addr(xdp_addr)))
+        .collect();
+        // a TX frame may be completed while the TX ring is read
+        filled.sort_unstable();
+        filled.dedup();
+        sent.sort_unstable();
+        sent.dedup();
+
+        // copies of shared chunks still held by the application must not be freed again
+        if let Some(refs) = &self.base.refs {
+            for address in filled.iter().chain(&sent) {
+                refs.reset(*address as u64);
+            }
+        }
+
+        if let Some(tracker) = &self.base.tracker {
+            let mut tracker = tracker.lock().unwrap();
+            for address in &filled {
+                tracker.receive(*address);
+                tracker.free(*address);
+            }
+            sent.iter().for_each(|address| tracker.complete(*address));
+        }
+
+        let lost = (self.filled_num + self.tx_issued_num as usize)
+            .saturating_sub(filled.len() + sent.len());
+        self.base.free_raw(filled.into_iter().chain(sent));
+        self.filled_num = 0;
+        self.tx_issued_num = 0;
+
+        self.base.reregister()?;
+        Ok(lost)
+    }
+
     pub fn recycle(&mut self) -> Result<usize, CamelliaError> {
         let mut start_index = 0;
         let completed = unsafe {
@@ -320,33 +845,115 @@ impl DedicatedAccessor {
         };
 
         for complete_index in 0..completed {
-            let xdp_addr = unsafe {
+            let xdp_addr = decode_desc_addr(unsafe {
                 *xsk_ring_cons__comp_addr(&self.base.completion.0, start_index + complete_index)
-            };
+            });
 
-            self.base.free_raw([xdp_addr as usize]);
+            let chunk_address = self.base.layout.chunk_base(xdp_addr);
+            if let Some(refs) = &self.base.refs {
+                if !refs.release(xdp_addr) {
+                    continue;
+                }
+            }
+            if let Some(tracker) = &self.base.tracker {
+                tracker.lock().unwrap().complete(chunk_address);
+            }
+            self.base.free_raw([chunk_address]);
         }
 
         unsafe {
             xsk_ring_cons__release(&mut self.base.completion.0, completed);
         }
+        self.tx_issued_num -= completed;
 
         Ok(completed as usize)
     }
 
-    pub fn extract_recv(&mut self, xdp_addr: u64) -> Chunk {
-        let base_address = xdp_addr - (xdp_addr % (self.base.chunk_size as u64));
-        // The chunk must be filled before
+    fn chunk_of(&self, xdp_addr: u64) -> Chunk {
         Chunk {
-            xdp_address: base_address as usize,
-            size: self.base.chunk_size as usize,
+            xdp_address: self.base.layout.chunk_base(xdp_addr),
+            size: self.base.layout.usable_size() as usize,
             mmap_area: self.base.area.clone(),
         }
     }
 
-    pub fn register_send(&mut self, _chunk: Chunk) {
+    pub fn extract_recv(&mut self, xdp_addr: u64) -> Chunk {
+        // The chunk must be filled before
+        let chunk = self.chunk_of(xdp_addr);
+        if let Some(tracker) = &self.base.tracker {
+            tracker.lock().unwrap().receive(chunk.xdp_address);
+        }
+        self.filled_num -= 1;
+        chunk
+    }
+
+    pub fn share(&mut self, xdp_addr: u64, n: u32) -> Result<(), CamelliaError> {
+        let Some(refs) = &self.base.refs else {
+            return Err(CamelliaError::InvalidArgument(
+                "chunks are not reference counted, see UMemBuilder::refcount_chunks".to_string(),
+            ));
+        };
+        refs.acquire(xdp_addr, n);
+        if let Some(tracker) = &self.base.tracker {
+            tracker
+                .lock()
+                .unwrap()
+                .share(self.base.layout.chunk_base(xdp_addr));
+        }
+        Ok(())
+    }
+
+    pub fn register_send(&mut self, chunk: Chunk) {
+        if let Some(tracker) = &self.base.tracker {
+            tracker.lock().unwrap().send(chunk.xdp_address);
+        }
         self.tx_issued_num += 1;
     }
+
+    pub fn umem_stat(&self) -> UMemStat {
+        let free_chunks = self.base.chunks.len();
+        let fill_ring = self.filled_num;
+        let tx_pending = self.tx_issued_num as usize;
+        UMemStat {
+            free_chunks,
+            fill_ring,
+            tx_pending,
+            // shared chunks are counted once per send
+            app_owned: self
+                .base
+                .num_chunks()
+                .saturating_sub(free_chunks + fill_ring + tx_pending),
+        }
+    }
+}
+
+impl AsRawFd for DedicatedAccessor {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.base.as_raw_fd()
+    }
+}
+
+impl StatsSource for UMem {
+    fn stats_id(&self) -> String {
+        format!("umem/{:#x}", self.inner as usize)
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        visit(Stat::gauge("free_chunks", self.chunks.len() as u64));
+        visit(Stat::gauge("num_chunks", self._num_chunks as u64));
+        visit(Stat::gauge("chunk_size", self.chunk_size as u64));
+    }
+}
+
+impl StatsSource for DedicatedAccessor {
+    fn stats_id(&self) -> String {
+        self.base.stats_id()
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.base.visit_stats(visit);
+        visit(Stat::gauge("tx_inflight", self.tx_issued_num as u64));
+    }
 }
 
 impl From<UMem> for Rc<RefCell<DedicatedAccessor>> {
@@ -354,6 +961,7 @@ impl From<UMem> for Rc<RefCell<DedicatedAccessor>> {
         Rc::new(RefCell::new(DedicatedAccessor {
             base: value,
             tx_issued_num: 0,
+            filled_num: 0,
         }))
     }
 }
@@ -366,16 +974,21 @@ impl AccessorRef for DedicatedAccessorRef {
     fn allocate(&self, n: usize) -> Result<Vec<AppFrame<Self>>, CamelliaError> {
         let mut umem = self.borrow_mut();
         if umem.base.chunks.len() < n {
-            return Err(CamelliaError::ResourceExhausted(format!(
-                "request {} frames, but only {} frames are available",
-                n,
-                umem.base.chunks.len()
-            )));
+            return Err(CamelliaError::UMemExhausted {
+                requested: n,
+                available: umem.base.chunks.len(),
+            });
+        }
+
+        let chunks = umem.base.allocate(n)?;
+        if let Some(tracker) = &umem.base.tracker {
+            let mut tracker = tracker.lock().unwrap();
+            chunks
+                .iter()
+                .for_each(|chunk| tracker.allocate(chunk.xdp_address));
         }
 
-        Ok(umem
-            .base
-            .allocate(n)?
+        Ok(chunks
             .into_iter()
             .map(|chunk| AppFrame::from_chunk(chunk, self.clone()))
             .collect())
@@ -405,6 +1018,16 @@ impl AccessorRef for DedicatedAccessorRef {
         self.borrow_mut().extract_recv(xdp_addr)
     }
 
+    fn translate(&self, xdp_addr: u64) -> usize {
+        self.borrow()
+            .chunk_of(xdp_addr)
+            .xdp_to_addr(xdp_addr as usize)
+    }
+
+    fn share(&self, xdp_addr: u64, n: u32) -> Result<(), CamelliaError> {
+        self.borrow_mut().share(xdp_addr, n)
+    }
+
     fn equal(&self, other: &Self) -> bool {
         Rc::ptr_eq(self, other)
     }
@@ -413,6 +1036,18 @@ impl AccessorRef for DedicatedAccessorRef {
         self.borrow_mut().register_send(chunk)
     }
 
+    fn tx_inflight(&self) -> usize {
+        self.borrow().tx_issued_num as usize
+    }
+
+    fn umem_stat(&self) -> UMemStat {
+        self.borrow().umem_stat()
+    }
+
+    fn completion_ring(&self) -> Option<RingState> {
+        Some(RingState::of_cons(&self.borrow().base.completion.0))
+    }
+
     fn inner(&self) -> usize {
         self.borrow().inner() as usize
     }
@@ -423,6 +1058,7 @@ mod test {
     use std::{cell::RefCell, ffi::CStr, io::Write, rc::Rc};
 
     use super::*;
+    use crate::umem::frame::TxFrame;
 
     #[test]
     fn test_umem_create() {
@@ -430,6 +1066,93 @@ mod test {
         assert_eq!(umem.chunks.len(), 1024);
     }
 
+    #[test]
+    fn test_staggered_layout() {
+        let layout = ChunkLayout::new(4096, true);
+        assert_eq!(layout.usable_size(), 4096 - 7 * 64);
+
+        for index in 0..64 {
+            let address = layout.chunk_address(index);
+            assert_eq!(address / 4096, index);
+            assert!(address % 4096 + layout.usable_size() as usize <= 4096);
+
+            // any address inside a chunk maps back to its base
+            assert_eq!(layout.chunk_base(address as u64), address);
+            assert_eq!(layout.chunk_base((address + 1500) as u64), address);
+        }
+        assert_ne!(
+            layout.chunk_address(0) % 4096,
+            layout.chunk_address(1) % 4096
+        );
+
+        let layout = ChunkLayout::new(4096, false);
+        assert_eq!(layout.chunk_address(3), 3 * 4096);
+        assert_eq!(layout.chunk_base(3 * 4096 + 256), 3 * 4096);
+
+        let layout = ChunkLayout::new(3000, false);
+        assert_eq!(layout.chunk_index(2 * 3000 + 2999), 2);
+        assert_eq!(layout.chunk_base(2 * 3000 + 256), 2 * 3000);
+
+        assert!(UMemBuilder::new()
+            .chunk_size(2048)
+            .num_chunks(16)
+            .stagger_headroom(true)
+            .build()
+            .is_err());
+
+        // the stagger offset comes out of the frame headroom
+        let builder = UMemBuilder::new().num_chunks(16).stagger_headroom(true);
+        let violations = builder.validate();
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].contains("frame headroom 0 is too small to stagger"));
+        assert!(builder
+            .frame_headroom(STAGGER_HEADROOM)
+            .validate()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_decode_desc_addr() {
+        let layout = ChunkLayout::new(4096, true);
+        let address = layout.chunk_address(3) as u64;
+        // RX descriptors of unaligned UMems carry the offset from the fill address
+        assert_eq!(decode_desc_addr(address | (320 << 48)), address + 320);
+        assert_eq!(
+            layout.chunk_base(decode_desc_addr(address | (320 << 48))),
+            address as usize
+        );
+        // addresses without offset, e.g., of aligned UMems, are unchanged
+        assert_eq!(decode_desc_addr(address + 320), address + 320);
+    }
+
+    #[test]
+    fn test_config_violations() {
+        let result = UMemBuilder::new()
+            .chunk_size(3000)
+            .fill_queue_size(1000)
+            .frame_headroom(4096)
+            .build();
+
+        let Err(CamelliaError::InvalidConfig(violations)) = result else {
+            panic!("invalid configuration is accepted");
+        };
+        assert_eq!(violations.len(), 4);
+        assert!(violations[0].contains("number of chunks"));
+        assert!(violations[1].contains("stagger_headroom"));
+        assert!(violations[2].contains("frame headroom 4096"));
+        assert!(violations[3].contains("fill queue size 1000"));
+    }
+
+    #[test]
+    fn test_ring_alignment() {
+        assert_eq!(std::mem::align_of::<FillQueue>(), 64);
+        assert_eq!(std::mem::align_of::<CompletionQueue>(), 64);
+
+        let rings = [FillQueue::default(), FillQueue::default()];
+        let distance = &rings[1] as *const _ as usize - &rings[0] as *const _ as usize;
+        assert_eq!(distance % 64, 0);
+    }
+
     #[test]
     fn test_frame_allocate() {
         let mut umem = UMemBuilder::new().num_chunks(1024).build().unwrap();
@@ -439,6 +1162,104 @@ mod test {
         assert_eq!(umem.chunks.len(), 0);
     }
 
+    #[test]
+    fn test_frame_share() {
+        let umem = UMemBuilder::new()
+            .num_chunks(16)
+            .refcount_chunks(true)
+            .track_chunks(true)
+            .build()
+            .unwrap();
+        let tracker = umem.tracker().unwrap().clone();
+        let accessor: DedicatedAccessorRef = umem.into();
+
+        let frame = accessor.allocate(1).unwrap().pop().unwrap();
+        let mut copies = TxFrame::from(frame).share(3).unwrap();
+        assert_eq!(copies.len(), 3);
+        assert!(copies
+            .iter()
+            .all(|copy| copy.xdp_address() == copies[0].xdp_address()));
+
+        copies.pop();
+        copies.pop();
+        assert_eq!(accessor.umem_stat().free_chunks, 15);
+        copies.pop();
+        assert_eq!(accessor.umem_stat().free_chunks, 16);
+        assert!(tracker.lock().unwrap().violations().is_empty());
+
+        let unshared = UMemBuilder::new().num_chunks(16).build().unwrap();
+        let accessor: DedicatedAccessorRef = unshared.into();
+        let frame = accessor.allocate(1).unwrap().pop().unwrap();
+        assert!(TxFrame::from(frame).share(2).is_err());
+        assert_eq!(accessor.umem_stat().free_chunks, 16);
+    }
+
+    // the kernel completing the transmission of chunks
+    fn kernel_complete(completion: &xsk_ring_cons, addresses: &[u64]) {
+        unsafe {
+            let producer = *completion.producer;
+            for (i, address) in addresses.iter().enumerate() {
+                let slot = producer.wrapping_add(i as u32) & completion.mask;
+                *(completion.ring as *mut u64).add(slot as usize) = *address;
+            }
+            *completion.producer = producer.wrapping_add(addresses.len() as u32);
+        }
+    }
+
+    #[test]
+    fn test_tx_inflight() {
+        let accessor: DedicatedAccessorRef = UMem::detached(16, 4).into();
+        let sent: Vec<u64> = accessor
+            .allocate(3)
+            .unwrap()
+            .into_iter()
+            .map(|frame| {
+                let chunk = TxFrame::from(frame).take();
+                let address = chunk.xdp_address as u64;
+                accessor.register_send(chunk);
+                address
+            })
+            .collect();
+        assert_eq!(accessor.tx_inflight(), 3);
+
+        kernel_complete(&accessor.borrow().base.completion.0, &sent[..2]);
+        assert_eq!(accessor.recycle().unwrap(), 2);
+        assert_eq!(accessor.tx_inflight(), 1);
+
+        // what XskSocket::close waits for
+        kernel_complete(&accessor.borrow().base.completion.0, &sent[2..]);
+        assert_eq!(accessor.recycle().unwrap(), 1);
+        assert_eq!(accessor.tx_inflight(), 0);
+        assert_eq!(accessor.umem_stat().free_chunks, 16);
+    }
+
+    #[test]
+    fn test_tx_pending_stat() {
+        let accessor: DedicatedAccessorRef = UMem::detached(16, 4).into();
+        let sent: Vec<u64> = accessor
+            .allocate(4)
+            .unwrap()
+            .into_iter()
+            .map(|frame| {
+                let chunk = TxFrame::from(frame).take();
+                let address = chunk.xdp_address as u64;
+                accessor.register_send(chunk);
+                address
+            })
+            .collect();
+        assert_eq!(accessor.umem_stat().tx_pending, 4);
+
+        kernel_complete(&accessor.borrow().base.completion.0, &sent[..1]);
+        accessor.recycle().unwrap();
+        assert_eq!(accessor.umem_stat().tx_pending, 3);
+
+        kernel_complete(&accessor.borrow().base.completion.0, &sent[1..]);
+        accessor.recycle().unwrap();
+        let stat = accessor.umem_stat();
+        assert_eq!(stat.tx_pending, 0);
+        assert_eq!(stat.free_chunks, 16);
+    }
+
     #[test]
     fn test_frame_write() {
         let umem = UMemBuilder::new().num_chunks(1024).build().unwrap();
@@ -29,7 +29,10 @@ impl Chunk {
     }
 
     pub fn is_xdp_array_valid(&self, xdp_address: usize, len: usize) -> bool {
-        (xdp_address >= self.xdp_address) && (xdp_address + len <= self.xdp_address + self.size)
+        (xdp_address >= self.xdp_address)
+            && xdp_address
+                .checked_add(len)
+                .is_some_and(|end| end <= self.xdp_address + self.size)
     }
 
     pub fn is_addr_valid(&self, address: usize) -> bool {
@@ -41,7 +44,9 @@ impl Chunk {
     pub fn is_array_valid(&self, address: usize, len: usize) -> bool {
         let base_address = self.mmap_area.as_ref().base_address();
         (address >= (base_address + self.xdp_address))
-            && (address + len <= (base_address + self.xdp_address + self.size))
+            && address
+                .checked_add(len)
+                .is_some_and(|end| end <= base_address + self.xdp_address + self.size)
     }
 
     pub fn xdp_to_addr(&self, xdp_address: usize) -> usize {
@@ -62,6 +67,10 @@ where
     umem: M,
     offset: usize,
     len: usize,
+    // nanoseconds of CLOCK_TAI when the packet should leave, see TxFrame::set_launch_time
+    launch_time: Option<u64>,
+    // nanoseconds when the packet was received, see RxFrame::timestamp
+    timestamp: Option<u64>,
 }
 
 impl<M> Drop for Frame<M>
@@ -107,30 +116,114 @@ where
     pub fn raw_buffer_resize(&mut self, size: usize) -> Result<&mut [u8], CamelliaError> {
         let chunk = self.chunk.as_ref().unwrap();
 
-        if size > chunk.size {
+        if size > chunk.size - self.offset {
             return Err(CamelliaError::InvalidArgument(format!(
-                "request size {} is larger than chunk size {}",
-                size, chunk.size
+                "request size {} is larger than chunk size {} at offset {}",
+                size, chunk.size, self.offset
             )));
         }
         self.len = size;
-        let base_address = chunk.address();
+        let base_address = chunk.address() + self.offset;
         Ok(unsafe { std::slice::from_raw_parts_mut(base_address as *mut u8, size) })
     }
 
     pub fn raw_buffer_append(&mut self, size: usize) -> Result<&mut [u8], CamelliaError> {
         let chunk = self.chunk.as_ref().unwrap();
-        if self.len + size > chunk.size {
+        // received packets start at an offset into the chunk
+        if size > self.tail_room() {
             return Err(CamelliaError::InvalidArgument(format!(
                 "request size {} is larger than available size (total: {}, used: {})",
-                size, chunk.size, self.len
+                size,
+                chunk.size,
+                self.offset + self.len
             )));
         }
-        let base_address = chunk.address() + self.len;
+        let base_address = chunk.address() + self.offset + self.len;
         self.len += size;
         Ok(unsafe { std::slice::from_raw_parts_mut(base_address as *mut u8, size) })
     }
 
+    // bytes which can be appended behind the packet
+    pub fn tail_room(&self) -> usize {
+        self.chunk.as_ref().unwrap().size - self.offset - self.len
+    }
+
+    // bytes which can be prepended in front of the packet, e.g., the XDP headroom of
+    // received packets
+    pub fn head_room(&self) -> usize {
+        self.offset
+    }
+
+    // grows the packet at its head by size bytes and returns them
+    pub fn push_head(&mut self, size: usize) -> Result<&mut [u8], CamelliaError> {
+        if size > self.offset {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "request size {} is larger than the head room {}",
+                size, self.offset
+            )));
+        }
+        self.offset -= size;
+        self.len += size;
+        Ok(&mut self.raw_buffer_mut()[..size])
+    }
+
+    // strips size bytes from the head of the packet
+    pub fn pull_head(&mut self, size: usize) -> Result<(), CamelliaError> {
+        if size > self.len {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "request size {} is larger than the packet of {} bytes",
+                size, self.len
+            )));
+        }
+        self.offset += size;
+        self.len -= size;
+        Ok(())
+    }
+
+    // starts an empty packet size bytes into the chunk, leaving head room for headers
+    // pushed later
+    pub fn reserve_head(&mut self, size: usize) -> Result<(), CamelliaError> {
+        let chunk_size = self.chunk.as_ref().unwrap().size;
+        if !self.is_empty() || size > chunk_size {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "can't reserve {} bytes in a chunk of {} bytes holding {} bytes",
+                size, chunk_size, self.len
+            )));
+        }
+        self.offset = size;
+        Ok(())
+    }
+
+    // shortens the packet to len bytes, longer lengths have no effect
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[u8]) -> Result<(), CamelliaError> {
+        self.raw_buffer_append(data.len())?.copy_from_slice(data);
+        Ok(())
+    }
+
+    // frames referring to the same chunk, freed once every frame is completed or dropped
+    fn share(&self, copies: usize) -> Result<Vec<Frame<M>>, CamelliaError> {
+        let chunk = self.chunk.as_ref().unwrap();
+        M::share(&self.umem, chunk.xdp_address as u64, copies as u32)?;
+        Ok((0..copies)
+            .map(|_| Frame {
+                chunk: Some(Chunk {
+                    xdp_address: chunk.xdp_address,
+                    size: chunk.size,
+                    mmap_area: chunk.mmap_area.clone(),
+                }),
+                umem: self.umem.clone(),
+                offset: self.offset,
+                len: self.len,
+                launch_time: self.launch_time,
+                timestamp: self.timestamp,
+            })
+            .collect())
+    }
+
     pub fn take_chunk(mut self) -> Chunk {
         self.chunk.take().unwrap()
     }
@@ -159,6 +252,8 @@ where
             offset: 0,
             len: 0,
             umem,
+            launch_time: None,
+            timestamp: None,
         })
     }
 
@@ -178,6 +273,22 @@ where
         self.0.raw_buffer_append(size)
     }
 
+    pub fn tail_room(&self) -> usize {
+        self.0.tail_room()
+    }
+
+    pub fn reserve_head(&mut self, size: usize) -> Result<(), CamelliaError> {
+        self.0.reserve_head(size)
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len)
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[u8]) -> Result<(), CamelliaError> {
+        self.0.extend_from_slice(data)
+    }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
@@ -199,29 +310,62 @@ impl<M> RxFrame<M>
 where
     M: AccessorRef,
 {
+    // Descriptors of the RX ring are trusted, a packet outside its chunk is a bug
     pub fn from_chunk(chunk: Chunk, umem: M, xdp_addr: usize, xdp_len: usize) -> Self {
+        Self::try_from_chunk(chunk, umem, xdp_addr, xdp_len).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // The packet at xdp_addr of xdp_len bytes in the chunk. The chunk goes back to the
+    // UMem if the packet doesn't fit in it.
+    pub fn try_from_chunk(
+        chunk: Chunk,
+        umem: M,
+        xdp_addr: usize,
+        xdp_len: usize,
+    ) -> Result<Self, CamelliaError> {
         if !chunk.is_xdp_array_valid(xdp_addr, xdp_len) {
-            panic!(
-                "{}",
-                format!(
-                    "invalid xdp address: {} or length: {} for chunk: {:?}",
-                    xdp_addr, xdp_len, chunk
-                )
-            )
+            let message = format!(
+                "invalid xdp address: {} or length: {} for chunk: {:?}",
+                xdp_addr, xdp_len, chunk
+            );
+            M::free(&umem, chunk);
+            return Err(CamelliaError::InvalidArgument(message));
         }
 
-        RxFrame(Frame {
+        Ok(RxFrame(Frame {
             offset: xdp_addr - chunk.xdp_address(),
             chunk: Some(chunk),
             umem,
             len: xdp_len,
-        })
+            launch_time: None,
+            timestamp: None,
+        }))
     }
 
     pub fn raw_buffer(&self) -> &[u8] {
         self.0.raw_buffer()
     }
 
+    pub fn raw_buffer_mut(&mut self) -> &mut [u8] {
+        self.0.raw_buffer_mut()
+    }
+
+    pub fn raw_buffer_append(&mut self, size: usize) -> Result<&mut [u8], CamelliaError> {
+        self.0.raw_buffer_append(size)
+    }
+
+    pub fn tail_room(&self) -> usize {
+        self.0.tail_room()
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.0.truncate(len)
+    }
+
+    pub fn extend_from_slice(&mut self, data: &[u8]) -> Result<(), CamelliaError> {
+        self.0.extend_from_slice(data)
+    }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
@@ -233,18 +377,97 @@ where
     pub fn umem(&self) -> &M {
         self.0.umem()
     }
+
+    // When the packet was dequeued from the RX ring, or the hardware timestamp of the NIC,
+    // if the socket stamps frames, see XskSocketBuilder::rx_timestamp
+    pub fn timestamp(&self) -> Option<u64> {
+        self.0.timestamp
+    }
+
+    pub fn set_timestamp(&mut self, ns: u64) {
+        self.0.timestamp = Some(ns);
+    }
+
+    // the len bytes in front of the packet, where XDP programs place metadata with
+    // bpf_xdp_adjust_meta
+    pub fn metadata(&self, len: usize) -> Option<&[u8]> {
+        if len > self.0.offset {
+            return None;
+        }
+        let chunk = self.0.chunk.as_ref().unwrap();
+        let address = chunk.address() + self.0.offset - len;
+        Some(unsafe { std::slice::from_raw_parts(address as *const u8, len) })
+    }
+
+    // Copies of the packet outliving the frame, so that the chunk can be returned to the
+    // fill ring early instead of holding it while the data is needed
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.raw_buffer().to_vec()
+    }
+
+    // copies the packet to the head of buf and returns its length
+    pub fn copy_into(&self, buf: &mut [u8]) -> Result<usize, CamelliaError> {
+        let packet = self.raw_buffer();
+        if buf.len() < packet.len() {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "buffer of {} bytes is smaller than the packet of {} bytes",
+                buf.len(),
+                packet.len()
+            )));
+        }
+        buf[..packet.len()].copy_from_slice(packet);
+        Ok(packet.len())
+    }
+
+    // A copy referring to the same chunk to send while the frame is processed further,
+    // the frame must not be modified until the copy is completed. See TxFrame::share.
+    pub fn mirror(&self) -> Result<TxFrame<M>, CamelliaError> {
+        Ok(TxFrame(self.0.share(1)?.pop().unwrap()))
+    }
 }
 
 impl<M> TxFrame<M>
 where
     M: AccessorRef,
 {
+    // a frame ready to send holding a copy of the packet
+    pub fn copy_from_slice(umem: &M, packet: &[u8]) -> Result<Self, CamelliaError> {
+        let mut frame = umem.allocate(1)?.pop().unwrap();
+        frame
+            .raw_buffer_append(packet.len())?
+            .copy_from_slice(packet);
+        Ok(frame.into())
+    }
+
+    // chunks of all packets are allocated at once, no frame is returned if any packet
+    // doesn't fit in a chunk
+    pub fn copy_from_slices<I, B>(umem: &M, packets: I) -> Result<Vec<Self>, CamelliaError>
+    where
+        I: IntoIterator<Item = B>,
+        B: AsRef<[u8]>,
+    {
+        let packets: Vec<B> = packets.into_iter().collect();
+        umem.allocate(packets.len())?
+            .into_iter()
+            .zip(packets)
+            .map(|(mut frame, packet)| {
+                let packet = packet.as_ref();
+                frame
+                    .raw_buffer_append(packet.len())?
+                    .copy_from_slice(packet);
+                Ok(frame.into())
+            })
+            .collect()
+    }
+
     pub fn from_chunk(chunk: Chunk, umem: M) -> Self {
         TxFrame(Frame {
             chunk: Some(chunk),
             umem,
             offset: 0,
             len: 0,
+            launch_time: None,
+            timestamp: None,
         })
     }
 
@@ -252,6 +475,17 @@ where
         self.0.xdp_address()
     }
 
+    // Sets when the packet should leave, in nanoseconds of CLOCK_TAI like the launch time
+    // of TX metadata. Launch times are honored by socket::shaper::Shaper, which paces
+    // frames in software since the UMem isn't registered with TX metadata.
+    pub fn set_launch_time(&mut self, ns: u64) {
+        self.0.launch_time = Some(ns);
+    }
+
+    pub fn launch_time(&self) -> Option<u64> {
+        self.0.launch_time
+    }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
@@ -267,8 +501,75 @@ where
     pub fn take(self) -> Chunk {
         self.0.take_chunk()
     }
+
+    // Splits the frame into copies referring to the same chunk, e.g., to send a packet on
+    // several sockets sharing the UMem without copying it. The chunk goes back to the pool
+    // once every copy is completed or dropped, see UMemBuilder::refcount_chunks.
+    pub fn share(self, copies: usize) -> Result<Vec<TxFrame<M>>, CamelliaError> {
+        if copies == 0 {
+            return Err(CamelliaError::InvalidArgument(
+                "a frame is shared by at least one copy".to_string(),
+            ));
+        }
+        let mut shared: Vec<TxFrame<M>> =
+            self.0.share(copies - 1)?.into_iter().map(TxFrame).collect();
+        shared.push(self);
+        Ok(shared)
+    }
+}
+
+// Frames whose packet can grow or shrink at its head, e.g., to push or pop the headers
+// of tunnels in place
+pub trait HeadRoom: AsRef<[u8]> + AsMut<[u8]> {
+    fn head_room(&self) -> usize;
+
+    fn push_head(&mut self, size: usize) -> Result<&mut [u8], CamelliaError>;
+
+    fn pull_head(&mut self, size: usize) -> Result<(), CamelliaError>;
+}
+
+// frames read as the bytes of their packet, e.g., by parsers taking slices
+macro_rules! impl_packet_bytes {
+    ($frame:ident) => {
+        impl<M: AccessorRef> HeadRoom for $frame<M> {
+            fn head_room(&self) -> usize {
+                self.0.head_room()
+            }
+
+            fn push_head(&mut self, size: usize) -> Result<&mut [u8], CamelliaError> {
+                self.0.push_head(size)
+            }
+
+            fn pull_head(&mut self, size: usize) -> Result<(), CamelliaError> {
+                self.0.pull_head(size)
+            }
+        }
+
+        impl<M: AccessorRef> std::ops::Deref for $frame<M> {
+            type Target = [u8];
+
+            fn deref(&self) -> &[u8] {
+                self.raw_buffer()
+            }
+        }
+
+        impl<M: AccessorRef> AsRef<[u8]> for $frame<M> {
+            fn as_ref(&self) -> &[u8] {
+                self.raw_buffer()
+            }
+        }
+
+        impl<M: AccessorRef> AsMut<[u8]> for $frame<M> {
+            fn as_mut(&mut self) -> &mut [u8] {
+                self.raw_buffer_mut()
+            }
+        }
+    };
 }
 
+impl_packet_bytes!(AppFrame);
+impl_packet_bytes!(RxFrame);
+
 impl<M: AccessorRef> From<AppFrame<M>> for TxFrame<M> {
     fn from(app_frame: AppFrame<M>) -> Self {
         TxFrame(app_frame.0)
@@ -286,3 +587,33 @@ impl<M: AccessorRef> From<RxFrame<M>> for AppFrame<M> {
         AppFrame(rx_frame.0)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::umem::plain::{PlainAccessorRef, PlainUMem};
+
+    #[test]
+    fn test_rx_frame_bounds() {
+        let umem = PlainAccessorRef::new(PlainUMem::new(4, 2048).unwrap());
+        let chunk = || TxFrame::from(umem.allocate(1).unwrap().pop().unwrap()).take();
+
+        // the chunk goes back to the UMem if the packet doesn't fit
+        let rx_chunk = chunk();
+        let base = rx_chunk.xdp_address;
+        assert!(RxFrame::try_from_chunk(rx_chunk, umem.clone(), base + 256, 2048).is_err());
+        assert!(RxFrame::try_from_chunk(chunk(), umem.clone(), usize::MAX, 2).is_err());
+        assert_eq!(umem.free_chunks(), 4);
+
+        let rx_chunk = chunk();
+        let base = rx_chunk.xdp_address;
+        let mut frame = RxFrame::try_from_chunk(rx_chunk, umem.clone(), base + 256, 1500).unwrap();
+        assert_eq!(frame.len(), 1500);
+        assert!(frame.push_head(257).is_err());
+        assert!(frame.pull_head(1501).is_err());
+        frame.push_head(256).unwrap();
+        assert_eq!(frame.len(), 1756);
+        drop(frame);
+        assert_eq!(umem.free_chunks(), 4);
+    }
+}
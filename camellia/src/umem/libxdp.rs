@@ -3,7 +3,7 @@ use std::{
     os::fd::{AsRawFd, BorrowedFd},
 };
 
-use libc::{recvfrom, sendto, MSG_DONTWAIT};
+use libc::{c_void, recvfrom, sendto, MSG_DONTWAIT};
 use libxdp_sys::{
     xsk_ring_cons, xsk_ring_cons__comp_addr, xsk_ring_cons__peek, xsk_ring_cons__release,
     xsk_ring_prod, xsk_ring_prod__fill_addr, xsk_ring_prod__needs_wakeup, xsk_ring_prod__reserve,
@@ -12,14 +12,18 @@ use libxdp_sys::{
 use nix::poll::{poll, PollFd};
 use nix::{errno::Errno, poll::PollTimeout};
 
-use crate::error::CamelliaError;
+use crate::{
+    error::CamelliaError,
+    umem::base::{decode_desc_addr, ChunkLayout},
+};
 
 pub fn populate_fill_ring(ring: &mut xsk_ring_prod, n: usize, chunks: &mut Vec<usize>) -> usize {
     let mut start_index = 0;
     let reserved = unsafe { xsk_ring_prod__reserve(ring, n as u32, &mut start_index) };
     let actual_filled = min(chunks.len(), reserved as usize);
 
-    for (fill_index, chunk) in chunks.drain(0..actual_filled).enumerate() {
+    // chunks are taken from the tail, so that the rest don't move
+    for (fill_index, chunk) in chunks.drain(chunks.len() - actual_filled..).enumerate() {
         unsafe {
             let fill_addr = xsk_ring_prod__fill_addr(ring, start_index + fill_index as u32);
             *fill_addr = chunk as u64;
@@ -36,7 +40,7 @@ pub fn populate_fill_ring(ring: &mut xsk_ring_prod, n: usize, chunks: &mut Vec<u
 pub fn recycle_compeletion_ring(
     ring: &mut xsk_ring_cons,
     n: usize,
-    chunk_size: u32,
+    layout: ChunkLayout,
     chunks: &mut Vec<usize>,
 ) -> usize {
     let mut start_index = 0;
@@ -44,8 +48,7 @@ pub fn recycle_compeletion_ring(
 
     for complete_index in 0..completed {
         let xdp_addr = unsafe { *xsk_ring_cons__comp_addr(ring, start_index + complete_index) };
-        let base_address = xdp_addr - (xdp_addr % chunk_size as u64);
-        chunks.push(base_address as usize)
+        chunks.push(layout.chunk_base(decode_desc_addr(xdp_addr)))
     }
 
     unsafe {
@@ -70,21 +73,28 @@ pub fn wakeup_rx(fd: BorrowedFd) -> Result<(), CamelliaError> {
 }
 
 pub fn wakeup_tx(fd: BorrowedFd) -> Result<(), CamelliaError> {
+    try_wakeup_tx(fd)?;
+    Ok(())
+}
+
+// Returns the transient error swallowed by the wakeup, if any
+pub fn try_wakeup_tx(fd: BorrowedFd) -> Result<Option<Errno>, CamelliaError> {
     unsafe {
-        Errno::result(sendto(
+        match Errno::result(sendto(
             fd.as_raw_fd(),
             std::ptr::null(),
             0,
             MSG_DONTWAIT,
             std::ptr::null(),
             0,
-        ))
-        .or_else(|e| match e {
-            Errno::EAGAIN | Errno::EBUSY | Errno::ENETDOWN | Errno::ENOBUFS => Ok(0),
-            _ => Err(e),
-        })?;
+        )) {
+            Ok(_) => Ok(None),
+            Err(e @ (Errno::EAGAIN | Errno::EBUSY | Errno::ENETDOWN | Errno::ENOBUFS)) => {
+                Ok(Some(e))
+            }
+            Err(e) => Err(e.into()),
+        }
     }
-    Ok(())
 }
 
 pub fn wakeup_rxtx(fd: BorrowedFd) -> Result<(), CamelliaError> {
@@ -116,3 +126,25 @@ pub fn wakeup_tx_if_necessary(
     }
     Ok(())
 }
+
+// Entries between the consumer and the producer of a ring, i.e., those still held by it.
+// The kernel must not access the ring anymore, e.g., once its socket is deleted.
+pub(crate) unsafe fn pending_entries<T: Copy>(
+    producer: *const u32,
+    consumer: *const u32,
+    ring: *const c_void,
+    mask: u32,
+) -> Vec<T> {
+    if ring.is_null() {
+        return Vec::new();
+    }
+
+    let producer = producer.read_volatile();
+    let consumer = consumer.read_volatile();
+    (0..producer.wrapping_sub(consumer))
+        .map(|offset| {
+            let index = consumer.wrapping_add(offset) & mask;
+            (ring as *const T).add(index as usize).read()
+        })
+        .collect()
+}
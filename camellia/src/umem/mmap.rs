@@ -1,12 +1,18 @@
 use crate::error::CamelliaError;
-use nix::sys::mman::{mmap_anonymous, munmap, MapFlags, ProtFlags};
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::sys::mman::{mmap, mmap_anonymous, munmap, MapFlags, ProtFlags};
+use std::ffi::CString;
+use std::fs::File;
 use std::num::NonZeroUsize;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
 use std::ptr::NonNull;
 
 #[derive(Debug)]
 pub struct MMapArea {
     base_address: usize,
     length: usize,
+    // the file backing the area if it can be mapped by other processes
+    memfd: Option<OwnedFd>,
 }
 
 impl MMapArea {
@@ -28,14 +34,61 @@ impl MMapArea {
         let mmap_area = Self {
             base_address: mmap_base.as_ptr() as usize,
             length: size,
+            memfd: None,
         };
 
         Ok(mmap_area)
     }
 
+    // An area backed by a memfd, which other processes map with from_memfd once they
+    // have the file descriptor, e.g., sent over a Unix socket with SCM_RIGHTS. The name
+    // only shows up in /proc/<pid>/fd.
+    pub fn memfd(name: &str, size: usize) -> Result<Self, CamelliaError> {
+        let name = CString::new(name)
+            .map_err(|e| CamelliaError::InvalidArgument(format!("invalid memfd name: {}", e)))?;
+        let memfd = memfd_create(&name, MemFdCreateFlag::MFD_CLOEXEC)?;
+        let file = File::from(memfd);
+        file.set_len(size as u64)?;
+        Self::from_memfd(file.into(), size)
+    }
+
+    // Maps the whole memfd of an area created by memfd, possibly in another process
+    pub fn from_memfd(memfd: OwnedFd, size: usize) -> Result<Self, CamelliaError> {
+        let Some(length) = NonZeroUsize::new(size) else {
+            return Err(CamelliaError::InvalidArgument(
+                "mmap size could not be zero".into(),
+            ));
+        };
+        let mmap_base = unsafe {
+            mmap(
+                None,
+                length,
+                ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                MapFlags::MAP_SHARED,
+                memfd.as_fd(),
+                0,
+            )?
+        };
+
+        Ok(Self {
+            base_address: mmap_base.as_ptr() as usize,
+            length: size,
+            memfd: Some(memfd),
+        })
+    }
+
     pub fn base_address(&self) -> usize {
         self.base_address
     }
+
+    pub fn size(&self) -> usize {
+        self.length
+    }
+
+    // the memfd, None for anonymous areas
+    pub fn fd(&self) -> Option<BorrowedFd> {
+        self.memfd.as_ref().map(|memfd| memfd.as_fd())
+    }
 }
 
 impl Drop for MMapArea {
@@ -63,4 +116,18 @@ mod test {
         let mmap_area = MMapArea::new(4096).unwrap();
         assert_ne!(mmap_area.base_address(), 0);
     }
+
+    #[test]
+    fn test_memfd() {
+        let area = MMapArea::memfd("camellia-test", 8192).unwrap();
+        let memfd = area.fd().unwrap().try_clone_to_owned().unwrap();
+        let mapped = MMapArea::from_memfd(memfd, 8192).unwrap();
+        assert_ne!(mapped.base_address(), area.base_address());
+
+        unsafe { *((area.base_address() + 4096) as *mut u8) = 0x2a };
+        assert_eq!(
+            unsafe { *((mapped.base_address() + 4096) as *const u8) },
+            0x2a
+        );
+    }
 }
@@ -1,3 +1,7 @@
+use std::fmt::Display;
+
+use libxdp_sys::{xsk_ring_cons, xsk_ring_prod};
+
 use crate::error::CamelliaError;
 
 use self::frame::{AppFrame, Chunk};
@@ -6,7 +10,71 @@ pub mod base;
 pub mod frame;
 pub mod libxdp;
 pub mod mmap;
+pub mod plain;
+pub mod refcount;
 pub mod shared;
+pub mod tracker;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UMemStat {
+    // chunks available for allocation, including those cached by shared accessors
+    pub free_chunks: usize,
+    // chunks in fill rings or RX rings
+    pub fill_ring: usize,
+    // chunks sent but not completed yet
+    pub tx_pending: usize,
+    // chunks held by the application
+    pub app_owned: usize,
+}
+
+// Positions of the producer and the consumer of a ring shared with the kernel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RingState {
+    pub producer: u32,
+    pub consumer: u32,
+    pub size: u32,
+}
+
+impl RingState {
+    pub fn of_prod(ring: &xsk_ring_prod) -> Self {
+        Self::read(ring.producer, ring.consumer, ring.size)
+    }
+
+    pub fn of_cons(ring: &xsk_ring_cons) -> Self {
+        Self::read(ring.producer, ring.consumer, ring.size)
+    }
+
+    fn read(producer: *const u32, consumer: *const u32, size: u32) -> Self {
+        if producer.is_null() || consumer.is_null() {
+            return Self::default();
+        }
+        unsafe {
+            Self {
+                producer: producer.read_volatile(),
+                consumer: consumer.read_volatile(),
+                size,
+            }
+        }
+    }
+
+    // entries produced but not consumed yet
+    pub fn pending(&self) -> u32 {
+        self.producer.wrapping_sub(self.consumer)
+    }
+}
+
+impl Display for RingState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "producer {}, consumer {}, {}/{} pending",
+            self.producer,
+            self.consumer,
+            self.pending(),
+            self.size
+        )
+    }
+}
 
 pub trait AccessorRef: Sized + Clone {
     type UMemRef;
@@ -25,7 +93,30 @@ pub trait AccessorRef: Sized + Clone {
 
     fn register_send(&self, chunk: Chunk);
 
+    // number of sent chunks not yet returned by the completion ring
+    fn tx_inflight(&self) -> usize;
+
+    // occupancy of the whole UMem, shared by all sockets of a shared UMem
+    fn umem_stat(&self) -> UMemStat;
+
+    // the completion ring of the socket, if backed by the kernel
+    fn completion_ring(&self) -> Option<RingState> {
+        None
+    }
+
     fn extract_recv(&self, xdp_addr: u64) -> Chunk;
 
+    // adds references to a chunk shared by frames, see TxFrame::share
+    fn share(&self, xdp_addr: u64, n: u32) -> Result<(), CamelliaError> {
+        let _ = (xdp_addr, n);
+        Err(CamelliaError::InvalidArgument(
+            "chunks of this UMem are not reference counted".to_string(),
+        ))
+    }
+
+    // virtual address of a descriptor still owned by the kernel, e.g., a peeked RX descriptor,
+    // must not touch any accounting since ownership does not change
+    fn translate(&self, xdp_addr: u64) -> usize;
+
     fn equal(&self, other: &Self) -> bool;
 }
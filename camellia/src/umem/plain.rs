@@ -0,0 +1,179 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    error::CamelliaError,
+    stats::{Stat, StatsSource},
+};
+
+use super::{
+    frame::{AppFrame, Chunk},
+    mmap::MMapArea,
+    AccessorRef, UMemStat,
+};
+
+// A UMem without any kernel ring attached, used by the mock and AF_PACKET socket backends.
+// It only needs an anonymous mapping, so no privilege is required.
+#[derive(Debug)]
+pub struct PlainUMem {
+    area: Arc<MMapArea>,
+    chunks: Vec<usize>,
+    chunk_size: u32,
+    num_chunks: u32,
+}
+
+impl PlainUMem {
+    pub fn new(num_chunks: u32, chunk_size: u32) -> Result<Self, CamelliaError> {
+        let area = Arc::new(MMapArea::new(num_chunks as usize * chunk_size as usize)?);
+        Ok(Self {
+            area,
+            chunks: (0..num_chunks as usize)
+                .map(|i| i * chunk_size as usize)
+                .collect(),
+            chunk_size,
+            num_chunks,
+        })
+    }
+
+    pub fn free_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn chunk_size(&self) -> u32 {
+        self.chunk_size
+    }
+
+    fn allocate(&mut self, n: usize) -> Result<Vec<Chunk>, CamelliaError> {
+        if self.chunks.len() < n {
+            return Err(CamelliaError::UMemExhausted {
+                requested: n,
+                available: self.chunks.len(),
+            });
+        }
+
+        Ok(self
+            .chunks
+            .drain(self.chunks.len() - n..)
+            .map(|address| Chunk {
+                xdp_address: address,
+                size: self.chunk_size as usize,
+                mmap_area: self.area.clone(),
+            })
+            .collect())
+    }
+}
+
+impl StatsSource for PlainUMem {
+    fn stats_id(&self) -> String {
+        format!("plain_umem/{:#x}", self.area.base_address())
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        visit(Stat::gauge("free_chunks", self.chunks.len() as u64));
+        visit(Stat::gauge("chunk_size", self.chunk_size as u64));
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlainAccessorRef {
+    inner: Arc<Mutex<PlainUMem>>,
+}
+
+impl PlainAccessorRef {
+    pub fn new(umem: PlainUMem) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(umem)),
+        }
+    }
+
+    pub fn free_chunks(&self) -> usize {
+        self.inner.lock().unwrap().free_chunks()
+    }
+
+    pub fn chunk_size(&self) -> u32 {
+        self.inner.lock().unwrap().chunk_size()
+    }
+}
+
+impl StatsSource for PlainAccessorRef {
+    fn stats_id(&self) -> String {
+        self.inner.stats_id()
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.inner.visit_stats(visit)
+    }
+}
+
+impl AccessorRef for PlainAccessorRef {
+    type UMemRef = PlainUMem;
+
+    fn inner(&self) -> usize {
+        Arc::as_ptr(&self.inner) as usize
+    }
+
+    fn need_wakeup(&self) -> bool {
+        false
+    }
+
+    fn allocate(&self, n: usize) -> Result<Vec<AppFrame<Self>>, CamelliaError> {
+        Ok(self
+            .inner
+            .lock()
+            .unwrap()
+            .allocate(n)?
+            .into_iter()
+            .map(|chunk| AppFrame::from_chunk(chunk, self.clone()))
+            .collect())
+    }
+
+    // There is no fill ring, chunks are taken from the pool on reception
+    fn fill(&self, n: usize) -> Result<usize, CamelliaError> {
+        Ok(n)
+    }
+
+    // Transmission completes synchronously, nothing to recycle
+    fn recycle(&self) -> Result<usize, CamelliaError> {
+        Ok(0)
+    }
+
+    fn free(&self, chunk: Chunk) {
+        self.inner.lock().unwrap().chunks.push(chunk.xdp_address);
+    }
+
+    fn register_send(&self, chunk: Chunk) {
+        self.free(chunk)
+    }
+
+    fn tx_inflight(&self) -> usize {
+        0
+    }
+
+    // there are no kernel rings, chunks are either free or owned by the application
+    fn umem_stat(&self) -> UMemStat {
+        let umem = self.inner.lock().unwrap();
+        UMemStat {
+            free_chunks: umem.chunks.len(),
+            fill_ring: 0,
+            tx_pending: 0,
+            app_owned: umem.num_chunks as usize - umem.chunks.len(),
+        }
+    }
+
+    fn extract_recv(&self, xdp_addr: u64) -> Chunk {
+        let umem = self.inner.lock().unwrap();
+        let base_address = xdp_addr - (xdp_addr % (umem.chunk_size as u64));
+        Chunk {
+            xdp_address: base_address as usize,
+            size: umem.chunk_size as usize,
+            mmap_area: umem.area.clone(),
+        }
+    }
+
+    fn translate(&self, xdp_addr: u64) -> usize {
+        self.inner.lock().unwrap().area.base_address() + xdp_addr as usize
+    }
+
+    fn equal(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
@@ -0,0 +1,78 @@
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+use super::base::ChunkLayout;
+
+// Extra references to chunks shared by several frames, e.g., a packet sent on several
+// sockets of a shared UMem. A chunk goes back to the pool when its last reference is
+// freed or completed, exclusively owned chunks have no extra reference.
+#[derive(Debug)]
+pub struct ChunkRefs {
+    layout: ChunkLayout,
+    extra: Vec<AtomicU32>,
+}
+
+pub type ChunkRefsRef = Arc<ChunkRefs>;
+
+impl ChunkRefs {
+    pub fn new(layout: ChunkLayout, num_chunks: usize) -> Self {
+        Self {
+            layout,
+            extra: (0..num_chunks).map(|_| AtomicU32::new(0)).collect(),
+        }
+    }
+
+    fn slot(&self, xdp_addr: u64) -> &AtomicU32 {
+        &self.extra[self.layout.chunk_index(xdp_addr)]
+    }
+
+    pub fn acquire(&self, xdp_addr: u64, n: u32) {
+        self.slot(xdp_addr).fetch_add(n, Ordering::Relaxed);
+    }
+
+    // drops one reference, true if it was the last one and the chunk can be freed
+    pub fn release(&self, xdp_addr: u64) -> bool {
+        self.slot(xdp_addr)
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |extra| {
+                extra.checked_sub(1)
+            })
+            .is_err()
+    }
+
+    // forgets the references of a chunk reclaimed from the rings
+    pub fn reset(&self, xdp_addr: u64) {
+        self.slot(xdp_addr).store(0, Ordering::Relaxed);
+    }
+
+    pub fn count(&self, xdp_addr: u64) -> u32 {
+        self.slot(xdp_addr).load(Ordering::Acquire) + 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chunk_refs() {
+        let refs = ChunkRefs::new(ChunkLayout::new(4096, false), 4);
+
+        // exclusively owned chunks are freed at once
+        assert_eq!(refs.count(4096), 1);
+        assert!(refs.release(4096));
+
+        refs.acquire(4096 + 256, 2);
+        assert_eq!(refs.count(4096), 3);
+        assert_eq!(refs.count(0), 1);
+        assert!(!refs.release(4096 + 512));
+        assert!(!refs.release(4096));
+        assert!(refs.release(4096));
+        assert_eq!(refs.count(4096), 1);
+
+        refs.acquire(8192, 5);
+        refs.reset(8192);
+        assert!(refs.release(8192));
+    }
+}
@@ -1,117 +1,398 @@
-use std::{
-    pin::Pin,
-    sync::{Arc, Mutex},
-};
+#[cfg(loom)]
+use std::collections::VecDeque;
+use std::{os::fd::AsRawFd, pin::Pin};
 
+#[cfg(not(loom))]
+use crossbeam_queue::SegQueue;
 use libxdp_sys::xsk_ring_prod__needs_wakeup;
+use serde::Deserialize;
 
-use crate::error::CamelliaError;
+use crate::{
+    error::CamelliaError,
+    stats::{Stat, StatsSource},
+    sync::{Arc, AtomicUsize, Mutex, Ordering},
+};
 
 use super::{
-    base::{CompletionQueue, FillQueue, UMem},
+    base::{ChunkLayout, CompletionQueue, FillQueue, UMem},
     frame::{AppFrame, Chunk},
     libxdp::{populate_fill_ring, recycle_compeletion_ring},
     mmap::MMapArea,
-    AccessorRef,
+    refcount::ChunkRefsRef,
+    tracker::ChunkTrackerRef,
+    AccessorRef, RingState, UMemStat,
 };
 
+// Free chunks passed between the caches of shared accessors in segments, so that
+// accessors only take the UMem mutex when all segments are gone
+#[derive(Debug, Default)]
+pub struct ChunkSegments {
+    queue: SegQueue<Vec<usize>>,
+    chunks: AtomicUsize,
+}
+
+// loom can't see into SegQueue, models take it as the linearizable queue it is
+#[cfg(loom)]
+#[derive(Debug, Default)]
+struct SegQueue<T>(Mutex<VecDeque<T>>);
+
+#[cfg(loom)]
+impl<T> SegQueue<T> {
+    fn push(&self, value: T) {
+        self.0.lock().unwrap().push_back(value);
+    }
+
+    fn pop(&self) -> Option<T> {
+        self.0.lock().unwrap().pop_front()
+    }
+}
+
+impl ChunkSegments {
+    pub fn push(&self, segment: Vec<usize>) {
+        self.chunks.fetch_add(segment.len(), Ordering::Relaxed);
+        self.queue.push(segment);
+    }
+
+    pub fn pop(&self) -> Option<Vec<usize>> {
+        let segment = self.queue.pop()?;
+        self.chunks.fetch_sub(segment.len(), Ordering::Relaxed);
+        Some(segment)
+    }
+
+    // number of chunks in all segments
+    pub fn len(&self) -> usize {
+        self.chunks.load(Ordering::Relaxed)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Per-socket bounds of the chunk cache of a shared accessor. The watermarks default to
+// one and two segments of the UMem.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SharedCacheConfig {
+    // chunks kept in the cache on top of the requested ones when it is refilled
+    pub low_watermark: Option<usize>,
+    // cached chunks beyond it are returned to the segments
+    pub high_watermark: Option<usize>,
+    // at most this many chunks are held in the fill ring and the cache together, so that
+    // a busy socket can't starve the others sharing the UMem
+    pub quota: Option<usize>,
+}
+
+// Chunk counts of one accessor, each accessor is the only writer of its own counters
+#[derive(Debug, Default)]
+pub struct SharedAccessorCounters {
+    cached: AtomicUsize,
+    fill_ring: AtomicUsize,
+    tx_pending: AtomicUsize,
+}
+
 #[derive(Debug)]
 pub struct SharedAccessor {
     shared_umem: Arc<Mutex<UMem>>,
     umem_id: usize,
-    mmap_area: Arc<MMapArea>,
+    mmap_area: std::sync::Arc<MMapArea>,
     cached_chunks: Vec<usize>,
+    segments: Arc<ChunkSegments>,
+    segment_size: usize,
+    low_watermark: usize,
+    high_watermark: usize,
+    quota: Option<usize>,
     fill: Pin<Box<FillQueue>>,
     completion: Pin<Box<CompletionQueue>>,
-    chunk_size: u32,
+    layout: ChunkLayout,
     tx_issued_num: usize,
+    filled_num: usize,
+    counters: Arc<SharedAccessorCounters>,
+    tracker: Option<ChunkTrackerRef>,
+    refs: Option<ChunkRefsRef>,
 }
 
-const SHARED_UMEM_DEFAULT_CHUNK_SIZE: usize = 128;
-
 impl SharedAccessor {
     pub fn new(
         shared_umem: Arc<Mutex<UMem>>,
         fill: Pin<Box<FillQueue>>,
         completion: Pin<Box<CompletionQueue>>,
+        cache: SharedCacheConfig,
     ) -> Result<SharedAccessor, CamelliaError> {
-        let chunk_size = shared_umem.lock().unwrap().chunk_size;
+        let layout = shared_umem.lock().unwrap().layout;
         let mmap_area = shared_umem.lock().unwrap().area.clone();
         let umem_id = shared_umem.lock().unwrap().inner() as usize;
+        let tracker = shared_umem.lock().unwrap().tracker().cloned();
+        let refs = shared_umem.lock().unwrap().refs().cloned();
+        let segments = shared_umem.lock().unwrap().segments.clone();
+        let segment_size = shared_umem.lock().unwrap().segment_size;
+        let low_watermark = cache.low_watermark.unwrap_or(segment_size);
+        let high_watermark = cache.high_watermark.unwrap_or(2 * segment_size);
+
+        let counters = Arc::new(SharedAccessorCounters::default());
+        shared_umem
+            .lock()
+            .unwrap()
+            .shared_counters
+            .push(counters.clone());
         Ok(Self {
             shared_umem,
             umem_id,
             mmap_area,
             cached_chunks: Vec::new(),
+            segments,
+            segment_size,
+            low_watermark,
+            high_watermark,
+            quota: cache.quota,
             fill,
             completion,
-            chunk_size,
+            layout,
             tx_issued_num: 0,
+            filled_num: 0,
+            counters,
+            tracker,
+            refs,
         })
     }
 
+    fn publish(&self) {
+        self.counters
+            .cached
+            .store(self.cached_chunks.len(), Ordering::Relaxed);
+        self.counters
+            .fill_ring
+            .store(self.filled_num, Ordering::Relaxed);
+        self.counters
+            .tx_pending
+            .store(self.tx_issued_num, Ordering::Relaxed);
+    }
+
+    // the most chunks the cache may hold without exceeding the high watermark or the quota
+    fn cache_limit(&self) -> usize {
+        match self.quota {
+            Some(quota) => self
+                .high_watermark
+                .min(quota.saturating_sub(self.filled_num)),
+            None => self.high_watermark,
+        }
+    }
+
     fn pre_alloc(&mut self, n: usize) -> Result<(), CamelliaError> {
+        if self.cached_chunks.len() >= n {
+            return Ok(());
+        }
+
+        let mut target = n + self.low_watermark;
+        if let Some(quota) = self.quota {
+            target = target.min(quota.saturating_sub(self.filled_num)).max(n);
+        }
+
+        while self.cached_chunks.len() < target {
+            match self.segments.pop() {
+                Some(mut segment) => self.cached_chunks.append(&mut segment),
+                None => break,
+            }
+        }
+
         if self.cached_chunks.len() < n {
-            self.cached_chunks.append(
-                &mut self.shared_umem.lock().unwrap().allocate_raw(
-                    SHARED_UMEM_DEFAULT_CHUNK_SIZE / 2 + n - self.cached_chunks.len(),
-                )?,
-            )
+            let needed = n - self.cached_chunks.len();
+            let mut shared_umem = self.shared_umem.lock().unwrap();
+            let wanted = (target - self.cached_chunks.len())
+                .min(shared_umem.chunks.len())
+                .max(needed);
+            self.cached_chunks
+                .append(&mut shared_umem.allocate_raw(wanted)?);
         }
         Ok(())
     }
 
     fn after_free(&mut self) {
-        if self.cached_chunks.len() > SHARED_UMEM_DEFAULT_CHUNK_SIZE {
-            self.shared_umem.lock().unwrap().free_raw(
-                self.cached_chunks
-                    .drain(0..SHARED_UMEM_DEFAULT_CHUNK_SIZE / 2),
-            );
+        let limit = self.cache_limit();
+        while self.cached_chunks.len() > limit {
+            let keep = self
+                .cached_chunks
+                .len()
+                .saturating_sub(self.segment_size)
+                .max(self.low_watermark.min(limit));
+            let segment = self.cached_chunks.split_off(keep);
+            self.segments.push(segment);
         }
     }
 
     fn free(&mut self, chunk: Chunk) {
+        if let Some(refs) = &self.refs {
+            if !refs.release(chunk.xdp_address as u64) {
+                return;
+            }
+        }
+        if let Some(tracker) = &self.tracker {
+            tracker.lock().unwrap().free(chunk.xdp_address);
+        }
         self.cached_chunks.push(chunk.xdp_address);
         self.after_free();
+        self.publish();
     }
 
     fn fill(&mut self, n: usize) -> Result<usize, CamelliaError> {
+        let n = match self.quota {
+            Some(quota) => n.min(quota.saturating_sub(self.filled_num)),
+            None => n,
+        };
         self.pre_alloc(n)?;
 
+        // chunks are taken from the tail of the cache
+        let candidates: Vec<usize> = match &self.tracker {
+            Some(_) => self.cached_chunks[self.cached_chunks.len().saturating_sub(n)..].to_vec(),
+            None => Vec::new(),
+        };
+
         let populated = populate_fill_ring(&mut self.fill.0, n, &mut self.cached_chunks);
+
+        if let Some(tracker) = &self.tracker {
+            let mut tracker = tracker.lock().unwrap();
+            candidates[candidates.len() - populated..]
+                .iter()
+                .for_each(|address| tracker.fill(*address));
+        }
+        self.filled_num += populated;
         // chunks may not be consumed if there is no enough room in the free ring,
         // check whether we need to return them to the shared pool
         self.after_free();
+        self.publish();
         Ok(populated)
     }
 
     fn recycle(&mut self) -> Result<usize, CamelliaError> {
+        let cached = self.cached_chunks.len();
         let recycled = recycle_compeletion_ring(
             &mut self.completion.0,
             self.tx_issued_num,
-            self.chunk_size,
+            self.layout,
             &mut self.cached_chunks,
         );
         self.tx_issued_num -= recycled;
 
+        // completed copies of shared chunks stay out of the cache until the last one
+        if let Some(refs) = &self.refs {
+            let completed = self.cached_chunks.split_off(cached);
+            self.cached_chunks.extend(
+                completed
+                    .into_iter()
+                    .filter(|address| refs.release(*address as u64)),
+            );
+        }
+
+        if let Some(tracker) = &self.tracker {
+            let mut tracker = tracker.lock().unwrap();
+            self.cached_chunks[cached..]
+                .iter()
+                .for_each(|address| tracker.complete(*address));
+        }
+
         self.after_free();
+        self.publish();
         Ok(recycled)
     }
 
-    pub fn extract_recv(&mut self, xdp_addr: u64) -> Chunk {
-        // TODO(minhuw): add a helper function to get chunk identifier
-        // from xdp address, will be different in aligned and unaligned
-        // moode.
-        let base_address = xdp_addr - (xdp_addr % (self.chunk_size as u64));
+    fn chunk_of(&self, xdp_addr: u64) -> Chunk {
         Chunk {
-            xdp_address: base_address as usize,
-            size: self.chunk_size as usize,
+            xdp_address: self.layout.chunk_base(xdp_addr),
+            size: self.layout.usable_size() as usize,
             mmap_area: self.mmap_area.clone(),
         }
     }
 
-    pub fn register_send(&mut self, _chunk: Chunk) {
+    pub fn extract_recv(&mut self, xdp_addr: u64) -> Chunk {
+        let chunk = self.chunk_of(xdp_addr);
+        if let Some(tracker) = &self.tracker {
+            tracker.lock().unwrap().receive(chunk.xdp_address);
+        }
+        self.filled_num -= 1;
+        self.publish();
+        chunk
+    }
+
+    pub fn share(&mut self, xdp_addr: u64, n: u32) -> Result<(), CamelliaError> {
+        let Some(refs) = &self.refs else {
+            return Err(CamelliaError::InvalidArgument(
+                "chunks are not reference counted, see UMemBuilder::refcount_chunks".to_string(),
+            ));
+        };
+        refs.acquire(xdp_addr, n);
+        if let Some(tracker) = &self.tracker {
+            tracker
+                .lock()
+                .unwrap()
+                .share(self.layout.chunk_base(xdp_addr));
+        }
+        Ok(())
+    }
+
+    pub fn register_send(&mut self, chunk: Chunk) {
+        if let Some(tracker) = &self.tracker {
+            tracker.lock().unwrap().send(chunk.xdp_address);
+        }
         self.tx_issued_num += 1;
+        self.publish();
+    }
+
+    pub fn umem_stat(&self) -> UMemStat {
+        let shared_umem = self.shared_umem.lock().unwrap();
+        let mut stat = UMemStat {
+            free_chunks: shared_umem.chunks.len() + self.segments.len(),
+            ..Default::default()
+        };
+
+        for counters in shared_umem.shared_counters.iter() {
+            stat.free_chunks += counters.cached.load(Ordering::Relaxed);
+            stat.fill_ring += counters.fill_ring.load(Ordering::Relaxed);
+            stat.tx_pending += counters.tx_pending.load(Ordering::Relaxed);
+        }
+
+        // counters of other sockets may be updated concurrently
+        stat.app_owned = shared_umem
+            .num_chunks()
+            .saturating_sub(stat.free_chunks + stat.fill_ring + stat.tx_pending);
+        stat
+    }
+}
+
+impl Drop for SharedAccessor {
+    fn drop(&mut self) {
+        // chunks still in the rings are lost, but cached ones go back to the shared pool
+        if !self.cached_chunks.is_empty() {
+            self.segments.push(std::mem::take(&mut self.cached_chunks));
+        }
+        self.shared_umem
+            .lock()
+            .unwrap()
+            .shared_counters
+            .retain(|counters| !Arc::ptr_eq(counters, &self.counters));
+    }
+}
+
+impl AsRawFd for SharedAccessor {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        self.shared_umem.lock().unwrap().as_raw_fd()
+    }
+}
+
+impl StatsSource for SharedAccessor {
+    // each socket sharing the UMem has its own fill and completion rings
+    fn stats_id(&self) -> String {
+        format!("umem/{:#x}/{:p}", self.umem_id, &self.fill.0)
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        let shared_umem = self.shared_umem.lock().unwrap();
+        visit(Stat::gauge("free_chunks", shared_umem.chunks.len() as u64));
+        visit(Stat::gauge("chunk_size", self.layout.chunk_size() as u64));
+        visit(Stat::gauge(
+            "cached_chunks",
+            self.cached_chunks.len() as u64,
+        ));
+        visit(Stat::gauge("tx_inflight", self.tx_issued_num as u64));
     }
 }
 
@@ -130,17 +411,35 @@ impl SharedAccessorRef {
     }
 }
 
+impl StatsSource for SharedAccessorRef {
+    fn stats_id(&self) -> String {
+        self.inner.lock().unwrap().stats_id()
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        self.inner.lock().unwrap().visit_stats(visit)
+    }
+}
+
 impl AccessorRef for SharedAccessorRef {
     type UMemRef = Arc<Mutex<UMem>>;
     fn allocate(&self, n: usize) -> Result<Vec<AppFrame<Self>>, CamelliaError> {
         let mut shared_umem = self.inner.lock().unwrap();
         shared_umem.pre_alloc(n)?;
-        let chunk_size = shared_umem.chunk_size as usize;
+        let chunk_size = shared_umem.layout.usable_size() as usize;
         let mmap_area = shared_umem.mmap_area.clone();
 
-        Ok(shared_umem
+        let start = shared_umem.cached_chunks.len() - n;
+        if let Some(tracker) = &shared_umem.tracker {
+            let mut tracker = tracker.lock().unwrap();
+            shared_umem.cached_chunks[start..]
+                .iter()
+                .for_each(|address| tracker.allocate(*address));
+        }
+
+        let frames = shared_umem
             .cached_chunks
-            .drain(0..n)
+            .drain(start..)
             .map(|address| {
                 AppFrame::from_chunk(
                     Chunk {
@@ -151,7 +450,10 @@ impl AccessorRef for SharedAccessorRef {
                     self.clone(),
                 )
             })
-            .collect())
+            .collect();
+        shared_umem.publish();
+
+        Ok(frames)
     }
 
     fn equal(&self, other: &Self) -> bool {
@@ -171,10 +473,34 @@ impl AccessorRef for SharedAccessorRef {
         self.inner.lock().unwrap().extract_recv(xdp_addr)
     }
 
+    fn translate(&self, xdp_addr: u64) -> usize {
+        self.inner
+            .lock()
+            .unwrap()
+            .chunk_of(xdp_addr)
+            .xdp_to_addr(xdp_addr as usize)
+    }
+
     fn register_send(&self, chunk: Chunk) {
         self.inner.lock().unwrap().register_send(chunk)
     }
 
+    fn share(&self, xdp_addr: u64, n: u32) -> Result<(), CamelliaError> {
+        self.inner.lock().unwrap().share(xdp_addr, n)
+    }
+
+    fn tx_inflight(&self) -> usize {
+        self.inner.lock().unwrap().tx_issued_num
+    }
+
+    fn completion_ring(&self) -> Option<RingState> {
+        Some(RingState::of_cons(&self.inner.lock().unwrap().completion.0))
+    }
+
+    fn umem_stat(&self) -> UMemStat {
+        self.inner.lock().unwrap().umem_stat()
+    }
+
     fn inner(&self) -> usize {
         self.inner
             .lock()
@@ -193,3 +519,143 @@ impl AccessorRef for SharedAccessorRef {
         self.inner.lock().unwrap().recycle()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::umem::frame::TxFrame;
+    use libxdp_sys::{xsk_ring_cons, xsk_ring_prod};
+    #[cfg(loom)]
+    use loom::thread;
+    #[cfg(not(loom))]
+    use std::thread;
+
+    const NUM_CHUNKS: u32 = 16;
+    const RING_SIZE: u32 = 4;
+
+    // the kernel taking chunks from the fill ring to receive packets into
+    fn kernel_receive(fill: &xsk_ring_prod, n: u32) -> Vec<u64> {
+        unsafe {
+            let consumer = *fill.consumer;
+            let available = (*fill.producer).wrapping_sub(consumer).min(n);
+            let addresses = (0..available)
+                .map(|i| {
+                    *(fill.ring as *const u64).add((consumer.wrapping_add(i) & fill.mask) as usize)
+                })
+                .collect();
+            *fill.consumer = consumer.wrapping_add(available);
+            addresses
+        }
+    }
+
+    // the kernel completing the transmission of chunks
+    fn kernel_complete(completion: &xsk_ring_cons, addresses: &[u64]) {
+        unsafe {
+            let producer = *completion.producer;
+            for (i, address) in addresses.iter().enumerate() {
+                let slot = producer.wrapping_add(i as u32) & completion.mask;
+                *(completion.ring as *mut u64).add(slot as usize) = *address;
+            }
+            *completion.producer = producer.wrapping_add(addresses.len() as u32);
+        }
+    }
+
+    // A socket receiving and sending a batch through its own accessor of the UMem
+    fn run_socket(umem: Arc<Mutex<UMem>>) {
+        let cache = SharedCacheConfig {
+            low_watermark: Some(1),
+            high_watermark: Some(2),
+            quota: None,
+        };
+        let accessor = SharedAccessorRef::new(Arc::new(Mutex::new(
+            SharedAccessor::new(
+                umem,
+                Box::pin(FillQueue::detached(RING_SIZE)),
+                Box::pin(CompletionQueue::detached(RING_SIZE)),
+                cache,
+            )
+            .unwrap(),
+        )));
+
+        assert_eq!(accessor.fill(2).unwrap(), 2);
+        let received = kernel_receive(&accessor.inner.lock().unwrap().fill.0, 2);
+        for address in received {
+            accessor.free(accessor.extract_recv(address));
+        }
+
+        let sent: Vec<u64> = accessor
+            .allocate(2)
+            .unwrap()
+            .into_iter()
+            .map(|frame| {
+                let chunk = TxFrame::from(frame).take();
+                let address = chunk.xdp_address as u64;
+                accessor.register_send(chunk);
+                address
+            })
+            .collect();
+        assert!(accessor.umem_stat().tx_pending >= 2);
+        kernel_complete(&accessor.inner.lock().unwrap().completion.0, &sent);
+        assert_eq!(accessor.recycle().unwrap(), 2);
+    }
+
+    // Sockets sharing the UMem allocate, fill and recycle concurrently. Once they are
+    // gone, every chunk is back in the UMem or the segments exactly once.
+    fn shared_accessors() {
+        let mut umem = UMem::detached(NUM_CHUNKS, RING_SIZE);
+        umem.segment_size = 2;
+        let umem = Arc::new(Mutex::new(umem));
+
+        let sockets: Vec<_> = (0..2)
+            .map(|_| {
+                let umem = umem.clone();
+                thread::spawn(move || run_socket(umem))
+            })
+            .collect();
+        for socket in sockets {
+            socket.join().unwrap();
+        }
+
+        let umem = umem.lock().unwrap();
+        assert!(umem.shared_counters.is_empty());
+        let mut chunks = umem.chunks.clone();
+        while let Some(mut segment) = umem.segments.pop() {
+            chunks.append(&mut segment);
+        }
+        assert_eq!(chunks.len(), NUM_CHUNKS as usize);
+        chunks.sort_unstable();
+        chunks.dedup();
+        assert_eq!(chunks.len(), NUM_CHUNKS as usize);
+    }
+
+    #[cfg(not(loom))]
+    #[test]
+    fn test_shared_accessors() {
+        shared_accessors();
+    }
+
+    // RUSTFLAGS="--cfg loom" cargo test -p camellia --release --lib loom
+    #[cfg(loom)]
+    #[test]
+    fn loom_shared_accessors() {
+        let mut builder = loom::model::Builder::new();
+        builder.preemption_bound = Some(2);
+        builder.check(shared_accessors);
+    }
+
+    #[test]
+    fn test_chunk_segments() {
+        let segments = ChunkSegments::default();
+        assert!(segments.is_empty());
+
+        segments.push(vec![0, 4096]);
+        segments.push(vec![8192]);
+        assert_eq!(segments.len(), 3);
+
+        assert_eq!(segments.pop(), Some(vec![0, 4096]));
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments.pop(), Some(vec![8192]));
+        assert_eq!(segments.pop(), None);
+        assert!(segments.is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ChunkState {
+    // in the UMem pool or in the cache of an accessor
+    Free,
+    // owned by the application
+    Allocated,
+    // in the fill ring or the RX ring
+    Filled,
+    // in the TX ring or the completion ring
+    TxPending,
+    // referred to by several frames until the last reference is freed or completed
+    Shared,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkViolation {
+    pub address: usize,
+    pub operation: &'static str,
+    pub state: ChunkState,
+}
+
+impl Display for ChunkViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} chunk {:#x} in state {:?}",
+            self.operation, self.address, self.state
+        )
+    }
+}
+
+// Records the state of every chunk of a UMem to catch leaks and double frees. It is
+// meant for debugging, every transition takes a lock and a hash lookup.
+#[derive(Debug, Default)]
+pub struct ChunkTracker {
+    states: HashMap<usize, ChunkState>,
+    violations: Vec<ChunkViolation>,
+}
+
+pub type ChunkTrackerRef = Arc<Mutex<ChunkTracker>>;
+
+impl ChunkTracker {
+    pub fn new(chunks: impl IntoIterator<Item = usize>) -> Self {
+        Self {
+            states: chunks
+                .into_iter()
+                .map(|address| (address, ChunkState::Free))
+                .collect(),
+            violations: Vec::new(),
+        }
+    }
+
+    fn transition(
+        &mut self,
+        address: usize,
+        operation: &'static str,
+        expected: ChunkState,
+        to: ChunkState,
+    ) {
+        let Some(state) = self.states.get_mut(&address) else {
+            log::error!("{} unknown chunk {:#x}", operation, address);
+            return;
+        };
+
+        if *state != expected {
+            let violation = ChunkViolation {
+                address,
+                operation,
+                state: *state,
+            };
+            log::error!("{}", violation);
+            self.violations.push(violation);
+        }
+
+        *state = to;
+    }
+
+    pub fn allocate(&mut self, address: usize) {
+        self.transition(address, "allocate", ChunkState::Free, ChunkState::Allocated)
+    }
+
+    pub fn fill(&mut self, address: usize) {
+        self.transition(address, "fill", ChunkState::Free, ChunkState::Filled)
+    }
+
+    pub fn receive(&mut self, address: usize) {
+        self.transition(
+            address,
+            "receive",
+            ChunkState::Filled,
+            ChunkState::Allocated,
+        )
+    }
+
+    pub fn share(&mut self, address: usize) {
+        if self.state(address) != Some(ChunkState::Shared) {
+            self.transition(address, "share", ChunkState::Allocated, ChunkState::Shared)
+        }
+    }
+
+    // copies of shared chunks are sent, freed and completed in any order
+    fn release_shared(&mut self, address: usize) -> bool {
+        match self.states.get_mut(&address) {
+            Some(state) if *state == ChunkState::Shared => {
+                *state = ChunkState::Free;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn send(&mut self, address: usize) {
+        if self.state(address) == Some(ChunkState::Shared) {
+            return;
+        }
+        self.transition(
+            address,
+            "send",
+            ChunkState::Allocated,
+            ChunkState::TxPending,
+        )
+    }
+
+    pub fn free(&mut self, address: usize) {
+        if self.release_shared(address) {
+            return;
+        }
+        self.transition(address, "free", ChunkState::Allocated, ChunkState::Free)
+    }
+
+    pub fn complete(&mut self, address: usize) {
+        if self.release_shared(address) {
+            return;
+        }
+        self.transition(address, "complete", ChunkState::TxPending, ChunkState::Free)
+    }
+
+    pub fn state(&self, address: usize) -> Option<ChunkState> {
+        self.states.get(&address).copied()
+    }
+
+    pub fn count(&self, state: ChunkState) -> usize {
+        self.states.values().filter(|s| **s == state).count()
+    }
+
+    pub fn violations(&self) -> &[ChunkViolation] {
+        &self.violations
+    }
+
+    // chunks held by the application or still in flight, filled chunks belong to the kernel
+    pub fn leaked(&self) -> Vec<(usize, ChunkState)> {
+        let mut leaked: Vec<_> = self
+            .states
+            .iter()
+            .filter(|(_, state)| {
+                matches!(
+                    state,
+                    ChunkState::Allocated | ChunkState::TxPending | ChunkState::Shared
+                )
+            })
+            .map(|(address, state)| (*address, *state))
+            .collect();
+        leaked.sort_by_key(|(address, _)| *address);
+        leaked
+    }
+
+    pub fn report(&self) {
+        let leaked = self.leaked();
+        if !leaked.is_empty() {
+            eprintln!(
+                "{} chunks leaked ({} allocated, {} TX pending), first: {:#x}",
+                leaked.len(),
+                self.count(ChunkState::Allocated),
+                self.count(ChunkState::TxPending),
+                leaked[0].0
+            );
+        }
+        if !self.violations.is_empty() {
+            eprintln!(
+                "{} chunk violations, first: {}",
+                self.violations.len(),
+                self.violations[0]
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_chunk_lifecycle() {
+        let mut tracker = ChunkTracker::new([0, 4096, 8192]);
+
+        tracker.allocate(0);
+        tracker.send(0);
+        tracker.complete(0);
+
+        tracker.fill(4096);
+        tracker.receive(4096);
+        tracker.free(4096);
+
+        assert!(tracker.violations().is_empty());
+        assert!(tracker.leaked().is_empty());
+        assert_eq!(tracker.count(ChunkState::Free), 3);
+
+        tracker.allocate(8192);
+        tracker.send(8192);
+        assert_eq!(tracker.leaked(), vec![(8192, ChunkState::TxPending)]);
+    }
+
+    #[test]
+    fn test_shared_chunk() {
+        let mut tracker = ChunkTracker::new([0, 4096]);
+
+        // the last reference is released by a completion
+        tracker.allocate(0);
+        tracker.share(0);
+        tracker.send(0);
+        tracker.send(0);
+        tracker.complete(0);
+
+        // or by freeing a copy which is never sent
+        tracker.allocate(4096);
+        tracker.share(4096);
+        tracker.send(4096);
+        assert_eq!(tracker.leaked(), vec![(4096, ChunkState::Shared)]);
+        tracker.free(4096);
+
+        assert!(tracker.violations().is_empty());
+        assert_eq!(tracker.count(ChunkState::Free), 2);
+    }
+
+    #[test]
+    fn test_double_free() {
+        let mut tracker = ChunkTracker::new([0, 4096]);
+
+        tracker.allocate(0);
+        tracker.free(0);
+        tracker.free(0);
+        // freeing a chunk still owned by the kernel
+        tracker.allocate(4096);
+        tracker.send(4096);
+        tracker.free(4096);
+
+        assert_eq!(
+            tracker.violations(),
+            &[
+                ChunkViolation {
+                    address: 0,
+                    operation: "free",
+                    state: ChunkState::Free,
+                },
+                ChunkViolation {
+                    address: 4096,
+                    operation: "free",
+                    state: ChunkState::TxPending,
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,110 @@
+#ifndef __CAMELLIA_COMMON_H
+#define __CAMELLIA_COMMON_H
+
+#include <linux/bpf.h>
+#include <linux/if_ether.h>
+#include <linux/in.h>
+#include <linux/ip.h>
+#include <linux/ipv6.h>
+#include <bpf/bpf_endian.h>
+#include <bpf/bpf_helpers.h>
+#include <xdp/xdp_helpers.h>
+
+/* fields are in network byte order, IPv4 addresses take the first word */
+struct flow {
+	__u32 src_addr[4];
+	__u32 dst_addr[4];
+	__be16 src_port;
+	__be16 dst_port;
+	__be16 eth_proto;
+	__u8 ip_proto;
+	__u8 pad;
+};
+
+/* packets and bytes per queue and XDP action, read by XdpProgStats */
+#define STATS_QUEUES 64
+#define STATS_ACTIONS (XDP_REDIRECT + 1)
+
+struct action_counter {
+	__u64 packets;
+	__u64 bytes;
+};
+
+struct {
+	__uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+	__uint(max_entries, STATS_QUEUES * STATS_ACTIONS);
+	__type(key, __u32);
+	__type(value, struct action_counter);
+} xdp_stats SEC(".maps");
+
+/* counts the packet and returns the action, queues beyond STATS_QUEUES are not counted */
+static __always_inline int record(struct xdp_md *ctx, int action)
+{
+	__u32 key = ctx->rx_queue_index * STATS_ACTIONS + action;
+	struct action_counter *counter;
+
+	if (ctx->rx_queue_index >= STATS_QUEUES || action < 0 || action >= STATS_ACTIONS)
+		return action;
+
+	counter = bpf_map_lookup_elem(&xdp_stats, &key);
+	if (counter) {
+		counter->packets++;
+		counter->bytes += ctx->data_end - ctx->data;
+	}
+	return action;
+}
+
+struct ports {
+	__be16 src;
+	__be16 dst;
+};
+
+/* Parses the headers of a packet up to the ports of TCP and UDP. Returns -1 if the
+ * packet is truncated, fields of unknown protocols are left zero. */
+static __always_inline int parse_flow(struct xdp_md *ctx, struct flow *flow)
+{
+	void *data = (void *)(long)ctx->data;
+	void *data_end = (void *)(long)ctx->data_end;
+	struct ethhdr *eth = data;
+	struct ports *ports;
+	void *l4;
+
+	if ((void *)(eth + 1) > data_end)
+		return -1;
+	flow->eth_proto = eth->h_proto;
+
+	if (eth->h_proto == bpf_htons(ETH_P_IP)) {
+		struct iphdr *ip = (void *)(eth + 1);
+
+		if ((void *)(ip + 1) > data_end)
+			return -1;
+		flow->ip_proto = ip->protocol;
+		flow->src_addr[0] = ip->saddr;
+		flow->dst_addr[0] = ip->daddr;
+		l4 = (void *)ip + ip->ihl * 4;
+	} else if (eth->h_proto == bpf_htons(ETH_P_IPV6)) {
+		struct ipv6hdr *ip6 = (void *)(eth + 1);
+
+		if ((void *)(ip6 + 1) > data_end)
+			return -1;
+		/* extension headers are not followed */
+		flow->ip_proto = ip6->nexthdr;
+		__builtin_memcpy(flow->src_addr, &ip6->saddr, sizeof(flow->src_addr));
+		__builtin_memcpy(flow->dst_addr, &ip6->daddr, sizeof(flow->dst_addr));
+		l4 = ip6 + 1;
+	} else {
+		return 0;
+	}
+
+	if (flow->ip_proto != IPPROTO_TCP && flow->ip_proto != IPPROTO_UDP)
+		return 0;
+
+	ports = l4;
+	if ((void *)(ports + 1) > data_end)
+		return -1;
+	flow->src_port = ports->src;
+	flow->dst_port = ports->dst;
+	return 0;
+}
+
+#endif
@@ -0,0 +1,60 @@
+#include "common.h"
+
+/* Flows matching a rule are passed to the kernel on the CPU of the rule, the others are
+ * redirected to the AF_XDP socket of the receiving queue. */
+struct cpu_rule {
+	__u8 ip_proto;
+	__u8 pad;
+	/* zero matches every port of the protocol */
+	__be16 dst_port;
+};
+
+struct {
+	__uint(type, BPF_MAP_TYPE_CPUMAP);
+	__uint(max_entries, 256);
+	__type(key, __u32);
+	__type(value, struct bpf_cpumap_val);
+} cpu_map SEC(".maps");
+
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, 1024);
+	__type(key, struct cpu_rule);
+	__type(value, __u32);
+} cpu_rules SEC(".maps");
+
+struct {
+	__uint(type, BPF_MAP_TYPE_XSKMAP);
+	__uint(max_entries, 64);
+	__type(key, __u32);
+	__type(value, __u32);
+} xsks_map SEC(".maps");
+
+struct {
+	__uint(priority, 20);
+	__uint(XDP_PASS, 1);
+} XDP_RUN_CONFIG(cpumap_redirect);
+
+SEC("xdp")
+int cpumap_redirect(struct xdp_md *ctx)
+{
+	struct flow flow = {};
+	struct cpu_rule rule = {};
+	__u32 *cpu;
+
+	if (parse_flow(ctx, &flow) == 0 && flow.ip_proto) {
+		rule.ip_proto = flow.ip_proto;
+		rule.dst_port = flow.dst_port;
+		cpu = bpf_map_lookup_elem(&cpu_rules, &rule);
+		if (!cpu) {
+			rule.dst_port = 0;
+			cpu = bpf_map_lookup_elem(&cpu_rules, &rule);
+		}
+		if (cpu)
+			return record(ctx, bpf_redirect_map(&cpu_map, *cpu, XDP_PASS));
+	}
+
+	return record(ctx, bpf_redirect_map(&xsks_map, ctx->rx_queue_index, XDP_PASS));
+}
+
+char _license[] SEC("license") = "GPL";
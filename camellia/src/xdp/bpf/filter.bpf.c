@@ -0,0 +1,137 @@
+#include "common.h"
+
+/* Decides whether packets reach the AF_XDP socket of the receiving queue. Rules on the
+ * source prefix take precedence over rules on the protocols, the most specific rule of
+ * each kind wins, and the default action applies if no rule matches. */
+#define FILTER_ALLOW 0
+#define FILTER_DENY 1
+#define FILTER_DROP 2
+
+/* struct FilterKey of filter.rs, zero fields match anything */
+struct filter_key {
+	__be16 eth_proto;
+	__u8 ip_proto;
+	__u8 pad;
+	__be16 dst_port;
+	__u16 pad2;
+};
+
+struct lpm_v4_key {
+	__u32 prefixlen;
+	__u8 addr[4];
+};
+
+struct lpm_v6_key {
+	__u32 prefixlen;
+	__u8 addr[16];
+};
+
+struct {
+	__uint(type, BPF_MAP_TYPE_XSKMAP);
+	__uint(max_entries, 64);
+	__type(key, __u32);
+	__type(value, __u32);
+} xsks_map SEC(".maps");
+
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, 4096);
+	__type(key, struct filter_key);
+	__type(value, __u32);
+} filter_rules SEC(".maps");
+
+struct {
+	__uint(type, BPF_MAP_TYPE_LPM_TRIE);
+	__uint(max_entries, 4096);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+	__type(key, struct lpm_v4_key);
+	__type(value, __u32);
+} filter_v4_prefixes SEC(".maps");
+
+struct {
+	__uint(type, BPF_MAP_TYPE_LPM_TRIE);
+	__uint(max_entries, 4096);
+	__uint(map_flags, BPF_F_NO_PREALLOC);
+	__type(key, struct lpm_v6_key);
+	__type(value, __u32);
+} filter_v6_prefixes SEC(".maps");
+
+/* the default action at index 0 */
+struct {
+	__uint(type, BPF_MAP_TYPE_ARRAY);
+	__uint(max_entries, 1);
+	__type(key, __u32);
+	__type(value, __u32);
+} filter_default SEC(".maps");
+
+struct {
+	__uint(priority, 20);
+	__uint(XDP_PASS, 1);
+} XDP_RUN_CONFIG(packet_filter);
+
+static __always_inline __u32 *match_prefix(struct flow *flow)
+{
+	if (flow->eth_proto == bpf_htons(ETH_P_IP)) {
+		struct lpm_v4_key key = { .prefixlen = 32 };
+
+		__builtin_memcpy(key.addr, flow->src_addr, sizeof(key.addr));
+		return bpf_map_lookup_elem(&filter_v4_prefixes, &key);
+	}
+	if (flow->eth_proto == bpf_htons(ETH_P_IPV6)) {
+		struct lpm_v6_key key = { .prefixlen = 128 };
+
+		__builtin_memcpy(key.addr, flow->src_addr, sizeof(key.addr));
+		return bpf_map_lookup_elem(&filter_v6_prefixes, &key);
+	}
+	return NULL;
+}
+
+static __always_inline __u32 *lookup_rule(__be16 eth_proto, __u8 ip_proto, __be16 dst_port)
+{
+	struct filter_key key = {
+		.eth_proto = eth_proto,
+		.ip_proto = ip_proto,
+		.dst_port = dst_port,
+	};
+
+	return bpf_map_lookup_elem(&filter_rules, &key);
+}
+
+static __always_inline __u32 *match_rule(struct flow *flow)
+{
+	__u32 *action;
+
+	if ((action = lookup_rule(flow->eth_proto, flow->ip_proto, flow->dst_port)))
+		return action;
+	if ((action = lookup_rule(flow->eth_proto, flow->ip_proto, 0)))
+		return action;
+	if ((action = lookup_rule(0, flow->ip_proto, flow->dst_port)))
+		return action;
+	if ((action = lookup_rule(0, flow->ip_proto, 0)))
+		return action;
+	return lookup_rule(flow->eth_proto, 0, 0);
+}
+
+SEC("xdp")
+int packet_filter(struct xdp_md *ctx)
+{
+	struct flow flow = {};
+	__u32 zero = 0;
+	__u32 *action = NULL;
+
+	if (parse_flow(ctx, &flow) == 0) {
+		action = match_prefix(&flow);
+		if (!action)
+			action = match_rule(&flow);
+	}
+	if (!action)
+		action = bpf_map_lookup_elem(&filter_default, &zero);
+
+	if (action && *action == FILTER_DENY)
+		return record(ctx, XDP_PASS);
+	if (action && *action == FILTER_DROP)
+		return record(ctx, XDP_DROP);
+	return record(ctx, bpf_redirect_map(&xsks_map, ctx->rx_queue_index, XDP_PASS));
+}
+
+char _license[] SEC("license") = "GPL";
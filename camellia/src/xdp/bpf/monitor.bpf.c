@@ -0,0 +1,105 @@
+#include <linux/bpf.h>
+#include <bpf/bpf_helpers.h>
+
+/* Forwards errors reported by the XDP tracepoints to user space, see
+ * /sys/kernel/tracing/events/xdp/ for the layouts of the contexts. */
+#define EVENT_EXCEPTION 0
+#define EVENT_REDIRECT_ERR 1
+
+struct xdp_exception_ctx {
+	__u64 __pad;
+	int prog_id;
+	__u32 act;
+	int ifindex;
+};
+
+struct xdp_redirect_ctx {
+	__u64 __pad;
+	int prog_id;
+	__u32 act;
+	int ifindex;
+	int err;
+	int to_ifindex;
+	__u32 map_id;
+	int map_index;
+};
+
+/* struct RawXdpEvent of monitor.rs */
+struct xdp_event {
+	__u32 kind;
+	int prog_id;
+	__u32 act;
+	int ifindex;
+	int err;
+	int to_ifindex;
+	__u32 map_id;
+	int map_index;
+};
+
+/* the interface to report events of, zero for all */
+struct {
+	__uint(type, BPF_MAP_TYPE_ARRAY);
+	__uint(max_entries, 1);
+	__type(key, __u32);
+	__type(value, int);
+} monitor_ifindex SEC(".maps");
+
+struct {
+	__uint(type, BPF_MAP_TYPE_RINGBUF);
+	__uint(max_entries, 256 * 1024);
+} events SEC(".maps");
+
+static __always_inline int monitored(int ifindex)
+{
+	__u32 key = 0;
+	int *target = bpf_map_lookup_elem(&monitor_ifindex, &key);
+
+	return !target || !*target || *target == ifindex;
+}
+
+SEC("tp/xdp/xdp_exception")
+int trace_xdp_exception(struct xdp_exception_ctx *ctx)
+{
+	struct xdp_event event = {
+		.kind = EVENT_EXCEPTION,
+		.prog_id = ctx->prog_id,
+		.act = ctx->act,
+		.ifindex = ctx->ifindex,
+	};
+
+	if (monitored(ctx->ifindex))
+		bpf_ringbuf_output(&events, &event, sizeof(event), 0);
+	return 0;
+}
+
+static __always_inline int redirect_err(struct xdp_redirect_ctx *ctx)
+{
+	struct xdp_event event = {
+		.kind = EVENT_REDIRECT_ERR,
+		.prog_id = ctx->prog_id,
+		.act = ctx->act,
+		.ifindex = ctx->ifindex,
+		.err = ctx->err,
+		.to_ifindex = ctx->to_ifindex,
+		.map_id = ctx->map_id,
+		.map_index = ctx->map_index,
+	};
+
+	if (monitored(ctx->ifindex))
+		bpf_ringbuf_output(&events, &event, sizeof(event), 0);
+	return 0;
+}
+
+SEC("tp/xdp/xdp_redirect_err")
+int trace_xdp_redirect_err(struct xdp_redirect_ctx *ctx)
+{
+	return redirect_err(ctx);
+}
+
+SEC("tp/xdp/xdp_redirect_map_err")
+int trace_xdp_redirect_map_err(struct xdp_redirect_ctx *ctx)
+{
+	return redirect_err(ctx);
+}
+
+char _license[] SEC("license") = "GPL";
@@ -0,0 +1,24 @@
+#include "common.h"
+
+/* Redirects every packet to the AF_XDP socket of the receiving queue and passes packets
+ * of queues without one to the kernel, like the default program of libxdp, but counts
+ * what happens to them in xdp_stats. */
+struct {
+	__uint(type, BPF_MAP_TYPE_XSKMAP);
+	__uint(max_entries, 64);
+	__type(key, __u32);
+	__type(value, __u32);
+} xsks_map SEC(".maps");
+
+struct {
+	__uint(priority, 20);
+	__uint(XDP_PASS, 1);
+} XDP_RUN_CONFIG(xsk_redirect);
+
+SEC("xdp")
+int xsk_redirect(struct xdp_md *ctx)
+{
+	return record(ctx, bpf_redirect_map(&xsks_map, ctx->rx_queue_index, XDP_PASS));
+}
+
+char _license[] SEC("license") = "GPL";
@@ -0,0 +1,74 @@
+#include "common.h"
+
+/* Spreads the packets of each queue over the sockets bound to it by hashing their flow,
+ * unless the flow is pinned to a socket in flows. The kernel only delivers packets to
+ * sockets bound to the receiving queue, so sockets of a queue share their UMem. */
+#define MAX_QUEUES 64
+#define SLOTS_PER_QUEUE 16
+
+/* keyed by queue * SLOTS_PER_QUEUE + slot */
+struct {
+	__uint(type, BPF_MAP_TYPE_XSKMAP);
+	__uint(max_entries, MAX_QUEUES * SLOTS_PER_QUEUE);
+	__type(key, __u32);
+	__type(value, __u32);
+} xsks_map SEC(".maps");
+
+/* number of slots of each queue, flows are hashed over them */
+struct {
+	__uint(type, BPF_MAP_TYPE_ARRAY);
+	__uint(max_entries, MAX_QUEUES);
+	__type(key, __u32);
+	__type(value, __u32);
+} slots SEC(".maps");
+
+struct {
+	__uint(type, BPF_MAP_TYPE_HASH);
+	__uint(max_entries, 65536);
+	__type(key, struct flow);
+	__type(value, __u32);
+} flows SEC(".maps");
+
+struct {
+	__uint(priority, 20);
+	__uint(XDP_PASS, 1);
+} XDP_RUN_CONFIG(steering);
+
+/* FNV-1a over the flow, Steering::slot_of computes the same */
+static __always_inline __u32 flow_hash(struct flow *flow)
+{
+	__u8 *bytes = (__u8 *)flow;
+	__u32 hash = 2166136261;
+
+#pragma unroll
+	for (int i = 0; i < sizeof(*flow); i++) {
+		hash ^= bytes[i];
+		hash *= 16777619;
+	}
+	return hash;
+}
+
+SEC("xdp")
+int steering(struct xdp_md *ctx)
+{
+	__u32 queue = ctx->rx_queue_index;
+	struct flow flow = {};
+	__u32 *count, *slot;
+	__u32 key;
+
+	count = bpf_map_lookup_elem(&slots, &queue);
+	if (!count || !*count || parse_flow(ctx, &flow) < 0)
+		return record(ctx, bpf_redirect_map(&xsks_map, queue * SLOTS_PER_QUEUE, XDP_PASS));
+
+	slot = bpf_map_lookup_elem(&flows, &flow);
+	if (slot)
+		key = *slot;
+	else
+		key = flow_hash(&flow) % *count;
+
+	if (key >= SLOTS_PER_QUEUE)
+		return record(ctx, XDP_ABORTED);
+	return record(ctx, bpf_redirect_map(&xsks_map, queue * SLOTS_PER_QUEUE + key, XDP_PASS));
+}
+
+char _license[] SEC("license") = "GPL";
@@ -0,0 +1,113 @@
+use crate::{
+    error::CamelliaError,
+    socket::af_xdp::XDPMode,
+    xdp::{
+        map::BpfMap,
+        program::{XdpProgram, XSKS_MAP},
+        stats::XdpProgStats,
+    },
+};
+
+static CPUMAP_REDIRECT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/cpumap_redirect.bpf.o"));
+
+// struct cpu_rule of cpumap_redirect.bpf.c
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CpuRule {
+    ip_proto: u8,
+    pad: u8,
+    // network byte order, zero matches every port
+    dst_port: u16,
+}
+
+impl CpuRule {
+    fn new(ip_proto: u8, dst_port: Option<u16>) -> Self {
+        Self {
+            ip_proto,
+            pad: 0,
+            dst_port: dst_port.unwrap_or(0).to_be(),
+        }
+    }
+}
+
+// struct bpf_cpumap_val without a program to run on the CPU
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CpumapValue {
+    qsize: u32,
+    prog_fd: i32,
+}
+
+// The built-in program passing selected flows to the kernel on chosen CPUs through a
+// CPUMAP, e.g., control-plane traffic, and redirecting the others to AF_XDP sockets.
+// Sockets are registered in its xsks_map, see XskSocketBuilder::xsks_map.
+#[derive(Debug)]
+pub struct CpumapRedirect {
+    program: XdpProgram,
+}
+
+impl CpumapRedirect {
+    pub fn new() -> Result<Self, CamelliaError> {
+        Ok(Self {
+            program: XdpProgram::from_bytes(CPUMAP_REDIRECT, Some("xdp"))?,
+        })
+    }
+
+    pub fn program(&self) -> &XdpProgram {
+        &self.program
+    }
+
+    pub fn program_mut(&mut self) -> &mut XdpProgram {
+        &mut self.program
+    }
+
+    // the maps below are available once attached
+    pub fn attach(&mut self, ifname: &str, mode: XDPMode) -> Result<(), CamelliaError> {
+        self.program.attach(ifname, mode)
+    }
+
+    pub fn xsks_map(&self) -> Result<BpfMap, CamelliaError> {
+        self.program.map(XSKS_MAP)
+    }
+
+    // packets passed to the kernel on a CPU are counted as redirected
+    pub fn stats(&self, queue_index: u32) -> Result<XdpProgStats, CamelliaError> {
+        XdpProgStats::new(&self.program, queue_index)
+    }
+
+    // Starts a kthread on the CPU processing redirected packets, queue_size packets are
+    // queued for it at most.
+    pub fn enable_cpu(&self, cpu: u32, queue_size: u32) -> Result<(), CamelliaError> {
+        self.program.map("cpu_map")?.update(
+            &cpu,
+            &CpumapValue {
+                qsize: queue_size,
+                prog_fd: 0,
+            },
+        )
+    }
+
+    // Packets of rules pointing to a disabled CPU are dropped.
+    pub fn disable_cpu(&self, cpu: u32) -> Result<bool, CamelliaError> {
+        self.program.map("cpu_map")?.delete(&cpu)
+    }
+
+    // Pass packets of the IP protocol to the kernel on the CPU, only those to dst_port if
+    // given. Rules with ports take precedence.
+    pub fn redirect_to_cpu(
+        &self,
+        ip_proto: u8,
+        dst_port: Option<u16>,
+        cpu: u32,
+    ) -> Result<(), CamelliaError> {
+        self.program
+            .map("cpu_rules")?
+            .update(&CpuRule::new(ip_proto, dst_port), &cpu)
+    }
+
+    pub fn remove_rule(&self, ip_proto: u8, dst_port: Option<u16>) -> Result<bool, CamelliaError> {
+        self.program
+            .map("cpu_rules")?
+            .delete(&CpuRule::new(ip_proto, dst_port))
+    }
+}
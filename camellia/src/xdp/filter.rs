@@ -0,0 +1,244 @@
+use std::net::IpAddr;
+
+use crate::{
+    error::CamelliaError,
+    socket::af_xdp::XDPMode,
+    xdp::{
+        map::BpfMap,
+        program::{XdpProgram, XSKS_MAP},
+        stats::XdpProgStats,
+    },
+};
+
+static FILTER: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/filter.bpf.o"));
+
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterAction {
+    // redirect to the AF_XDP socket
+    Allow = 0,
+    // pass to the kernel
+    Deny = 1,
+    Drop = 2,
+}
+
+// What a rule matches, unset fields match anything. Rules on the destination port need
+// the IP protocol.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FilterMatch {
+    pub ethertype: Option<u16>,
+    pub ip_proto: Option<u8>,
+    pub dst_port: Option<u16>,
+}
+
+impl FilterMatch {
+    pub fn ethertype(mut self, ethertype: u16) -> Self {
+        self.ethertype = Some(ethertype);
+        self
+    }
+
+    pub fn ip_proto(mut self, ip_proto: u8) -> Self {
+        self.ip_proto = Some(ip_proto);
+        self
+    }
+
+    pub fn dst_port(mut self, dst_port: u16) -> Self {
+        self.dst_port = Some(dst_port);
+        self
+    }
+
+    fn key(&self) -> Result<FilterKey, CamelliaError> {
+        match self {
+            FilterMatch {
+                ip_proto: None,
+                dst_port: Some(_),
+                ..
+            } => Err(CamelliaError::InvalidArgument(
+                "a rule on the destination port needs the IP protocol".to_string(),
+            )),
+            FilterMatch {
+                ethertype: None,
+                ip_proto: None,
+                ..
+            } => Err(CamelliaError::InvalidArgument(
+                "a rule matching everything is the default action".to_string(),
+            )),
+            _ => Ok(FilterKey {
+                eth_proto: self.ethertype.unwrap_or(0).to_be(),
+                ip_proto: self.ip_proto.unwrap_or(0),
+                pad: 0,
+                dst_port: self.dst_port.unwrap_or(0).to_be(),
+                pad2: 0,
+            }),
+        }
+    }
+}
+
+// struct filter_key of filter.bpf.c
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct FilterKey {
+    eth_proto: u16,
+    ip_proto: u8,
+    pad: u8,
+    dst_port: u16,
+    pad2: u16,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct LpmKey<const N: usize> {
+    prefix_len: u32,
+    addr: [u8; N],
+}
+
+fn masked<const N: usize>(octets: [u8; N], prefix_len: u8) -> LpmKey<N> {
+    let mut addr = [0; N];
+    for (index, byte) in octets.iter().enumerate() {
+        let bits = (prefix_len as usize).saturating_sub(index * 8).min(8);
+        addr[index] = byte & !(0xffu8.checked_shr(bits as u32).unwrap_or(0));
+    }
+    LpmKey {
+        prefix_len: prefix_len as u32,
+        addr,
+    }
+}
+
+// The built-in program deciding which packets reach the AF_XDP sockets by rules updated
+// at runtime. Rules on the source prefix take precedence over rules on protocols, the
+// most specific rule of each kind wins. Sockets are registered in its xsks_map.
+#[derive(Debug)]
+pub struct PacketFilter {
+    program: XdpProgram,
+}
+
+impl PacketFilter {
+    pub fn new() -> Result<Self, CamelliaError> {
+        Ok(Self {
+            program: XdpProgram::from_bytes(FILTER, Some("xdp"))?,
+        })
+    }
+
+    pub fn program(&self) -> &XdpProgram {
+        &self.program
+    }
+
+    pub fn program_mut(&mut self) -> &mut XdpProgram {
+        &mut self.program
+    }
+
+    // the maps below are available once attached, every packet is allowed by default
+    pub fn attach(&mut self, ifname: &str, mode: XDPMode) -> Result<(), CamelliaError> {
+        self.program.attach(ifname, mode)
+    }
+
+    pub fn xsks_map(&self) -> Result<BpfMap, CamelliaError> {
+        self.program.map(XSKS_MAP)
+    }
+
+    pub fn stats(&self, queue_index: u32) -> Result<XdpProgStats, CamelliaError> {
+        XdpProgStats::new(&self.program, queue_index)
+    }
+
+    pub fn set_default(&self, action: FilterAction) -> Result<(), CamelliaError> {
+        self.program
+            .map("filter_default")?
+            .update(&0u32, &(action as u32))
+    }
+
+    // replaces the action of an existing rule with the same match
+    pub fn add_rule(&self, rule: FilterMatch, action: FilterAction) -> Result<(), CamelliaError> {
+        self.program
+            .map("filter_rules")?
+            .update(&rule.key()?, &(action as u32))
+    }
+
+    pub fn remove_rule(&self, rule: FilterMatch) -> Result<bool, CamelliaError> {
+        self.program.map("filter_rules")?.delete(&rule.key()?)
+    }
+
+    // a rule on packets from the prefix, host bits of addr are ignored
+    pub fn add_prefix(
+        &self,
+        addr: IpAddr,
+        prefix_len: u8,
+        action: FilterAction,
+    ) -> Result<(), CamelliaError> {
+        Self::check_prefix(addr, prefix_len)?;
+        let action = action as u32;
+        match addr {
+            IpAddr::V4(addr) => self
+                .program
+                .map("filter_v4_prefixes")?
+                .update(&masked(addr.octets(), prefix_len), &action),
+            IpAddr::V6(addr) => self
+                .program
+                .map("filter_v6_prefixes")?
+                .update(&masked(addr.octets(), prefix_len), &action),
+        }
+    }
+
+    pub fn remove_prefix(&self, addr: IpAddr, prefix_len: u8) -> Result<bool, CamelliaError> {
+        Self::check_prefix(addr, prefix_len)?;
+        match addr {
+            IpAddr::V4(addr) => self
+                .program
+                .map("filter_v4_prefixes")?
+                .delete(&masked(addr.octets(), prefix_len)),
+            IpAddr::V6(addr) => self
+                .program
+                .map("filter_v6_prefixes")?
+                .delete(&masked(addr.octets(), prefix_len)),
+        }
+    }
+
+    fn check_prefix(addr: IpAddr, prefix_len: u8) -> Result<(), CamelliaError> {
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "prefix length {} of {} is longer than {}",
+                prefix_len, addr, max
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn test_filter_key() {
+        let key = FilterMatch::default()
+            .ethertype(0x0800)
+            .ip_proto(17)
+            .dst_port(53)
+            .key()
+            .unwrap();
+        assert_eq!(key.eth_proto, 0x0800u16.to_be());
+        assert_eq!(key.dst_port, 53u16.to_be());
+
+        assert!(FilterMatch::default().dst_port(53).key().is_err());
+        assert!(FilterMatch::default().key().is_err());
+        assert!(FilterMatch::default().ip_proto(1).key().is_ok());
+    }
+
+    #[test]
+    fn test_prefix_mask() {
+        let key = masked(Ipv4Addr::new(10, 1, 255, 7).octets(), 20);
+        assert_eq!(key.prefix_len, 20);
+        assert_eq!(key.addr, [10, 1, 240, 0]);
+
+        assert_eq!(masked([192, 168, 1, 1], 32).addr, [192, 168, 1, 1]);
+        assert_eq!(masked([192, 168, 1, 1], 0).addr, [0; 4]);
+
+        let addr: Ipv6Addr = "2001:db8:ffff::1".parse().unwrap();
+        let expected: Ipv6Addr = "2001:db8:ff00::".parse().unwrap();
+        assert_eq!(masked(addr.octets(), 40).addr, expected.octets());
+
+        assert!(PacketFilter::check_prefix(Ipv4Addr::LOCALHOST.into(), 33).is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    ffi::CString,
+    mem::MaybeUninit,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+    path::Path,
+};
+
+use libxdp_sys::{
+    bpf_map_delete_elem, bpf_map_lookup_elem, bpf_map_update_elem, bpf_obj_get, bpf_obj_pin,
+    libbpf_num_possible_cpus,
+};
+use nix::errno::Errno;
+
+use crate::{error::CamelliaError, xdp::program::check_ret};
+
+pub(crate) fn path_to_cstring(path: &Path) -> Result<CString, CamelliaError> {
+    CString::new(path.as_os_str().as_encoded_bytes())
+        .map_err(|e| CamelliaError::InvalidArgument(e.to_string()))
+}
+
+// A BPF map referenced by its own file descriptor, so it stays valid after the program
+// defining it is closed, e.g., a map pinned to bpffs by a previous run of the process.
+#[derive(Debug)]
+pub struct BpfMap {
+    fd: OwnedFd,
+}
+
+impl BpfMap {
+    pub fn open_pinned(path: impl AsRef<Path>) -> Result<Self, CamelliaError> {
+        let path = path_to_cstring(path.as_ref())?;
+        let fd = unsafe { bpf_obj_get(path.as_ptr()) };
+        check_ret("bpf_obj_get", fd)?;
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    pub fn from_fd(fd: BorrowedFd) -> Result<Self, CamelliaError> {
+        Ok(Self {
+            fd: fd.try_clone_to_owned().map_err(CamelliaError::from)?,
+        })
+    }
+
+    pub fn try_clone(&self) -> Result<Self, CamelliaError> {
+        Self::from_fd(self.fd.as_fd())
+    }
+
+    // the directory of the path must be on a mounted bpffs
+    pub fn pin(&self, path: impl AsRef<Path>) -> Result<(), CamelliaError> {
+        let path = path_to_cstring(path.as_ref())?;
+        check_ret("bpf_obj_pin", unsafe {
+            bpf_obj_pin(self.fd.as_raw_fd(), path.as_ptr())
+        })
+    }
+
+    // K and V must match the key and value sizes of the map
+    pub fn update<K: Copy, V: Copy>(&self, key: &K, value: &V) -> Result<(), CamelliaError> {
+        check_ret("bpf_map_update_elem", unsafe {
+            bpf_map_update_elem(
+                self.fd.as_raw_fd(),
+                key as *const K as *const _,
+                value as *const V as *const _,
+                0,
+            )
+        })
+    }
+
+    pub fn lookup<K: Copy, V: Copy>(&self, key: &K) -> Result<Option<V>, CamelliaError> {
+        let mut value = MaybeUninit::<V>::uninit();
+        let ret = unsafe {
+            bpf_map_lookup_elem(
+                self.fd.as_raw_fd(),
+                key as *const K as *const _,
+                value.as_mut_ptr() as *mut _,
+            )
+        };
+        match check_ret("bpf_map_lookup_elem", ret) {
+            Ok(()) => Ok(Some(unsafe { value.assume_init() })),
+            Err(e) if e.errno() == Some(Errno::ENOENT) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Values of a per-CPU map, one per possible CPU. V must be a multiple of 8 bytes as
+    // the kernel aligns values of each CPU to it.
+    pub fn lookup_percpu<K: Copy, V: Copy>(
+        &self,
+        key: &K,
+    ) -> Result<Option<Vec<V>>, CamelliaError> {
+        assert_eq!(std::mem::size_of::<V>() % 8, 0);
+        let cpus = unsafe { libbpf_num_possible_cpus() };
+        check_ret("libbpf_num_possible_cpus", cpus)?;
+
+        let mut values: Vec<MaybeUninit<V>> = Vec::with_capacity(cpus as usize);
+        values.resize_with(cpus as usize, MaybeUninit::uninit);
+        let ret = unsafe {
+            bpf_map_lookup_elem(
+                self.fd.as_raw_fd(),
+                key as *const K as *const _,
+                values.as_mut_ptr() as *mut _,
+            )
+        };
+        match check_ret("bpf_map_lookup_elem", ret) {
+            Ok(()) => Ok(Some(
+                values
+                    .into_iter()
+                    .map(|value| unsafe { value.assume_init() })
+                    .collect(),
+            )),
+            Err(e) if e.errno() == Some(Errno::ENOENT) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // returns whether the key was present
+    pub fn delete<K: Copy>(&self, key: &K) -> Result<bool, CamelliaError> {
+        let ret = unsafe { bpf_map_delete_elem(self.fd.as_raw_fd(), key as *const K as *const _) };
+        match check_ret("bpf_map_delete_elem", ret) {
+            Ok(()) => Ok(true),
+            Err(e) if e.errno() == Some(Errno::ENOENT) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl AsFd for BpfMap {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for BpfMap {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
@@ -0,0 +1,8 @@
+pub mod cpumap;
+pub mod filter;
+pub mod map;
+pub mod monitor;
+pub mod program;
+pub mod redirect;
+pub mod stats;
+pub mod steering;
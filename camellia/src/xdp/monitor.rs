@@ -0,0 +1,239 @@
+use std::{
+    collections::VecDeque,
+    ffi::CString,
+    fmt::Display,
+    os::fd::{AsRawFd, BorrowedFd},
+    time::Duration,
+};
+
+use libc::c_void;
+use libxdp_sys::{
+    bpf_link, bpf_link__destroy, bpf_object, bpf_object__close, bpf_object__find_map_fd_by_name,
+    bpf_object__load, bpf_object__next_program, bpf_object__open_mem, bpf_program__attach,
+    ring_buffer, ring_buffer__free, ring_buffer__new, ring_buffer__poll,
+};
+use nix::errno::Errno;
+
+use crate::{
+    error::CamelliaError,
+    xdp::{
+        map::BpfMap,
+        program::{check_ret, ifindex},
+    },
+};
+
+static MONITOR: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/monitor.bpf.o"));
+
+const EVENT_EXCEPTION: u32 = 0;
+
+// struct xdp_event of monitor.bpf.c
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct RawXdpEvent {
+    kind: u32,
+    prog_id: i32,
+    act: u32,
+    ifindex: i32,
+    err: i32,
+    to_ifindex: i32,
+    map_id: u32,
+    map_index: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XdpEventKind {
+    // a program returned XDP_ABORTED or an action the driver doesn't support
+    Exception,
+    // the target of XDP_REDIRECT refused the packet, e.g., a socket bound to another
+    // queue or with a full RX ring
+    RedirectError,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct XdpEvent {
+    pub kind: XdpEventKind,
+    pub prog_id: u32,
+    pub action: u32,
+    pub ifindex: u32,
+    pub error: Option<Errno>,
+    // the interface the packet was redirected to, if known
+    pub to_ifindex: Option<u32>,
+    // the map and the index the packet was redirected to, if any
+    pub map_id: Option<u32>,
+    pub map_index: u32,
+}
+
+impl From<RawXdpEvent> for XdpEvent {
+    fn from(raw: RawXdpEvent) -> Self {
+        Self {
+            kind: match raw.kind {
+                EVENT_EXCEPTION => XdpEventKind::Exception,
+                _ => XdpEventKind::RedirectError,
+            },
+            prog_id: raw.prog_id as u32,
+            action: raw.act,
+            ifindex: raw.ifindex as u32,
+            error: (raw.err != 0).then(|| Errno::from_raw(-raw.err)),
+            to_ifindex: (raw.to_ifindex > 0).then_some(raw.to_ifindex as u32),
+            map_id: (raw.map_id != 0).then_some(raw.map_id),
+            map_index: raw.map_index as u32,
+        }
+    }
+}
+
+impl Display for XdpEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:?} of program {} on interface {}, action {}",
+            self.kind, self.prog_id, self.ifindex, self.action
+        )?;
+        if let Some(errno) = self.error {
+            write!(f, ", {}", errno)?;
+        }
+        if let Some(map_id) = self.map_id {
+            write!(f, ", map {} index {}", map_id, self.map_index)?;
+        }
+        Ok(())
+    }
+}
+
+unsafe extern "C" fn collect_event(ctx: *mut c_void, data: *mut c_void, size: usize) -> i32 {
+    if size < std::mem::size_of::<RawXdpEvent>() {
+        return 0;
+    }
+    let events = &mut *(ctx as *mut VecDeque<XdpEvent>);
+    events.push_back(std::ptr::read_unaligned(data as *const RawXdpEvent).into());
+    0
+}
+
+fn libbpf_ptr<T>(call: &'static str, ptr: *mut T) -> Result<*mut T, CamelliaError> {
+    match ptr.is_null() {
+        true => Err(CamelliaError::syscall(call, Errno::last())),
+        false => Ok(ptr),
+    }
+}
+
+// Delivers errors of XDP programs reported by the xdp:xdp_exception and
+// xdp:xdp_redirect_err tracepoints, which otherwise only show up as vanished packets.
+// The tracepoint programs run for every interface but report only the monitored one.
+pub struct XdpMonitor {
+    object: *mut bpf_object,
+    links: Vec<*mut bpf_link>,
+    ring: *mut ring_buffer,
+    // written by the ring buffer callback, boxed so that its address is stable
+    #[allow(clippy::box_collection)]
+    events: Box<VecDeque<XdpEvent>>,
+}
+
+unsafe impl Send for XdpMonitor {}
+
+impl XdpMonitor {
+    // events of every interface if ifname is None
+    pub fn new(ifname: Option<&str>) -> Result<Self, CamelliaError> {
+        let target = ifname.map(ifindex).transpose()?.unwrap_or(0);
+
+        let object = libbpf_ptr("bpf_object__open_mem", unsafe {
+            bpf_object__open_mem(
+                MONITOR.as_ptr() as *const _,
+                MONITOR.len(),
+                std::ptr::null(),
+            )
+        })?;
+        let mut monitor = Self {
+            object,
+            links: Vec::new(),
+            ring: std::ptr::null_mut(),
+            events: Box::default(),
+        };
+
+        check_ret("bpf_object__load", unsafe { bpf_object__load(object) })?;
+        monitor.map("monitor_ifindex")?.update(&0u32, &target)?;
+
+        let mut program = unsafe { bpf_object__next_program(object, std::ptr::null_mut()) };
+        while !program.is_null() {
+            let link = libbpf_ptr("bpf_program__attach", unsafe {
+                bpf_program__attach(program)
+            })?;
+            monitor.links.push(link);
+            program = unsafe { bpf_object__next_program(object, program) };
+        }
+
+        let events = monitor.map("events")?;
+        monitor.ring = libbpf_ptr("ring_buffer__new", unsafe {
+            ring_buffer__new(
+                events.as_raw_fd(),
+                Some(collect_event),
+                monitor.events.as_mut() as *mut VecDeque<XdpEvent> as *mut c_void,
+                std::ptr::null(),
+            )
+        })?;
+
+        Ok(monitor)
+    }
+
+    fn map(&self, name: &str) -> Result<BpfMap, CamelliaError> {
+        let name = CString::new(name).unwrap();
+        let fd = unsafe { bpf_object__find_map_fd_by_name(self.object, name.as_ptr()) };
+        check_ret("bpf_object__find_map_fd_by_name", fd)?;
+        BpfMap::from_fd(unsafe { BorrowedFd::borrow_raw(fd) })
+    }
+
+    // Waits up to timeout for events and returns every pending one, a zero timeout
+    // doesn't block.
+    pub fn poll(&mut self, timeout: Duration) -> Result<Vec<XdpEvent>, CamelliaError> {
+        let ret = unsafe { ring_buffer__poll(self.ring, timeout.as_millis() as i32) };
+        match check_ret("ring_buffer__poll", ret) {
+            Ok(()) => {}
+            Err(e) if e.errno() == Some(Errno::EINTR) => {}
+            Err(e) => return Err(e),
+        }
+        Ok(self.events.drain(..).collect())
+    }
+}
+
+impl Drop for XdpMonitor {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.ring.is_null() {
+                ring_buffer__free(self.ring);
+            }
+            for link in self.links.drain(..) {
+                bpf_link__destroy(link);
+            }
+            bpf_object__close(self.object);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_event() {
+        let raw = RawXdpEvent {
+            kind: 1,
+            prog_id: 42,
+            act: 4,
+            ifindex: 3,
+            err: -22,
+            to_ifindex: 0,
+            map_id: 7,
+            map_index: 0,
+        };
+
+        let event = XdpEvent::from(raw);
+        assert_eq!(event.kind, XdpEventKind::RedirectError);
+        assert_eq!(event.error, Some(Errno::EINVAL));
+        assert_eq!(event.to_ifindex, None);
+        assert_eq!(event.map_id, Some(7));
+        assert_eq!(
+            event.to_string(),
+            format!(
+                "RedirectError of program 42 on interface 3, action 4, {}, map 7 index 0",
+                Errno::EINVAL
+            )
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use std::{
+    ffi::{CStr, CString},
+    os::fd::{AsRawFd, BorrowedFd, RawFd},
+    path::Path,
+};
+
+use libxdp_sys::{
+    bpf_map__name, bpf_map__reuse_fd, bpf_object, bpf_object__close,
+    bpf_object__find_map_fd_by_name, bpf_object__next_map, bpf_object__open_mem,
+    bpf_object__pin_maps, bpf_object__unpin_maps, libxdp_get_error, xdp_attach_mode, xdp_multiprog,
+    xdp_multiprog__close, xdp_multiprog__get_from_ifindex, xdp_multiprog__is_legacy,
+    xdp_multiprog__main_prog, xdp_multiprog__next_prog, xdp_program, xdp_program__attach,
+    xdp_program__bpf_obj, xdp_program__chain_call_enabled, xdp_program__close, xdp_program__detach,
+    xdp_program__fd, xdp_program__find_file, xdp_program__from_bpf_obj, xdp_program__from_id,
+    xdp_program__id, xdp_program__name, xdp_program__open_file, xdp_program__run_prio,
+    xdp_program__set_chain_call_enabled, xdp_program__set_run_prio,
+};
+use nix::errno::Errno;
+
+use crate::{
+    error::CamelliaError,
+    library::LibraryContext,
+    socket::af_xdp::XDPMode,
+    xdp::{
+        map::{path_to_cstring, BpfMap},
+        stats::ProgRunStats,
+    },
+};
+
+// the map AF_XDP sockets are registered in by convention
+pub const XSKS_MAP: &str = "xsks_map";
+
+// the redirect program shipped with libxdp, sockets created without no_default_prog
+// register in the xsks_map of an attached instance instead of loading another one
+const XSK_DEFAULT_PROG_FILE: &str = "xsk_def_xdp_prog.o";
+// the name of the program in XSK_DEFAULT_PROG_FILE
+const XSK_DEFAULT_PROG_NAME: &str = "xsk_def_prog";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XdpAction {
+    Aborted = 0,
+    Drop = 1,
+    Pass = 2,
+    Tx = 3,
+    Redirect = 4,
+}
+
+impl XdpAction {
+    pub const ALL: [XdpAction; 5] = [
+        XdpAction::Aborted,
+        XdpAction::Drop,
+        XdpAction::Pass,
+        XdpAction::Tx,
+        XdpAction::Redirect,
+    ];
+}
+
+// a program found in the dispatcher of an interface
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AttachedProgram {
+    pub name: String,
+    pub id: u32,
+    pub run_prio: u32,
+    // actions after which the dispatcher runs the next program
+    pub chain_call_actions: Vec<XdpAction>,
+}
+
+impl AttachedProgram {
+    // the program is borrowed from the multiprog, which owns it
+    unsafe fn from_raw(program: *const xdp_program) -> Self {
+        Self {
+            name: CStr::from_ptr(xdp_program__name(program))
+                .to_string_lossy()
+                .into_owned(),
+            id: xdp_program__id(program),
+            run_prio: xdp_program__run_prio(program),
+            chain_call_actions: XdpAction::ALL
+                .into_iter()
+                .filter(|action| xdp_program__chain_call_enabled(program, *action as u32))
+                .collect(),
+        }
+    }
+}
+
+// Lists XDP programs attached to the interface in the order the dispatcher runs them. A
+// program attached without the dispatcher is returned alone.
+pub fn attached_programs(ifname: &str) -> Result<Vec<AttachedProgram>, CamelliaError> {
+    let multiprog: *mut xdp_multiprog = match check_ptr("xdp_multiprog__get_from_ifindex", unsafe {
+        xdp_multiprog__get_from_ifindex(ifindex(ifname)?)
+    }) {
+        Ok(multiprog) => multiprog,
+        // nothing is attached
+        Err(e) if e.errno() == Some(Errno::ENOENT) => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut programs = Vec::new();
+    unsafe {
+        if xdp_multiprog__is_legacy(multiprog) {
+            programs.push(AttachedProgram::from_raw(xdp_multiprog__main_prog(
+                multiprog,
+            )));
+        } else {
+            let mut program = xdp_multiprog__next_prog(std::ptr::null(), multiprog);
+            while !program.is_null() {
+                programs.push(AttachedProgram::from_raw(program));
+                program = xdp_multiprog__next_prog(program, multiprog);
+            }
+        }
+        xdp_multiprog__close(multiprog);
+    }
+
+    Ok(programs)
+}
+
+// Detaches the default program of AF_XDP sockets from the interface, like xdp-loader
+// unload, whether a socket or XdpProgram::xsk_default loaded it. Returns false if it is
+// not attached.
+pub fn detach_xsk_default(ifname: &str, mode: XDPMode) -> Result<bool, CamelliaError> {
+    let Some(attached) = attached_programs(ifname)?
+        .into_iter()
+        .find(|program| program.name == XSK_DEFAULT_PROG_NAME)
+    else {
+        return Ok(false);
+    };
+
+    let ifindex = ifindex(ifname)?;
+    let program = check_ptr("xdp_program__from_id", unsafe {
+        xdp_program__from_id(attached.id)
+    })?;
+    let ret = unsafe { xdp_program__detach(program, ifindex, mode.attach_mode(), 0) };
+    unsafe { xdp_program__close(program) };
+    check_ret("xdp_program__detach", ret)?;
+    Ok(true)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Attachment {
+    ifindex: i32,
+    mode: xdp_attach_mode,
+}
+
+// An XDP program managed by libxdp. Programs are attached through the libxdp dispatcher,
+// so several of them share an interface, and are detached when dropped.
+#[derive(Debug)]
+pub struct XdpProgram {
+    inner: *mut xdp_program,
+    // the object opened by camellia, null if it is owned by libxdp
+    object: *mut bpf_object,
+    attachment: Option<Attachment>,
+}
+
+unsafe impl Send for XdpProgram {}
+
+fn check_ptr<T>(call: &'static str, ptr: *mut T) -> Result<*mut T, CamelliaError> {
+    match unsafe { libxdp_get_error(ptr as *const _) } {
+        0 if !ptr.is_null() => Ok(ptr),
+        0 => Err(CamelliaError::syscall(call, Errno::ENOENT)),
+        errno => Err(CamelliaError::syscall(call, Errno::from_raw(-errno as i32))),
+    }
+}
+
+pub(crate) fn check_ret(call: &'static str, ret: i32) -> Result<(), CamelliaError> {
+    match ret {
+        ret if ret >= 0 => Ok(()),
+        errno => Err(CamelliaError::syscall(call, Errno::from_raw(-errno))),
+    }
+}
+
+pub(crate) fn ifindex(ifname: &str) -> Result<i32, CamelliaError> {
+    nix::net::if_::if_nametoindex(ifname)
+        .map(|ifindex| ifindex as i32)
+        .map_err(|_| CamelliaError::InterfaceNotFound {
+            name: ifname.to_string(),
+        })
+}
+
+impl XdpProgram {
+    // the first program in the section, or in the object if no section is given
+    pub fn open_file(path: impl AsRef<Path>, section: Option<&str>) -> Result<Self, CamelliaError> {
+        let path = CString::new(path.as_ref().as_os_str().as_encoded_bytes())
+            .map_err(|e| CamelliaError::InvalidArgument(e.to_string()))?;
+        let section = section
+            .map(CString::new)
+            .transpose()
+            .map_err(|e| CamelliaError::InvalidArgument(e.to_string()))?;
+
+        let inner = check_ptr("xdp_program__open_file", unsafe {
+            xdp_program__open_file(
+                path.as_ptr(),
+                section.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+                std::ptr::null_mut(),
+            )
+        })?;
+
+        Ok(Self {
+            inner,
+            object: std::ptr::null_mut(),
+            attachment: None,
+        })
+    }
+
+    // Opens a program from an object in memory, e.g., one embedded in the binary. The
+    // bytes are copied by libbpf.
+    pub fn from_bytes(bytes: &[u8], section: Option<&str>) -> Result<Self, CamelliaError> {
+        let section = section
+            .map(CString::new)
+            .transpose()
+            .map_err(|e| CamelliaError::InvalidArgument(e.to_string()))?;
+
+        let object = unsafe {
+            bpf_object__open_mem(bytes.as_ptr() as *const _, bytes.len(), std::ptr::null())
+        };
+        if object.is_null() {
+            return Err(CamelliaError::syscall(
+                "bpf_object__open_mem",
+                Errno::last(),
+            ));
+        }
+
+        match check_ptr("xdp_program__from_bpf_obj", unsafe {
+            xdp_program__from_bpf_obj(
+                object,
+                section.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            )
+        }) {
+            Ok(inner) => Ok(Self {
+                inner,
+                object,
+                attachment: None,
+            }),
+            Err(e) => {
+                unsafe { bpf_object__close(object) };
+                Err(e)
+            }
+        }
+    }
+
+    // Opens the redirect program of libxdp from its installation directory. Attaching it
+    // with a chosen priority before creating sockets lets them share the interface with
+    // other programs in that order.
+    pub fn xsk_default() -> Result<Self, CamelliaError> {
+        let file = CString::new(XSK_DEFAULT_PROG_FILE).unwrap();
+        let inner = check_ptr("xdp_program__find_file", unsafe {
+            xdp_program__find_file(file.as_ptr(), std::ptr::null(), std::ptr::null_mut())
+        })?;
+
+        Ok(Self {
+            inner,
+            object: std::ptr::null_mut(),
+            attachment: None,
+        })
+    }
+
+    pub fn name(&self) -> String {
+        unsafe { CStr::from_ptr(xdp_program__name(self.inner)) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    // 0 until the program is loaded by attaching it
+    pub fn id(&self) -> u32 {
+        unsafe { xdp_program__id(self.inner) }
+    }
+
+    pub fn fd(&self) -> Option<RawFd> {
+        let fd = unsafe { xdp_program__fd(self.inner) };
+        (fd >= 0).then_some(fd)
+    }
+
+    pub fn run_prio(&self) -> u32 {
+        unsafe { xdp_program__run_prio(self.inner) }
+    }
+
+    // Programs with lower priorities run first. It is read from the program metadata if
+    // not set, and can only be changed before the program is attached.
+    pub fn set_run_prio(&mut self, run_prio: u32) -> Result<(), CamelliaError> {
+        check_ret("xdp_program__set_run_prio", unsafe {
+            xdp_program__set_run_prio(self.inner, run_prio)
+        })
+    }
+
+    pub fn chain_call_enabled(&self, action: XdpAction) -> bool {
+        unsafe { xdp_program__chain_call_enabled(self.inner, action as u32) }
+    }
+
+    // Whether the dispatcher runs the next program after this one returns the action, by
+    // default only after XDP_PASS. Like the priority, it is fixed once attached.
+    pub fn set_chain_call(
+        &mut self,
+        action: XdpAction,
+        enabled: bool,
+    ) -> Result<(), CamelliaError> {
+        check_ret("xdp_program__set_chain_call_enabled", unsafe {
+            xdp_program__set_chain_call_enabled(self.inner, action as u32, enabled)
+        })
+    }
+
+    pub fn is_attached(&self) -> bool {
+        self.attachment.is_some()
+    }
+
+    // Statistics of the program run by the kernel, see enable_run_stats. Programs attached
+    // through the dispatcher are not accounted separately, so these of the dispatcher,
+    // i.e., every program on the interface, are returned for them.
+    pub fn run_stats(&self) -> Result<ProgRunStats, CamelliaError> {
+        let Some(attachment) = self.attachment else {
+            return match self.fd() {
+                Some(fd) => ProgRunStats::of_fd(fd),
+                None => Err(CamelliaError::InvalidArgument(format!(
+                    "program {} is not loaded",
+                    self.name()
+                ))),
+            };
+        };
+
+        let multiprog = check_ptr("xdp_multiprog__get_from_ifindex", unsafe {
+            xdp_multiprog__get_from_ifindex(attachment.ifindex)
+        })?;
+        let stats = unsafe {
+            let main = xdp_multiprog__main_prog(multiprog);
+            ProgRunStats::of_fd(xdp_program__fd(main))
+        };
+        unsafe { xdp_multiprog__close(multiprog) };
+        stats
+    }
+
+    // names of the maps defined by the program
+    pub fn map_names(&self) -> Vec<String> {
+        let object = unsafe { xdp_program__bpf_obj(self.inner) };
+        let mut names = Vec::new();
+        let mut map = unsafe { bpf_object__next_map(object, std::ptr::null()) };
+        while !map.is_null() {
+            names.push(
+                unsafe { CStr::from_ptr(bpf_map__name(map)) }
+                    .to_string_lossy()
+                    .into_owned(),
+            );
+            map = unsafe { bpf_object__next_map(object, map) };
+        }
+        names
+    }
+
+    // only available once the program is loaded
+    pub fn map_fd(&self, name: &str) -> Option<RawFd> {
+        let name = CString::new(name).ok()?;
+        let fd = unsafe {
+            bpf_object__find_map_fd_by_name(xdp_program__bpf_obj(self.inner), name.as_ptr())
+        };
+        (fd >= 0).then_some(fd)
+    }
+
+    pub fn map(&self, name: &str) -> Result<BpfMap, CamelliaError> {
+        let fd = self.map_fd(name).ok_or_else(|| {
+            CamelliaError::InvalidArgument(format!(
+                "program {} has no map {} or is not loaded",
+                self.name(),
+                name
+            ))
+        })?;
+        BpfMap::from_fd(unsafe { BorrowedFd::borrow_raw(fd) })
+    }
+
+    // Pins every map of the loaded program as dir/<map name>, so that a later run of the
+    // process re-opens them while the program stays attached.
+    pub fn pin_maps(&self, dir: impl AsRef<Path>) -> Result<(), CamelliaError> {
+        let dir = path_to_cstring(dir.as_ref())?;
+        check_ret("bpf_object__pin_maps", unsafe {
+            bpf_object__pin_maps(xdp_program__bpf_obj(self.inner), dir.as_ptr())
+        })
+    }
+
+    pub fn unpin_maps(&self, dir: impl AsRef<Path>) -> Result<(), CamelliaError> {
+        let dir = path_to_cstring(dir.as_ref())?;
+        check_ret("bpf_object__unpin_maps", unsafe {
+            bpf_object__unpin_maps(xdp_program__bpf_obj(self.inner), dir.as_ptr())
+        })
+    }
+
+    // Reuses maps pinned in dir by pin_maps instead of creating them when the program is
+    // loaded, returning the names of reused maps. Maps not pinned there are created.
+    pub fn reuse_pinned_maps(
+        &mut self,
+        dir: impl AsRef<Path>,
+    ) -> Result<Vec<String>, CamelliaError> {
+        let mut reused = Vec::new();
+        for name in self.map_names() {
+            let path = dir.as_ref().join(&name);
+            if !path.exists() {
+                continue;
+            }
+            // libbpf duplicates the descriptor
+            let map = BpfMap::open_pinned(&path)?;
+            self.reuse_map(&name, map.as_raw_fd())?;
+            reused.push(name);
+        }
+        Ok(reused)
+    }
+
+    // Use an existing map instead of creating the map when the program is loaded, e.g., a
+    // map of a program being replaced.
+    pub fn reuse_map(&mut self, name: &str, fd: RawFd) -> Result<(), CamelliaError> {
+        let object = unsafe { xdp_program__bpf_obj(self.inner) };
+        let c_name =
+            CString::new(name).map_err(|e| CamelliaError::InvalidArgument(e.to_string()))?;
+        let map = unsafe { libxdp_sys::bpf_object__find_map_by_name(object, c_name.as_ptr()) };
+        if map.is_null() {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "program {} has no map {}",
+                self.name(),
+                name
+            )));
+        }
+        check_ret("bpf_map__reuse_fd", unsafe { bpf_map__reuse_fd(map, fd) })
+    }
+
+    pub fn attach(&mut self, ifname: &str, mode: XDPMode) -> Result<(), CamelliaError> {
+        if self.attachment.is_some() {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "program {} is already attached",
+                self.name()
+            )));
+        }
+
+        let attachment = Attachment {
+            ifindex: ifindex(ifname)?,
+            mode: mode.attach_mode(),
+        };
+        let _context = LibraryContext::enter(ifname, None);
+        check_ret("xdp_program__attach", unsafe {
+            xdp_program__attach(self.inner, attachment.ifindex, attachment.mode, 0)
+        })?;
+        self.attachment = Some(attachment);
+        Ok(())
+    }
+
+    pub fn detach(&mut self) -> Result<(), CamelliaError> {
+        let Some(attachment) = self.attachment else {
+            return Ok(());
+        };
+        check_ret("xdp_program__detach", unsafe {
+            xdp_program__detach(self.inner, attachment.ifindex, attachment.mode, 0)
+        })?;
+        self.attachment = None;
+        Ok(())
+    }
+
+    // Closes the handle but leaves the program attached, e.g., when the process exits
+    // for a restart and the next run re-registers sockets in the pinned xsks_map.
+    pub fn keep_attached(mut self) {
+        self.attachment = None;
+    }
+
+    // Replaces this attached program by next without a window in which packets miss
+    // both. Maps of next named like maps of this program reuse them, so sockets in the
+    // xsks_map stay registered. next is attached with the same priority before this
+    // program is detached, and libxdp swaps the dispatcher atomically at both steps. On
+    // failure, this program stays attached and managed, and next is detached.
+    pub fn replace(&mut self, mut next: XdpProgram) -> Result<(), CamelliaError> {
+        let Some(attachment) = self.attachment else {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "program {} is not attached",
+                self.name()
+            )));
+        };
+
+        for name in self.map_names() {
+            if let Some(fd) = self.map_fd(&name) {
+                if next.map_names().contains(&name) {
+                    next.reuse_map(&name, fd)?;
+                }
+            }
+        }
+
+        check_ret("xdp_program__set_run_prio", unsafe {
+            xdp_program__set_run_prio(next.inner, self.run_prio())
+        })?;
+        check_ret("xdp_program__attach", unsafe {
+            xdp_program__attach(next.inner, attachment.ifindex, attachment.mode, 0)
+        })?;
+        next.attachment = Some(attachment);
+
+        if let Err(e) = self.detach() {
+            // detached on drop, leaving this program as the only one attached
+            drop(next);
+            return Err(e);
+        }
+        std::mem::swap(self, &mut next);
+        Ok(())
+    }
+}
+
+impl Drop for XdpProgram {
+    fn drop(&mut self) {
+        if let Err(e) = self.detach() {
+            log::error!("failed to detach XDP program {}: {}", self.name(), e);
+        }
+        unsafe {
+            xdp_program__close(self.inner);
+            if !self.object.is_null() {
+                bpf_object__close(self.object);
+            }
+        }
+    }
+}
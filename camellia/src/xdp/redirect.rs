@@ -0,0 +1,47 @@
+use crate::{
+    error::CamelliaError,
+    socket::af_xdp::XDPMode,
+    xdp::{
+        map::BpfMap,
+        program::{XdpProgram, XSKS_MAP},
+        stats::XdpProgStats,
+    },
+};
+
+static XSK_REDIRECT: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/redirect.bpf.o"));
+
+// The built-in counterpart of the default program of libxdp, redirecting packets to the
+// socket of the receiving queue and counting them per queue and action. Sockets are
+// registered in its xsks_map, see XskSocketBuilder::xsks_map.
+#[derive(Debug)]
+pub struct XskRedirect {
+    program: XdpProgram,
+}
+
+impl XskRedirect {
+    pub fn new() -> Result<Self, CamelliaError> {
+        Ok(Self {
+            program: XdpProgram::from_bytes(XSK_REDIRECT, Some("xdp"))?,
+        })
+    }
+
+    pub fn program(&self) -> &XdpProgram {
+        &self.program
+    }
+
+    pub fn program_mut(&mut self) -> &mut XdpProgram {
+        &mut self.program
+    }
+
+    pub fn attach(&mut self, ifname: &str, mode: XDPMode) -> Result<(), CamelliaError> {
+        self.program.attach(ifname, mode)
+    }
+
+    pub fn xsks_map(&self) -> Result<BpfMap, CamelliaError> {
+        self.program.map(XSKS_MAP)
+    }
+
+    pub fn stats(&self, queue_index: u32) -> Result<XdpProgStats, CamelliaError> {
+        XdpProgStats::new(&self.program, queue_index)
+    }
+}
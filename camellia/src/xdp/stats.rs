@@ -0,0 +1,247 @@
+use std::{
+    fmt::Display,
+    os::fd::{FromRawFd, OwnedFd, RawFd},
+    time::Duration,
+};
+
+use libxdp_sys::{
+    bpf_enable_stats, bpf_obj_get_info_by_fd, bpf_prog_info, bpf_stats_type_BPF_STATS_RUN_TIME,
+};
+
+use crate::{
+    error::CamelliaError,
+    stats::{Stat, StatsSource},
+    xdp::{
+        map::BpfMap,
+        program::{check_ret, XdpAction, XdpProgram},
+    },
+};
+
+// keep in sync with STATS_QUEUES and STATS_ACTIONS of bpf/common.h
+pub const STATS_QUEUES: u32 = 64;
+const STATS_ACTIONS: u32 = XdpAction::Redirect as u32 + 1;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ActionCounter {
+    pub packets: u64,
+    pub bytes: u64,
+}
+
+impl std::ops::AddAssign for ActionCounter {
+    fn add_assign(&mut self, rhs: Self) {
+        self.packets += rhs.packets;
+        self.bytes += rhs.bytes;
+    }
+}
+
+// What the program did with packets of a queue, summed over CPUs. Packets passed by the
+// redirect programs arrived at a queue without a socket in the xsks_map.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct XdpQueueStats {
+    pub counters: [ActionCounter; STATS_ACTIONS as usize],
+}
+
+impl XdpQueueStats {
+    pub fn get(&self, action: XdpAction) -> ActionCounter {
+        self.counters[action as usize]
+    }
+}
+
+impl Display for XdpQueueStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "redirected: {}, passed: {}, dropped: {}, aborted: {}",
+            self.get(XdpAction::Redirect).packets,
+            self.get(XdpAction::Pass).packets,
+            self.get(XdpAction::Drop).packets,
+            self.get(XdpAction::Aborted).packets
+        )
+    }
+}
+
+// Reads the per-CPU xdp_stats map of a built-in program for one queue, which shows
+// packets handled before they reach the socket, e.g., passed because no socket is bound.
+#[derive(Debug)]
+pub struct XdpProgStats {
+    map: BpfMap,
+    name: String,
+    queue_index: u32,
+}
+
+impl XdpProgStats {
+    // the program must be attached
+    pub fn new(program: &XdpProgram, queue_index: u32) -> Result<Self, CamelliaError> {
+        if queue_index >= STATS_QUEUES {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "queue {} is not counted, only the first {} queues are",
+                queue_index, STATS_QUEUES
+            )));
+        }
+
+        Ok(Self {
+            map: program.map("xdp_stats")?,
+            name: program.name(),
+            queue_index,
+        })
+    }
+
+    pub fn read(&self) -> Result<XdpQueueStats, CamelliaError> {
+        let mut stats = XdpQueueStats::default();
+        for action in XdpAction::ALL {
+            let key = self.queue_index * STATS_ACTIONS + action as u32;
+            let per_cpu: Vec<ActionCounter> = self.map.lookup_percpu(&key)?.unwrap_or_default();
+            for counter in per_cpu {
+                stats.counters[action as usize] += counter;
+            }
+        }
+        Ok(stats)
+    }
+}
+
+impl StatsSource for XdpProgStats {
+    fn stats_id(&self) -> String {
+        format!("xdp/{}/{}", self.name, self.queue_index)
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        let stats = match self.read() {
+            Ok(stats) => stats,
+            Err(e) => {
+                log::warn!(
+                    "failed to read statistics of XDP program {}: {}",
+                    self.name,
+                    e
+                );
+                return;
+            }
+        };
+
+        visit(Stat::counter(
+            "xdp_redirect_packets",
+            stats.get(XdpAction::Redirect).packets,
+        ));
+        visit(Stat::counter(
+            "xdp_redirect_bytes",
+            stats.get(XdpAction::Redirect).bytes,
+        ));
+        visit(Stat::counter(
+            "xdp_pass_packets",
+            stats.get(XdpAction::Pass).packets,
+        ));
+        visit(Stat::counter(
+            "xdp_drop_packets",
+            stats.get(XdpAction::Drop).packets,
+        ));
+        visit(Stat::counter(
+            "xdp_aborted_packets",
+            stats.get(XdpAction::Aborted).packets,
+        ));
+    }
+}
+
+// Run time statistics of BPF programs are collected while a guard is alive, as they
+// cost two clock reads per run. The kernel keeps them on until every guard is dropped.
+#[derive(Debug)]
+pub struct RunStatsGuard {
+    _fd: OwnedFd,
+}
+
+pub fn enable_run_stats() -> Result<RunStatsGuard, CamelliaError> {
+    let fd = unsafe { bpf_enable_stats(bpf_stats_type_BPF_STATS_RUN_TIME) };
+    check_ret("bpf_enable_stats", fd)?;
+    Ok(RunStatsGuard {
+        _fd: unsafe { OwnedFd::from_raw_fd(fd) },
+    })
+}
+
+// how often a program ran and for how long in total, while run stats are enabled
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProgRunStats {
+    pub run_count: u64,
+    pub run_time: Duration,
+}
+
+impl ProgRunStats {
+    pub(crate) fn of_fd(fd: RawFd) -> Result<Self, CamelliaError> {
+        let mut info: bpf_prog_info = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of::<bpf_prog_info>() as u32;
+        check_ret("bpf_obj_get_info_by_fd", unsafe {
+            bpf_obj_get_info_by_fd(fd, &mut info as *mut _ as *mut _, &mut len)
+        })?;
+
+        Ok(Self {
+            run_count: info.run_cnt,
+            run_time: Duration::from_nanos(info.run_time_ns),
+        })
+    }
+
+    pub fn delta(&self, prev: &ProgRunStats) -> ProgRunStats {
+        ProgRunStats {
+            run_count: self.run_count.saturating_sub(prev.run_count),
+            run_time: self.run_time.saturating_sub(prev.run_time),
+        }
+    }
+
+    // XDP programs run once per packet
+    pub fn avg_run_time(&self) -> Duration {
+        match self.run_count {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos((self.run_time.as_nanos() / count as u128) as u64),
+        }
+    }
+}
+
+impl Display for ProgRunStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "runs: {}, run time: {:?}, avg: {:?}",
+            self.run_count,
+            self.run_time,
+            self.avg_run_time()
+        )
+    }
+}
+
+impl StatsSource for XdpProgram {
+    fn stats_id(&self) -> String {
+        format!("xdp/{}", self.name())
+    }
+
+    fn visit_stats(&self, visit: &mut dyn FnMut(Stat)) {
+        match self.run_stats() {
+            Ok(stats) => {
+                visit(Stat::counter("xdp_run_count", stats.run_count));
+                visit(Stat::counter(
+                    "xdp_run_time_ns",
+                    stats.run_time.as_nanos() as u64,
+                ));
+            }
+            Err(e) => log::warn!("failed to read run stats of {}: {}", self.name(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_stats_delta() {
+        let prev = ProgRunStats {
+            run_count: 100,
+            run_time: Duration::from_micros(10),
+        };
+        let now = ProgRunStats {
+            run_count: 300,
+            run_time: Duration::from_micros(40),
+        };
+
+        let delta = now.delta(&prev);
+        assert_eq!(delta.run_count, 200);
+        assert_eq!(delta.avg_run_time(), Duration::from_nanos(150));
+        assert_eq!(prev.delta(&now).avg_run_time(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,261 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    os::fd::{AsFd, AsRawFd},
+};
+
+use crate::{
+    error::CamelliaError,
+    socket::af_xdp::XDPMode,
+    xdp::{
+        map::BpfMap,
+        program::{XdpProgram, XSKS_MAP},
+        stats::XdpProgStats,
+    },
+};
+
+static STEERING: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/steering.bpf.o"));
+
+// keep in sync with steering.bpf.c
+pub const MAX_QUEUES: u32 = 64;
+pub const SLOTS_PER_QUEUE: u32 = 16;
+
+const ETH_P_IP: u16 = 0x0800;
+const ETH_P_IPV6: u16 = 0x86dd;
+
+// struct flow of bpf/common.h, fields are in network byte order
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Flow {
+    src_addr: [u32; 4],
+    dst_addr: [u32; 4],
+    src_port: u16,
+    dst_port: u16,
+    eth_proto: u16,
+    ip_proto: u8,
+    pad: u8,
+}
+
+fn addr_words(ip: IpAddr) -> [u32; 4] {
+    let mut words = [0; 4];
+    match ip {
+        IpAddr::V4(ip) => words[0] = u32::from_ne_bytes(ip.octets()),
+        IpAddr::V6(ip) => {
+            for (word, bytes) in words.iter_mut().zip(ip.octets().chunks_exact(4)) {
+                *word = u32::from_ne_bytes(bytes.try_into().unwrap());
+            }
+        }
+    }
+    words
+}
+
+impl Flow {
+    pub fn new(ip_proto: u8, src: SocketAddr, dst: SocketAddr) -> Result<Self, CamelliaError> {
+        let eth_proto = match (src, dst) {
+            (SocketAddr::V4(_), SocketAddr::V4(_)) => ETH_P_IP,
+            (SocketAddr::V6(_), SocketAddr::V6(_)) => ETH_P_IPV6,
+            _ => {
+                return Err(CamelliaError::InvalidArgument(format!(
+                    "addresses {} and {} are of different families",
+                    src, dst
+                )))
+            }
+        };
+
+        // the program parses ports of TCP and UDP only
+        let with_ports = matches!(ip_proto as i32, libc::IPPROTO_TCP | libc::IPPROTO_UDP);
+        Ok(Self {
+            src_addr: addr_words(src.ip()),
+            dst_addr: addr_words(dst.ip()),
+            src_port: if with_ports { src.port().to_be() } else { 0 },
+            dst_port: if with_ports { dst.port().to_be() } else { 0 },
+            eth_proto: eth_proto.to_be(),
+            ip_proto,
+            pad: 0,
+        })
+    }
+
+    pub fn tcp(src: SocketAddr, dst: SocketAddr) -> Result<Self, CamelliaError> {
+        Self::new(libc::IPPROTO_TCP as u8, src, dst)
+    }
+
+    pub fn udp(src: SocketAddr, dst: SocketAddr) -> Result<Self, CamelliaError> {
+        Self::new(libc::IPPROTO_UDP as u8, src, dst)
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self as *const Self as *const u8,
+                std::mem::size_of::<Self>(),
+            )
+        }
+    }
+
+    // FNV-1a, as flow_hash of steering.bpf.c
+    pub fn hash(&self) -> u32 {
+        self.as_bytes().iter().fold(2166136261u32, |hash, byte| {
+            (hash ^ *byte as u32).wrapping_mul(16777619)
+        })
+    }
+}
+
+// The built-in program spreading flows over several sockets of each queue, or pinning
+// them to one. The kernel only delivers packets to sockets bound to the receiving queue,
+// so the sockets of a queue are built on a shared UMem, and steering flows across queues
+// is left to the NIC, e.g., with ntuple rules.
+#[derive(Debug)]
+pub struct Steering {
+    program: XdpProgram,
+}
+
+impl Steering {
+    pub fn new() -> Result<Self, CamelliaError> {
+        Ok(Self {
+            program: XdpProgram::from_bytes(STEERING, Some("xdp"))?,
+        })
+    }
+
+    pub fn program(&self) -> &XdpProgram {
+        &self.program
+    }
+
+    pub fn program_mut(&mut self) -> &mut XdpProgram {
+        &mut self.program
+    }
+
+    // the maps below are available once attached
+    pub fn attach(&mut self, ifname: &str, mode: XDPMode) -> Result<(), CamelliaError> {
+        self.program.attach(ifname, mode)
+    }
+
+    pub fn stats(&self, queue_index: u32) -> Result<XdpProgStats, CamelliaError> {
+        XdpProgStats::new(&self.program, queue_index)
+    }
+
+    fn check_slot(queue_index: u32, slot: u32) -> Result<u32, CamelliaError> {
+        if queue_index >= MAX_QUEUES || slot >= SLOTS_PER_QUEUE {
+            return Err(CamelliaError::InvalidArgument(format!(
+                "slot {} of queue {} is out of range, {} queues with {} slots each are supported",
+                slot, queue_index, MAX_QUEUES, SLOTS_PER_QUEUE
+            )));
+        }
+        Ok(queue_index * SLOTS_PER_QUEUE + slot)
+    }
+
+    // Puts a socket bound to the queue into the slot. Flows are hashed over the slots
+    // below the highest one taken, packets hashed to an empty slot are passed to the kernel.
+    pub fn add_socket(
+        &self,
+        queue_index: u32,
+        slot: u32,
+        socket: &impl AsFd,
+    ) -> Result<(), CamelliaError> {
+        let key = Self::check_slot(queue_index, slot)?;
+        self.program
+            .map(XSKS_MAP)?
+            .update(&key, &socket.as_fd().as_raw_fd())?;
+
+        let slots = self.program.map("slots")?;
+        let count: u32 = slots.lookup(&queue_index)?.unwrap_or(0);
+        if slot >= count {
+            slots.update(&queue_index, &(slot + 1))?;
+        }
+        Ok(())
+    }
+
+    // the number of slots stays, flows hashed to the slot are passed to the kernel
+    pub fn remove_socket(&self, queue_index: u32, slot: u32) -> Result<bool, CamelliaError> {
+        let key = Self::check_slot(queue_index, slot)?;
+        self.program.map(XSKS_MAP)?.delete(&key)
+    }
+
+    pub fn slots(&self, queue_index: u32) -> Result<u32, CamelliaError> {
+        Ok(self
+            .program
+            .map("slots")?
+            .lookup(&queue_index)?
+            .unwrap_or(0))
+    }
+
+    // the slot a flow not pinned by set_flow is hashed to
+    pub fn slot_of(&self, flow: &Flow, queue_index: u32) -> Result<Option<u32>, CamelliaError> {
+        Ok(match self.slots(queue_index)? {
+            0 => None,
+            slots => Some(flow.hash() % slots),
+        })
+    }
+
+    // pins the flow to the slot of whichever queue receives it
+    pub fn set_flow(&self, flow: &Flow, slot: u32) -> Result<(), CamelliaError> {
+        Self::check_slot(0, slot)?;
+        self.program.map("flows")?.update(flow, &slot)
+    }
+
+    pub fn remove_flow(&self, flow: &Flow) -> Result<bool, CamelliaError> {
+        self.program.map("flows")?.delete(flow)
+    }
+
+    pub fn xsks_map(&self) -> Result<BpfMap, CamelliaError> {
+        self.program.map(XSKS_MAP)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_flow_layout() {
+        let flow = Flow::tcp(
+            "10.0.0.1:1234".parse().unwrap(),
+            "10.0.0.2:80".parse().unwrap(),
+        )
+        .unwrap();
+
+        let bytes = flow.as_bytes();
+        assert_eq!(bytes.len(), 40);
+        assert_eq!(&bytes[0..4], &[10, 0, 0, 1]);
+        assert_eq!(&bytes[16..20], &[10, 0, 0, 2]);
+        assert_eq!(&bytes[32..34], &1234u16.to_be_bytes());
+        assert_eq!(&bytes[34..36], &80u16.to_be_bytes());
+        assert_eq!(&bytes[36..38], &[0x08, 0x00]);
+        assert_eq!(bytes[38], 6);
+
+        // ports of other protocols are not parsed
+        let icmp = Flow::new(
+            libc::IPPROTO_ICMP as u8,
+            "10.0.0.1:1234".parse().unwrap(),
+            "10.0.0.2:80".parse().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(&icmp.as_bytes()[32..36], &[0; 4]);
+
+        assert!(Flow::udp("10.0.0.1:53".parse().unwrap(), "[::1]:53".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_flow_hash() {
+        assert_eq!(Flow::default().hash(), {
+            let mut hash = 2166136261u32;
+            for _ in 0..40 {
+                hash = hash.wrapping_mul(16777619);
+            }
+            hash
+        });
+
+        let flows: Vec<Flow> = (0..1000)
+            .map(|port| {
+                Flow::udp(
+                    format!("10.0.0.1:{}", 1024 + port).parse().unwrap(),
+                    "10.0.0.2:53".parse().unwrap(),
+                )
+                .unwrap()
+            })
+            .collect();
+        let mut per_slot = [0; 4];
+        for flow in &flows {
+            per_slot[(flow.hash() % 4) as usize] += 1;
+        }
+        assert!(per_slot.iter().all(|count| *count > 150));
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    net::{IpAddr, Ipv4Addr, UdpSocket},
+    thread::sleep,
+    time::Duration,
+};
+
+use camellia::socket::{af_packet::PacketSocketBuilder, Socket};
+use etherparse::{IpNumber, PacketBuilder};
+use test_utils::veth::VethDeviceBuilder;
+
+#[test]
+fn test_af_packet_io() {
+    let left_device = VethDeviceBuilder::new("packet-left")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x3a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 12, 1)), 24);
+
+    let right_device = VethDeviceBuilder::new("packet-right")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x3b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 12, 2)), 24);
+
+    let veth_pair = right_device.build(left_device).unwrap();
+
+    let mut left_socket = PacketSocketBuilder::new()
+        .ifname("packet-left")
+        .build()
+        .unwrap();
+    let mut right_socket = PacketSocketBuilder::new()
+        .ifname("packet-right")
+        .build()
+        .unwrap();
+
+    let builder = PacketBuilder::ethernet2(
+        veth_pair.left.mac_addr.bytes(),
+        veth_pair.right.mac_addr.bytes(),
+    )
+    .ipv4([192, 168, 12, 1], [192, 168, 12, 2], 255);
+    let payload = "hello, af_packet!".as_bytes();
+    let packet_size = builder.size(payload.len());
+
+    let mut frame = left_socket.allocate(1).unwrap().pop().unwrap();
+    {
+        let mut buffer = frame.raw_buffer_append(packet_size).unwrap();
+        builder.write(&mut buffer, IpNumber::UDP, payload).unwrap();
+    }
+    let expected = frame.raw_buffer().to_vec();
+
+    assert!(left_socket.send(frame).unwrap().is_none());
+    assert_eq!(left_socket.stat().tx_packets, 1);
+
+    sleep(Duration::from_millis(100));
+
+    // the peer may also see unrelated traffic, e.g., IPv6 neighbor discovery
+    let frames = right_socket.recv_bulk(64).unwrap();
+    assert!(frames.iter().any(|frame| frame.raw_buffer() == expected));
+}
+
+#[test]
+fn test_af_packet_oversized_frame() {
+    let left_device = VethDeviceBuilder::new("pktbig-left")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x4a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 13, 1)), 24);
+
+    let right_device = VethDeviceBuilder::new("pktbig-right")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x4b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 13, 2)), 24);
+
+    let veth_pair = right_device.build(left_device).unwrap();
+
+    // received packets would not fit in the chunks
+    assert!(PacketSocketBuilder::new()
+        .ifname("pktbig-left")
+        .frame_size(4096)
+        .build()
+        .is_err());
+
+    let mut left_socket = PacketSocketBuilder::new()
+        .ifname("pktbig-left")
+        .frame_size(1024)
+        .build()
+        .unwrap();
+    let mut right_socket = PacketSocketBuilder::new()
+        .ifname("pktbig-right")
+        .build()
+        .unwrap();
+
+    let frames: Vec<_> = [16, 1400, 16]
+        .into_iter()
+        .map(|payload_size| {
+            let builder = PacketBuilder::ethernet2(
+                veth_pair.left.mac_addr.bytes(),
+                veth_pair.right.mac_addr.bytes(),
+            )
+            .ipv4([192, 168, 13, 1], [192, 168, 13, 2], 255);
+            let payload = vec![0xab; payload_size];
+            let mut frame = left_socket.allocate(1).unwrap().pop().unwrap();
+            let mut buffer = frame
+                .raw_buffer_append(builder.size(payload.len()))
+                .unwrap();
+            builder.write(&mut buffer, IpNumber::UDP, &payload).unwrap();
+            frame
+        })
+        .collect();
+    let expected = frames[0].raw_buffer().to_vec();
+
+    // the frame before the oversized one is still sent
+    let error = left_socket.send_bulk(frames).unwrap_err();
+    assert!(error.to_string().contains("1 frames before it were sent"));
+    assert_eq!(left_socket.stat().tx_packets, 1);
+
+    sleep(Duration::from_millis(100));
+
+    let frames = right_socket.recv_bulk(64).unwrap();
+    assert!(frames.iter().any(|frame| frame.raw_buffer() == expected));
+}
+
+#[test]
+fn test_af_packet_ignores_outgoing() {
+    let left_device = VethDeviceBuilder::new("pktout-left")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x5a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 14, 1)), 24);
+
+    let right_device = VethDeviceBuilder::new("pktout-right")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x5b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 14, 2)), 24);
+
+    let veth_pair = right_device.build(left_device).unwrap();
+
+    let mut left_socket = PacketSocketBuilder::new()
+        .ifname("pktout-left")
+        .build()
+        .unwrap();
+    let mut right_socket = PacketSocketBuilder::new()
+        .ifname("pktout-right")
+        .build()
+        .unwrap();
+
+    // the host stack sends ARP and UDP out of pktout-left
+    let host = UdpSocket::bind("192.168.14.1:0").unwrap();
+    for _ in 0..4 {
+        host.send_to(b"from the host", "192.168.14.2:9").unwrap();
+        sleep(Duration::from_millis(25));
+    }
+    sleep(Duration::from_millis(100));
+
+    let sent_by = |frame: &[u8], mac: [u8; 6]| frame.len() >= 12 && frame[6..12] == mac;
+    let left_mac = veth_pair.left.mac_addr.bytes();
+    assert!(right_socket
+        .recv_bulk(64)
+        .unwrap()
+        .iter()
+        .any(|frame| sent_by(frame.raw_buffer(), left_mac)));
+    // only packets arriving at pktout-left are received, e.g., ARP replies
+    assert!(!left_socket
+        .recv_bulk(64)
+        .unwrap()
+        .iter()
+        .any(|frame| sent_by(frame.raw_buffer(), left_mac)));
+}
@@ -0,0 +1,147 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread::{self, sleep},
+    time::{Duration, Instant},
+};
+
+use camellia::{
+    error::CamelliaError,
+    socket::af_xdp::{XskSocket, XskSocketBuilder},
+    umem::base::{DedicatedAccessorRef, UMemBuilder},
+};
+use test_utils::{
+    chaos::{ChaosBuilder, Fault},
+    netns::NetNs,
+    veth::{VethDeviceBuilder, VethPair},
+};
+
+fn setup_veth() -> anyhow::Result<VethPair> {
+    let left_device = VethDeviceBuilder::new("chaos-left")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 13, 1)), 24)
+        .namespace(NetNs::new_unique("chaos-ns")?);
+
+    let right_device = VethDeviceBuilder::new("chaos-right")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 13, 2)), 24);
+
+    left_device.build(right_device)
+}
+
+fn socket(ifname: &str) -> XskSocket<DedicatedAccessorRef> {
+    XskSocketBuilder::new()
+        .ifname(ifname)
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .build()
+        .unwrap()
+}
+
+// Sends received frames back. Without cooperate schedule every flush is a syscall, so
+// the state of the device shows up even when no packet arrives.
+fn bounce(socket: &mut XskSocket<DedicatedAccessorRef>) -> Result<(), CamelliaError> {
+    let frames = socket.recv_bulk(32)?;
+    socket.send_bulk(frames)?;
+    socket.flush_tx()
+}
+
+// Rebinds until the device is back, anything but the device being down or missing fails
+fn recover(socket: &mut XskSocket<DedicatedAccessorRef>) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match socket.rebind() {
+            Ok(()) => return,
+            Err(e)
+                if Instant::now() < deadline
+                    && (e.is_retryable()
+                        || matches!(
+                            e,
+                            CamelliaError::DeviceDown { .. }
+                                | CamelliaError::InterfaceNotFound { .. }
+                        )) =>
+            {
+                sleep(Duration::from_millis(10))
+            }
+            Err(e) => panic!("failed to recover: {}", e),
+        }
+    }
+}
+
+#[test]
+fn test_forward_under_chaos() {
+    let chaos = ChaosBuilder::new()
+        .interval(Duration::from_millis(200))
+        .outage(Duration::from_millis(100))
+        .link_flaps()
+        .mtus(&[1280, 1500, 3000])
+        .namespace_churn()
+        .start(setup_veth)
+        .unwrap();
+
+    let running = Arc::new(AtomicBool::new(true));
+    let errors = Arc::new(AtomicUsize::new(0));
+    let forwarder = {
+        let (running, errors) = (running.clone(), errors.clone());
+        thread::spawn(move || {
+            let mut socket = socket("chaos-right");
+            while running.load(Ordering::Relaxed) {
+                match bounce(&mut socket) {
+                    Ok(()) => {}
+                    Err(e) if e.is_retryable() => {}
+                    Err(CamelliaError::DeviceDown { .. } | CamelliaError::DeviceRemoved { .. }) => {
+                        errors.fetch_add(1, Ordering::Relaxed);
+                        recover(&mut socket);
+                    }
+                    Err(e) => panic!("untyped error under chaos: {}", e),
+                }
+            }
+        })
+    };
+
+    sleep(Duration::from_secs(10));
+    let (veth_pair, report) = chaos.stop().unwrap();
+    println!("{} faults with seed {}", report.faults.len(), report.seed);
+
+    // the forwarder notices every outage, MTU changes don't break the socket
+    if report
+        .faults
+        .iter()
+        .any(|fault| !matches!(fault, Fault::MtuChange { .. }))
+    {
+        assert!(errors.load(Ordering::Relaxed) > 0);
+    }
+
+    // frames sent to the restored pair come back
+    let received = {
+        let _guard = veth_pair.left.namespace.enter().unwrap();
+        let mut socket = socket("chaos-left");
+        let mut packet = [0u8; 64];
+        packet[..6].copy_from_slice(&[0xff; 6]);
+        packet[6..12].copy_from_slice(&veth_pair.left.mac_addr.bytes());
+        packet[12..14].copy_from_slice(&0x88b5u16.to_be_bytes());
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut received = 0;
+        while received == 0 && Instant::now() < deadline {
+            let mut frames = socket.allocate(8).unwrap();
+            for frame in frames.iter_mut() {
+                frame
+                    .raw_buffer_append(packet.len())
+                    .unwrap()
+                    .copy_from_slice(&packet);
+            }
+            socket.send_bulk(frames).unwrap();
+            sleep(Duration::from_millis(100));
+            received += socket.recv_bulk(32).unwrap().len();
+        }
+        received
+    };
+
+    running.store(false, Ordering::Relaxed);
+    forwarder.join().unwrap();
+    assert!(received > 0);
+}
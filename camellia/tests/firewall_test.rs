@@ -0,0 +1,210 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use camellia::{
+    net::{firewall::Firewall, FlowKey, IPPROTO_UDP},
+    socket::af_xdp::{XDPMode, XskSocket, XskSocketBuilder},
+    umem::{
+        base::{DedicatedAccessorRef, UMem, UMemBuilder},
+        shared::SharedAccessorRef,
+    },
+    xdp::{
+        filter::{FilterAction, FilterMatch, PacketFilter},
+        program::XdpAction,
+    },
+};
+use etherparse::PacketBuilder;
+use test_utils::veth::{VethDeviceBuilder, VethPair};
+
+const INSIDE_HOST: [u8; 4] = [192, 168, 21, 1];
+const OUTSIDE_HOST: [u8; 4] = [192, 168, 22, 1];
+
+// the host end is the left one
+fn setup_veth(host: &str, firewall: &str, subnet: u8, mac: u8) -> VethPair {
+    let host_device = VethDeviceBuilder::new(host)
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x88, mac].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, subnet, 1)), 24);
+
+    let firewall_device = VethDeviceBuilder::new(firewall)
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x88, mac + 1].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, subnet, 2)), 24);
+
+    firewall_device.build(host_device).unwrap()
+}
+
+fn filtered_socket(
+    ifname: &str,
+    umem: &Arc<Mutex<UMem>>,
+) -> (PacketFilter, XskSocket<SharedAccessorRef>) {
+    let mut filter = PacketFilter::new().unwrap();
+    filter.attach(ifname, XDPMode::Driver).unwrap();
+    let socket = XskSocketBuilder::<SharedAccessorRef>::new()
+        .ifname(ifname)
+        .queue_index(0)
+        .with_umem(umem.clone())
+        .xsks_map(Arc::new(filter.xsks_map().unwrap()))
+        .build_shared()
+        .unwrap();
+    (filter, socket)
+}
+
+fn host_socket(ifname: &str) -> XskSocket<DedicatedAccessorRef> {
+    XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname(ifname)
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap()
+}
+
+struct Fixture {
+    // the protected network and the rest of the world
+    inside_pair: VethPair,
+    outside_pair: VethPair,
+    inside_host: XskSocket<DedicatedAccessorRef>,
+    outside_host: XskSocket<DedicatedAccessorRef>,
+    inside_filter: PacketFilter,
+    outside_filter: PacketFilter,
+    inside: XskSocket<SharedAccessorRef>,
+    outside: XskSocket<SharedAccessorRef>,
+    firewall: Firewall,
+}
+
+impl Fixture {
+    fn send(&mut self, from_inside: bool, src_port: u16, dst_port: u16) {
+        let (socket, source, destination, src, dst) = if from_inside {
+            (
+                &mut self.inside_host,
+                &self.inside_pair.left,
+                &self.outside_pair.left,
+                INSIDE_HOST,
+                OUTSIDE_HOST,
+            )
+        } else {
+            (
+                &mut self.outside_host,
+                &self.outside_pair.left,
+                &self.inside_pair.left,
+                OUTSIDE_HOST,
+                INSIDE_HOST,
+            )
+        };
+        let builder =
+            PacketBuilder::ethernet2(source.mac_addr.bytes(), destination.mac_addr.bytes())
+                .ipv4(src, dst, 64)
+                .udp(src_port, dst_port);
+        let payload = [0u8; 32];
+        let mut frame = socket.allocate(1).unwrap().pop().unwrap();
+        {
+            let mut buffer = frame
+                .raw_buffer_append(builder.size(payload.len()))
+                .unwrap();
+            builder.write(&mut buffer, &payload).unwrap();
+        }
+        assert!(socket.send(frame).unwrap().is_none());
+    }
+
+    // forwards through the firewall for a while, returns whether the other host got the
+    // frame from src_port to dst_port
+    fn delivered(&mut self, to_inside: bool, src_port: u16, dst_port: u16) -> bool {
+        let mut delivered = false;
+        let deadline = Instant::now() + Duration::from_millis(300);
+        while Instant::now() < deadline {
+            let now = Instant::now();
+            let mut frames = self.inside.recv_bulk(32).unwrap();
+            frames.retain(|frame| self.firewall.accept_outbound(frame.raw_buffer(), now));
+            self.outside.send_bulk(frames).unwrap();
+            let mut frames = self.outside.recv_bulk(32).unwrap();
+            frames.retain(|frame| self.firewall.accept_inbound(frame.raw_buffer(), now));
+            self.inside.send_bulk(frames).unwrap();
+
+            let host = if to_inside {
+                &mut self.inside_host
+            } else {
+                &mut self.outside_host
+            };
+            // the hosts also see unrelated traffic, e.g., IPv6 neighbor discovery
+            delivered |= host.recv_bulk(32).unwrap().iter().any(|frame| {
+                FlowKey::from_frame(frame.raw_buffer()).is_some_and(|key| {
+                    key.protocol == IPPROTO_UDP
+                        && key.src_port == src_port
+                        && key.dst_port == dst_port
+                })
+            });
+        }
+        delivered
+    }
+}
+
+#[test]
+fn test_firewall() {
+    let inside_pair = setup_veth("fw-host-in", "fw-inside", 21, 0x10);
+    let outside_pair = setup_veth("fw-host-out", "fw-outside", 22, 0x20);
+
+    let umem = Arc::new(Mutex::new(
+        UMemBuilder::new().num_chunks(4096).build().unwrap(),
+    ));
+    let (inside_filter, inside) = filtered_socket("fw-inside", &umem);
+    let (outside_filter, outside) = filtered_socket("fw-outside", &umem);
+    let mut fixture = Fixture {
+        inside_pair,
+        outside_pair,
+        inside_host: host_socket("fw-host-in"),
+        outside_host: host_socket("fw-host-out"),
+        inside_filter,
+        outside_filter,
+        inside,
+        outside,
+        firewall: Firewall::new(1024).ttl(Duration::from_secs(60)),
+    };
+
+    // nothing gets in before the inside host opens the connection
+    fixture.send(false, 6000, 5000);
+    assert!(!fixture.delivered(true, 6000, 5000));
+    fixture.send(true, 5000, 6000);
+    assert!(fixture.delivered(false, 5000, 6000));
+    fixture.send(false, 6000, 5000);
+    assert!(fixture.delivered(true, 6000, 5000));
+    // the kernels of the hosts send IPv6 neighbor discovery as well
+    assert!(fixture.firewall.connections() >= 1);
+    assert!(fixture.firewall.stats().rejected >= 1);
+
+    // new inbound connections to an allowed port
+    let service = FilterMatch::default().ip_proto(IPPROTO_UDP).dst_port(8000);
+    fixture.send(false, 6000, 8000);
+    assert!(!fixture.delivered(true, 6000, 8000));
+    fixture.firewall.allow_inbound(service);
+    fixture.send(false, 6000, 8000);
+    assert!(fixture.delivered(true, 6000, 8000));
+
+    // denied outbound frames are dropped by the filter of the inside interface
+    let stats = fixture.inside_filter.stats(0).unwrap();
+    let dropped = stats.read().unwrap().get(XdpAction::Drop).packets;
+    fixture
+        .inside_filter
+        .add_rule(
+            FilterMatch::default().ip_proto(IPPROTO_UDP).dst_port(7000),
+            FilterAction::Drop,
+        )
+        .unwrap();
+    fixture.send(true, 5000, 7000);
+    assert!(!fixture.delivered(false, 5000, 7000));
+    assert_eq!(
+        stats.read().unwrap().get(XdpAction::Drop).packets,
+        dropped + 1
+    );
+
+    // deny rules of the outside interface take precedence over tracked connections
+    fixture
+        .outside_filter
+        .add_rule(
+            FilterMatch::default().ip_proto(IPPROTO_UDP).dst_port(5000),
+            FilterAction::Drop,
+        )
+        .unwrap();
+    fixture.send(false, 6000, 5000);
+    assert!(!fixture.delivered(true, 6000, 5000));
+}
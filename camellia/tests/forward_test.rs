@@ -10,11 +10,30 @@ use camellia::{
 };
 
 use nix::sys::epoll::{self, EpollCreateFlags, EpollEvent};
-use test_utils::{stdenv, veth::MacAddr};
+use test_utils::{iperf::Iperf3, netem::Netem, stdenv};
 
-fn packet_forward(epoll: bool, busy_polling: bool) {
+// the topology of stdenv is shared by the tests
+static TOPOLOGY: Mutex<()> = Mutex::new(());
+
+// broadcast and multicast, e.g., ARP requests and the neighbor solicitations of IPv6
+fn is_group_address(mac_address: [u8; 6]) -> bool {
+    mac_address[0] & 0x01 != 0
+}
+
+fn packet_forward(
+    epoll: bool,
+    busy_polling: bool,
+    server_address: &'static str,
+    impairment: Option<Netem>,
+) {
     let veth_pair = stdenv::setup_veth().unwrap();
 
+    // on the egress of the client, towards the forwarder
+    if let Some(netem) = impairment {
+        let _guard = veth_pair.0.left.namespace.enter().unwrap();
+        netem.apply(&veth_pair.0.left.name).unwrap();
+    }
+
     let running = Arc::new(AtomicBool::new(true));
     let ready = Arc::new(AtomicBool::new(false));
     let running_clone = running.clone();
@@ -28,7 +47,6 @@ fn packet_forward(epoll: bool, busy_polling: bool) {
     let handle = std::thread::spawn(move || {
         core_affinity::set_for_current(core_affinity::CoreId { id: 2 });
 
-        let broadcase_address = MacAddr::new([0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
         let mac_address_client = veth_pair.0.left.mac_addr;
         let mac_address_server = veth_pair.1.right.mac_addr;
 
@@ -77,7 +95,7 @@ fn packet_forward(epoll: bool, busy_polling: bool) {
                         log::debug!("receive packet from right socket: {:?}", ether_header);
 
                         if ether_header.destination == mac_address_server.bytes()
-                            || ether_header.destination == broadcase_address.bytes()
+                            || is_group_address(ether_header.destination)
                         {
                             Some(frame)
                         } else {
@@ -102,7 +120,7 @@ fn packet_forward(epoll: bool, busy_polling: bool) {
                         log::debug!("receive packet from right socket: {:?}", ether_header);
 
                         if ether_header.destination == mac_address_client.bytes()
-                            || ether_header.destination == broadcase_address.bytes()
+                            || is_group_address(ether_header.destination)
                         {
                             Some(frame)
                         } else {
@@ -151,7 +169,7 @@ fn packet_forward(epoll: bool, busy_polling: bool) {
                                 log::debug!("receive packet from right socket: {:?}", ether_header);
 
                                 if ether_header.destination == mac_address_server.bytes()
-                                    || ether_header.destination == broadcase_address.bytes()
+                                    || is_group_address(ether_header.destination)
                                 {
                                     Some(frame)
                                 } else {
@@ -177,7 +195,7 @@ fn packet_forward(epoll: bool, busy_polling: bool) {
                                 log::debug!("receive packet from right socket: {:?}", ether_header);
 
                                 if ether_header.destination == mac_address_client.bytes()
-                                    || ether_header.destination == broadcase_address.bytes()
+                                    || is_group_address(ether_header.destination)
                                 {
                                     Some(frame)
                                 } else {
@@ -219,30 +237,18 @@ fn packet_forward(epoll: bool, busy_polling: bool) {
 
     while !ready.load(std::sync::atomic::Ordering::SeqCst) {}
 
-    let server_handle = std::thread::spawn(move || {
-        core_affinity::set_for_current(core_affinity::CoreId { id: 3 });
-        let _guard = server_namespace.enter().unwrap();
-
-        std::process::Command::new("iperf3")
-            .args(["-s", "-1"])
-            .output()
-            .unwrap();
-    });
-
-    let client_handle = std::thread::spawn(move || {
-        core_affinity::set_for_current(core_affinity::CoreId { id: 1 });
-        std::thread::sleep(Duration::from_secs(1));
-        let _guard = client_namespace.enter().unwrap();
-
-        let mut handle = std::process::Command::new("iperf3")
-            .args(["-c", "192.168.12.1", "-t", "10", "-C", "reno"])
-            .spawn()
-            .unwrap();
-
-        handle.wait().unwrap();
-    });
-    server_handle.join().unwrap();
-    client_handle.join().unwrap();
+    let result = Iperf3::new()
+        .congestion("reno")
+        .server_cpu(3)
+        .client_cpu(1)
+        .run(
+            &server_namespace,
+            &client_namespace,
+            server_address.parse().unwrap(),
+        )
+        .unwrap();
+    println!("{:?}", result);
+    assert!(result.received.bytes > 0);
 
     running_clone_secondary.store(false, std::sync::atomic::Ordering::SeqCst);
     handle.join().unwrap();
@@ -252,7 +258,27 @@ fn packet_forward(epoll: bool, busy_polling: bool) {
 
 #[test]
 fn test_packet_forward() {
-    packet_forward(true, false);
-    packet_forward(false, false);
-    packet_forward(false, true);
+    let _topology = TOPOLOGY.lock().unwrap_or_else(|e| e.into_inner());
+    packet_forward(true, false, "192.168.12.1", None);
+    packet_forward(false, false, "192.168.12.1", None);
+    packet_forward(false, true, "192.168.12.1", None);
+}
+
+// TCP still gets through the forwarder over a lossy link with reordering
+#[test]
+fn test_packet_forward_impaired() {
+    let _topology = TOPOLOGY.lock().unwrap_or_else(|e| e.into_inner());
+    let netem = Netem::new()
+        .delay(Duration::from_millis(5))
+        .jitter(Duration::from_millis(1))
+        .loss(1.0)
+        .reorder(10.0)
+        .rate(1_000_000_000);
+    packet_forward(false, false, "192.168.12.1", Some(netem));
+}
+
+#[test]
+fn test_packet_forward_ipv6() {
+    let _topology = TOPOLOGY.lock().unwrap_or_else(|e| e.into_inner());
+    packet_forward(false, false, "fd00:12::1", None);
 }
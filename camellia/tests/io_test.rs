@@ -1,26 +1,37 @@
 use std::{
     cmp::max,
     net::{IpAddr, Ipv4Addr},
-    time::Duration,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
 use camellia::{
-    socket::af_xdp::XskSocketBuilder,
+    error::CamelliaError,
+    runtime::XskRuntimeBuilder,
+    socket::{
+        af_xdp::{NeedWakeup, XDPMode, XskSocket, XskSocketBuilder},
+        hooks::Hooks,
+    },
     umem::{
-        base::{DedicatedAccessorRef, UMemBuilder},
-        frame::AppFrame,
+        base::{DedicatedAccessorRef, UMemBuilder, STAGGER_HEADROOM},
+        frame::{AppFrame, RxFrame, TxFrame},
+        shared::SharedAccessorRef,
+        tracker::ChunkState,
     },
 };
 use etherparse::{IpNumber, PacketBuilder};
 use std::thread::sleep;
-use test_utils::veth::{VethDeviceBuilder, VethPair};
+use test_utils::{
+    capture::{write_pcap, Capture, Direction},
+    veth::{delete_device, down_device, set_mtu, up_device, VethDeviceBuilder, VethPair},
+};
 
-fn setup_veth() -> VethPair {
-    let left_device = VethDeviceBuilder::new("test-left")
+fn setup_veth(left: &str, right: &str) -> VethPair {
+    let left_device = VethDeviceBuilder::new(left)
         .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2a].into())
         .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 11, 1)), 24);
 
-    let right_device = VethDeviceBuilder::new("test-right")
+    let right_device = VethDeviceBuilder::new(right)
         .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2b].into())
         .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 11, 1)), 24);
 
@@ -52,7 +63,7 @@ fn build_a_packet(
 fn test_packet_io() {
     env_logger::init();
 
-    let veth_pair = setup_veth();
+    let veth_pair = setup_veth("test-left", "test-right");
 
     let umem_left = UMemBuilder::new().num_chunks(4096).build().unwrap();
     let umem_right = UMemBuilder::new().num_chunks(4096).build().unwrap();
@@ -102,3 +113,1038 @@ fn test_packet_io() {
         max(packet_size, bounced_frame.len())
     );
 }
+
+#[test]
+fn test_send_before_deadline() {
+    let veth_pair = setup_veth("deadline-left", "deadline-right");
+
+    let umem = UMemBuilder::new().num_chunks(4096).build().unwrap();
+
+    let mut socket = XskSocketBuilder::new()
+        .ifname("deadline-left")
+        .queue_index(0)
+        .with_umem(umem)
+        .enable_cooperate_schedule()
+        .build()
+        .unwrap();
+
+    let frames: Vec<_> = socket
+        .allocate(8)
+        .unwrap()
+        .into_iter()
+        .map(|frame| build_a_packet(&veth_pair, frame))
+        .collect();
+
+    // an expired deadline must not submit anything
+    let (remaining, stat) = socket.send_bulk_before(frames, Instant::now()).unwrap();
+    assert_eq!(remaining.len(), 8);
+    assert_eq!(stat.sent, 0);
+    assert_eq!(stat.attempts, 0);
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let (remaining, stat) = socket.send_bulk_before(remaining, deadline).unwrap();
+    assert!(remaining.is_empty());
+    assert_eq!(stat.sent, 8);
+    assert!(stat.overshoot.is_zero());
+}
+
+#[test]
+fn test_close_drains_tx() {
+    let veth_pair = setup_veth("close-left", "close-right");
+
+    let umem = UMemBuilder::new().num_chunks(4096).build().unwrap();
+
+    let mut socket = XskSocketBuilder::new()
+        .ifname("close-left")
+        .queue_index(0)
+        .with_umem(umem)
+        .enable_cooperate_schedule()
+        .build()
+        .unwrap();
+
+    let frames: Vec<_> = socket
+        .allocate(32)
+        .unwrap()
+        .into_iter()
+        .map(|frame| build_a_packet(&veth_pair, frame))
+        .collect();
+    assert!(socket.send_bulk(frames).unwrap().is_empty());
+
+    assert_eq!(socket.close(Duration::from_secs(1)).unwrap(), 0);
+}
+
+#[test]
+fn test_capture_sent_frame() {
+    let veth_pair = setup_veth("capture-left", "capture-right");
+    let capture = Capture::start(&veth_pair.right.namespace, "capture-right").unwrap();
+
+    let mut socket = XskSocketBuilder::new()
+        .ifname("capture-left")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .build()
+        .unwrap();
+    let frame = build_a_packet(&veth_pair, socket.allocate(1).unwrap().pop().unwrap());
+    let sent = frame.raw_buffer().to_vec();
+    assert!(socket.send(frame).unwrap().is_none());
+
+    let captured = capture
+        .wait_for(Duration::from_secs(1), |packet| packet.data == sent)
+        .expect("the frame never arrived at the peer");
+    assert_eq!(captured.direction, Direction::Incoming);
+    assert_eq!(captured.orig_len, sent.len());
+
+    let packets = capture.stop().unwrap();
+    let path = std::env::temp_dir().join(format!("capture-{}.pcap", std::process::id()));
+    write_pcap(&path, &packets).unwrap();
+    let read = camellia::testing::pcap::read_pcap(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(read.len(), packets.len());
+    assert!(read.iter().any(|packet| packet.data == sent));
+}
+
+#[test]
+fn test_rebind_after_device_down() {
+    let veth_pair = setup_veth("flap-left", "flap-right");
+
+    let mut socket = XskSocketBuilder::new()
+        .ifname("flap-left")
+        .queue_index(0)
+        .rx_queue_size(1024)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .build()
+        .unwrap();
+
+    down_device("flap-left").unwrap();
+    let frame = build_a_packet(&veth_pair, socket.allocate(1).unwrap().pop().unwrap());
+    assert!(matches!(
+        socket.send(frame),
+        Err(CamelliaError::DeviceDown { .. })
+    ));
+    assert!(matches!(
+        socket.rebind(),
+        Err(CamelliaError::DeviceDown { .. })
+    ));
+
+    up_device("flap-left").unwrap();
+    socket.rebind().unwrap();
+    // the frame stuck in the old TX ring is reclaimed
+    let stat = socket.umem_stat();
+    assert_eq!(stat.app_owned, 0);
+    assert_eq!(stat.tx_pending, 0);
+    assert_eq!(stat.fill_ring, 1024);
+
+    let frame = build_a_packet(&veth_pair, socket.allocate(1).unwrap().pop().unwrap());
+    assert!(socket.send(frame).unwrap().is_none());
+}
+
+// like setup_veth, with num_queues queues on both ends
+fn setup_veth_queues(left: &str, right: &str, num_queues: u32) -> VethPair {
+    let left_device = VethDeviceBuilder::new(left)
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 11, 1)), 24)
+        .num_queues(num_queues);
+
+    let right_device = VethDeviceBuilder::new(right)
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 11, 1)), 24)
+        .num_queues(num_queues);
+
+    right_device.build(left_device).unwrap()
+}
+
+#[test]
+fn test_failed_rebind() {
+    let veth_pair = setup_veth_queues("unbound-left", "unbound-right", 2);
+
+    let mut socket = XskSocketBuilder::new()
+        .ifname("unbound-left")
+        .queue_index(1)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .build()
+        .unwrap();
+
+    // the interface comes back without the queue of the socket
+    delete_device("unbound-left").unwrap();
+    drop(veth_pair);
+    let veth_pair = setup_veth_queues("unbound-left", "unbound-right", 1);
+    assert!(socket.rebind().is_err());
+    assert!(!socket.is_bound());
+
+    assert!(matches!(
+        socket.recv_bulk(32),
+        Err(CamelliaError::DeviceDown { .. })
+    ));
+    assert!(socket.recv_peek_bulk(32).is_empty());
+    let frame = build_a_packet(&veth_pair, socket.allocate(1).unwrap().pop().unwrap());
+    assert!(matches!(
+        socket.send(frame),
+        Err(CamelliaError::DeviceDown { .. })
+    ));
+    // the chunks of the old rings are reclaimed all the same
+    let stat = socket.umem_stat();
+    assert_eq!(stat.fill_ring, 0);
+    assert_eq!(stat.tx_pending, 0);
+    assert_eq!(socket.close(Duration::from_millis(10)).unwrap(), 0);
+}
+
+#[test]
+fn test_tx_inflight_bytes_cap() {
+    let veth_pair = setup_veth("cap-left", "cap-right");
+
+    let umem = UMemBuilder::new().num_chunks(4096).build().unwrap();
+
+    let mut socket = XskSocketBuilder::new()
+        .ifname("cap-left")
+        .queue_index(0)
+        .with_umem(umem)
+        .enable_cooperate_schedule()
+        .max_tx_inflight_bytes(256)
+        .build()
+        .unwrap();
+
+    let mut remaining: Vec<_> = socket
+        .allocate(32)
+        .unwrap()
+        .into_iter()
+        .map(|frame| build_a_packet(&veth_pair, frame))
+        .collect();
+
+    // the cap is exceeded by at most one frame
+    remaining = socket.send_bulk(remaining).unwrap();
+    assert!(!remaining.is_empty());
+    assert!(socket.stat.tx_inflight_bytes < 256 + 64);
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while !remaining.is_empty() && Instant::now() < deadline {
+        remaining = socket.send_bulk(remaining).unwrap();
+        assert!(socket.stat.tx_inflight_bytes < 256 + 64);
+    }
+    assert!(remaining.is_empty());
+}
+
+#[test]
+fn test_umem_stat() {
+    let _veth_pair = setup_veth("stat-left", "stat-right");
+
+    let umem = UMemBuilder::new().num_chunks(4096).build().unwrap();
+
+    let mut socket = XskSocketBuilder::new()
+        .ifname("stat-left")
+        .queue_index(0)
+        .with_umem(umem)
+        .rx_queue_size(1024)
+        .enable_cooperate_schedule()
+        .build()
+        .unwrap();
+
+    let stat = socket.umem_stat();
+    assert_eq!(stat.fill_ring, 1024);
+    assert_eq!(stat.free_chunks, 4096 - 1024);
+    assert_eq!(stat.app_owned, 0);
+
+    let frames = socket.allocate(16).unwrap();
+    assert_eq!(socket.umem_stat().app_owned, 16);
+    drop(frames);
+    assert_eq!(socket.umem_stat().app_owned, 0);
+}
+
+#[test]
+fn test_build_validation() {
+    let veth_pair = setup_veth("valid-left", "valid-right");
+
+    let socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifindex(veth_pair.left.index)
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(socket.ifname(), "valid-left");
+    drop(socket);
+
+    down_device("valid-left").unwrap();
+    let result = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("valid-left")
+        .queue_index(8)
+        .rx_queue_size(1000)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build();
+
+    let Err(CamelliaError::InvalidConfig(violations)) = result else {
+        panic!("invalid configuration is accepted");
+    };
+    assert_eq!(violations.len(), 3);
+    assert!(violations.iter().any(|v| v.contains("is down")));
+    assert!(violations
+        .iter()
+        .any(|v| v.contains("queue 8 is out of range")));
+}
+
+#[test]
+fn test_copy_mode() {
+    let _veth_pair = setup_veth("copy-left", "copy-right");
+
+    let result = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("copy-left")
+        .queue_index(0)
+        .enable_zero_copy()
+        .force_copy_mode()
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build();
+    assert!(matches!(
+        result,
+        Err(CamelliaError::InvalidConfig(violations)) if violations.len() == 1
+    ));
+
+    let mut socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("copy-left")
+        .queue_index(0)
+        .force_copy_mode()
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    assert!(socket.recv_bulk(32).unwrap().is_empty());
+}
+
+#[test]
+fn test_jumbo_frames() {
+    let veth_pair = setup_veth("jumbo-left", "jumbo-right");
+    set_mtu("jumbo-left", 9000).unwrap();
+    set_mtu("jumbo-right", 9000).unwrap();
+
+    let result = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("jumbo-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Generic)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build();
+    assert!(matches!(
+        result,
+        Err(CamelliaError::FrameTooLarge { mtu: 9000, .. })
+    ));
+
+    // frames span several chunks instead
+    let mut socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("jumbo-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Generic)
+        .enable_multi_buffer()
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    let mut sender = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("jumbo-right")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Generic)
+        .enable_multi_buffer()
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+
+    // a frame of 6000 bytes, which takes two chunks at least
+    let builder = PacketBuilder::ethernet2(
+        veth_pair.right.mac_addr.bytes(),
+        veth_pair.left.mac_addr.bytes(),
+    )
+    .ipv4([192, 168, 11, 2], [192, 168, 11, 1], 64);
+    let payload: Vec<u8> = (0..6000 - builder.size(0)).map(|i| i as u8).collect();
+    let mut expected = Vec::new();
+    builder
+        .write(&mut expected, IpNumber::TCP, &payload)
+        .unwrap();
+    assert_eq!(expected.len(), 6000);
+
+    let mut fragments = Vec::new();
+    let mut rest = expected.as_slice();
+    while !rest.is_empty() {
+        let mut frame = sender.allocate(1).unwrap().pop().unwrap();
+        let len = frame.tail_room().min(rest.len());
+        frame
+            .raw_buffer_append(len)
+            .unwrap()
+            .copy_from_slice(&rest[..len]);
+        rest = &rest[len..];
+        fragments.push(TxFrame::from(frame));
+    }
+    assert!(fragments.len() > 1);
+    assert!(sender.send_fragments(fragments).unwrap().is_none());
+
+    // the veths also carry IPv6 neighbor discovery
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut received = None;
+    while received.is_none() && Instant::now() < deadline {
+        received = socket
+            .recv_packets(32)
+            .unwrap()
+            .into_iter()
+            .find(|packet| packet.iter().map(RxFrame::len).sum::<usize>() == expected.len());
+    }
+    let packet = received.expect("the jumbo frame is not received");
+    assert!(packet.len() > 1);
+    let reassembled: Vec<u8> = packet
+        .iter()
+        .flat_map(|fragment| fragment.raw_buffer().to_vec())
+        .collect();
+    assert_eq!(reassembled, expected);
+}
+
+fn recv_exactly(
+    socket: &mut XskSocket<DedicatedAccessorRef>,
+    n: usize,
+) -> Vec<RxFrame<DedicatedAccessorRef>> {
+    let mut received = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while received.len() < n && Instant::now() < deadline {
+        let remaining = n - received.len();
+        socket.recv_bulk_into(&mut received, remaining).unwrap();
+    }
+    received
+}
+
+#[test]
+fn test_staggered_rx() {
+    let veth_pair = setup_veth("stagger-left", "stagger-right");
+
+    let staggered_umem = || {
+        UMemBuilder::new()
+            .num_chunks(1024)
+            .stagger_headroom(true)
+            .frame_headroom(STAGGER_HEADROOM)
+            .build()
+            .unwrap()
+    };
+    let mut left_socket = XskSocketBuilder::new()
+        .ifname("stagger-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Generic)
+        .with_umem(staggered_umem())
+        .build()
+        .unwrap();
+    let mut right_socket = XskSocketBuilder::new()
+        .ifname("stagger-right")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Generic)
+        .with_umem(staggered_umem())
+        .build()
+        .unwrap();
+
+    // twice the number of stagger offsets
+    let frames: Vec<_> = left_socket
+        .allocate(16)
+        .unwrap()
+        .into_iter()
+        .map(|frame| build_a_packet(&veth_pair, frame))
+        .collect();
+    let packet = frames[0].raw_buffer().to_vec();
+    assert!(left_socket.send_bulk(frames).unwrap().is_empty());
+
+    let received = recv_exactly(&mut right_socket, 16);
+    assert_eq!(received.len(), 16);
+    let mut offsets: Vec<_> = received
+        .iter()
+        .map(|frame| {
+            assert_eq!(frame.raw_buffer(), packet.as_slice());
+            frame.0.xdp_address() % 4096
+        })
+        .collect();
+    offsets.sort_unstable();
+    offsets.dedup();
+    assert!(offsets.len() > 1);
+
+    // back out of the chunks they were received into
+    assert!(right_socket.send_bulk(received).unwrap().is_empty());
+    let bounced = recv_exactly(&mut left_socket, 16);
+    assert_eq!(bounced.len(), 16);
+    for frame in &bounced {
+        assert_eq!(frame.raw_buffer(), packet.as_slice());
+    }
+
+    // the completions of both sides are decoded back to their chunks
+    drop(bounced);
+    for socket in [&mut left_socket, &mut right_socket] {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while socket.umem_stat().tx_pending > 0 && Instant::now() < deadline {
+            socket.check_tx_stall().unwrap();
+        }
+        assert_eq!(socket.umem_stat().tx_pending, 0);
+    }
+}
+
+#[test]
+fn test_queue_conflict() {
+    let _veth_pair = setup_veth("busy-left", "busy-right");
+
+    let socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("busy-left")
+        .auto_queue()
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(socket.queue_index(), 0);
+
+    let result = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("busy-left")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build();
+    assert!(matches!(
+        result,
+        Err(CamelliaError::QueueInUse { queue: 0, .. })
+    ));
+
+    // veth has a single queue
+    let result = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("busy-left")
+        .auto_queue()
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build();
+    assert!(matches!(result, Err(CamelliaError::ResourceExhausted(_))));
+
+    drop(socket);
+    assert!(XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("busy-left")
+        .auto_queue()
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .is_ok());
+}
+
+#[test]
+fn test_auto_xdp_mode() {
+    let _veth_pair = setup_veth("auto-left", "auto-right");
+
+    // veth has no offload but supports native XDP
+    let socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("auto-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Auto)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(socket.xdp_mode(), XDPMode::Driver);
+}
+
+#[test]
+fn test_chunk_quota() {
+    let _veth_pair = setup_veth("quota-left", "quota-right");
+
+    let umem = Arc::new(Mutex::new(
+        UMemBuilder::new().num_chunks(4096).build().unwrap(),
+    ));
+
+    let left_socket = XskSocketBuilder::<SharedAccessorRef>::new()
+        .ifname("quota-left")
+        .queue_index(0)
+        .with_umem(umem.clone())
+        .rx_queue_size(1024)
+        .chunk_quota(256)
+        .build_shared()
+        .unwrap();
+
+    let right_socket = XskSocketBuilder::<SharedAccessorRef>::new()
+        .ifname("quota-right")
+        .queue_index(0)
+        .with_umem(umem.clone())
+        .rx_queue_size(1024)
+        .cache_watermarks(32, 128)
+        .build_shared()
+        .unwrap();
+
+    let stat = left_socket.umem_stat();
+    assert_eq!(stat.fill_ring, 256 + 1024);
+    assert_eq!(stat.free_chunks, 4096 - 256 - 1024);
+    assert_eq!(right_socket.umem_stat(), stat);
+
+    assert!(XskSocketBuilder::<SharedAccessorRef>::new()
+        .ifname("quota-right")
+        .queue_index(0)
+        .with_umem(umem)
+        .cache_watermarks(128, 128)
+        .build_shared()
+        .is_err());
+}
+
+#[test]
+fn test_runtime_bounce() {
+    let veth_pair = setup_veth("rt-left", "rt-right");
+
+    // reflect every frame received on the left side
+    let runtime = XskRuntimeBuilder::new()
+        .queue("rt-left", 0)
+        .num_chunks(1024)
+        .spawn(|frames| frames.iter().map(|frame| frame.mirror().unwrap()).collect())
+        .unwrap();
+
+    let umem = UMemBuilder::new().num_chunks(1024).build().unwrap();
+    let mut socket = XskSocketBuilder::new()
+        .ifname("rt-right")
+        .queue_index(0)
+        .with_umem(umem)
+        .build()
+        .unwrap();
+
+    let frame = build_a_packet(&veth_pair, socket.allocate(1).unwrap().pop().unwrap());
+    let expected = frame.raw_buffer().to_vec();
+    assert!(socket.send(frame).unwrap().is_none());
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut bounced = false;
+    while !bounced && Instant::now() < deadline {
+        bounced = socket
+            .recv_bulk(32)
+            .unwrap()
+            .iter()
+            .any(|frame| frame.raw_buffer() == expected);
+    }
+    assert!(bounced);
+
+    let stats = runtime.stop().unwrap();
+    assert_eq!(stats.len(), 1);
+    assert!(stats[0].xsk.rx_packets >= 1);
+    assert!(stats[0].xsk.tx_packets >= 1);
+    assert_eq!(stats[0].tx_dropped, 0);
+}
+
+#[derive(Clone, Debug, Default)]
+struct CountingHooks {
+    counts: Arc<Mutex<(usize, usize, usize)>>,
+}
+
+impl Hooks for CountingHooks {
+    fn on_rx_batch(&mut self, frames: usize, _bytes: u64) {
+        self.counts.lock().unwrap().0 += frames;
+    }
+
+    fn on_tx_batch(&mut self, frames: usize, _bytes: u64) {
+        self.counts.lock().unwrap().1 += frames;
+    }
+
+    fn on_fill(&mut self, _requested: usize, filled: usize) {
+        self.counts.lock().unwrap().2 += filled;
+    }
+}
+
+#[test]
+fn test_hooks() {
+    let veth_pair = setup_veth("hook-left", "hook-right");
+
+    let left_hooks = CountingHooks::default();
+    let right_hooks = CountingHooks::default();
+
+    let mut left_socket = XskSocketBuilder::new()
+        .ifname("hook-left")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .hooks(left_hooks.clone())
+        .build()
+        .unwrap();
+
+    let mut right_socket = XskSocketBuilder::new()
+        .ifname("hook-right")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .hooks(right_hooks.clone())
+        .build()
+        .unwrap();
+
+    let frame = build_a_packet(&veth_pair, left_socket.allocate(1).unwrap().pop().unwrap());
+    assert!(left_socket.send(frame).unwrap().is_none());
+    sleep(Duration::from_millis(100));
+
+    let received = right_socket.recv_bulk(32).unwrap();
+    assert!(!received.is_empty());
+
+    assert_eq!(left_hooks.counts.lock().unwrap().1, 1);
+    let (rx_frames, _, filled) = *right_hooks.counts.lock().unwrap();
+    assert_eq!(rx_frames, received.len());
+    assert_eq!(filled, received.len());
+}
+
+#[test]
+fn test_raw_rings() {
+    let veth_pair = setup_veth("raw-left", "raw-right");
+
+    let mut sender = XskSocketBuilder::new()
+        .ifname("raw-left")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    let mut receiver = XskSocketBuilder::new()
+        .ifname("raw-right")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+
+    let frames: Vec<_> = sender
+        .allocate(4)
+        .unwrap()
+        .into_iter()
+        .map(|frame| build_a_packet(&veth_pair, frame))
+        .collect();
+    {
+        let mut tx = unsafe { sender.tx_ring_raw() };
+        let (start_index, reserved) = tx.reserve(4).unwrap();
+        assert_eq!(reserved, 4);
+        for (i, frame) in frames.into_iter().enumerate() {
+            let desc = tx.write_frame(start_index + i as u32, frame).unwrap();
+            assert_eq!(desc.options, 0);
+        }
+        tx.submit(reserved);
+        tx.wakeup().unwrap();
+    }
+    assert_eq!(sender.stat.tx_inflight_bytes, 4 * 64);
+    sleep(Duration::from_millis(100));
+
+    let mut received = Vec::new();
+    {
+        let mut rx = unsafe { receiver.rx_ring_raw() };
+        let (start_index, peeked) = rx.peek(16);
+        assert_eq!(peeked, 4);
+        for index in start_index..start_index + peeked {
+            assert_eq!(rx.desc(index).options, 0);
+            assert_eq!(rx.data(index).len(), 64);
+            received.push(rx.take_frame(index));
+        }
+        rx.release(peeked);
+        assert_eq!(rx.fill(peeked as usize).unwrap(), peeked as usize);
+    }
+    assert!(received.iter().all(|frame| frame.len() == 64));
+}
+
+#[test]
+fn test_shared_frame_multicast() {
+    let _veth_pair = setup_veth("mcast-left", "mcast-right");
+
+    let umem = UMemBuilder::new()
+        .num_chunks(4096)
+        .refcount_chunks(true)
+        .track_chunks(true)
+        .build()
+        .unwrap();
+    let tracker = umem.tracker().unwrap().clone();
+    let umem = Arc::new(Mutex::new(umem));
+
+    let mut sockets: Vec<_> = ["mcast-left", "mcast-right"]
+        .into_iter()
+        .map(|ifname| {
+            XskSocketBuilder::<SharedAccessorRef>::new()
+                .ifname(ifname)
+                .queue_index(0)
+                .with_umem(umem.clone())
+                .build_shared()
+                .unwrap()
+        })
+        .collect();
+
+    let mut frame = sockets[0].allocate(1).unwrap().pop().unwrap();
+    frame.raw_buffer_append(64).unwrap().fill(0xff);
+    let address = frame.chunk().xdp_address();
+    let copies = TxFrame::from(frame).share(2).unwrap();
+    for (socket, copy) in sockets.iter_mut().zip(copies) {
+        assert!(socket.send(copy).unwrap().is_none());
+    }
+    assert_eq!(
+        tracker.lock().unwrap().state(address),
+        Some(ChunkState::Shared)
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while sockets[0].umem_stat().tx_pending > 0 && Instant::now() < deadline {
+        for socket in sockets.iter_mut() {
+            socket.check_tx_stall().unwrap();
+        }
+    }
+    assert_eq!(sockets[0].umem_stat().tx_pending, 0);
+    assert_eq!(
+        tracker.lock().unwrap().state(address),
+        Some(ChunkState::Free)
+    );
+    assert!(tracker.lock().unwrap().violations().is_empty());
+}
+
+#[test]
+fn test_allocate_waits_for_completions() {
+    let veth_pair = setup_veth("alloc-left", "alloc-right");
+
+    let mut socket = XskSocketBuilder::new()
+        .ifname("alloc-left")
+        .queue_index(0)
+        .rx_queue_size(64)
+        .with_umem(
+            UMemBuilder::new()
+                .num_chunks(128)
+                .fill_queue_size(64)
+                .build()
+                .unwrap(),
+        )
+        .build()
+        .unwrap();
+
+    let frames: Vec<_> = socket
+        .allocate(64)
+        .unwrap()
+        .into_iter()
+        .map(|frame| build_a_packet(&veth_pair, frame))
+        .collect();
+    assert!(socket.send_bulk(frames).unwrap().is_empty());
+    assert!(socket.allocate(1).is_err());
+
+    let frames = socket
+        .allocate_blocking(32, Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(frames.len(), 32);
+    // chunks in the fill ring don't come back, more than the UMem has fail at once
+    let start = Instant::now();
+    assert!(socket
+        .allocate_blocking(64, Duration::from_millis(100))
+        .is_err());
+    assert!(socket
+        .allocate_blocking(129, Duration::from_secs(10))
+        .is_err());
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    let frames: Vec<_> = frames
+        .into_iter()
+        .map(|frame| build_a_packet(&veth_pair, frame))
+        .collect();
+    assert!(socket.send_bulk(frames).unwrap().is_empty());
+    let frames = block_on(socket.allocate_async(64)).unwrap();
+    assert_eq!(frames.len(), 64);
+}
+
+#[test]
+fn test_bulk_into() {
+    let veth_pair = setup_veth("bulk-left", "bulk-right");
+
+    let mut left_socket = XskSocketBuilder::new()
+        .ifname("bulk-left")
+        .queue_index(0)
+        .tx_queue_size(16)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .build()
+        .unwrap();
+
+    let mut right_socket = XskSocketBuilder::new()
+        .ifname("bulk-right")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .build()
+        .unwrap();
+
+    let mut pending: Vec<TxFrame<_>> = left_socket
+        .allocate(24)
+        .unwrap()
+        .into_iter()
+        .map(|frame| build_a_packet(&veth_pair, frame).into())
+        .collect();
+    let capacity = pending.capacity();
+    let address = pending[16].xdp_address();
+
+    // the TX ring takes 16 frames, the others stay in order
+    assert_eq!(left_socket.send_bulk_drain(&mut pending).unwrap(), 16);
+    assert_eq!(pending.len(), 8);
+    assert_eq!(pending[0].xdp_address(), address);
+    assert_eq!(pending.capacity(), capacity);
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while !pending.is_empty() && Instant::now() < deadline {
+        left_socket.send_bulk_drain(&mut pending).unwrap();
+    }
+    assert!(pending.is_empty());
+
+    let mut received = Vec::with_capacity(32);
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while received.len() < 24 && Instant::now() < deadline {
+        let before = received.len();
+        let n = right_socket.recv_bulk_into(&mut received, 32).unwrap();
+        assert_eq!(received.len(), before + n);
+    }
+    assert_eq!(received.len(), 24);
+    assert_eq!(received.capacity(), 32);
+
+    received.clear();
+    assert_eq!(right_socket.recv_bulk_into(&mut received, 32).unwrap(), 0);
+}
+
+#[test]
+fn test_recv_peek_commit() {
+    let veth_pair = setup_veth("peek-left", "peek-right");
+
+    let mut left_socket = XskSocketBuilder::new()
+        .ifname("peek-left")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+
+    let mut right_socket = XskSocketBuilder::new()
+        .ifname("peek-right")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+
+    // the last byte of the padding tells the frames apart
+    let frames: Vec<_> = left_socket
+        .allocate(4)
+        .unwrap()
+        .into_iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let mut frame = build_a_packet(&veth_pair, frame);
+            *frame.raw_buffer_mut().last_mut().unwrap() = i as u8;
+            frame
+        })
+        .collect();
+    let expected: Vec<Vec<u8>> = frames
+        .iter()
+        .map(|frame| frame.raw_buffer().to_vec())
+        .collect();
+    assert!(left_socket.send_bulk(frames).unwrap().is_empty());
+
+    let peek = |socket: &mut XskSocket<DedicatedAccessorRef>| -> Vec<(u64, Vec<u8>)> {
+        socket
+            .recv_peek_bulk(32)
+            .iter()
+            .map(|view| (view.xdp_address, view.raw_buffer().to_vec()))
+            .collect()
+    };
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    let mut peeked = Vec::new();
+    while peeked.len() < 4 && Instant::now() < deadline {
+        right_socket.kick_rx().unwrap();
+        peeked = peek(&mut right_socket);
+    }
+    let contents: Vec<Vec<u8>> = peeked.iter().map(|(_, data)| data.clone()).collect();
+    assert_eq!(contents, expected);
+
+    // without a commit, the same descriptors are seen again
+    assert_eq!(peek(&mut right_socket), peeked);
+
+    // a partial commit consumes the first descriptors only
+    let committed = right_socket.recv_commit(2).unwrap();
+    assert_eq!(committed.len(), 2);
+    for (frame, (address, data)) in committed.iter().zip(&peeked) {
+        assert_eq!(frame.0.xdp_address() as u64, *address);
+        assert_eq!(frame.raw_buffer(), data.as_slice());
+    }
+    assert_eq!(peek(&mut right_socket), peeked[2..]);
+
+    let committed = right_socket.recv_commit(32).unwrap();
+    assert_eq!(committed.len(), 2);
+    assert_eq!(committed[0].raw_buffer(), expected[2].as_slice());
+    assert_eq!(committed[1].raw_buffer(), expected[3].as_slice());
+    assert!(right_socket.recv_peek_bulk(32).is_empty());
+}
+
+#[test]
+fn test_manual_wakeup() {
+    let veth_pair = setup_veth("kick-left", "kick-right");
+
+    let mut left_socket = XskSocketBuilder::new()
+        .ifname("kick-left")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .build()
+        .unwrap();
+
+    let mut right_socket = XskSocketBuilder::new()
+        .ifname("kick-right")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .build()
+        .unwrap();
+
+    left_socket.set_manual_wakeup(true);
+    for _ in 0..4 {
+        let frames: Vec<_> = left_socket
+            .allocate(8)
+            .unwrap()
+            .into_iter()
+            .map(|frame| build_a_packet(&veth_pair, frame))
+            .collect();
+        assert!(left_socket.send_bulk(frames).unwrap().is_empty());
+    }
+    assert_eq!(left_socket.stat.tx_wakeup, 0);
+
+    left_socket.flush_tx().unwrap();
+    assert_eq!(left_socket.stat.tx_wakeup, 1);
+
+    let mut received = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while received.len() < 32 && Instant::now() < deadline {
+        right_socket.recv_bulk_into(&mut received, 32).unwrap();
+    }
+    assert_eq!(received.len(), 32);
+
+    // receiving doesn't wake up the RX queue either
+    left_socket.recv_bulk(32).unwrap();
+    assert_eq!(left_socket.stat.rx_wakeup, 0);
+    left_socket.kick_rx().unwrap();
+}
+
+#[test]
+fn test_need_wakeup_per_direction() {
+    let veth_pair = setup_veth("nw-left", "nw-right");
+
+    let rx_only = NeedWakeup {
+        rx: true,
+        tx: false,
+    };
+    let mut left_socket = XskSocketBuilder::new()
+        .ifname("nw-left")
+        .queue_index(0)
+        .need_wakeup(rx_only)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(left_socket.need_wakeup(), rx_only);
+
+    let mut right_socket = XskSocketBuilder::new()
+        .ifname("nw-right")
+        .queue_index(0)
+        .need_wakeup(rx_only)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+        .build()
+        .unwrap();
+
+    // TX doesn't follow the flag, every batch wakes the kernel up
+    for _ in 0..2 {
+        let frames: Vec<_> = left_socket
+            .allocate(8)
+            .unwrap()
+            .into_iter()
+            .map(|frame| build_a_packet(&veth_pair, frame))
+            .collect();
+        assert!(left_socket.send_bulk(frames).unwrap().is_empty());
+    }
+    assert_eq!(left_socket.stat.tx_wakeup, 2);
+
+    let mut received = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while received.len() < 16 && Instant::now() < deadline {
+        right_socket.recv_bulk_into(&mut received, 32).unwrap();
+    }
+    assert_eq!(received.len(), 16);
+}
+
+// polls the future until it is ready, tasks are woken up by polling again anyway
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    struct Noop;
+
+    impl std::task::Wake for Noop {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    let waker = Arc::new(Noop).into();
+    let mut cx = std::task::Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let std::task::Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
@@ -0,0 +1,187 @@
+// The io and forward paths against physical NICs, which veth pairs can't stand in for
+// in the zero-copy mode. Skipped unless CAMELLIA_NIC_LEFT and CAMELLIA_NIC_RIGHT name
+// two connected ports, see test_utils::nic.
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use camellia::{
+    error::CamelliaError,
+    probe::interface_features,
+    socket::af_xdp::{XskSocket, XskSocketBuilder},
+    umem::base::{DedicatedAccessorRef, UMemBuilder},
+};
+use test_utils::{
+    nic::{Nic, NicPair},
+    veth::MacAddr,
+};
+
+const ETHERTYPE: u16 = 0x88b5;
+const NUM_FRAMES: usize = 256;
+
+fn socket(nic: &Nic, zero_copy: bool) -> Result<XskSocket<DedicatedAccessorRef>, CamelliaError> {
+    let builder = XskSocketBuilder::new()
+        .ifname(&nic.name)
+        .queue_index(nic.queue)
+        .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap());
+    if zero_copy {
+        builder.enable_zero_copy().build()
+    } else {
+        builder.force_copy_mode().build()
+    }
+}
+
+fn supports_zero_copy(nic: &Nic) -> bool {
+    match interface_features(&nic.name).unwrap().xdp {
+        Some(xdp) => xdp.zero_copy,
+        // before Linux 6.3, only binding tells
+        None => match socket(nic, true) {
+            Ok(_) => true,
+            Err(CamelliaError::ZeroCopyUnsupported { .. }) => false,
+            Err(e) => panic!("failed to probe zero-copy on {}: {}", nic.name, e),
+        },
+    }
+}
+
+// The copy mode always, the zero-copy mode if both drivers support it
+fn zero_copy_modes(pair: &NicPair) -> Vec<bool> {
+    if supports_zero_copy(&pair.left) && supports_zero_copy(&pair.right) {
+        return vec![false, true];
+    }
+    assert!(
+        !pair.require_zero_copy,
+        "{} or {} doesn't support zero-copy",
+        pair.left.name, pair.right.name
+    );
+    eprintln!(
+        "skipping the zero-copy mode: unsupported by {} or {}",
+        pair.left.name, pair.right.name
+    );
+    vec![false]
+}
+
+// broadcast, so that the other port takes it without being promiscuous
+fn test_frame(source: MacAddr, seq: usize) -> [u8; 64] {
+    let mut frame = [0u8; 64];
+    frame[..6].copy_from_slice(&[0xff; 6]);
+    frame[6..12].copy_from_slice(&source.bytes());
+    frame[12..14].copy_from_slice(&ETHERTYPE.to_be_bytes());
+    frame[14..22].copy_from_slice(&(seq as u64).to_be_bytes());
+    frame
+}
+
+fn is_test_frame(frame: &[u8], source: MacAddr) -> bool {
+    frame.len() >= 22 && frame[6..12] == source.bytes() && frame[12..14] == ETHERTYPE.to_be_bytes()
+}
+
+fn send(socket: &mut XskSocket<DedicatedAccessorRef>, source: MacAddr, num_frames: usize) {
+    let mut frames = socket.allocate(num_frames).unwrap();
+    for (seq, frame) in frames.iter_mut().enumerate() {
+        let packet = test_frame(source, seq);
+        frame
+            .raw_buffer_append(packet.len())
+            .unwrap()
+            .copy_from_slice(&packet);
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(1);
+    while !frames.is_empty() && Instant::now() < deadline {
+        frames = socket.send_bulk(frames).unwrap();
+    }
+    assert!(frames.is_empty(), "TX ring stays full");
+}
+
+// Counts test frames of the source until all arrived or the timeout elapses, other
+// traffic of the ports, e.g., LLDP, is ignored
+fn receive(
+    socket: &mut XskSocket<DedicatedAccessorRef>,
+    source: MacAddr,
+    num_frames: usize,
+) -> usize {
+    let deadline = Instant::now() + Duration::from_secs(2);
+    let mut received = 0;
+    while received < num_frames && Instant::now() < deadline {
+        received += socket
+            .recv_bulk(64)
+            .unwrap()
+            .iter()
+            .filter(|frame| is_test_frame(frame.raw_buffer(), source))
+            .count();
+    }
+    received
+}
+
+#[test]
+fn test_nic_packet_io() {
+    let Some(pair) = NicPair::from_env_or_skip("test_nic_packet_io") else {
+        return;
+    };
+
+    for zero_copy in zero_copy_modes(&pair) {
+        let mut left = socket(&pair.left, zero_copy).unwrap();
+        let mut right = socket(&pair.right, zero_copy).unwrap();
+
+        send(&mut left, pair.left.mac_addr, NUM_FRAMES);
+        assert_eq!(
+            receive(&mut right, pair.left.mac_addr, NUM_FRAMES),
+            NUM_FRAMES,
+            "frames lost with zero-copy {}",
+            zero_copy
+        );
+
+        send(&mut right, pair.right.mac_addr, NUM_FRAMES);
+        assert_eq!(
+            receive(&mut left, pair.right.mac_addr, NUM_FRAMES),
+            NUM_FRAMES,
+            "frames lost with zero-copy {}",
+            zero_copy
+        );
+    }
+}
+
+// The right port sends every frame back as received, from the UMem it was received into
+#[test]
+fn test_nic_forward() {
+    let Some(pair) = NicPair::from_env_or_skip("test_nic_forward") else {
+        return;
+    };
+
+    for zero_copy in zero_copy_modes(&pair) {
+        let mut left = socket(&pair.left, zero_copy).unwrap();
+        let mut right = socket(&pair.right, zero_copy).unwrap();
+
+        let running = Arc::new(AtomicBool::new(true));
+        let forwarder = {
+            let running = running.clone();
+            thread::spawn(move || {
+                let mut forwarded = 0;
+                while running.load(Ordering::Relaxed) {
+                    let frames = right.recv_bulk(64).unwrap();
+                    forwarded += frames.len();
+                    let mut frames = right.send_bulk(frames).unwrap();
+                    while !frames.is_empty() {
+                        frames = right.send_bulk(frames).unwrap();
+                    }
+                }
+                forwarded
+            })
+        };
+
+        send(&mut left, pair.left.mac_addr, NUM_FRAMES);
+        let received = receive(&mut left, pair.left.mac_addr, NUM_FRAMES);
+        running.store(false, Ordering::Relaxed);
+        let forwarded = forwarder.join().unwrap();
+
+        assert!(forwarded >= NUM_FRAMES);
+        assert_eq!(
+            received, NUM_FRAMES,
+            "frames lost with zero-copy {}",
+            zero_copy
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    thread::sleep,
+    time::Duration,
+};
+
+use camellia::{
+    error::CamelliaError,
+    socket::af_xdp::{interface_kind, InterfaceKind, XDPMode, XskSocketBuilder},
+    umem::base::{DedicatedAccessorRef, UMemBuilder},
+};
+use test_utils::{
+    link::StackedDevice,
+    veth::{VethDeviceBuilder, VethPair},
+};
+
+fn setup_veth(left: &str, right: &str) -> VethPair {
+    let left_device = VethDeviceBuilder::new(left)
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 11, 1)), 24);
+
+    let right_device = VethDeviceBuilder::new(right)
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 11, 1)), 24);
+
+    right_device.build(left_device).unwrap()
+}
+
+#[test]
+fn test_vlan_socket() {
+    let veth_pair = setup_veth("vl-left", "vl-right");
+    let vlan = StackedDevice::vlan("vl-left", "vl-left.10", 10).unwrap();
+    assert_eq!(interface_kind(&vlan.name), Some(InterfaceKind::Vlan));
+    assert_eq!(interface_kind("vl-left"), Some(InterfaceKind::Virtual));
+
+    assert!(matches!(
+        XskSocketBuilder::<DedicatedAccessorRef>::new()
+            .ifname(&vlan.name)
+            .queue_index(0)
+            .enable_zero_copy()
+            .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+            .build(),
+        Err(CamelliaError::InvalidConfig(_))
+    ));
+
+    // the driver mode falls back to the generic mode
+    let mut receiver = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname(&vlan.name)
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(receiver.xdp_mode(), XDPMode::Generic);
+
+    let mut sender = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("vl-right")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+
+    let mut frame = sender.allocate(1).unwrap().pop().unwrap();
+    {
+        let buffer = frame.raw_buffer_append(64).unwrap();
+        buffer.fill(0);
+        buffer[0..6].copy_from_slice(&veth_pair.left.mac_addr.bytes());
+        buffer[6..12].copy_from_slice(&veth_pair.right.mac_addr.bytes());
+        // 802.1Q tag of VLAN 10, then the local experimental ethertype
+        buffer[12..14].copy_from_slice(&0x8100u16.to_be_bytes());
+        buffer[14..16].copy_from_slice(&10u16.to_be_bytes());
+        buffer[16..18].copy_from_slice(&0x88b5u16.to_be_bytes());
+    }
+    assert!(sender.send(frame).unwrap().is_none());
+    sleep(Duration::from_millis(100));
+
+    // the tag is stripped before the frame reaches the VLAN device
+    let frame = receiver.recv().unwrap().unwrap();
+    assert_eq!(frame.raw_buffer()[12..14], 0x88b5u16.to_be_bytes());
+}
+
+#[test]
+fn test_bond_and_macvlan_kinds() {
+    let _veth_pair = setup_veth("bd-left", "bd-right");
+
+    {
+        let macvlan = StackedDevice::macvlan("bd-right", "bd-mac").unwrap();
+        assert_eq!(interface_kind(&macvlan.name), Some(InterfaceKind::Macvlan));
+        let socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+            .ifname(&macvlan.name)
+            .queue_index(0)
+            .xdp_mode(XDPMode::Auto)
+            .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+            .build()
+            .unwrap();
+        assert_eq!(socket.xdp_mode(), XDPMode::Generic);
+    }
+
+    let bond = StackedDevice::bond("bd-bond", &["bd-left"]).unwrap();
+    assert_eq!(interface_kind(&bond.name), Some(InterfaceKind::Bond));
+    assert!(XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname(&bond.name)
+        .queue_index(0)
+        .xdp_mode(XDPMode::Auto)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .is_ok());
+}
@@ -1,6 +1,13 @@
+use camellia::{
+    error::CamelliaError,
+    probe::interface_features,
+    socket::af_xdp::{XDPMode, XskSocketBuilder},
+    umem::base::{DedicatedAccessorRef, UMemBuilder},
+    xdp::program::attached_programs,
+};
 use std::net::{IpAddr, Ipv4Addr};
 use std::process::Command;
-use test_utils::veth::VethDeviceBuilder;
+use test_utils::{veth::VethDeviceBuilder, xdp::detach_xdp};
 
 #[test]
 fn test_veth_setup() {
@@ -33,3 +40,63 @@ fn test_veth_setup() {
     assert!(!output.contains("test-left@test-right"));
     assert!(!output.contains("test-right@test-left"));
 }
+
+#[test]
+fn test_multi_queue_veth() {
+    let left_device = VethDeviceBuilder::new("mq-left")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 14, 1)), 24)
+        .num_queues(4);
+
+    let right_device = VethDeviceBuilder::new("mq-right")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 14, 2)), 24)
+        .num_queues(4);
+
+    let veth_pair = right_device.build(left_device).unwrap();
+    assert_eq!(veth_pair.left.num_queues, 4);
+    assert_eq!(interface_features("mq-left").unwrap().queues(), 4);
+
+    // every queue can be bound, the one past the last can't
+    let build = |queue| {
+        XskSocketBuilder::new()
+            .ifname("mq-left")
+            .queue_index(queue)
+            .with_umem(UMemBuilder::new().num_chunks(4096).build().unwrap())
+            .build()
+    };
+    let _sockets: Vec<_> = (0..4).map(|queue| build(queue).unwrap()).collect();
+    assert!(matches!(
+        build(4),
+        Err(CamelliaError::QueueOutOfRange { queue: 4, max: 4 })
+    ));
+}
+
+#[test]
+fn test_peer_xdp_pass() {
+    let left_device = VethDeviceBuilder::new("pass-left")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 15, 1)), 24);
+
+    let right_device = VethDeviceBuilder::new("pass-right")
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 15, 2)), 24)
+        .xdp_pass();
+
+    let _veth_pair = right_device.build(left_device).unwrap();
+    let programs = attached_programs("pass-right").unwrap();
+    assert_eq!(programs.len(), 1);
+    assert_eq!(programs[0].name, "xdp_pass");
+
+    let socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("pass-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Driver)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(socket.xdp_mode(), XDPMode::Driver);
+
+    detach_xdp("pass-right").unwrap();
+    assert!(attached_programs("pass-right").unwrap().is_empty());
+}
@@ -0,0 +1,389 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    os::fd::AsFd,
+    sync::Arc,
+    thread::sleep,
+    time::Duration,
+};
+
+use camellia::{
+    socket::af_xdp::{XDPMode, XskSocketBuilder},
+    umem::base::{DedicatedAccessorRef, UMemBuilder},
+    xdp::{
+        cpumap::CpumapRedirect,
+        filter::{FilterAction, FilterMatch, PacketFilter},
+        map::BpfMap,
+        monitor::{XdpEventKind, XdpMonitor},
+        program::{attached_programs, detach_xsk_default, XdpAction, XdpProgram, XSKS_MAP},
+        redirect::XskRedirect,
+        stats::enable_run_stats,
+        steering::{Flow, Steering},
+    },
+};
+use etherparse::PacketBuilder;
+use nix::errno::Errno;
+use test_utils::veth::{VethDeviceBuilder, VethPair};
+
+fn setup_veth(left: &str, right: &str) -> VethPair {
+    let left_device = VethDeviceBuilder::new(left)
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2a].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 11, 1)), 24);
+
+    let right_device = VethDeviceBuilder::new(right)
+        .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2b].into())
+        .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 11, 1)), 24);
+
+    right_device.build(left_device).unwrap()
+}
+
+#[test]
+fn test_multiprog_priority() {
+    let _veth_pair = setup_veth("prio-left", "prio-right");
+
+    let mut program = XdpProgram::xsk_default().unwrap();
+    program.set_run_prio(10).unwrap();
+    program.set_chain_call(XdpAction::Drop, true).unwrap();
+    program.attach("prio-left", XDPMode::Driver).unwrap();
+
+    let attached = attached_programs("prio-left").unwrap();
+    assert_eq!(attached.len(), 1);
+    assert_eq!(attached[0].run_prio, 10);
+    assert_eq!(
+        attached[0].chain_call_actions,
+        vec![XdpAction::Drop, XdpAction::Pass]
+    );
+
+    // the socket registers in the attached program instead of loading another one
+    let _socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("prio-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Driver)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(attached_programs("prio-left").unwrap(), attached);
+
+    program.detach().unwrap();
+    assert!(attached_programs("prio-left").unwrap().is_empty());
+}
+
+#[test]
+fn test_unload_default_prog() {
+    let left = VethDeviceBuilder::new("unload-left").num_queues(2);
+    let right = VethDeviceBuilder::new("unload-right").num_queues(2);
+    let _veth_pair = right.build(left).unwrap();
+
+    let socket = |queue_index| {
+        XskSocketBuilder::<DedicatedAccessorRef>::new()
+            .ifname("unload-left")
+            .queue_index(queue_index)
+            .xdp_mode(XDPMode::Driver)
+            .unload_default_prog()
+            .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+            .build()
+            .unwrap()
+    };
+    let first = socket(0);
+    let second = socket(1);
+    assert_eq!(attached_programs("unload-left").unwrap().len(), 1);
+
+    // the program stays while a socket is left
+    drop(first);
+    assert_eq!(attached_programs("unload-left").unwrap().len(), 1);
+    drop(second);
+    assert!(attached_programs("unload-left").unwrap().is_empty());
+
+    // without the option, the program outlives the socket
+    drop(
+        XskSocketBuilder::<DedicatedAccessorRef>::new()
+            .ifname("unload-left")
+            .queue_index(0)
+            .xdp_mode(XDPMode::Driver)
+            .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+            .build()
+            .unwrap(),
+    );
+    assert_eq!(attached_programs("unload-left").unwrap().len(), 1);
+    assert!(detach_xsk_default("unload-left", XDPMode::Driver).unwrap());
+    assert!(!detach_xsk_default("unload-left", XDPMode::Driver).unwrap());
+}
+
+#[test]
+fn test_pinned_maps_restart() {
+    let _veth_pair = setup_veth("pin-left", "pin-right");
+    let pin_dir = "/sys/fs/bpf/camellia-pin-test";
+
+    {
+        let mut program = XdpProgram::xsk_default().unwrap();
+        program.attach("pin-left", XDPMode::Driver).unwrap();
+        program.pin_maps(pin_dir).unwrap();
+        let id = program.id();
+        program.keep_attached();
+        assert_eq!(attached_programs("pin-left").unwrap()[0].id, id);
+    }
+
+    // the next run registers sockets in the pinned map without loading the program again
+    let programs = attached_programs("pin-left").unwrap();
+    let xsks_map = Arc::new(BpfMap::open_pinned(format!("{}/{}", pin_dir, XSKS_MAP)).unwrap());
+    let _socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("pin-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Driver)
+        .xsks_map(xsks_map)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    assert_eq!(attached_programs("pin-left").unwrap(), programs);
+
+    std::fs::remove_dir_all(pin_dir).unwrap();
+}
+
+#[test]
+fn test_cpumap_redirect() {
+    let _veth_pair = setup_veth("cpu-left", "cpu-right");
+
+    let mut redirect = CpumapRedirect::new().unwrap();
+    redirect.attach("cpu-left", XDPMode::Driver).unwrap();
+    redirect.enable_cpu(0, 192).unwrap();
+    redirect
+        .redirect_to_cpu(libc::IPPROTO_UDP as u8, Some(53), 0)
+        .unwrap();
+    redirect
+        .redirect_to_cpu(libc::IPPROTO_ICMP as u8, None, 0)
+        .unwrap();
+    assert!(redirect
+        .remove_rule(libc::IPPROTO_UDP as u8, Some(53))
+        .unwrap());
+    assert!(!redirect
+        .remove_rule(libc::IPPROTO_UDP as u8, Some(53))
+        .unwrap());
+
+    let _socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("cpu-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Driver)
+        .xsks_map(Arc::new(redirect.xsks_map().unwrap()))
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+
+    let attached = attached_programs("cpu-left").unwrap();
+    assert_eq!(attached.len(), 1);
+    assert_eq!(attached[0].name, "cpumap_redirect");
+}
+
+#[test]
+fn test_redirect_stats() {
+    let _veth_pair = setup_veth("stat-left", "stat-right");
+    let _run_stats = enable_run_stats().unwrap();
+
+    let mut redirect = XskRedirect::new().unwrap();
+    redirect.attach("stat-left", XDPMode::Driver).unwrap();
+    let stats = redirect.stats(0).unwrap();
+
+    let mut sender = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("stat-right")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    let mut send_one = || {
+        let mut frame = sender.allocate(1).unwrap().pop().unwrap();
+        frame.raw_buffer_append(60).unwrap();
+        assert!(sender.send(frame).unwrap().is_none());
+        sleep(Duration::from_millis(100));
+    };
+
+    // no socket is bound to the queue yet
+    send_one();
+    assert!(stats.read().unwrap().get(XdpAction::Pass).packets >= 1);
+
+    let mut receiver = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("stat-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Driver)
+        .xsks_map(Arc::new(redirect.xsks_map().unwrap()))
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    send_one();
+    assert!(receiver.recv().unwrap().is_some());
+
+    let redirected = stats.read().unwrap().get(XdpAction::Redirect);
+    assert!(redirected.packets >= 1);
+    assert!(redirected.bytes >= 60);
+
+    let run_stats = redirect.program().run_stats().unwrap();
+    assert!(run_stats.run_count >= 2);
+    assert!(run_stats.run_time > Duration::ZERO);
+}
+
+#[test]
+fn test_register_in_xskmap() {
+    let _veth_pair = setup_veth("xreg-left", "xreg-right");
+
+    let mut redirect = XskRedirect::new().unwrap();
+    redirect.attach("xreg-left", XDPMode::Driver).unwrap();
+    let xsks_map = redirect.xsks_map().unwrap();
+
+    let mut receiver = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("xreg-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Driver)
+        .no_default_prog()
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    receiver.register_in_xskmap(xsks_map.as_fd(), 0).unwrap();
+
+    let mut sender = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("xreg-right")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    let mut frame = sender.allocate(1).unwrap().pop().unwrap();
+    frame.raw_buffer_append(60).unwrap();
+    assert!(sender.send(frame).unwrap().is_none());
+    sleep(Duration::from_millis(100));
+    assert!(receiver.recv().unwrap().is_some());
+
+    // the entry is gone with the socket
+    drop(receiver);
+    assert!(!xsks_map.delete(&0u32).unwrap());
+}
+
+#[test]
+fn test_monitor_redirect_error() {
+    let veth_pair = setup_veth("mon-left", "mon-right");
+
+    let mut redirect = XskRedirect::new().unwrap();
+    redirect.attach("mon-left", XDPMode::Driver).unwrap();
+    let mut monitor = XdpMonitor::new(Some("mon-left")).unwrap();
+
+    // packets of mon-left are redirected to a socket bound to mon-right, which refuses them
+    let mut socket = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("mon-right")
+        .queue_index(0)
+        .xsks_map(Arc::new(redirect.xsks_map().unwrap()))
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    let mut frame = socket.allocate(1).unwrap().pop().unwrap();
+    frame.raw_buffer_append(60).unwrap();
+    assert!(socket.send(frame).unwrap().is_none());
+
+    let events = monitor.poll(Duration::from_millis(500)).unwrap();
+    assert!(events.iter().any(|event| {
+        event.kind == XdpEventKind::RedirectError
+            && event.ifindex == veth_pair.left.index
+            && event.error == Some(Errno::EINVAL)
+    }));
+}
+
+#[test]
+fn test_steering_pinned_flow() {
+    let veth_pair = setup_veth("steer-left", "steer-right");
+
+    let mut steering = Steering::new().unwrap();
+    steering.attach("steer-left", XDPMode::Driver).unwrap();
+
+    let mut receiver = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("steer-left")
+        .queue_index(0)
+        .xdp_mode(XDPMode::Driver)
+        .no_default_prog()
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    steering.add_socket(0, 1, &receiver).unwrap();
+    assert_eq!(steering.slots(0).unwrap(), 2);
+
+    let flow = Flow::udp(
+        "192.168.11.2:5000".parse().unwrap(),
+        "192.168.11.1:6000".parse().unwrap(),
+    )
+    .unwrap();
+    steering.set_flow(&flow, 1).unwrap();
+
+    let mut sender = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("steer-right")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    let builder = PacketBuilder::ethernet2(
+        veth_pair.right.mac_addr.bytes(),
+        veth_pair.left.mac_addr.bytes(),
+    )
+    .ipv4([192, 168, 11, 2], [192, 168, 11, 1], 64)
+    .udp(5000, 6000);
+    let payload = [0u8; 32];
+    let mut frame = sender.allocate(1).unwrap().pop().unwrap();
+    {
+        let mut buffer = frame
+            .raw_buffer_append(builder.size(payload.len()))
+            .unwrap();
+        builder.write(&mut buffer, &payload).unwrap();
+    }
+    assert!(sender.send(frame).unwrap().is_none());
+    sleep(Duration::from_millis(100));
+
+    assert!(receiver.recv().unwrap().is_some());
+    assert!(steering.remove_flow(&flow).unwrap());
+    assert!(steering.remove_socket(0, 1).unwrap());
+}
+
+#[test]
+fn test_packet_filter_rules() {
+    let veth_pair = setup_veth("filt-left", "filt-right");
+
+    let mut filter = PacketFilter::new().unwrap();
+    filter.attach("filt-left", XDPMode::Driver).unwrap();
+    let stats = filter.stats(0).unwrap();
+
+    let mut sender = XskSocketBuilder::<DedicatedAccessorRef>::new()
+        .ifname("filt-right")
+        .queue_index(0)
+        .with_umem(UMemBuilder::new().num_chunks(1024).build().unwrap())
+        .build()
+        .unwrap();
+    let mut send_one = || {
+        let builder = PacketBuilder::ethernet2(
+            veth_pair.right.mac_addr.bytes(),
+            veth_pair.left.mac_addr.bytes(),
+        )
+        .ipv4([192, 168, 11, 2], [192, 168, 11, 1], 64)
+        .udp(5000, 6000);
+        let payload = [0u8; 32];
+        let mut frame = sender.allocate(1).unwrap().pop().unwrap();
+        {
+            let mut buffer = frame
+                .raw_buffer_append(builder.size(payload.len()))
+                .unwrap();
+            builder.write(&mut buffer, &payload).unwrap();
+        }
+        assert!(sender.send(frame).unwrap().is_none());
+        sleep(Duration::from_millis(100));
+    };
+
+    let rule = FilterMatch::default().ip_proto(17).dst_port(6000);
+    filter.add_rule(rule, FilterAction::Drop).unwrap();
+    send_one();
+    assert_eq!(stats.read().unwrap().get(XdpAction::Drop).packets, 1);
+
+    // the source prefix takes precedence
+    let prefix = IpAddr::V4(Ipv4Addr::new(192, 168, 11, 0));
+    filter.add_prefix(prefix, 24, FilterAction::Deny).unwrap();
+    send_one();
+    assert!(stats.read().unwrap().get(XdpAction::Pass).packets >= 1);
+    assert_eq!(stats.read().unwrap().get(XdpAction::Drop).packets, 1);
+
+    assert!(filter.remove_prefix(prefix, 24).unwrap());
+    assert!(filter.remove_rule(rule).unwrap());
+    assert!(!filter.remove_rule(rule).unwrap());
+
+    filter.set_default(FilterAction::Drop).unwrap();
+    send_one();
+    assert_eq!(stats.read().unwrap().get(XdpAction::Drop).packets, 2);
+}
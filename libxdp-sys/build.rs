@@ -114,6 +114,8 @@ fn main() -> anyhow::Result<()> {
     println!("cargo:rustc-link-lib=elf");
     println!("cargo:rustc-link-lib=z");
     println!("cargo:rerun-if-changed=wrapper.h");
+    // BPF programs of dependents are compiled against the same libbpf headers
+    println!("cargo:include={}", include_path.display());
 
     let bindings = bindgen::Builder::default()
         .header("wrapper.h")
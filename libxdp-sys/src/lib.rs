@@ -4,3 +4,7 @@
 #![allow(clippy::useless_transmute)]
 #![allow(clippy::missing_safety_doc)]
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
+
+// the version of the bundled xdp-tools, as build metadata of the crate version, libxdp
+// doesn't report its version at runtime
+pub const LIBXDP_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,38 @@
+// Housekeeping of the test environment, e.g., after a test run crashed
+//
+//   camellia-cli cleanup
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Removes namespaces, veth devices and XDP dispatcher pins left by crashed test runs
+    Cleanup,
+}
+
+fn main() -> anyhow::Result<()> {
+    match Cli::parse().command {
+        Command::Cleanup => {
+            let report = test_utils::cleanup()?;
+            for namespace in &report.namespaces {
+                println!("removed namespace {}", namespace.display());
+            }
+            for device in &report.devices {
+                println!("removed device {}", device);
+            }
+            for pin in &report.pins {
+                println!("removed pin {}", pin.display());
+            }
+            if report.is_empty() {
+                println!("nothing to clean up");
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,133 @@
+// A cargo runner executing test binaries with the capabilities they need instead of as
+// root, so that cargo itself and everything it builds run unprivileged, e.g.,
+//
+//   CARGO_TARGET_X86_64_UNKNOWN_LINUX_GNU_RUNNER=camellia-testd cargo test
+//
+// tools/setup_testd.sh installs it with the capabilities below permitted, which it
+// raises into the ambient set, so that the tests and the commands they run, e.g., ip,
+// tc and iperf3, inherit them. Only members of the camellia-test group may run it, and it
+// only runs test binaries cargo built. Tests entering namespaces still need root.
+use std::{
+    ffi::OsStr,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{exit, Command},
+};
+
+use anyhow::{anyhow, Result};
+use nix::{errno::Errno, libc};
+
+// include/uapi/linux/capability.h
+const CAP_NET_ADMIN: u32 = 12;
+const CAP_NET_RAW: u32 = 13;
+const CAP_BPF: u32 = 39;
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+// UMems are locked against RLIMIT_MEMLOCK, which the group raises instead of CAP_IPC_LOCK
+const CAPABILITIES: [(u32, &str); 3] = [
+    // devices, addresses, qdiscs and XDP programs
+    (CAP_NET_ADMIN, "cap_net_admin"),
+    // AF_XDP and AF_PACKET sockets
+    (CAP_NET_RAW, "cap_net_raw"),
+    // loading BPF programs and maps
+    (CAP_BPF, "cap_bpf"),
+];
+
+#[repr(C)]
+struct CapHeader {
+    version: u32,
+    pid: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct CapData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+fn has(data: &[CapData; 2], cap: u32, set: fn(&CapData) -> u32) -> bool {
+    set(&data[(cap / 32) as usize]) & (1 << (cap % 32)) != 0
+}
+
+// Ambient capabilities must be permitted and inheritable, and are kept across execve of
+// programs without file capabilities
+fn raise_ambient() -> Result<Vec<&'static str>> {
+    let mut header = CapHeader {
+        version: LINUX_CAPABILITY_VERSION_3,
+        pid: 0,
+    };
+    let mut data = [CapData::default(); 2];
+    Errno::result(unsafe { libc::syscall(libc::SYS_capget, &mut header, data.as_mut_ptr()) })?;
+
+    let mut missing = Vec::new();
+    for (cap, name) in CAPABILITIES {
+        if has(&data, cap, |set| set.permitted) {
+            data[(cap / 32) as usize].inheritable |= 1 << (cap % 32);
+        } else {
+            missing.push(name);
+        }
+    }
+    if missing.len() == CAPABILITIES.len() {
+        return Err(anyhow!(
+            "camellia-testd has no capabilities, install it with tools/setup_testd.sh"
+        ));
+    }
+    Errno::result(unsafe { libc::syscall(libc::SYS_capset, &mut header, data.as_ptr()) })?;
+
+    for (cap, _) in CAPABILITIES {
+        if has(&data, cap, |set| set.inheritable) {
+            Errno::result(unsafe {
+                libc::prctl(
+                    libc::PR_CAP_AMBIENT,
+                    libc::PR_CAP_AMBIENT_RAISE,
+                    cap as libc::c_ulong,
+                    0,
+                    0,
+                )
+            })?;
+        }
+    }
+    Ok(missing)
+}
+
+// Test binaries are <target dir>/[<triple>/]<profile>/deps/<name>-<hash>, anything else,
+// e.g., a shell, doesn't get the capabilities
+fn test_binary(program: &OsStr) -> Result<PathBuf> {
+    let path = std::fs::canonicalize(program)?;
+    let target_dir = std::env::var_os("CARGO_TARGET_DIR")
+        .map(std::fs::canonicalize)
+        .transpose()?;
+    let in_target_dir = path.ancestors().skip(1).any(|dir| match &target_dir {
+        Some(target_dir) => dir == target_dir,
+        None => dir.file_name() == Some(OsStr::new("target")),
+    });
+    let in_deps = path.parent().and_then(Path::file_name) == Some(OsStr::new("deps"));
+    if !(in_target_dir && in_deps && path.is_file()) {
+        return Err(anyhow!(
+            "{} is not a test binary of the cargo target directory",
+            path.display()
+        ));
+    }
+    Ok(path)
+}
+
+fn main() -> Result<()> {
+    let mut args = std::env::args_os().skip(1);
+    let program = args
+        .next()
+        .ok_or_else(|| anyhow!("usage: camellia-testd <test binary> [args...]"))?;
+    let program = test_binary(&program)?;
+
+    for name in raise_ambient()? {
+        eprintln!("camellia-testd: {} is not permitted", name);
+    }
+
+    let status = Command::new(&program).args(args).status()?;
+    exit(
+        status
+            .code()
+            .unwrap_or_else(|| 128 + status.signal().unwrap_or(0)),
+    );
+}
@@ -0,0 +1,237 @@
+use std::{
+    fs::File,
+    io::Write,
+    mem::size_of,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+use nix::{errno::Errno, libc, net::if_::if_nametoindex};
+
+use crate::netns::NetNs;
+
+// large enough for the GSO packets of veth, longer ones are truncated
+const SNAPLEN: usize = 65536;
+
+const PCAP_MAGIC_NANOS: u32 = 0xa1b23c4d;
+const LINKTYPE_ETHERNET: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Incoming,
+    Outgoing,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CapturedPacket {
+    // since the epoch, taken when the packet is read
+    pub timestamp: Duration,
+    pub direction: Direction,
+    // original length on the wire, may be larger than data if the capture is truncated
+    pub orig_len: usize,
+    pub data: Vec<u8>,
+}
+
+// A packet capture on a device with an AF_PACKET socket, e.g.,
+//
+//   let capture = Capture::start(&veth_pair.right.namespace, "test-right")?;
+//   ...
+//   let packets = capture.stop()?;
+//
+// like tcpdump, except that packets come back to the test instead of a terminal. The
+// socket sees what the kernel stack sees, frames redirected to AF_XDP sockets never show
+// up on their device.
+pub struct Capture {
+    running: Arc<AtomicBool>,
+    packets: Arc<Mutex<Vec<CapturedPacket>>>,
+    handle: Option<JoinHandle<Result<()>>>,
+}
+
+impl Capture {
+    // Returns once the capture is running, the packets of the device from then on are
+    // captured until stopped
+    pub fn start(namespace: &Arc<NetNs>, device: &str) -> Result<Capture> {
+        let running = Arc::new(AtomicBool::new(true));
+        let packets = Arc::new(Mutex::new(Vec::new()));
+        let (ready_tx, ready_rx) = mpsc::channel();
+
+        let handle = {
+            let (namespace, device) = (namespace.clone(), device.to_string());
+            let (running, packets) = (running.clone(), packets.clone());
+            thread::spawn(move || {
+                // the socket stays in the namespace it is created in
+                let socket = match namespace.enter().and_then(|_guard| open(&device)) {
+                    Ok(socket) => {
+                        let _ = ready_tx.send(Ok(()));
+                        socket
+                    }
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(anyhow!("failed to capture {}: {}", device, e)));
+                        return Ok(());
+                    }
+                };
+                capture(&socket, &running, &packets)
+            })
+        };
+
+        ready_rx
+            .recv()
+            .map_err(|_| anyhow!("capture thread exited"))??;
+        Ok(Capture {
+            running,
+            packets,
+            handle: Some(handle),
+        })
+    }
+
+    // the packets captured so far
+    pub fn packets(&self) -> Vec<CapturedPacket> {
+        self.packets.lock().unwrap().clone()
+    }
+
+    // Waits until a captured packet matches or the timeout elapses
+    pub fn wait_for<F>(&self, timeout: Duration, predicate: F) -> Option<CapturedPacket>
+    where
+        F: Fn(&CapturedPacket) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(packet) = self.packets.lock().unwrap().iter().find(|p| predicate(p)) {
+                return Some(packet.clone());
+            }
+            if Instant::now() >= deadline {
+                return None;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    pub fn stop(mut self) -> Result<Vec<CapturedPacket>> {
+        self.running.store(false, Ordering::Relaxed);
+        self.handle
+            .take()
+            .unwrap()
+            .join()
+            .map_err(|e| anyhow!("capture thread panicked: {:?}", e))??;
+        Ok(std::mem::take(&mut *self.packets.lock().unwrap()))
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+// Bound to the device before it listens to any protocol, so that no packet of another
+// device slips in between
+fn open(device: &str) -> Result<OwnedFd> {
+    let ifindex = if_nametoindex(device)?;
+    let fd = Errno::result(unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, 0) })?;
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+    address.sll_family = libc::AF_PACKET as u16;
+    address.sll_protocol = (libc::ETH_P_ALL as u16).to_be();
+    address.sll_ifindex = ifindex as i32;
+    Errno::result(unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &address as *const libc::sockaddr_ll as *const libc::sockaddr,
+            size_of::<libc::sockaddr_ll>() as u32,
+        )
+    })?;
+
+    // so that a stop is noticed without any traffic
+    let timeout = libc::timeval {
+        tv_sec: 0,
+        tv_usec: 10_000,
+    };
+    Errno::result(unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVTIMEO,
+            &timeout as *const libc::timeval as *const libc::c_void,
+            size_of::<libc::timeval>() as u32,
+        )
+    })?;
+    Ok(socket)
+}
+
+fn capture(
+    socket: &OwnedFd,
+    running: &AtomicBool,
+    packets: &Mutex<Vec<CapturedPacket>>,
+) -> Result<()> {
+    let mut buffer = vec![0u8; SNAPLEN];
+    while running.load(Ordering::Relaxed) {
+        let mut address: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        let mut address_len = size_of::<libc::sockaddr_ll>() as libc::socklen_t;
+        // with MSG_TRUNC, the length on the wire is returned even if the buffer is shorter
+        let len = unsafe {
+            libc::recvfrom(
+                socket.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                libc::MSG_TRUNC,
+                &mut address as *mut libc::sockaddr_ll as *mut libc::sockaddr,
+                &mut address_len,
+            )
+        };
+        let orig_len = match Errno::result(len) {
+            Ok(len) => len as usize,
+            Err(Errno::EAGAIN | Errno::EINTR) => continue,
+            Err(e) => return Err(e.into()),
+        };
+
+        let direction = if address.sll_pkttype == libc::PACKET_OUTGOING {
+            Direction::Outgoing
+        } else {
+            Direction::Incoming
+        };
+        packets.lock().unwrap().push(CapturedPacket {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap(),
+            direction,
+            orig_len,
+            data: buffer[..orig_len.min(buffer.len())].to_vec(),
+        });
+    }
+    Ok(())
+}
+
+// Writes the packets as a pcap file with nanosecond timestamps, for wireshark or
+// tcpdump -r when an assertion fails
+pub fn write_pcap<P: AsRef<Path>>(path: P, packets: &[CapturedPacket]) -> Result<()> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&PCAP_MAGIC_NANOS.to_le_bytes());
+    // version 2.4
+    bytes.extend_from_slice(&[2, 0, 4, 0]);
+    // timezone, accuracy, snaplen and link type
+    for field in [0, 0, SNAPLEN as u32, LINKTYPE_ETHERNET] {
+        bytes.extend_from_slice(&field.to_le_bytes());
+    }
+    for packet in packets {
+        for field in [
+            packet.timestamp.as_secs() as u32,
+            packet.timestamp.subsec_nanos(),
+            packet.data.len() as u32,
+            packet.orig_len as u32,
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend_from_slice(&packet.data);
+    }
+    File::create(path)?.write_all(&bytes)?;
+    Ok(())
+}
@@ -0,0 +1,299 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::veth::{delete_device, down_device, set_mtu, up_device, VethDevice, VethPair};
+
+// the MTU veth devices are created with, restored when the chaos stops
+const DEFAULT_MTU: u32 = 1500;
+
+// A fault injected into a veth pair while the code under test keeps running
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    // the device is down for the outage, then up again
+    LinkFlap { device: String },
+    MtuChange { device: String, mtu: u32 },
+    // the namespace of the left device is deleted with the pair, and both are recreated
+    // by the setup after the outage
+    NamespaceChurn { namespace: String },
+}
+
+#[derive(Clone, Copy)]
+enum FaultKind {
+    LinkFlap,
+    MtuChange,
+    NamespaceChurn,
+}
+
+// the pair at the end and the faults injected into it
+type ChaosResult = Result<(VethPair, Vec<Fault>)>;
+
+pub struct ChaosReport {
+    pub seed: u64,
+    // in the order of injection
+    pub faults: Vec<Fault>,
+}
+
+// splitmix64, any seed is fine and the faults are the same for the same seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+// Randomly flaps the devices of a veth pair, changes their MTU and churns the namespace
+// of the left device, to reproduce the failures happy-path tests never see. The seed is
+// taken from CHAOS_SEED if set, and printed so that a failing run can be replayed.
+pub struct ChaosBuilder {
+    seed: u64,
+    interval: Duration,
+    outage: Duration,
+    link_flaps: bool,
+    mtus: Vec<u32>,
+    namespace_churn: bool,
+}
+
+impl Default for ChaosBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChaosBuilder {
+    pub fn new() -> ChaosBuilder {
+        let seed = std::env::var("CHAOS_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_nanos() as u64
+            });
+        ChaosBuilder {
+            seed,
+            interval: Duration::from_millis(500),
+            outage: Duration::from_millis(100),
+            link_flaps: false,
+            mtus: Vec::new(),
+            namespace_churn: false,
+        }
+    }
+
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    // the time between two faults
+    #[must_use]
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    // how long a device stays down or a namespace stays deleted
+    #[must_use]
+    pub fn outage(mut self, outage: Duration) -> Self {
+        self.outage = outage;
+        self
+    }
+
+    #[must_use]
+    pub fn link_flaps(mut self) -> Self {
+        self.link_flaps = true;
+        self
+    }
+
+    // MTUs picked from by MTU changes
+    #[must_use]
+    pub fn mtus(mut self, mtus: &[u32]) -> Self {
+        self.mtus = mtus.to_vec();
+        self
+    }
+
+    // the left device must be in a namespace of its own, which setup creates again
+    #[must_use]
+    pub fn namespace_churn(mut self) -> Self {
+        self.namespace_churn = true;
+        self
+    }
+
+    // Builds the pair with setup and injects faults into it from another thread until
+    // stopped. Namespace churn calls setup again to recreate the pair.
+    pub fn start<F>(self, mut setup: F) -> Result<Chaos>
+    where
+        F: FnMut() -> Result<VethPair> + Send + 'static,
+    {
+        if !self.link_flaps && self.mtus.is_empty() && !self.namespace_churn {
+            return Err(anyhow!("no fault is enabled"));
+        }
+
+        let pair = setup()?;
+        if self.namespace_churn && !pair.left.namespace.path().starts_with("/var/run/netns") {
+            return Err(anyhow!(
+                "namespace churn needs {} in a named namespace",
+                pair.left.name
+            ));
+        }
+
+        println!("chaos seed: {}", self.seed);
+        let seed = self.seed;
+        let running = Arc::new(AtomicBool::new(true));
+        let handle = {
+            let running = running.clone();
+            thread::spawn(move || self.run(pair, &mut setup, &running))
+        };
+
+        Ok(Chaos {
+            seed,
+            running,
+            handle: Some(handle),
+        })
+    }
+
+    fn run<F>(self, mut pair: VethPair, setup: &mut F, running: &AtomicBool) -> ChaosResult
+    where
+        F: FnMut() -> Result<VethPair>,
+    {
+        let mut rng = Rng(self.seed);
+        let mut faults = Vec::new();
+
+        let mut kinds = Vec::new();
+        if self.link_flaps {
+            kinds.push(FaultKind::LinkFlap);
+        }
+        if !self.mtus.is_empty() {
+            kinds.push(FaultKind::MtuChange);
+        }
+        if self.namespace_churn {
+            kinds.push(FaultKind::NamespaceChurn);
+        }
+
+        while pause(running, self.interval) {
+            let device = if rng.below(2) == 0 {
+                pair.left.clone()
+            } else {
+                pair.right.clone()
+            };
+
+            let fault = match kinds[rng.below(kinds.len())] {
+                FaultKind::LinkFlap => {
+                    in_namespace(&device, || down_device(&device.name))?;
+                    thread::sleep(self.outage);
+                    in_namespace(&device, || up_device(&device.name))?;
+                    Fault::LinkFlap {
+                        device: device.name.clone(),
+                    }
+                }
+                FaultKind::MtuChange => {
+                    let mtu = self.mtus[rng.below(self.mtus.len())];
+                    in_namespace(&device, || set_mtu(&device.name, mtu))?;
+                    Fault::MtuChange {
+                        device: device.name.clone(),
+                        mtu,
+                    }
+                }
+                FaultKind::NamespaceChurn => {
+                    let namespace = pair.left.namespace.path().display().to_string();
+                    in_namespace(&pair.left, || delete_device(&pair.left.name))?;
+                    // the namespace is unmounted with its last reference
+                    drop(device);
+                    drop(pair);
+                    thread::sleep(self.outage);
+                    pair = setup()?;
+                    Fault::NamespaceChurn { namespace }
+                }
+            };
+            log::info!("chaos: {:?}", fault);
+            faults.push(fault);
+        }
+
+        for device in [&pair.left, &pair.right] {
+            in_namespace(device, || {
+                if !self.mtus.is_empty() {
+                    set_mtu(&device.name, DEFAULT_MTU)?;
+                }
+                up_device(&device.name)
+            })?;
+        }
+        Ok((pair, faults))
+    }
+}
+
+fn in_namespace<T>(device: &VethDevice, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let _guard = device.namespace.enter()?;
+    f()
+}
+
+// sleeps for the duration unless stopped earlier, returns whether still running
+fn pause(running: &AtomicBool, duration: Duration) -> bool {
+    let deadline = Instant::now() + duration;
+    while running.load(Ordering::Relaxed) {
+        let now = Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        thread::sleep((deadline - now).min(Duration::from_millis(10)));
+    }
+    false
+}
+
+// Faults are injected until stopped or dropped
+pub struct Chaos {
+    seed: u64,
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<ChaosResult>>,
+}
+
+impl Chaos {
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Stops injecting faults and returns the pair, with both devices up and back at the
+    // default MTU
+    pub fn stop(mut self) -> Result<(VethPair, ChaosReport)> {
+        self.running.store(false, Ordering::Relaxed);
+        let (pair, faults) = self
+            .handle
+            .take()
+            .unwrap()
+            .join()
+            .map_err(|e| anyhow!("chaos thread panicked: {:?}", e))??;
+        Ok((
+            pair,
+            ChaosReport {
+                seed: self.seed,
+                faults,
+            },
+        ))
+    }
+}
+
+impl Drop for Chaos {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
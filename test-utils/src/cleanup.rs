@@ -0,0 +1,175 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Path, PathBuf},
+    thread,
+};
+
+use anyhow::{anyhow, Result};
+use nix::{
+    net::if_::if_nameindex,
+    sched::{setns, CloneFlags},
+};
+
+use crate::{
+    netns::{is_alive, DefaultEnv},
+    veth::delete_device,
+};
+
+// the alias of devices created by test-utils, followed by the pid of the creator
+const MARKER: &str = "camellia";
+
+// the pins of libxdp are under $LIBXDP_BPFFS/xdp, one directory per dispatcher named
+// dispatch-<ifindex>-<program id>
+const DEFAULT_BPFFS: &str = "/sys/fs/bpf";
+
+// What a cleanup removed
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CleanupReport {
+    pub namespaces: Vec<PathBuf>,
+    pub devices: Vec<String>,
+    pub pins: Vec<PathBuf>,
+}
+
+impl CleanupReport {
+    pub fn is_empty(&self) -> bool {
+        self.namespaces.is_empty() && self.devices.is_empty() && self.pins.is_empty()
+    }
+}
+
+pub(crate) fn marker() -> String {
+    format!("{}:{}", MARKER, std::process::id())
+}
+
+fn owner(alias: &str) -> Option<u32> {
+    alias
+        .trim()
+        .strip_prefix(MARKER)?
+        .strip_prefix(':')?
+        .parse()
+        .ok()
+}
+
+// Removes what crashed test runs left behind, in this order:
+//
+// - namespaces created by NetNs::new_unique, the devices in them go with them
+// - devices of the current namespace marked by a process that is gone, along with
+//   their peers
+// - libxdp dispatcher pins of the devices removed above, i.e., of the stale namespaces
+//   and the marked devices and their peers, unless an interface of the same index exists
+//   in the current namespace or any named one
+//
+// Anything of a running process is left alone. Devices are found through /sys, so this
+// is meant to run in the namespace /sys is mounted for, usually the root one.
+pub fn cleanup() -> Result<CleanupReport> {
+    // the interfaces of the namespaces are gone once they are removed, files that are no
+    // namespaces have none
+    let mut removed_indexes = HashSet::new();
+    for path in DefaultEnv.stale()? {
+        if let Ok(found) = ifindexes_in(path) {
+            removed_indexes.extend(found);
+        }
+    }
+    let namespaces = DefaultEnv.clean_stale()?;
+    let devices = clean_devices(&mut removed_indexes)?;
+    let pins = clean_pins(&removed_indexes)?;
+
+    Ok(CleanupReport {
+        namespaces,
+        devices,
+        pins,
+    })
+}
+
+// ifindexes of the removed devices and their peers are added to removed_indexes
+fn clean_devices(removed_indexes: &mut HashSet<u32>) -> Result<Vec<String>> {
+    let mut removed = Vec::new();
+    for interface in if_nameindex()?.iter() {
+        let name = interface.name().to_string_lossy().into_owned();
+        let Ok(alias) = std::fs::read_to_string(format!("/sys/class/net/{name}/ifalias")) else {
+            continue;
+        };
+        let Some(pid) = owner(&alias) else {
+            continue;
+        };
+        if is_alive(pid) {
+            continue;
+        }
+        let peer = std::fs::read_to_string(format!("/sys/class/net/{name}/iflink"))
+            .ok()
+            .and_then(|iflink| iflink.trim().parse::<u32>().ok());
+        // the peer of a device removed earlier in the loop is gone already
+        if delete_device(&name).is_ok() {
+            removed_indexes.insert(interface.index());
+            removed_indexes.extend(peer);
+            removed.push(name);
+        }
+    }
+    Ok(removed)
+}
+
+// Pins of other devices are left alone, their dispatchers may be in use by programs not
+// bound to any interface visible here
+fn clean_pins(removed_indexes: &HashSet<u32>) -> Result<Vec<PathBuf>> {
+    let bpffs = std::env::var("LIBXDP_BPFFS").unwrap_or_else(|_| DEFAULT_BPFFS.to_string());
+    let entries = match std::fs::read_dir(Path::new(&bpffs).join("xdp")) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let indexes = all_ifindexes()?;
+    let mut removed = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let Some(ifindex) = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("dispatch-"))
+            .and_then(|name| name.split('-').next())
+            .and_then(|ifindex| ifindex.parse::<u32>().ok())
+        else {
+            continue;
+        };
+        if !removed_indexes.contains(&ifindex) || indexes.contains(&ifindex) {
+            continue;
+        }
+        std::fs::remove_dir_all(&path)
+            .map_err(|e| anyhow!("failed to remove {}: {}", path.display(), e))?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+// Dispatchers are pinned by ifindex only, which could be of any namespace. Files in the
+// persist dir that are not namespaces, e.g., of a run that crashed while creating one,
+// have no interfaces.
+fn all_ifindexes() -> Result<HashSet<u32>> {
+    let mut indexes = ifindexes()?;
+    let Ok(entries) = std::fs::read_dir(DefaultEnv.persist_dir()) else {
+        return Ok(indexes);
+    };
+    for entry in entries {
+        if let Ok(found) = ifindexes_in(entry?.path()) {
+            indexes.extend(found);
+        }
+    }
+    Ok(indexes)
+}
+
+fn ifindexes_in(namespace: PathBuf) -> Result<HashSet<u32>> {
+    let file = File::open(namespace)?;
+    thread::spawn(move || -> Result<HashSet<u32>> {
+        setns(&file, CloneFlags::CLONE_NEWNET)?;
+        ifindexes()
+    })
+    .join()
+    .map_err(|e| anyhow!("{:?}", e))?
+}
+
+fn ifindexes() -> Result<HashSet<u32>> {
+    Ok(if_nameindex()?
+        .iter()
+        .map(|interface| interface.index())
+        .collect())
+}
@@ -0,0 +1,226 @@
+use anyhow::{anyhow, Result};
+use nix::{errno::Errno, libc};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+// include/uapi/linux/ethtool.h
+const ETHTOOL_GCOALESCE: u32 = 0x0e;
+const ETHTOOL_SCOALESCE: u32 = 0x0f;
+pub(crate) const ETHTOOL_SRXCSUM: u32 = 0x15;
+pub(crate) const ETHTOOL_STXCSUM: u32 = 0x17;
+const ETHTOOL_GCHANNELS: u32 = 0x3c;
+const ETHTOOL_SCHANNELS: u32 = 0x3d;
+
+#[repr(C)]
+struct EthtoolValue {
+    cmd: u32,
+    data: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct EthtoolChannels {
+    cmd: u32,
+    max_rx: u32,
+    max_tx: u32,
+    max_other: u32,
+    max_combined: u32,
+    rx_count: u32,
+    tx_count: u32,
+    other_count: u32,
+    combined_count: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct EthtoolCoalesce {
+    cmd: u32,
+    rx_coalesce_usecs: u32,
+    rx_max_coalesced_frames: u32,
+    rx_coalesce_usecs_irq: u32,
+    rx_max_coalesced_frames_irq: u32,
+    tx_coalesce_usecs: u32,
+    tx_max_coalesced_frames: u32,
+    tx_coalesce_usecs_irq: u32,
+    tx_max_coalesced_frames_irq: u32,
+    stats_block_coalesce_usecs: u32,
+    use_adaptive_rx_coalesce: u32,
+    use_adaptive_tx_coalesce: u32,
+    pkt_rate_low: u32,
+    rx_coalesce_usecs_low: u32,
+    rx_max_coalesced_frames_low: u32,
+    tx_coalesce_usecs_low: u32,
+    tx_max_coalesced_frames_low: u32,
+    pkt_rate_high: u32,
+    rx_coalesce_usecs_high: u32,
+    rx_max_coalesced_frames_high: u32,
+    tx_coalesce_usecs_high: u32,
+    tx_max_coalesced_frames_high: u32,
+    rate_sample_interval: u32,
+}
+
+#[repr(C)]
+struct IfreqData {
+    name: [libc::c_char; libc::IFNAMSIZ],
+    data: *mut libc::c_void,
+    // the rest of the union in struct ifreq
+    _pad: [u8; 16],
+}
+
+// The ethtool ioctl, command is one of the ethtool structs starting with its cmd
+fn ethtool<T>(name: &str, command: &mut T) -> Result<()> {
+    if name.len() >= libc::IFNAMSIZ {
+        return Err(anyhow!("Invalid interface name: {}", name));
+    }
+    let fd = Errno::result(unsafe {
+        libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0)
+    })?;
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    let mut ifreq = IfreqData {
+        name: [0; libc::IFNAMSIZ],
+        data: command as *mut T as *mut libc::c_void,
+        _pad: [0; 16],
+    };
+    for (dst, src) in ifreq.name.iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+
+    Errno::result(unsafe { libc::ioctl(fd.as_raw_fd(), libc::SIOCETHTOOL as _, &mut ifreq) })?;
+    Ok(())
+}
+
+// the commands setting a single value, e.g., ETHTOOL_SRXCSUM
+pub(crate) fn set_value(name: &str, cmd: u32, data: u32) -> Result<()> {
+    ethtool(name, &mut EthtoolValue { cmd, data })
+}
+
+// The channels of a device like ethtool -l shows them, a channel being an interrupt with
+// the queues it serves. Most NICs only have combined channels, each with an RX and a TX
+// queue.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Channels {
+    pub rx: u32,
+    pub tx: u32,
+    pub other: u32,
+    pub combined: u32,
+}
+
+fn ethtool_channels(name: &str) -> Result<EthtoolChannels> {
+    let mut channels = EthtoolChannels {
+        cmd: ETHTOOL_GCHANNELS,
+        ..Default::default()
+    };
+    ethtool(name, &mut channels)?;
+    Ok(channels)
+}
+
+pub fn get_channels(name: &str) -> Result<Channels> {
+    let channels = ethtool_channels(name)?;
+    Ok(Channels {
+        rx: channels.rx_count,
+        tx: channels.tx_count,
+        other: channels.other_count,
+        combined: channels.combined_count,
+    })
+}
+
+// the most channels of each kind the device supports
+pub fn max_channels(name: &str) -> Result<Channels> {
+    let channels = ethtool_channels(name)?;
+    Ok(Channels {
+        rx: channels.max_rx,
+        tx: channels.max_tx,
+        other: channels.max_other,
+        combined: channels.max_combined,
+    })
+}
+
+// Like ethtool -L, the driver rejects counts above the maxima
+pub fn set_channels(name: &str, channels: Channels) -> Result<()> {
+    let mut command = EthtoolChannels {
+        cmd: ETHTOOL_SCHANNELS,
+        rx_count: channels.rx,
+        tx_count: channels.tx,
+        other_count: channels.other,
+        combined_count: channels.combined,
+        ..Default::default()
+    };
+    ethtool(name, &mut command).map_err(|e| {
+        anyhow!(
+            "Failed to set channels of {} to {:?}: {}",
+            name,
+            channels,
+            e
+        )
+    })
+}
+
+// How long a device delays the interrupt of a packet, like ethtool -c shows it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Coalescing {
+    pub rx_usecs: u32,
+    pub rx_frames: u32,
+    pub tx_usecs: u32,
+    pub tx_frames: u32,
+    pub adaptive_rx: bool,
+    pub adaptive_tx: bool,
+}
+
+fn ethtool_coalesce(name: &str) -> Result<EthtoolCoalesce> {
+    let mut coalesce = EthtoolCoalesce {
+        cmd: ETHTOOL_GCOALESCE,
+        ..Default::default()
+    };
+    ethtool(name, &mut coalesce)?;
+    Ok(coalesce)
+}
+
+pub fn get_coalescing(name: &str) -> Result<Coalescing> {
+    let coalesce = ethtool_coalesce(name)?;
+    Ok(Coalescing {
+        rx_usecs: coalesce.rx_coalesce_usecs,
+        rx_frames: coalesce.rx_max_coalesced_frames,
+        tx_usecs: coalesce.tx_coalesce_usecs,
+        tx_frames: coalesce.tx_max_coalesced_frames,
+        adaptive_rx: coalesce.use_adaptive_rx_coalesce != 0,
+        adaptive_tx: coalesce.use_adaptive_tx_coalesce != 0,
+    })
+}
+
+// Like ethtool -C, parameters the driver doesn't support must stay as get_coalescing
+// returns them
+pub fn set_coalescing_params(name: &str, coalescing: Coalescing) -> Result<()> {
+    // the other parameters of the device are kept
+    let mut coalesce = ethtool_coalesce(name)?;
+    coalesce.cmd = ETHTOOL_SCOALESCE;
+    coalesce.rx_coalesce_usecs = coalescing.rx_usecs;
+    coalesce.rx_max_coalesced_frames = coalescing.rx_frames;
+    coalesce.tx_coalesce_usecs = coalescing.tx_usecs;
+    coalesce.tx_max_coalesced_frames = coalescing.tx_frames;
+    coalesce.use_adaptive_rx_coalesce = coalescing.adaptive_rx as u32;
+    coalesce.use_adaptive_tx_coalesce = coalescing.adaptive_tx as u32;
+    ethtool(name, &mut coalesce).map_err(|e| {
+        anyhow!(
+            "Failed to set coalescing of {} to {:?}: {}",
+            name,
+            coalescing,
+            e
+        )
+    })
+}
+
+// Interrupts after usecs or frames, whichever comes first, in both directions. Adaptive
+// coalescing is turned off so that the setting holds during a benchmark.
+pub fn set_coalescing(name: &str, usecs: u32, frames: u32) -> Result<()> {
+    set_coalescing_params(
+        name,
+        Coalescing {
+            rx_usecs: usecs,
+            rx_frames: frames,
+            tx_usecs: usecs,
+            tx_frames: frames,
+            adaptive_rx: false,
+            adaptive_tx: false,
+        },
+    )
+}
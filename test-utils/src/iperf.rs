@@ -0,0 +1,238 @@
+use std::{
+    net::IpAddr,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::netns::NetNs;
+
+// A TCP test of iperf3 between two namespaces, run with JSON output so that tests can
+// assert on the numbers instead of the exit status only, e.g.,
+//
+//   let result = Iperf3::new().congestion("reno").run(&server_ns, &client_ns, address)?;
+//   assert!(result.received.bits_per_second > 1e9);
+#[derive(Clone, Debug)]
+pub struct Iperf3 {
+    port: u16,
+    duration: Duration,
+    congestion: Option<String>,
+    parallel: u32,
+    reverse: bool,
+    server_cpu: Option<usize>,
+    client_cpu: Option<usize>,
+}
+
+// The totals of one direction of a test
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Throughput {
+    pub bytes: u64,
+    pub seconds: f64,
+    pub bits_per_second: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Iperf3Result {
+    // as seen by the sender and the receiver, they differ by what is lost or in flight
+    pub sent: Throughput,
+    pub received: Throughput,
+    pub retransmits: u64,
+    // the congestion control of the sender
+    pub congestion: Option<String>,
+    // CPU utilization of the client and the server
+    pub host_cpu_percent: f64,
+    pub remote_cpu_percent: f64,
+}
+
+// the summary is empty if the test failed
+#[derive(Deserialize)]
+struct Report {
+    error: Option<String>,
+    end: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize)]
+struct End {
+    sum_sent: SumSent,
+    sum_received: Throughput,
+    cpu_utilization_percent: CpuUtilization,
+    sender_tcp_congestion: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SumSent {
+    #[serde(flatten)]
+    throughput: Throughput,
+    #[serde(default)]
+    retransmits: u64,
+}
+
+#[derive(Deserialize)]
+struct CpuUtilization {
+    host_total: f64,
+    remote_total: f64,
+}
+
+impl Default for Iperf3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Iperf3 {
+    pub fn new() -> Iperf3 {
+        Iperf3 {
+            port: 5201,
+            duration: Duration::from_secs(10),
+            congestion: None,
+            parallel: 1,
+            reverse: false,
+            server_cpu: None,
+            client_cpu: None,
+        }
+    }
+
+    #[must_use]
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    #[must_use]
+    pub fn duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    // e.g., reno, cubic or bbr
+    #[must_use]
+    pub fn congestion<S: AsRef<str>>(mut self, congestion: S) -> Self {
+        self.congestion = Some(congestion.as_ref().to_string());
+        self
+    }
+
+    // parallel streams, summed up in the result
+    #[must_use]
+    pub fn parallel(mut self, parallel: u32) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    // the server sends and the client receives
+    #[must_use]
+    pub fn reverse(mut self) -> Self {
+        self.reverse = true;
+        self
+    }
+
+    #[must_use]
+    pub fn server_cpu(mut self, cpu: usize) -> Self {
+        self.server_cpu = Some(cpu);
+        self
+    }
+
+    #[must_use]
+    pub fn client_cpu(mut self, cpu: usize) -> Self {
+        self.client_cpu = Some(cpu);
+        self
+    }
+
+    // Runs a one-off server in server_ns and the client in client_ns against the server
+    // address, and returns the summary of the client
+    pub fn run(
+        &self,
+        server_ns: &NetNs,
+        client_ns: &NetNs,
+        server: IpAddr,
+    ) -> Result<Iperf3Result> {
+        let mut command = Command::new("iperf3");
+        command.args(["-s", "-1", "-p", self.port.to_string().as_str()]);
+        if let Some(cpu) = self.server_cpu {
+            command.args(["-A", cpu.to_string().as_str()]);
+        }
+        let mut server_process =
+            server_ns.spawn(command.stdout(Stdio::null()).stderr(Stdio::null()))?;
+
+        let result = self.run_client(client_ns, server);
+        // the server exits after the test, unless the client never reached it
+        reap(&mut server_process, Duration::from_secs(5))?;
+        result
+    }
+
+    fn run_client(&self, client_ns: &NetNs, server: IpAddr) -> Result<Iperf3Result> {
+        let mut command = Command::new("iperf3");
+        command.args([
+            "-c",
+            server.to_string().as_str(),
+            "-p",
+            self.port.to_string().as_str(),
+            "-t",
+            self.duration.as_secs().max(1).to_string().as_str(),
+            "-P",
+            self.parallel.to_string().as_str(),
+            "-J",
+        ]);
+        if let Some(congestion) = &self.congestion {
+            command.args(["-C", congestion.as_str()]);
+        }
+        if let Some(cpu) = self.client_cpu {
+            command.args(["-A", cpu.to_string().as_str()]);
+        }
+        if self.reverse {
+            command.arg("-R");
+        }
+
+        // the server may not listen yet right after it is spawned
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let output =
+                client_ns.run_timeout(&mut command, self.duration + Duration::from_secs(30))?;
+            match parse(&String::from_utf8_lossy(&output.stdout)) {
+                Err(e)
+                    if e.to_string().contains("unable to connect") && Instant::now() < deadline =>
+                {
+                    thread::sleep(Duration::from_millis(100));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+// Parses the JSON output of an iperf3 client
+pub fn parse(json: &str) -> Result<Iperf3Result> {
+    let report: Report = serde_json::from_str(json)
+        .map_err(|e| anyhow!("invalid iperf3 output, {}: {}", e, json))?;
+    if let Some(error) = report.error {
+        return Err(anyhow!("iperf3: {}", error));
+    }
+    let end: End = report
+        .end
+        .and_then(|end| serde_json::from_value(end).ok())
+        .ok_or_else(|| anyhow!("iperf3 output without a summary: {}", json))?;
+
+    Ok(Iperf3Result {
+        sent: end.sum_sent.throughput,
+        received: end.sum_received,
+        retransmits: end.sum_sent.retransmits,
+        congestion: end.sender_tcp_congestion,
+        host_cpu_percent: end.cpu_utilization_percent.host_total,
+        remote_cpu_percent: end.cpu_utilization_percent.remote_total,
+    })
+}
+
+fn reap(process: &mut Child, timeout: Duration) -> Result<()> {
+    let deadline = Instant::now() + timeout;
+    while process.try_wait()?.is_none() {
+        if Instant::now() >= deadline {
+            process.kill()?;
+            process.wait()?;
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
@@ -1,3 +1,14 @@
+pub mod capture;
+pub mod chaos;
+pub mod cleanup;
+pub mod ethtool;
+pub mod iperf;
+pub mod link;
+pub mod netem;
 pub mod netns;
+pub mod nic;
 pub mod stdenv;
 pub mod veth;
+pub mod xdp;
+
+pub use cleanup::cleanup;
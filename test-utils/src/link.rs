@@ -0,0 +1,73 @@
+use anyhow::{anyhow, Result};
+use nix::net::if_::if_nametoindex;
+use std::process::Command;
+
+use crate::veth::up_device;
+
+// A device stacked on other devices, e.g., a VLAN on top of a veth. The device is
+// deleted when dropped, its lower devices are left alone.
+pub struct StackedDevice {
+    pub name: String,
+    pub index: u32,
+}
+
+fn ip(args: &[&str]) -> Result<()> {
+    let output = Command::new("ip").args(args).output()?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(String::from_utf8_lossy(&output.stderr).into_owned()))
+    }
+}
+
+impl StackedDevice {
+    fn up(name: &str) -> Result<Self> {
+        up_device(name)?;
+        Ok(Self {
+            name: name.to_string(),
+            index: if_nametoindex(name)?,
+        })
+    }
+
+    pub fn vlan(parent: &str, name: &str, vlan_id: u16) -> Result<Self> {
+        ip(&[
+            "link",
+            "add",
+            "link",
+            parent,
+            "name",
+            name,
+            "type",
+            "vlan",
+            "id",
+            vlan_id.to_string().as_str(),
+        ])?;
+        Self::up(name)
+    }
+
+    pub fn macvlan(parent: &str, name: &str) -> Result<Self> {
+        ip(&[
+            "link", "add", "link", parent, "name", name, "type", "macvlan", "mode", "bridge",
+        ])?;
+        Self::up(name)
+    }
+
+    // slaves are brought down to be enslaved, the bond brings them up again
+    pub fn bond(name: &str, slaves: &[&str]) -> Result<Self> {
+        ip(&["link", "add", name, "type", "bond", "mode", "active-backup"])?;
+        for slave in slaves {
+            ip(&["link", "set", "dev", slave, "down"])?;
+            ip(&["link", "set", "dev", slave, "master", name])?;
+        }
+        Self::up(name)
+    }
+}
+
+impl Drop for StackedDevice {
+    fn drop(&mut self) {
+        if let Err(e) = ip(&["link", "del", "dev", self.name.as_str()]) {
+            log::warn!("failed to delete {}: {}", self.name, e);
+        }
+    }
+}
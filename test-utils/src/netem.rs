@@ -0,0 +1,120 @@
+use std::{process::Command, time::Duration};
+
+use anyhow::{anyhow, Result};
+
+// Impairments of a link emulated by a netem qdisc on the egress of a device, e.g.,
+//
+//   Netem::new().delay(Duration::from_millis(10)).loss(1.0).apply("test-left")
+//
+// tc has no netlink counterpart for netem in rtnetlink, so it is run as is.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Netem {
+    delay: Option<Duration>,
+    jitter: Option<Duration>,
+    // in percent
+    loss: Option<f64>,
+    reorder: Option<f64>,
+    // in bits per second
+    rate: Option<u64>,
+    // packets queued by the qdisc, 1000 by default
+    limit: Option<u32>,
+}
+
+impl Netem {
+    pub fn new() -> Netem {
+        Netem::default()
+    }
+
+    #[must_use]
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    // the delay varies by up to the jitter either way
+    #[must_use]
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = Some(jitter);
+        self
+    }
+
+    #[must_use]
+    pub fn loss(mut self, percent: f64) -> Self {
+        self.loss = Some(percent);
+        self
+    }
+
+    // the percentage of packets sent right away, ahead of the delayed ones
+    #[must_use]
+    pub fn reorder(mut self, percent: f64) -> Self {
+        self.reorder = Some(percent);
+        self
+    }
+
+    #[must_use]
+    pub fn rate(mut self, bits_per_second: u64) -> Self {
+        self.rate = Some(bits_per_second);
+        self
+    }
+
+    #[must_use]
+    pub fn limit(mut self, packets: u32) -> Self {
+        self.limit = Some(packets);
+        self
+    }
+
+    fn args(&self) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        if let Some(limit) = self.limit {
+            args.extend(["limit".to_string(), limit.to_string()]);
+        }
+        match (self.delay, self.jitter) {
+            (Some(delay), jitter) => {
+                args.extend(["delay".to_string(), format!("{}us", delay.as_micros())]);
+                if let Some(jitter) = jitter {
+                    args.push(format!("{}us", jitter.as_micros()));
+                }
+            }
+            (None, Some(_)) => return Err(anyhow!("netem jitter needs a delay")),
+            (None, None) => {}
+        }
+        for (name, percent) in [("loss", self.loss), ("reorder", self.reorder)] {
+            let Some(percent) = percent else { continue };
+            if !(0.0..=100.0).contains(&percent) {
+                return Err(anyhow!("netem {} of {}% is out of range", name, percent));
+            }
+            args.extend([name.to_string(), format!("{percent}%")]);
+        }
+        if self.reorder.is_some() && self.delay.is_none() {
+            return Err(anyhow!("netem reorder needs a delay"));
+        }
+        if let Some(rate) = self.rate {
+            args.extend(["rate".to_string(), format!("{rate}bit")]);
+        }
+        Ok(args)
+    }
+
+    // Replaces the root qdisc of the device in the namespace of the current thread, e.g.,
+    // the fq qdisc installed by stdenv
+    pub fn apply(&self, name: &str) -> Result<()> {
+        let args = self.args()?;
+        tc(["qdisc", "replace", "dev", name, "root", "netem"]
+            .into_iter()
+            .chain(args.iter().map(String::as_str)))
+    }
+}
+
+// Removes the root qdisc of the device, which goes back to the default one
+pub fn remove_netem(name: &str) -> Result<()> {
+    tc(["qdisc", "del", "dev", name, "root"])
+}
+
+fn tc<'a>(args: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    let output = Command::new("tc").args(args).output()?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(String::from_utf8_lossy(&output.stderr).into_owned()))
+    }
+}
@@ -1,13 +1,21 @@
 use std::fs::File;
+use std::io::Read;
+use std::os::fd::BorrowedFd;
 use std::os::unix::fs::MetadataExt;
 use std::os::unix::io::AsRawFd;
+use std::os::unix::process::CommandExt;
 use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use nix::mount::{mount, umount2, MntFlags, MsFlags};
 use nix::sched::{setns, unshare, CloneFlags};
-use nix::unistd::gettid;
+use nix::sys::signal::{killpg, Signal};
+use nix::unistd::{gettid, Pid};
 
 /// Defines a NetNs environment behavior.
 pub trait Env {
@@ -41,7 +49,7 @@ pub struct DefaultEnv;
 
 /// path argument to functions defined here is prefixed with self.persist_dir()
 impl DefaultEnv {
-    fn persist_dir(&self) -> PathBuf {
+    pub(crate) fn persist_dir(&self) -> PathBuf {
         PathBuf::from("/var/run/netns")
     }
 
@@ -93,6 +101,49 @@ impl DefaultEnv {
         }
     }
 
+    /// Returns the namespaces left behind by processes that exited without dropping them,
+    /// e.g., a crashed test run.
+    ///
+    /// Only names generated by [`NetNs::new_unique`] are considered, the pid in them tells
+    /// whether the owner is still alive.
+    pub fn stale(&self) -> Result<Vec<PathBuf>> {
+        let mut stale = Vec::new();
+        let entries = match std::fs::read_dir(self.persist_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(stale),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+            let Some(pid) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(owner)
+            else {
+                continue;
+            };
+            if !is_alive(pid) {
+                stale.push(path);
+            }
+        }
+        Ok(stale)
+    }
+
+    /// Removes the namespaces returned by [`DefaultEnv::stale`] and returns their paths.
+    pub fn clean_stale(&self) -> Result<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+        for path in self.stale()? {
+            // another run may be cleaning up the same namespace, whoever removes the file
+            // reports it
+            let _ = umount2(&path, MntFlags::MNT_DETACH);
+            if std::fs::remove_file(&path).is_ok() {
+                removed.push(path);
+            }
+        }
+        Ok(removed)
+    }
+
     #[inline]
     fn get_current_netns_path() -> PathBuf {
         PathBuf::from(format!("/proc/self/task/{}/ns/net", gettid()))
@@ -279,6 +330,74 @@ impl<E: Env> NetNs<E> {
         Ok(())
     }
 
+    /// Spawns the command inside this network namespace.
+    ///
+    /// The forked child enters the namespace before exec, so the calling thread stays in its
+    /// own namespace and no guard is held while the command runs.
+    pub fn spawn(&self, command: &mut Command) -> Result<Child> {
+        let fd = self.as_raw_fd();
+        unsafe {
+            command.pre_exec(move || {
+                setns(BorrowedFd::borrow_raw(fd), CloneFlags::CLONE_NEWNET)
+                    .map_err(std::io::Error::from)
+            });
+        }
+        Ok(command.spawn()?)
+    }
+
+    /// Runs the command inside this network namespace and waits for it, capturing its
+    /// stdout and stderr.
+    pub fn run(&self, command: &mut Command) -> Result<Output> {
+        self.run_with_timeout(command, None)
+    }
+
+    /// Like [`NetNs::run`], but kills the command once the timeout elapses and fails with
+    /// what it printed so far.
+    pub fn run_timeout(&self, command: &mut Command, timeout: Duration) -> Result<Output> {
+        self.run_with_timeout(command, Some(timeout))
+    }
+
+    fn run_with_timeout(&self, command: &mut Command, timeout: Option<Duration>) -> Result<Output> {
+        // in a group of its own, so that a timeout kills the children of the command too,
+        // which would hold the pipes open otherwise
+        let mut child = self.spawn(
+            command
+                .process_group(0)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped()),
+        )?;
+        // drained on their own threads, so that a chatty command never blocks on a full pipe
+        let stdout = drain(child.stdout.take());
+        let stderr = drain(child.stderr.take());
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                killpg(Pid::from_raw(child.id() as i32), Signal::SIGKILL)?;
+                child.wait()?;
+                return Err(anyhow!(
+                    "{:?} timed out after {:?} in {}, stdout: {}, stderr: {}",
+                    command,
+                    timeout.unwrap(),
+                    self.path.display(),
+                    String::from_utf8_lossy(&stdout.join().unwrap()),
+                    String::from_utf8_lossy(&stderr.join().unwrap())
+                ));
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        Ok(Output {
+            status,
+            stdout: stdout.join().unwrap(),
+            stderr: stderr.join().unwrap(),
+        })
+    }
+
     /// Gets the path of this `NetNs`.
     pub fn path(&self) -> &Path {
         &self.path
@@ -299,16 +418,40 @@ impl<E: Env> NetNs<E> {
 
 impl<E: Env> Drop for NetNs<E> {
     fn drop(&mut self) {
-        let fd = self.file.as_raw_fd();
-        if let Err(e) = nix::unistd::close(fd) {
-            eprintln!("Failed to close netns: {e}");
-        }
+        // the file is closed when dropped after this, closing it here too is a double close
         if let Err(e) = self.env.clone().remove(self) {
             eprintln!("Failed to remove netns: {e}");
         }
     }
 }
 
+fn drain<R: Read + Send + 'static>(pipe: Option<R>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buffer = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buffer);
+        }
+        buffer
+    })
+}
+
+/// Returns the pid in a name generated by [`NetNs::new_unique`], i.e., `<prefix>-<pid>-<seq>`.
+fn owner(name: &str) -> Option<u32> {
+    let mut parts = name.rsplitn(3, '-');
+    let seq = parts.next()?;
+    let pid = parts.next()?;
+    let prefix = parts.next()?;
+    if prefix.is_empty() || seq.is_empty() || !seq.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    pid.parse().ok()
+}
+
+/// Returns `true` if a process with the pid exists in the pid namespace of this one.
+pub(crate) fn is_alive(pid: u32) -> bool {
+    pid == std::process::id() || Path::new(&format!("/proc/{pid}")).exists()
+}
+
 pub struct NetNsGuard<E: Env = DefaultEnv> {
     old: std::sync::Arc<NetNs<E>>,
 }
@@ -342,6 +485,43 @@ impl NetNs {
         Self::new_with_env(ns_name, default_env)
     }
 
+    /// Creates a new persistent network namespace like [`NetNs::new`], named after the prefix,
+    /// the pid and a sequence number, so that concurrent test runs never share a namespace.
+    ///
+    /// The first call in a process removes the namespaces of crashed runs, see
+    /// [`DefaultEnv::clean_stale`].
+    ///
+    /// Requires elevated privileges.
+    pub fn new_unique<S: AsRef<str>>(prefix: S) -> Result<std::sync::Arc<Self>> {
+        static CLEAN_STALE: Once = Once::new();
+        static SEQ: AtomicUsize = AtomicUsize::new(0);
+
+        let default_env = std::sync::Arc::new(DefaultEnv);
+        default_env.init()?;
+        CLEAN_STALE.call_once(|| match default_env.clean_stale() {
+            Ok(removed) => {
+                for path in removed {
+                    log::info!("removed stale namespace: {}", path.display());
+                }
+            }
+            Err(e) => log::warn!("failed to remove stale namespaces: {e}"),
+        });
+
+        // a name could still be taken by a run with the same pid in another pid namespace
+        let name = loop {
+            let name = format!(
+                "{}-{}-{}",
+                prefix.as_ref(),
+                std::process::id(),
+                SEQ.fetch_add(1, Ordering::Relaxed)
+            );
+            if !default_env.persist_dir().join(&name).exists() {
+                break name;
+            }
+        };
+        Self::new_with_env(name, default_env)
+    }
+
     pub fn current() -> Result<std::sync::Arc<Self>> {
         let default_env = std::sync::Arc::new(DefaultEnv);
         default_env.init()?;
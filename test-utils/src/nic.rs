@@ -0,0 +1,84 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use nix::net::if_::if_nametoindex;
+
+use crate::veth::{up_device, MacAddr};
+
+// the two ports, cabled back to back or through a switch
+pub const LEFT_VAR: &str = "CAMELLIA_NIC_LEFT";
+pub const RIGHT_VAR: &str = "CAMELLIA_NIC_RIGHT";
+// the queue test frames are steered to on both ports, 0 by default
+pub const QUEUE_VAR: &str = "CAMELLIA_NIC_QUEUE";
+// set to 1 to fail instead of skipping zero-copy tests if the driver lacks it, e.g., on
+// a machine known to have a capable NIC
+pub const REQUIRE_ZERO_COPY_VAR: &str = "CAMELLIA_NIC_REQUIRE_ZERO_COPY";
+
+pub struct Nic {
+    pub name: String,
+    pub index: u32,
+    pub mac_addr: MacAddr,
+    pub queue: u32,
+}
+
+// Two physical ports to run tests against instead of a veth pair, e.g.,
+//
+//   CAMELLIA_NIC_LEFT=enp1s0f0 CAMELLIA_NIC_RIGHT=enp1s0f1 cargo test --test nic_test
+//
+// Frames sent by a test must arrive at the queue it binds to, so either give each port
+// a single channel or steer the test traffic, ethertype 0x88b5, with an ntuple rule.
+// The ports are brought up, nothing else is changed.
+pub struct NicPair {
+    pub left: Nic,
+    pub right: Nic,
+    pub require_zero_copy: bool,
+}
+
+impl NicPair {
+    // None if the ports are not configured, so that tests are skipped on machines
+    // without them. Ports that are configured but missing are an error.
+    pub fn from_env() -> Result<Option<NicPair>> {
+        let (Ok(left), Ok(right)) = (std::env::var(LEFT_VAR), std::env::var(RIGHT_VAR)) else {
+            return Ok(None);
+        };
+        let queue = match std::env::var(QUEUE_VAR) {
+            Ok(queue) => queue
+                .parse()
+                .map_err(|e| anyhow!("invalid {}={}: {}", QUEUE_VAR, queue, e))?,
+            Err(_) => 0,
+        };
+
+        Ok(Some(NicPair {
+            left: Nic::new(&left, queue)?,
+            right: Nic::new(&right, queue)?,
+            require_zero_copy: std::env::var(REQUIRE_ZERO_COPY_VAR).is_ok_and(|v| v == "1"),
+        }))
+    }
+
+    // Like from_env, but prints why the test is skipped
+    pub fn from_env_or_skip(test: &str) -> Option<NicPair> {
+        let pair = NicPair::from_env().unwrap();
+        if pair.is_none() {
+            eprintln!(
+                "skipping {}: set {} and {} to run it against physical NICs",
+                test, LEFT_VAR, RIGHT_VAR
+            );
+        }
+        pair
+    }
+}
+
+impl Nic {
+    fn new(name: &str, queue: u32) -> Result<Nic> {
+        let index = if_nametoindex(name).map_err(|e| anyhow!("NIC {}: {}", name, e))?;
+        let address = std::fs::read_to_string(format!("/sys/class/net/{name}/address"))?;
+        up_device(name)?;
+
+        Ok(Nic {
+            name: name.to_string(),
+            index,
+            mac_addr: MacAddr::from_str(address.trim())?,
+            queue,
+        })
+    }
+}
@@ -1,36 +1,94 @@
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use anyhow::Result;
 
 use crate::{
     netns::NetNs,
-    veth::{set_preferred_busy_polling, set_promiscuous, set_rps_cores},
+    veth::{add_ipv6_default_route, set_preferred_busy_polling, set_promiscuous, set_rps_cores},
     veth::{VethDeviceBuilder, VethPair},
+    xdp::attach_xdp_pass,
 };
 
 pub fn setup_veth() -> Result<(VethPair, VethPair)> {
-    let client_netns = NetNs::new("client-ns").unwrap();
-    let server_netns = NetNs::new("server-ns").unwrap();
-    let forward_netns = NetNs::new("forward-ns").unwrap();
+    StdEnvBuilder::new().build()
+}
+
+// The same topology with every device having num_queues RX and TX queues
+pub fn setup_veth_with_queues(num_queues: u32) -> Result<(VethPair, VethPair)> {
+    StdEnvBuilder::new().num_queues(num_queues).build()
+}
+
+// A client and a server namespace connected through a forwarder namespace by two veth
+// pairs, forward-left and forward-right being the devices of the forwarder
+pub struct StdEnvBuilder {
+    num_queues: u32,
+    peer_xdp_pass: bool,
+}
+
+impl Default for StdEnvBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StdEnvBuilder {
+    pub fn new() -> StdEnvBuilder {
+        StdEnvBuilder {
+            num_queues: 1,
+            peer_xdp_pass: false,
+        }
+    }
+
+    #[must_use]
+    pub fn num_queues(mut self, num_queues: u32) -> Self {
+        self.num_queues = num_queues;
+        self
+    }
+
+    // attaches an XDP pass program on the client and server devices, which the forwarder
+    // needs to redirect frames to them in the native mode
+    #[must_use]
+    pub fn peer_xdp_pass(mut self) -> Self {
+        self.peer_xdp_pass = true;
+        self
+    }
+
+    pub fn build(self) -> Result<(VethPair, VethPair)> {
+        setup(self.num_queues, self.peer_xdp_pass)
+    }
+}
+
+fn setup(num_queues: u32, peer_xdp_pass: bool) -> Result<(VethPair, VethPair)> {
+    let client_netns = NetNs::new_unique("client-ns").unwrap();
+    let server_netns = NetNs::new_unique("server-ns").unwrap();
+    let forward_netns = NetNs::new_unique("forward-ns").unwrap();
 
     let client_device = VethDeviceBuilder::new("test-left")
         .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2a].into())
         .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 11, 1)), 24)
+        .ipv6_addr(Ipv6Addr::new(0xfd00, 0x11, 0, 0, 0, 0, 0, 1), 64)
+        .num_queues(num_queues)
         .namespace(client_netns.clone());
 
     let left_device = VethDeviceBuilder::new("forward-left")
         .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2b].into())
         .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 11, 2)), 24)
+        .ipv6_addr(Ipv6Addr::new(0xfd00, 0x11, 0, 0, 0, 0, 0, 2), 64)
+        .num_queues(num_queues)
         .namespace(forward_netns.clone());
 
     let right_device = VethDeviceBuilder::new("forward-right")
         .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2c].into())
         .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 12, 2)), 24)
+        .ipv6_addr(Ipv6Addr::new(0xfd00, 0x12, 0, 0, 0, 0, 0, 2), 64)
+        .num_queues(num_queues)
         .namespace(forward_netns.clone());
 
     let server_device = VethDeviceBuilder::new("test-right")
         .mac_addr([0x38, 0x7e, 0x58, 0xe7, 0x87, 0x2d].into())
         .ip_addr(IpAddr::V4(Ipv4Addr::new(192, 168, 12, 1)), 24)
+        .ipv6_addr(Ipv6Addr::new(0xfd00, 0x12, 0, 0, 0, 0, 0, 1), 64)
+        .num_queues(num_queues)
         .namespace(server_netns.clone());
 
     let left_pair = client_device.build(left_device).unwrap();
@@ -63,7 +121,14 @@ pub fn setup_veth() -> Result<(VethPair, VethPair)> {
             .wait()
             .unwrap();
 
+        // IPv6 has no route via a local address, the forwarder bridges the links anyway
+        add_ipv6_default_route(left_pair.left.name.as_str()).unwrap();
+
         set_rps_cores(left_pair.left.name.as_str(), &[1]);
+
+        if peer_xdp_pass {
+            attach_xdp_pass(left_pair.left.name.as_str())?;
+        }
     }
 
     {
@@ -93,7 +158,13 @@ pub fn setup_veth() -> Result<(VethPair, VethPair)> {
             .wait()
             .unwrap();
 
+        add_ipv6_default_route(right_pair.right.name.as_str()).unwrap();
+
         set_rps_cores(right_pair.right.name.as_str(), &[3]);
+
+        if peer_xdp_pass {
+            attach_xdp_pass(right_pair.right.name.as_str())?;
+        }
     }
 
     {
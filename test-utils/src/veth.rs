@@ -1,10 +1,23 @@
-use super::netns::NetNs;
+use super::{
+    cleanup,
+    ethtool::{get_channels, set_channels, set_value, Channels, ETHTOOL_SRXCSUM, ETHTOOL_STXCSUM},
+    netns::NetNs,
+    xdp::attach_xdp_pass,
+};
 use anyhow::{anyhow, Result};
-use nix::net::if_::if_nametoindex;
+use netlink_packet_route::link::{
+    InfoData, InfoKind, InfoVeth, LinkAttribute, LinkInfo, LinkMessage,
+};
+use nix::{
+    mount::{mount, MsFlags},
+    net::if_::if_nametoindex,
+};
 use once_cell::sync::OnceCell;
+use rtnetlink::Handle;
 use std::{
-    net::IpAddr,
-    process::Command,
+    future::Future,
+    net::{IpAddr, Ipv6Addr},
+    os::fd::AsRawFd,
     sync::{Arc, Weak},
 };
 use tempfile::TempDir;
@@ -18,62 +31,19 @@ pub struct VethPairBuilder;
 
 impl VethPairBuilder {
     pub fn build(left: VethDeviceBuilder, right: VethDeviceBuilder) -> Result<VethPair> {
-        let handle = Command::new("ip")
-            .arg("link")
-            .arg("add")
-            .arg(&left.name)
-            .arg("type")
-            .arg("veth")
-            .arg("peer")
-            .arg("name")
-            .arg(&right.name)
-            .spawn()
-            .unwrap();
-        let output = handle.wait_with_output().unwrap();
-
-        if !output.status.success() {
-            return Err(anyhow!(
-                "Failed to create veth pair: {}",
-                String::from_utf8_lossy(&output.stderr)
-            ));
-        }
-        bind_namespace(&left.name, &left.namespace.as_ref().unwrap().clone()).unwrap();
-        bind_namespace(&right.name, &right.namespace.as_ref().unwrap().clone()).unwrap();
-
-        let left_index = {
-            let _guard = left.namespace.as_ref().unwrap().enter().unwrap();
-            set_device_l2_addr(&left.name, left.mac_addr.unwrap()).unwrap();
-            set_l3_addr(&left.name, left.ip_addr.unwrap().0, left.ip_addr.unwrap().1).unwrap();
-            disable_checksum_offload(&left.name).unwrap();
-            set_num_rx_queues(&left.name, 1);
-            set_num_tx_queues(&left.name, 1);
-            up_device(&left.name).unwrap();
-
-            if_nametoindex(left.name.as_str()).unwrap()
-        };
-
-        let right_index = {
-            let _guard = right.namespace.as_ref().unwrap().enter().unwrap();
-            set_device_l2_addr(&right.name, right.mac_addr.unwrap()).unwrap();
-            set_l3_addr(
-                &right.name,
-                right.ip_addr.unwrap().0,
-                right.ip_addr.unwrap().1,
-            )
-            .unwrap();
-            disable_checksum_offload(&right.name).unwrap();
-            set_num_rx_queues(&right.name, 1);
-            set_num_tx_queues(&right.name, 1);
-            up_device(&right.name).unwrap();
-
-            if_nametoindex(right.name.as_str()).unwrap()
-        };
+        create_veth_pair(&left, &right)
+            .map_err(|e| anyhow!("Failed to create veth pair: {}", e))?;
+
+        let left_index = configure_device(&left)?;
+        let right_index = configure_device(&right)?;
 
         let left_device = Arc::new(VethDevice {
             name: left.name,
             index: left_index,
             mac_addr: left.mac_addr.unwrap(),
             ip_addr: left.ip_addr.unwrap(),
+            ipv6_addr: left.ipv6_addr,
+            num_queues: left.num_queues,
             peer: OnceCell::new(),
             namespace: left.namespace.unwrap(),
         });
@@ -83,6 +53,8 @@ impl VethPairBuilder {
             index: right_index,
             mac_addr: right.mac_addr.unwrap(),
             ip_addr: right.ip_addr.unwrap(),
+            ipv6_addr: right.ipv6_addr,
+            num_queues: right.num_queues,
             peer: OnceCell::new(),
             namespace: right.namespace.unwrap(),
         });
@@ -97,11 +69,84 @@ impl VethPairBuilder {
     }
 }
 
+// Both ends are created right in their namespaces, with their number of queues
+fn create_veth_pair(left: &VethDeviceBuilder, right: &VethDeviceBuilder) -> Result<()> {
+    let current = NetNs::current()?;
+    let attributes = |device: &VethDeviceBuilder| {
+        let namespace = device.namespace.as_ref().unwrap();
+        let mut attributes = vec![
+            LinkAttribute::IfName(device.name.clone()),
+            LinkAttribute::NumTxQueues(device.num_queues),
+            LinkAttribute::NumRxQueues(device.num_queues),
+        ];
+        if **namespace != *current {
+            attributes.push(LinkAttribute::NetNsFd(namespace.as_raw_fd()));
+        }
+        attributes
+    };
+
+    let mut peer = LinkMessage::default();
+    peer.attributes.extend(attributes(right));
+    let mut message = attributes(left);
+    message.push(LinkAttribute::LinkInfo(vec![
+        LinkInfo::Kind(InfoKind::Veth),
+        LinkInfo::Data(InfoData::Veth(InfoVeth::Peer(peer))),
+    ]));
+
+    netlink(|handle| {
+        let mut request = handle.link().add();
+        request.message_mut().attributes = message;
+        request.execute()
+    })
+}
+
+fn configure_device(device: &VethDeviceBuilder) -> Result<u32> {
+    let _guard = device.namespace.as_ref().unwrap().enter()?;
+    let (ip_addr, prefix) = device.ip_addr.unwrap();
+    if ip_addr.is_ipv6() || device.ipv6_addr.is_some() {
+        disable_router_advertisements(&device.name)?;
+    }
+    set_device_l2_addr(&device.name, device.mac_addr.unwrap())?;
+    set_l3_addr(&device.name, ip_addr, prefix)?;
+    if let Some((ipv6_addr, prefix)) = device.ipv6_addr {
+        set_l3_addr(&device.name, IpAddr::V6(ipv6_addr), prefix)?;
+    }
+    disable_checksum_offload(&device.name)?;
+    set_alias(&device.name, &cleanup::marker())?;
+    up_device(&device.name)?;
+    if device.xdp_pass {
+        attach_xdp_pass(&device.name)?;
+    }
+
+    Ok(if_nametoindex(device.name.as_str())?)
+}
+
+// Runs a request on a netlink connection opened in the namespace of the current thread
+pub(crate) fn netlink<F, Fut>(request: F) -> Result<()>
+where
+    F: FnOnce(Handle) -> Fut,
+    Fut: Future<Output = Result<(), rtnetlink::Error>>,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()?;
+    runtime.block_on(async {
+        let (connection, handle, _) = rtnetlink::new_connection()?;
+        tokio::spawn(connection);
+        request(handle).await?;
+        Ok(())
+    })
+}
+
 pub struct VethDevice {
     pub name: String,
     pub index: u32,
     pub mac_addr: MacAddr,
     pub ip_addr: (IpAddr, u8),
+    // the IPv6 address of a dual-stack device
+    pub ipv6_addr: Option<(Ipv6Addr, u8)>,
+    // RX and TX queues alike
+    pub num_queues: u32,
     pub peer: OnceCell<Weak<VethDevice>>,
     pub namespace: std::sync::Arc<NetNs>,
 }
@@ -110,6 +155,9 @@ pub struct VethDeviceBuilder {
     name: String,
     mac_addr: Option<MacAddr>,
     ip_addr: Option<(IpAddr, u8)>,
+    ipv6_addr: Option<(Ipv6Addr, u8)>,
+    num_queues: u32,
+    xdp_pass: bool,
     namespace: Option<std::sync::Arc<NetNs>>,
 }
 
@@ -119,6 +167,9 @@ impl VethDeviceBuilder {
             name: name.as_ref().to_string(),
             mac_addr: None,
             ip_addr: None,
+            ipv6_addr: None,
+            num_queues: 1,
+            xdp_pass: false,
             namespace: Some(NetNs::current().unwrap()),
         }
     }
@@ -135,6 +186,29 @@ impl VethDeviceBuilder {
         self
     }
 
+    // an IPv6 address next to the one of ip_addr, for dual-stack devices
+    #[must_use]
+    pub fn ipv6_addr(mut self, ipv6_addr: Ipv6Addr, prefix: u8) -> Self {
+        self.ipv6_addr = Some((ipv6_addr, prefix));
+        self
+    }
+
+    // RX and TX queues of the device, one by default. XDP on veth needs at least as many
+    // RX queues as the peer has TX queues, so both ends usually have the same number.
+    #[must_use]
+    pub fn num_queues(mut self, num_queues: u32) -> Self {
+        self.num_queues = num_queues;
+        self
+    }
+
+    // attaches a program passing every frame in the native mode, for the peer of a device
+    // with native-mode sockets, see attach_xdp_pass
+    #[must_use]
+    pub fn xdp_pass(mut self) -> Self {
+        self.xdp_pass = true;
+        self
+    }
+
     #[must_use]
     pub fn namespace(mut self, namespace: std::sync::Arc<NetNs>) -> Self {
         self.namespace = Some(namespace);
@@ -142,7 +216,7 @@ impl VethDeviceBuilder {
     }
 
     fn complete(&self) -> bool {
-        self.mac_addr.is_some() && self.ip_addr.is_some()
+        self.mac_addr.is_some() && self.ip_addr.is_some() && self.num_queues > 0
     }
 
     pub fn build(self, peer: VethDeviceBuilder) -> Result<VethPair> {
@@ -154,126 +228,133 @@ impl VethDeviceBuilder {
     }
 }
 
-pub fn _down_device(name: &str) -> Result<()> {
-    let output = Command::new("ip")
-        .arg("link")
-        .arg("set")
-        .arg("dev")
-        .arg(name)
-        .arg("down")
-        .output()
-        .unwrap();
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(anyhow!(String::from_utf8(output.stderr).unwrap()))
-    }
+pub fn down_device(name: &str) -> Result<()> {
+    let index = if_nametoindex(name)?;
+    netlink(|handle| handle.link().set(index).down().execute())
 }
 
 pub fn up_device(name: &str) -> Result<()> {
-    let output = Command::new("ip")
-        .arg("link")
-        .arg("set")
-        .arg("dev")
-        .arg(name)
-        .arg("up")
-        .output()?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(anyhow!(String::from_utf8(output.stderr).unwrap()))
-    }
+    let index = if_nametoindex(name)?;
+    netlink(|handle| handle.link().set(index).up().execute())
+}
+
+// deleting either end of a veth pair deletes both
+pub fn delete_device(name: &str) -> Result<()> {
+    let index = if_nametoindex(name)?;
+    netlink(|handle| handle.link().del(index).execute())
+}
+
+pub fn set_mtu(name: &str, mtu: u32) -> Result<()> {
+    let index = if_nametoindex(name)?;
+    netlink(|handle| handle.link().set(index).mtu(mtu).execute())
+}
+
+// shown by ip link, see cleanup::marker for what test devices are marked with
+pub fn set_alias(name: &str, alias: &str) -> Result<()> {
+    let index = if_nametoindex(name)?;
+    let alias = alias.to_string();
+    netlink(|handle| {
+        let mut request = handle.link().set(index);
+        request
+            .message_mut()
+            .attributes
+            .push(LinkAttribute::IfAlias(alias));
+        request.execute()
+    })
 }
 
 pub fn set_device_l2_addr(name: &str, mac_addr: MacAddr) -> Result<()> {
-    let output = Command::new("ip")
-        .arg("link")
-        .arg("set")
-        .arg("dev")
-        .arg(name)
-        .arg("address")
-        .arg(mac_addr.to_string())
-        .output()?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(anyhow!(String::from_utf8(output.stderr).unwrap()))
-    }
+    let index = if_nametoindex(name)?;
+    netlink(|handle| {
+        handle
+            .link()
+            .set(index)
+            .address(mac_addr.bytes().to_vec())
+            .execute()
+    })
 }
 
 pub fn set_l3_addr(name: &str, ip_addr: IpAddr, prefix: u8) -> Result<()> {
-    let output = Command::new("ip")
-        .arg("address")
-        .arg("add")
-        .arg(format!("{ip_addr}/{prefix}"))
-        .arg("dev")
-        .arg(name)
-        .output()?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(anyhow!(String::from_utf8(output.stderr)?))
+    let index = if_nametoindex(name)?;
+    // without duplicate address detection, IPv6 addresses are usable right away instead
+    // of staying tentative for a second or two
+    if ip_addr.is_ipv6() {
+        set_ipv6_sysctl(name, "accept_dad", "0")?;
     }
+    netlink(|handle| handle.address().add(index, ip_addr, prefix).execute())
+}
+
+// Routes every IPv6 destination out of the device as on the link, without a gateway,
+// e.g., to reach the other side of a forwarder bridging two links
+pub fn add_ipv6_default_route(name: &str) -> Result<()> {
+    let index = if_nametoindex(name)?;
+    netlink(|handle| handle.route().add().v6().output_interface(index).execute())
+}
+
+// So that the addresses and routes of a test are the only ones of the device. Duplicate
+// address detection is disabled as well, for the link-local address the device gets once
+// it is up, which set_l3_addr doesn't add.
+pub fn disable_router_advertisements(name: &str) -> Result<()> {
+    set_ipv6_sysctl(name, "accept_ra", "0")?;
+    set_ipv6_sysctl(name, "accept_dad", "0")
+}
+
+// the sysctls of the namespace of the current thread
+fn set_ipv6_sysctl(name: &str, key: &str, value: &str) -> Result<()> {
+    let path = format!("/proc/sys/net/ipv6/conf/{name}/{key}");
+    std::fs::write(&path, value).map_err(|e| anyhow!("Failed to write {}: {}", path, e))
 }
 
 pub fn set_num_rx_queues(name: &str, num_rx_queues: usize) {
-    let output = Command::new("ethtool")
-        .args(["-L", name, "rx", num_rx_queues.to_string().as_str()])
-        .output()
-        .unwrap();
-
-    if !output.status.success() {
-        eprintln!(
-            "Failed to set number of RX queues: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+    let result = get_channels(name).and_then(|channels| {
+        set_channels(
+            name,
+            Channels {
+                rx: num_rx_queues as u32,
+                ..channels
+            },
+        )
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to set number of RX queues: {}", e);
     }
 }
 
 pub fn set_num_tx_queues(name: &str, num_tx_queues: usize) {
-    let output = Command::new("ethtool")
-        .args(["-L", name, "tx", num_tx_queues.to_string().as_str()])
-        .output()
-        .unwrap();
-
-    if !output.status.success() {
-        eprintln!(
-            "Failed to set number of TX queues: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+    let result = get_channels(name).and_then(|channels| {
+        set_channels(
+            name,
+            Channels {
+                tx: num_tx_queues as u32,
+                ..channels
+            },
+        )
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to set number of TX queues: {}", e);
     }
 }
 
 pub fn set_promiscuous(name: &str) {
-    let output = Command::new("ip")
-        .args(["link", "set", "dev", name, "promisc", "on"])
-        .output()
-        .unwrap();
-
-    if !output.status.success() {
-        eprintln!(
-            "Failed to set promisc: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+    let result = if_nametoindex(name)
+        .map_err(anyhow::Error::from)
+        .and_then(|index| netlink(|handle| handle.link().set(index).promiscuous(true).execute()));
+
+    if let Err(e) = result {
+        eprintln!("Failed to set promisc: {}", e);
     }
 }
 
 fn remount_sys() -> Result<tempfile::TempDir> {
     let temp_dir = TempDir::with_prefix("ns_sys")?;
 
-    Command::new("mount")
-        .args([
-            "-t",
-            "sysfs",
-            "none",
-            temp_dir.path().as_os_str().to_str().unwrap(),
-        ])
-        .spawn()?
-        .wait()?;
+    mount(
+        Some("none"),
+        temp_dir.path(),
+        Some("sysfs"),
+        MsFlags::empty(),
+        None::<&str>,
+    )?;
 
     Ok(temp_dir)
 }
@@ -329,15 +410,10 @@ pub fn set_preferred_busy_polling(name: &str) {
 }
 
 pub fn disable_checksum_offload(name: &str) -> Result<()> {
-    let output = Command::new("ethtool")
-        .args(["-K", name, "tx", "off", "rx", "off"])
-        .output()?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(anyhow!(String::from_utf8(output.stderr).unwrap()))
+    for cmd in [ETHTOOL_SRXCSUM, ETHTOOL_STXCSUM] {
+        set_value(name, cmd, 0)?;
     }
+    Ok(())
 }
 
 pub fn bind_namespace(name: &str, netns: &std::sync::Arc<NetNs>) -> Result<()> {
@@ -345,24 +421,9 @@ pub fn bind_namespace(name: &str, netns: &std::sync::Arc<NetNs>) -> Result<()> {
         return Ok(());
     }
 
-    let ns_name = netns.as_ref().path().strip_prefix("/var/run/netns/")?;
-
-    let output = Command::new("ip")
-        .args([
-            "link",
-            "set",
-            "dev",
-            name,
-            "netns",
-            ns_name.as_os_str().to_str().unwrap(),
-        ])
-        .output()?;
-
-    if output.status.success() {
-        Ok(())
-    } else {
-        Err(anyhow!(String::from_utf8(output.stderr).unwrap()))
-    }
+    let index = if_nametoindex(name)?;
+    let fd = netns.as_raw_fd();
+    netlink(|handle| handle.link().set(index).setns_by_fd(fd).execute())
 }
 
 impl VethDevice {
@@ -409,8 +470,11 @@ impl std::str::FromStr for MacAddr {
                 return Err(anyhow!("Invalid MAC address: {}", input));
             }
 
-            array[nth] =
-                u8::from_str_radix(byte, 16).map_err(|_| anyhow!("Invalid radix digit"))?;
+            // from_str_radix takes a sign and any number of leading zeros
+            if byte.is_empty() || byte.len() > 2 || !byte.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(anyhow!("Invalid MAC address: {}", input));
+            }
+            array[nth] = u8::from_str_radix(byte, 16).unwrap();
 
             nth += 1;
         }
@@ -0,0 +1,114 @@
+use std::{
+    ffi::CString,
+    mem::size_of,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+};
+
+use anyhow::{anyhow, Result};
+use netlink_packet_route::link::{LinkAttribute, LinkXdp};
+use nix::{errno::Errno, libc, net::if_::if_nametoindex};
+
+use crate::veth::netlink;
+
+const BPF_PROG_LOAD: libc::c_long = 5;
+const BPF_PROG_TYPE_XDP: u32 = 6;
+const XDP_FLAGS_DRV_MODE: u32 = 1 << 2;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct BpfInsn {
+    code: u8,
+    // the destination register in the low nibble, the source one in the high nibble
+    regs: u8,
+    off: i16,
+    imm: i32,
+}
+
+// the fields of union bpf_attr for BPF_PROG_LOAD up to prog_name, the kernel takes the
+// rest of the union as zero
+#[repr(C)]
+#[derive(Default)]
+struct BpfProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    prog_flags: u32,
+    prog_name: [u8; 16],
+}
+
+// r0 = XDP_PASS; exit
+const XDP_PASS_PROGRAM: [BpfInsn; 2] = [
+    BpfInsn {
+        code: 0xb7,
+        regs: 0,
+        off: 0,
+        imm: 2,
+    },
+    BpfInsn {
+        code: 0x95,
+        regs: 0,
+        off: 0,
+        imm: 0,
+    },
+];
+
+// Loads a program passing every frame to the stack, named xdp_pass. It needs no BPF
+// toolchain or object file.
+fn load_xdp_pass() -> Result<OwnedFd> {
+    let license = CString::new("GPL").unwrap();
+    let mut attr = BpfProgLoadAttr {
+        prog_type: BPF_PROG_TYPE_XDP,
+        insn_cnt: XDP_PASS_PROGRAM.len() as u32,
+        insns: XDP_PASS_PROGRAM.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        ..Default::default()
+    };
+    attr.prog_name[..8].copy_from_slice(b"xdp_pass");
+
+    let fd = Errno::result(unsafe {
+        libc::syscall(
+            libc::SYS_bpf,
+            BPF_PROG_LOAD,
+            &attr as *const BpfProgLoadAttr,
+            size_of::<BpfProgLoadAttr>(),
+        )
+    })
+    .map_err(|e| anyhow!("Failed to load the XDP pass program: {}", e))?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+// Attaches a program passing every frame in the native mode of the device. Frames
+// redirected by XDP to a veth device, e.g., by XDP_TX or a forwarder between two devices,
+// are dropped by its peer unless the peer runs an XDP program itself, so the peers of
+// devices with native-mode sockets need one. The program stays attached until detached
+// or the device is deleted.
+pub fn attach_xdp_pass(name: &str) -> Result<()> {
+    let index = if_nametoindex(name)?;
+    let program = load_xdp_pass()?;
+    set_xdp(index, program.as_raw_fd())
+}
+
+// Detaches the native-mode program of the device, whichever it is
+pub fn detach_xdp(name: &str) -> Result<()> {
+    let index = if_nametoindex(name)?;
+    set_xdp(index, -1)
+}
+
+fn set_xdp(index: u32, fd: i32) -> Result<()> {
+    netlink(|handle| {
+        let mut request = handle.link().set(index);
+        request
+            .message_mut()
+            .attributes
+            .push(LinkAttribute::Xdp(vec![
+                LinkXdp::Fd(fd),
+                LinkXdp::Flags(XDP_FLAGS_DRV_MODE),
+            ]));
+        request.execute()
+    })
+}
@@ -1,5 +1,5 @@
 #!/usr/bin/env bash
 
-sudo ip netns delete client-ns
-sudo ip netns delete server-ns
-sudo ip netns delete forward-ns
+# namespaces of crashed runs are named <prefix>-<pid>-<seq>, see NetNs::new_unique
+cargo build -p test-utils --bin camellia-cli
+sudo "$(dirname "$0")/../target/debug/camellia-cli" cleanup
@@ -0,0 +1,22 @@
+#!/usr/bin/env bash
+# Installs camellia-testd with the capabilities the tests need, so that members of the
+# camellia-test group run
+#
+#   CARGO_TARGET_X86_64_UNKNOWN_LINUX_GNU_RUNNER=camellia-testd cargo test
+#
+# without sudo. The binary is owned by root, so nobody can swap it for another one keeping
+# the capabilities, and only the group may execute it.
+set -euo pipefail
+
+PREFIX=${PREFIX:-/usr/local}
+GROUP=${GROUP:-camellia-test}
+
+cd "$(dirname "$0")/.."
+cargo build --release -p test-utils --bin camellia-testd
+getent group "$GROUP" >/dev/null || sudo groupadd --system "$GROUP"
+sudo install -m 0750 -o root -g "$GROUP" target/release/camellia-testd \
+    "$PREFIX/bin/camellia-testd"
+# CAP_BPF needs Linux 5.8
+sudo setcap cap_net_admin,cap_net_raw,cap_bpf+p "$PREFIX/bin/camellia-testd"
+getcap "$PREFIX/bin/camellia-testd"
+echo "add users with: sudo usermod -aG $GROUP <user>"